@@ -357,7 +357,10 @@ interface KillPredictRow {
 async function parseKillCsv(csvPath: string): Promise<KillPredictRow[]> {
   try {
     const content = await readFile(csvPath, "utf8");
-    const lines = content.trim().split("\n");
+    const lines = content
+      .trim()
+      .split("\n")
+      .filter((line) => !line.startsWith("#"));
     if (lines.length < 2) return [];
     const parts0 = lines[0].split(",").map((p) => p.trim().toLowerCase());
     const ti = parts0.indexOf("t");
@@ -385,7 +388,10 @@ async function parseKillCsv(csvPath: string): Promise<KillPredictRow[]> {
 async function parseExpressionCsv(csvPath: string): Promise<ExpressionAnalyzeRow[]> {
   try {
     const content = await readFile(csvPath, "utf8");
-    const lines = content.trim().split("\n");
+    const lines = content
+      .trim()
+      .split("\n")
+      .filter((line) => !line.startsWith("#"));
     if (lines.length < 2) return [];
     const rows: ExpressionAnalyzeRow[] = [];
     for (let i = 1; i < lines.length; i++) {
@@ -409,7 +415,10 @@ async function parseExpressionCsv(csvPath: string): Promise<ExpressionAnalyzeRow
 async function parseTissueCsv(csvPath: string): Promise<TissueAnalyzeRow[]> {
   try {
     const content = await readFile(csvPath, "utf8");
-    const lines = content.trim().split("\n");
+    const lines = content
+      .trim()
+      .split("\n")
+      .filter((line) => !line.startsWith("#"));
     if (lines.length < 2) return [];
     const rows: TissueAnalyzeRow[] = [];
     for (let i = 1; i < lines.length; i++) {
@@ -1514,6 +1523,8 @@ function registerWorkspaceStateIpc(): void {
         String(payload.pos),
         "--channel",
         String(payload.channel),
+        "--time",
+        "all",
         "--output",
         payload.output,
       ];
@@ -1565,6 +1576,8 @@ function registerWorkspaceStateIpc(): void {
         path.join(payload.workspacePath, "crops.zarr"),
         "--pos",
         String(payload.pos),
+        "--time",
+        "all",
         "--model",
         payload.modelPath,
         "--output",
@@ -1732,6 +1745,10 @@ function registerWorkspaceStateIpc(): void {
         payload.pos,
         "--time",
         payload.time,
+        "--channel",
+        "all",
+        "--z",
+        "all",
         "--output",
         payload.output,
         "--yes",
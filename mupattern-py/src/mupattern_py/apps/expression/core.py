@@ -70,7 +70,7 @@ def run_plot(input_csv: Path, output: Path) -> None:
     output = output.resolve()
     output.parent.mkdir(parents=True, exist_ok=True)
 
-    df = pd.read_csv(input_csv, dtype={"crop": str})
+    df = pd.read_csv(input_csv, dtype={"crop": str}, comment="#")
     df["intensity_above_bg"] = df["intensity"] - df["area"] * df["background"]
     crops = sorted(df["crop"].unique())
     size = 6
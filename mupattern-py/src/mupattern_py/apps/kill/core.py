@@ -16,7 +16,7 @@ from ...common.progress import ProgressCallback
 
 def _load_csv(csv_path: Path) -> pd.DataFrame:
     """Load a predictions/annotations CSV (t,crop,label) into a DataFrame."""
-    df = pd.read_csv(csv_path, dtype={"crop": str})
+    df = pd.read_csv(csv_path, dtype={"crop": str}, comment="#")
     if df["label"].dtype == object:
         df["label"] = df["label"].map({"true": True, "false": False})
     else:
@@ -66,7 +66,7 @@ def run_plot(input_csv: Path, output: Path) -> None:
     matplotlib.use("Agg")
     import matplotlib.pyplot as plt
 
-    df = pd.read_csv(input_csv, dtype={"crop": str})
+    df = pd.read_csv(input_csv, dtype={"crop": str}, comment="#")
     counts = df.groupby(["t", "crop"]).size().reset_index(name="count")
     max_t = counts["t"].max()
 
@@ -255,7 +255,7 @@ def run_plot(input_csv: Path, output_dir: Path, gfp_threshold: float) -> None:
     count_path = output_dir / "gfp_count.png"
     fluo_path = output_dir / "median_fluorescence.png"
 
-    df = pd.read_csv(input_csv, dtype={"crop": str})
+    df = pd.read_csv(input_csv, dtype={"crop": str}, comment="#")
     df["mean_above_bg"] = (df["total_fluorescence"] / df["cell_area"]) - df["background"]
     df["fluo_above_bg"] = df["total_fluorescence"] - df["cell_area"] * df["background"]
     gfp = df[df["mean_above_bg"] > gfp_threshold]
@@ -0,0 +1,425 @@
+//! Audit-background: recompute the `/pos/{p}/background` medians of `crop --background` on
+//! a sample of frames from the source TIFFs (or ND2) and compare them with the stored
+//! values. Stores written by older `crop` builds with a different chunk-index convention
+//! hold the right medians at the wrong (t, c, z); when the stored values match another
+//! axis order, the report names it so the store can be re-cropped before quantification.
+
+use clap::Args;
+use nd2_rs::Nd2File;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::calibration::PixelCalibration;
+use crate::cancel;
+use crate::console;
+use crate::crop::{self, Bbox, FrameData, TiffIndex};
+use crate::crop_index;
+use crate::defects::DefectMap;
+use crate::progress::ProgressEvent;
+use crate::zarr;
+
+#[derive(Args, Clone)]
+pub struct AuditBackgroundArgs {
+    /// Path to crops.zarr
+    #[arg(long)]
+    pub input: String,
+    /// TIFF root the store was cropped from (Pos{N} folders)
+    #[arg(long, required_unless_present = "nd2")]
+    pub source: Option<String>,
+    /// ND2 file the store was cropped from (`crop --nd2`)
+    #[arg(long, conflicts_with = "source")]
+    pub nd2: Option<String>,
+    /// Filename regex given to `crop --pattern`
+    #[arg(long)]
+    pub pattern: Option<String>,
+    /// Only audit this position (default: every position with a background array)
+    #[arg(long)]
+    pub pos: Option<u32>,
+    /// Timepoints sampled per position, evenly spaced (every channel and z of each)
+    #[arg(long, default_value_t = 5)]
+    pub samples: usize,
+    /// Largest difference in counts still reported as a match
+    #[arg(long, default_value_t = 0)]
+    pub tolerance: u16,
+    /// Bbox CSV given to `crop` ({pos} is replaced by the position number); only needed for
+    /// stores without a crop registry
+    #[arg(long)]
+    pub bbox: Option<String>,
+    /// Defect map given to `crop --fix-defects`
+    #[arg(long)]
+    pub fix_defects: Option<String>,
+}
+
+/// Axis orders an older `crop` may have laid the background frames out in, as the order
+/// its flat frame index ran over (t, c, z); the first is the current convention.
+const ORDERS: [(&str, [usize; 3]); 6] = [
+    ("t, c, z", [0, 1, 2]),
+    ("t, z, c", [0, 2, 1]),
+    ("c, t, z", [1, 0, 2]),
+    ("c, z, t", [1, 2, 0]),
+    ("z, t, c", [2, 0, 1]),
+    ("z, c, t", [2, 1, 0]),
+];
+
+/// Where frame `tcz` of a `shape` (T, C, Z) array was written under axis `order`: its
+/// flat index in that order, read back in (t, c, z) order.
+fn written_at(order: [usize; 3], tcz: [u64; 3], shape: [u64; 3]) -> [u64; 3] {
+    let flat = order.iter().fold(0, |flat, &a| flat * shape[a] + tcz[a]);
+    let (tc, z) = (flat / shape[2], flat % shape[2]);
+    [tc / shape[1], tc % shape[1], z]
+}
+
+/// Up to `n` output timepoints of `n_t`, evenly spaced and including the first and last.
+fn sample_times(n_t: u64, n: usize) -> Vec<u64> {
+    if n_t == 0 || n == 0 {
+        return Vec::new();
+    }
+    if n as u64 >= n_t {
+        return (0..n_t).collect();
+    }
+    if n == 1 {
+        return vec![0];
+    }
+    let mut times: Vec<u64> = (0..n as u64)
+        .map(|i| i * (n_t - 1) / (n as u64 - 1))
+        .collect();
+    times.dedup();
+    times
+}
+
+/// Where the frames of the audited store came from.
+enum Source {
+    Tiffs(TiffIndex),
+    Nd2 {
+        file: Nd2File,
+        pos: usize,
+        size: (u32, u32),
+    },
+}
+
+/// One sampled frame: array coordinates, stored and recomputed median.
+struct Sampled {
+    tcz: [u64; 3],
+    stored: u16,
+    recomputed: u16,
+}
+
+fn differs(a: u16, b: u16, tolerance: u16) -> bool {
+    a.abs_diff(b) > tolerance
+}
+
+/// Positions with a background array, as zero-padded IDs.
+fn background_positions(root: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut ids: Vec<String> = fs::read_dir(root.join("pos"))?
+        .filter_map(|e| {
+            let e = e.ok()?;
+            e.path().join("background").is_dir().then_some(())?;
+            e.file_name().to_str().map(String::from)
+        })
+        .collect();
+    ids.sort();
+    Ok(ids)
+}
+
+/// Boxes `crop` masked out of the background: the crop registry, else `--bbox`.
+fn position_boxes(
+    args: &AuditBackgroundArgs,
+    root: &Path,
+    store: &zarr::Store,
+    pos_id: &str,
+    pos: u32,
+    (width, height): (u32, u32),
+) -> Result<Vec<Bbox>, Box<dyn std::error::Error>> {
+    if let Some(entry) = crop_index::registry(root, pos_id)? {
+        return Ok(entry
+            .crops
+            .into_iter()
+            .enumerate()
+            .map(|(row, c)| Bbox {
+                label: c.id.clone(),
+                id: c.id,
+                row,
+                x: c.bbox.x,
+                y: c.bbox.y,
+                w: c.bbox.w,
+                h: c.bbox.h,
+            })
+            .collect());
+    }
+    let Some(bbox) = &args.bbox else {
+        return Err(format!(
+            "Pos{} has no crop registry (older crop); pass the bbox CSV with --bbox",
+            pos
+        )
+        .into());
+    };
+    let path = bbox.replace("{pos}", &pos.to_string());
+    let um_per_px = PixelCalibration::from_store(store).um_per_px;
+    crop::clamp_bboxes(
+        crop::parse_bbox_csv(Path::new(&path), um_per_px)?,
+        width,
+        height,
+    )
+}
+
+/// Whether channel `c` was stored with `crop --scale-u8 to-u16-range`.
+fn scaled_u8(bg: &zarr::StoreArray, c: u64) -> bool {
+    let scaling = bg
+        .attributes()
+        .get("channel_scaling")
+        .and_then(|s| s.as_array());
+    scaling.into_iter().flatten().any(|entry| {
+        entry["channel"].as_u64() == Some(c) && entry["scaling"].as_str() == Some("to-u16-range")
+    })
+}
+
+/// Sample `bg` of one position and recompute each sampled median from `source`.
+fn audit_position(
+    args: &AuditBackgroundArgs,
+    root: &Path,
+    store: &zarr::Store,
+    pos_id: &str,
+    defects: Option<&DefectMap>,
+) -> Result<(Vec<Sampled>, zarr::StoreArray), Box<dyn std::error::Error>> {
+    let pos: u32 = pos_id.parse()?;
+    let bg = zarr::open_array(store, &format!("/pos/{}/background", pos_id))?;
+    let shape = bg.shape().to_vec();
+    let (n_t, n_c, n_z) = (shape[0], shape[1], shape[2]);
+    let mut source = match (&args.nd2, &args.source) {
+        (Some(path), _) => {
+            let mut file = Nd2File::open(path)?;
+            let sizes = file.sizes()?;
+            let [width, height] = ["X", "Y"].map(|axis| *sizes.get(axis).unwrap_or(&1) as u32);
+            Source::Nd2 {
+                file,
+                pos: pos as usize,
+                size: (width, height),
+            }
+        }
+        (None, Some(root)) => {
+            let pos_dir = Path::new(root).join(format!("Pos{}", pos));
+            if !pos_dir.exists() {
+                return Err(format!("Position directory not found: {}", pos_dir.display()).into());
+            }
+            Source::Tiffs(crop::discover_tiffs(
+                &pos_dir,
+                pos,
+                args.pattern.as_deref(),
+            )?)
+        }
+        (None, None) => return Err("audit-background needs --source or --nd2".into()),
+    };
+
+    // `crop --missing skip` dropped whole timepoints; the rest were renumbered.
+    let missing = bg.attributes().get(crop::MISSING_FRAMES_ATTR).cloned();
+    let skipped: HashSet<u64> = missing
+        .as_ref()
+        .and_then(|m| m.get("skipped_t"))
+        .and_then(|s| s.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|t| t.as_u64())
+        .collect();
+    let source_times: Vec<u64> = (0..)
+        .filter(|t| !skipped.contains(t))
+        .take(n_t as usize)
+        .collect();
+    let filled = crop::missing_frames(&bg);
+
+    let mut mask: Option<Vec<bool>> = None;
+    let mut sampled = Vec::new();
+    for t in sample_times(n_t, args.samples) {
+        for c in 0..n_c {
+            for z in 0..n_z {
+                cancel::check()?;
+                if filled.contains(&(t, c, z)) {
+                    continue;
+                }
+                let t_src = source_times[t as usize];
+                let (mut frame, width, height) = match &mut source {
+                    Source::Nd2 { file, pos, size } => {
+                        let data =
+                            file.read_frame_2d(*pos, t_src as usize, c as usize, z as usize)?;
+                        (FrameData::U16(data), size.0, size.1)
+                    }
+                    Source::Tiffs(index) => {
+                        let Some(path) = index.get(&(c as u32, t_src as u32, z as u32)) else {
+                            continue;
+                        };
+                        crop::read_tiff_frame(path)?
+                    }
+                };
+                if let (FrameData::U8(v), true) = (&frame, scaled_u8(&bg, c)) {
+                    frame = FrameData::U16(v.iter().map(|&p| p as u16 * 257).collect());
+                }
+                if let Some(map) = defects {
+                    map.repair_frame(c as u32, &mut frame, width, height);
+                }
+                let frame: Vec<u16> = match frame {
+                    FrameData::U16(v) => v,
+                    FrameData::U8(v) => v.into_iter().map(u16::from).collect(),
+                };
+                if mask.is_none() {
+                    let boxes = position_boxes(args, root, store, pos_id, pos, (width, height))?;
+                    mask = Some(crop::box_mask(&boxes, width, height));
+                }
+                let mask = mask.as_deref().unwrap_or_default();
+                if mask.len() != frame.len() {
+                    return Err(format!("Pos{}: frame sizes differ across the source", pos).into());
+                }
+                sampled.push(Sampled {
+                    tcz: [t, c, z],
+                    stored: zarr::read_frame_u16(&bg, &[t, c, z])?[0],
+                    recomputed: crop::median_outside_mask_u16(&frame, width, height, mask),
+                });
+            }
+        }
+    }
+    Ok((sampled, bg))
+}
+
+/// The axis order (other than the current one) under which every sampled median is found
+/// in `bg`, if any.
+fn matching_order(
+    bg: &zarr::StoreArray,
+    sampled: &[Sampled],
+    tolerance: u16,
+) -> Result<Option<&'static str>, Box<dyn std::error::Error>> {
+    let shape = bg.shape();
+    let shape = [shape[0], shape[1], shape[2]];
+    for (name, order) in &ORDERS[1..] {
+        let mut all = true;
+        for s in sampled {
+            let at = written_at(*order, s.tcz, shape);
+            if differs(zarr::read_frame_u16(bg, &at)?[0], s.recomputed, tolerance) {
+                all = false;
+                break;
+            }
+        }
+        if all {
+            return Ok(Some(name));
+        }
+    }
+    Ok(None)
+}
+
+pub fn run(
+    args: AuditBackgroundArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = Path::new(&args.input);
+    if !root.join("pos").exists() {
+        return Err(format!("{} has no pos group; not a crops.zarr?", root.display()).into());
+    }
+    if args.samples == 0 {
+        return Err("--samples must be at least 1".into());
+    }
+    let pos_ids = match args.pos {
+        Some(pos) => vec![format!("{:03}", pos)],
+        None => background_positions(root)?,
+    };
+    if pos_ids.is_empty() {
+        return Err(format!(
+            "No background arrays in {} (crop without --background)",
+            root.display()
+        )
+        .into());
+    }
+    let defects = args
+        .fix_defects
+        .as_deref()
+        .map(DefectMap::load)
+        .transpose()?;
+
+    let store = zarr::open_store(root)?;
+    let mut failed = 0usize;
+    let mut checked = 0usize;
+    for (i, pos_id) in pos_ids.iter().enumerate() {
+        progress(ProgressEvent::new(
+            "audit",
+            i as u64,
+            pos_ids.len() as u64,
+            &format!("Auditing {}/{}: Pos{}", i + 1, pos_ids.len(), pos_id),
+        ));
+        let (sampled, bg) = audit_position(&args, root, &store, pos_id, defects.as_ref())?;
+        checked += sampled.len();
+        let wrong: Vec<&Sampled> = sampled
+            .iter()
+            .filter(|s| differs(s.stored, s.recomputed, args.tolerance))
+            .collect();
+        if wrong.is_empty() {
+            continue;
+        }
+        failed += 1;
+        for s in &wrong {
+            let [t, c, z] = s.tcz;
+            console::info(&format!(
+                "audit-background: Pos{} t={} c={} z={}: stored {}, recomputed {}",
+                pos_id, t, c, z, s.stored, s.recomputed
+            ));
+        }
+        let hint = match matching_order(&bg, &sampled, args.tolerance)? {
+            Some(order) => format!(
+                "; the stored values follow a ({}) frame order from an older crop",
+                order
+            ),
+            None => String::new(),
+        };
+        console::warn(&format!(
+            "Pos{}: {} of {} sampled background value(s) differ{}; re-run crop --background",
+            pos_id,
+            wrong.len(),
+            sampled.len(),
+            hint
+        ));
+    }
+
+    if failed > 0 {
+        return Err(format!(
+            "{} of {} position(s) have inconsistent background values",
+            failed,
+            pos_ids.len()
+        )
+        .into());
+    }
+    progress(ProgressEvent::done(&format!(
+        "Background consistent: {} frame(s) in {} position(s)",
+        checked,
+        pos_ids.len()
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axis_orders_and_samples() {
+        let shape = [4, 3, 2];
+        // The current convention writes every frame in place.
+        for tcz in [[0, 0, 0], [3, 2, 1], [1, 2, 0]] {
+            assert_eq!(written_at(ORDERS[0].1, tcz, shape), tcz);
+        }
+        // (c, t, z): frame t=1, c=2, z=0 has flat index (2 * 4 + 1) * 2 = 18 → t=3, c=0.
+        assert_eq!(written_at([1, 0, 2], [1, 2, 0], shape), [3, 0, 0]);
+        // Every order is a permutation of the frames.
+        for (_, order) in ORDERS {
+            let mut seen = HashSet::new();
+            for t in 0..4 {
+                for c in 0..3 {
+                    for z in 0..2 {
+                        assert!(seen.insert(written_at(order, [t, c, z], shape)));
+                    }
+                }
+            }
+        }
+
+        assert_eq!(sample_times(100, 5), [0, 24, 49, 74, 99]);
+        assert_eq!(sample_times(3, 5), [0, 1, 2]);
+        assert_eq!(sample_times(10, 1), [0]);
+        assert_eq!(sample_times(2, 3), [0, 1]);
+        assert!(sample_times(0, 5).is_empty());
+    }
+}
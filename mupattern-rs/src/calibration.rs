@@ -0,0 +1,73 @@
+//! Pixel calibration (µm/px, frame interval) shared across the pipeline.
+//!
+//! `convert` writes it as `calibration.json` next to the Pos* folders; `crop` copies it
+//! (or values from flags) into the crops.zarr root attrs under "calibration", where
+//! `spot`, `tissue` and `movie` pick it up.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::zarr;
+
+pub const SIDECAR_NAME: &str = "calibration.json";
+const ATTRS_KEY: &str = "calibration";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PixelCalibration {
+    /// Pixel size in µm
+    pub um_per_px: Option<f64>,
+    /// Acquisition frame interval in seconds
+    pub frame_interval_s: Option<f64>,
+}
+
+impl PixelCalibration {
+    pub fn is_empty(&self) -> bool {
+        self.um_per_px.is_none() && self.frame_interval_s.is_none()
+    }
+
+    /// Values from `other` take precedence where set.
+    pub fn merged_with(self, other: PixelCalibration) -> PixelCalibration {
+        PixelCalibration {
+            um_per_px: other.um_per_px.or(self.um_per_px),
+            frame_interval_s: other.frame_interval_s.or(self.frame_interval_s),
+        }
+    }
+
+    pub fn length_um(&self, px: f64) -> Option<f64> {
+        self.um_per_px.map(|s| px * s)
+    }
+
+    pub fn area_um2(&self, px: u64) -> Option<f64> {
+        self.um_per_px.map(|s| px as f64 * s * s)
+    }
+
+    /// Read `calibration.json` from a convert output directory, if present.
+    pub fn read_sidecar(dir: &Path) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let path = dir.join(SIDECAR_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)?;
+        Ok(Some(serde_json::from_str(&text)?))
+    }
+
+    pub fn write_sidecar(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(dir.join(SIDECAR_NAME), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Read from the store root attrs; uncalibrated if missing.
+    pub fn from_store(store: &zarr::Store) -> Self {
+        zarr::read_root_attrs(store)
+            .get(ATTRS_KEY)
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write_to_store(&self, store: &zarr::Store) -> Result<(), Box<dyn std::error::Error>> {
+        let mut attrs = serde_json::Map::new();
+        attrs.insert(ATTRS_KEY.to_string(), serde_json::to_value(self)?);
+        zarr::update_root_attrs(store, attrs)
+    }
+}
@@ -0,0 +1,44 @@
+//! Cooperative cancellation over stdin (`--control-stdin`). The parent process writes
+//! one JSON object per line; `{"cmd":"cancel"}` makes the next `check()` fail so the
+//! command unwinds through its normal error path (summary, notify, exit code 1).
+//! Used by GUIs on Windows, where signalling a child process is unreliable.
+
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::console;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Start a background thread reading control messages from stdin.
+/// Unknown commands and malformed lines are reported and ignored; EOF ends the listener.
+pub fn listen_stdin() {
+    std::thread::spawn(|| {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let Ok(line) = line else { break };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(msg) if msg["cmd"] == "cancel" => {
+                    CANCELLED.store(true, Ordering::SeqCst);
+                    console::info("control: cancel requested");
+                }
+                Ok(msg) => {
+                    console::warn(&format!("control: ignoring unknown command {}", msg["cmd"]))
+                }
+                Err(e) => console::warn(&format!("control: ignoring malformed line ({})", e)),
+            }
+        }
+    });
+}
+
+/// Fail with "Cancelled" once a cancel was requested. Call between units of work.
+pub fn check() -> Result<(), Box<dyn std::error::Error>> {
+    if CANCELLED.load(Ordering::SeqCst) {
+        return Err("Cancelled".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! Channel names shared across the pipeline.
+//!
+//! `convert` writes the ND2 channel names as `channels.json` next to the Pos* folders;
+//! `crop` copies them (or `--channel-names`) into the crops.zarr root attrs under
+//! "channel_names". Every channel argument takes an index or a name ("GFP", any case)
+//! resolved against them, so a run does not silently pick the wrong channel when an
+//! experiment was acquired in a different channel order.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::zarr;
+
+pub const SIDECAR_NAME: &str = "channels.json";
+const ATTRS_KEY: &str = "channel_names";
+
+/// Channel names in channel order; empty when none were recorded.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelNames(pub Vec<String>);
+
+impl ChannelNames {
+    /// Names from a comma-separated list, e.g. "Phase,GFP".
+    pub fn parse(s: &str) -> Self {
+        Self(s.split(',').map(|n| n.trim().to_string()).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|n| n.is_empty())
+    }
+
+    /// Read `channels.json` from a convert output directory, if present.
+    pub fn read_sidecar(dir: &Path) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let path = dir.join(SIDECAR_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)?;
+        Ok(Some(serde_json::from_str(&text)?))
+    }
+
+    pub fn write_sidecar(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(dir.join(SIDECAR_NAME), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Read from the store root attrs; no names if missing.
+    pub fn from_store(store: &zarr::Store) -> Self {
+        zarr::read_root_attrs(store)
+            .get(ATTRS_KEY)
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write_to_store(&self, store: &zarr::Store) -> Result<(), Box<dyn std::error::Error>> {
+        let mut attrs = serde_json::Map::new();
+        attrs.insert(ATTRS_KEY.to_string(), serde_json::to_value(self)?);
+        zarr::update_root_attrs(store, attrs)
+    }
+
+    /// Names for the data at `input`: the root attrs of a zarr store, else the sidecar of
+    /// a convert output folder.
+    pub fn load(input: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if input.join("zarr.json").exists() {
+            return Ok(Self::from_store(&zarr::open_store(input)?));
+        }
+        Ok(Self::read_sidecar(input)?.unwrap_or_default())
+    }
+
+    /// Channel index for `spec`: an index as-is, or a recorded name (case-insensitive).
+    pub fn resolve(&self, spec: &str) -> Result<u32, Box<dyn std::error::Error>> {
+        let spec = spec.trim();
+        if let Ok(index) = spec.parse::<u32>() {
+            return Ok(index);
+        }
+        if let Some(index) = self.0.iter().position(|n| n.eq_ignore_ascii_case(spec)) {
+            return Ok(index as u32);
+        }
+        if self.is_empty() {
+            return Err(format!(
+                "Unknown channel {:?}: no channel names recorded (use an index, or crop --channel-names)",
+                spec
+            )
+            .into());
+        }
+        Err(format!(
+            "Unknown channel {:?} (channels: {})",
+            spec,
+            self.0.join(", ")
+        )
+        .into())
+    }
+
+    /// Channel indices for a comma-separated list of indices and names, e.g. "0,GFP".
+    pub fn resolve_list(&self, spec: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        spec.split(',').map(|s| self.resolve(s)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_indices_resolve() {
+        let names = ChannelNames::parse("Phase, GFP,mCherry");
+        assert_eq!(names.resolve("gfp").unwrap(), 1);
+        assert_eq!(names.resolve(" 2 ").unwrap(), 2);
+        assert_eq!(names.resolve_list("Phase,2").unwrap(), [0, 2]);
+        let err = names.resolve("DAPI").unwrap_err().to_string();
+        assert!(err.contains("Phase, GFP, mCherry"), "{}", err);
+        assert!(ChannelNames::default().resolve("GFP").is_err());
+        assert_eq!(ChannelNames::default().resolve("3").unwrap(), 3);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            ChannelNames::load(dir.path()).unwrap(),
+            ChannelNames::default()
+        );
+        names.write_sidecar(dir.path()).unwrap();
+        assert_eq!(ChannelNames::load(dir.path()).unwrap(), names);
+    }
+}
@@ -0,0 +1,138 @@
+//! Per-array content checksums for crops.zarr (`crop --checksums`, `validate --checksums`).
+//!
+//! The digest is XXH3-64 over the XXH3-64 hashes of every frame, taken in C order over the
+//! leading (frame) axes, so it does not depend on chunking or on the order frames were
+//! written. Stored in the array attributes as
+//! `"checksum": {"algorithm": "xxh3-64-frames", "frame_axes": 3, "value": "<hex>"}`.
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::zarr;
+
+pub const ALGORITHM: &str = "xxh3-64-frames";
+pub const ATTR_KEY: &str = "checksum";
+
+fn frame_hash(data: &[u16]) -> u64 {
+    let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+    xxh3_64(&bytes)
+}
+
+/// Frame hashes for one array, filled in any order and combined with `finish`.
+pub struct FrameDigest {
+    hashes: Vec<u64>,
+}
+
+impl FrameDigest {
+    pub fn new(n_frames: usize) -> Self {
+        Self {
+            hashes: vec![0; n_frames],
+        }
+    }
+
+    /// Record frame `index` (flattened C-order index over the frame axes).
+    pub fn record(&mut self, index: usize, data: &[u16]) {
+        self.hashes[index] = frame_hash(data);
+    }
+
+    pub fn finish(&self) -> String {
+        let bytes: Vec<u8> = self.hashes.iter().flat_map(|h| h.to_le_bytes()).collect();
+        format!("{:016x}", xxh3_64(&bytes))
+    }
+}
+
+/// Store a digest in the attributes of the array at `path`.
+pub fn write(
+    store: &zarr::Store,
+    path: &str,
+    digest: &FrameDigest,
+    frame_axes: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut attrs = serde_json::Map::new();
+    attrs.insert(
+        ATTR_KEY.to_string(),
+        serde_json::json!({
+            "algorithm": ALGORITHM,
+            "frame_axes": frame_axes,
+            "value": digest.finish(),
+        }),
+    );
+    zarr::update_array_attrs(store, path, attrs)
+}
+
+/// Outcome of verifying one array.
+pub enum Verification {
+    Match,
+    Mismatch {
+        expected: String,
+        actual: String,
+    },
+    /// The array has no stored checksum.
+    Missing,
+}
+
+/// Recompute the digest of the array at `path` and compare it with the stored one.
+pub fn verify(store: &zarr::Store, path: &str) -> Result<Verification, Box<dyn std::error::Error>> {
+    let array = zarr::open_array(store, path)?;
+    let Some(entry) = array.attributes().get(ATTR_KEY) else {
+        return Ok(Verification::Missing);
+    };
+    if entry["algorithm"] != ALGORITHM {
+        return Err(format!(
+            "{path}: unsupported checksum algorithm {}",
+            entry["algorithm"]
+        )
+        .into());
+    }
+    let expected = entry["value"]
+        .as_str()
+        .ok_or("checksum value missing")?
+        .to_string();
+    let frame_axes = entry["frame_axes"]
+        .as_u64()
+        .ok_or("checksum frame_axes missing")? as usize;
+
+    let shape = array.shape().to_vec();
+    if frame_axes > shape.len() {
+        return Err(format!("{path}: frame_axes {frame_axes} exceeds array rank").into());
+    }
+    let lead = &shape[..frame_axes];
+    let n_frames = lead.iter().product::<u64>() as usize;
+    let mut digest = FrameDigest::new(n_frames);
+    let mut idx = vec![0u64; frame_axes];
+    for i in 0..n_frames {
+        let mut rem = i as u64;
+        for axis in (0..frame_axes).rev() {
+            idx[axis] = rem % lead[axis];
+            rem /= lead[axis];
+        }
+        digest.record(i, &zarr::read_frame_u16(&array, &idx)?);
+    }
+    let actual = digest.finish();
+    Ok(if actual == expected {
+        Verification::Match
+    } else {
+        Verification::Mismatch { expected, actual }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_ignores_write_order_but_not_content() {
+        let frames = [vec![1u16, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+        let mut forward = FrameDigest::new(3);
+        for (i, f) in frames.iter().enumerate() {
+            forward.record(i, f);
+        }
+        let mut reverse = FrameDigest::new(3);
+        for (i, f) in frames.iter().enumerate().rev() {
+            reverse.record(i, f);
+        }
+        assert_eq!(forward.finish(), reverse.finish());
+
+        reverse.record(1, &[4, 5, 7]);
+        assert_ne!(forward.finish(), reverse.finish());
+    }
+}
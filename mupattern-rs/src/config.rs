@@ -0,0 +1,273 @@
+//! Per-machine settings from `mupattern.toml`, looked up in the current directory and then
+//! in the user config directory (`$XDG_CONFIG_HOME/mupattern/`, `~/.config/mupattern/`, or
+//! `%APPDATA%\mupattern\` on Windows). The first file found wins.
+//!
+//! Keys are flag names (`batch-size` or `batch_size`) and become flag defaults: top-level
+//! keys apply to the global flags and to every command with that flag, a `[command]` table
+//! (e.g. `[kill] model = "..."`) to that command only. Precedence is command line, then the
+//! flag's `MUPATTERN_*` environment variable, then `[command]`, then top level.
+//!
+//! Every flag can also be set through `MUPATTERN_<FLAG>` (`--batch-size` ->
+//! `MUPATTERN_BATCH_SIZE`, shared by all commands with that flag), for containers and
+//! workflow engines that configure through the environment.
+
+use clap::Command;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::console;
+
+pub const FILE_NAME: &str = "mupattern.toml";
+
+static CONFIG: OnceLock<toml::Table> = OnceLock::new();
+
+fn user_config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("mupattern"));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = std::env::var_os("APPDATA") {
+            return Some(PathBuf::from(dir).join("mupattern"));
+        }
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".config").join("mupattern"))
+}
+
+/// The config file in use, if any.
+pub fn path() -> Option<PathBuf> {
+    let local = PathBuf::from(FILE_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+    let user = user_config_dir()?.join(FILE_NAME);
+    user.is_file().then_some(user)
+}
+
+fn table() -> &'static toml::Table {
+    CONFIG.get_or_init(|| {
+        let Some(path) = path() else {
+            return toml::Table::new();
+        };
+        match std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| text.parse::<toml::Table>().map_err(|e| e.to_string()))
+        {
+            Ok(table) => table,
+            Err(e) => {
+                console::warn(&format!("config: ignoring {} ({})", path.display(), e));
+                toml::Table::new()
+            }
+        }
+    })
+}
+
+/// String value of a top-level key.
+pub fn get_str(key: &str) -> Option<&'static str> {
+    table().get(key)?.as_str()
+}
+
+/// Environment variable for the flag `--{long}`.
+fn env_var(long: &str) -> String {
+    format!("MUPATTERN_{}", long.to_uppercase().replace('-', "_"))
+}
+
+/// Let every long flag of `cmd` and its subcommands be read from `MUPATTERN_<FLAG>`.
+pub fn apply_env(mut cmd: Command) -> Command {
+    let flags: Vec<(clap::Id, String)> = cmd
+        .get_arguments()
+        .filter_map(|a| Some((a.get_id().clone(), a.get_long()?.to_string())))
+        .filter(|(_, long)| long != "help" && long != "version")
+        .collect();
+    for (id, long) in flags {
+        cmd = cmd.mut_arg(id, |a| a.env(env_var(&long)));
+    }
+    let names: Vec<String> = cmd
+        .get_subcommands()
+        .map(|s| s.get_name().to_string())
+        .collect();
+    for name in names {
+        cmd = cmd.mut_subcommand(name, apply_env);
+    }
+    cmd
+}
+
+/// Default values for one flag, or None for values a flag cannot take (tables, dates).
+fn default_values(value: &toml::Value) -> Option<Vec<String>> {
+    match value {
+        toml::Value::String(s) => Some(vec![s.clone()]),
+        toml::Value::Integer(i) => Some(vec![i.to_string()]),
+        toml::Value::Float(f) => Some(vec![f.to_string()]),
+        toml::Value::Boolean(b) => Some(vec![b.to_string()]),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(|v| {
+                default_values(v)
+                    .filter(|v| v.len() == 1)
+                    .map(|mut v| v.remove(0))
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Set defaults on `cmd`'s own flags from `entries`; returns the keys that matched.
+fn set_defaults<'a>(
+    mut cmd: Command,
+    entries: &[(&'a str, &toml::Value)],
+    scope: &str,
+) -> (Command, Vec<&'a str>) {
+    let mut matched = Vec::new();
+    for &(key, value) in entries {
+        let long = key.replace('_', "-");
+        let Some(id) = cmd
+            .get_arguments()
+            .find(|a| a.get_long() == Some(long.as_str()))
+            .map(|a| a.get_id().clone())
+        else {
+            continue;
+        };
+        matched.push(key);
+        if std::env::var_os(env_var(&long)).is_some() {
+            continue;
+        }
+        match default_values(value) {
+            Some(values) => cmd = cmd.mut_arg(id, |a| a.default_values(values).required(false)),
+            None => console::warn(&format!(
+                "config: ignoring {}{}: unsupported value {}",
+                scope, key, value
+            )),
+        }
+    }
+    (cmd, matched)
+}
+
+fn apply_table(cmd: Command, table: &toml::Table) -> Command {
+    let shared: Vec<(&str, &toml::Value)> = table
+        .iter()
+        .filter(|(_, v)| !v.is_table())
+        .map(|(k, v)| (k.as_str(), v))
+        .collect();
+    let (mut cmd, mut used) = set_defaults(cmd, &shared, "");
+
+    let names: Vec<String> = cmd
+        .get_subcommands()
+        .map(|s| s.get_name().to_string())
+        .collect();
+    for name in names {
+        let own: Vec<(&str, &toml::Value)> = table
+            .get(&name)
+            .and_then(|v| v.as_table())
+            .map(|t| t.iter().map(|(k, v)| (k.as_str(), v)).collect())
+            .unwrap_or_default();
+        cmd = cmd.mut_subcommand(&name, |sub| {
+            let (sub, shared_used) = set_defaults(sub, &shared, "");
+            used.extend(shared_used);
+            let scope = format!("[{}] ", name);
+            let (sub, own_used) = set_defaults(sub, &own, &scope);
+            for (key, _) in &own {
+                if !own_used.contains(key) {
+                    console::warn(&format!(
+                        "config: ignoring {}{}: {} has no --{}",
+                        scope,
+                        key,
+                        name,
+                        key.replace('_', "-")
+                    ));
+                }
+            }
+            sub
+        });
+    }
+
+    for (key, value) in table {
+        if value.is_table() {
+            if cmd.find_subcommand(key).is_none() {
+                console::warn(&format!("config: ignoring [{}]: no such command", key));
+            }
+        } else if !used.contains(&key.as_str()) {
+            console::warn(&format!(
+                "config: ignoring {}: no command has --{}",
+                key,
+                key.replace('_', "-")
+            ));
+        }
+    }
+    cmd
+}
+
+/// Apply the config file (see module docs) as flag defaults to the CLI definition.
+pub fn apply_defaults(cmd: Command) -> Command {
+    apply_table(cmd, table())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    #[test]
+    fn command_tables_override_top_level_and_flags_override_both() {
+        let cli = || {
+            Command::new("mupattern")
+                .arg(Arg::new("max_memory").long("max-memory").global(true))
+                .subcommand(
+                    Command::new("kill")
+                        .arg(Arg::new("model").long("model").required(true))
+                        .arg(Arg::new("batch_size").long("batch-size")),
+                )
+                .subcommand(Command::new("movie").arg(Arg::new("batch_size").long("batch-size")))
+        };
+        let table: toml::Table =
+            "max-memory = \"8G\"\nbatch_size = 64\n[kill]\nmodel = \"models/m\"\nbatch-size = 16\n"
+                .parse()
+                .unwrap();
+        let get = |args: &[&str], sub: &str, id: &str| {
+            let m = apply_table(cli(), &table).get_matches_from(args);
+            let m = m.subcommand_matches(sub).unwrap();
+            m.get_one::<String>(id).cloned()
+        };
+        assert_eq!(
+            get(&["mupattern", "kill"], "kill", "model").as_deref(),
+            Some("models/m")
+        );
+        assert_eq!(
+            get(&["mupattern", "kill"], "kill", "batch_size").as_deref(),
+            Some("16")
+        );
+        assert_eq!(
+            get(&["mupattern", "movie"], "movie", "batch_size").as_deref(),
+            Some("64")
+        );
+        assert_eq!(
+            get(&["mupattern", "kill"], "kill", "max_memory").as_deref(),
+            Some("8G")
+        );
+        assert_eq!(
+            get(&["mupattern", "kill", "--model", "other"], "kill", "model").as_deref(),
+            Some("other")
+        );
+    }
+
+    #[test]
+    fn environment_sets_flags_below_the_command_line() {
+        let cli = || {
+            apply_env(Command::new("mupattern").subcommand(
+                Command::new("kill").arg(Arg::new("test_env_model").long("test-env-model")),
+            ))
+        };
+        std::env::set_var("MUPATTERN_TEST_ENV_MODEL", "from-env");
+        let value = |args: &[&str]| {
+            cli()
+                .get_matches_from(args)
+                .subcommand_matches("kill")
+                .and_then(|m| m.get_one::<String>("test_env_model").cloned())
+        };
+        assert_eq!(value(&["mupattern", "kill"]).as_deref(), Some("from-env"));
+        assert_eq!(
+            value(&["mupattern", "kill", "--test-env-model", "flag"]).as_deref(),
+            Some("flag")
+        );
+        std::env::remove_var("MUPATTERN_TEST_ENV_MODEL");
+    }
+}
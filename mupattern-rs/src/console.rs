@@ -0,0 +1,41 @@
+//! Human-readable log lines on stderr. With `--quiet`, progress and `info` lines are
+//! dropped; warnings and errors are always printed. Stdout carries only the result manifest.
+//!
+//! Warnings are recoverable data problems (a clamped bbox, an empty crop, a skipped file):
+//! the command keeps going, each one is printed as a `{"stage": "warning", "message"}`
+//! JSON line next to the progress events, and all of them are repeated in the manifest.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Status line for humans; silenced by `--quiet`.
+pub fn info(msg: &str) {
+    if !quiet() {
+        eprintln!("{}", msg);
+    }
+}
+
+/// Record a recoverable problem and print it as a warning event, even with `--quiet`.
+pub fn warn(msg: &str) {
+    WARNINGS.lock().unwrap().push(msg.to_string());
+    let line = serde_json::json!({"stage": "warning", "message": msg});
+    let _ = writeln!(io::stderr(), "{}", line);
+    let _ = io::stderr().flush();
+}
+
+/// Warnings recorded so far, clearing the list.
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap())
+}
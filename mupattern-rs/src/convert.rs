@@ -1,15 +1,34 @@
 use clap::Args;
 use nd2_rs::Nd2File;
+use std::collections::HashMap;
 use std::fs;
 use std::io::BufWriter;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
-use crate::slices;
+use crate::calibration::PixelCalibration;
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::console;
+use crate::crop;
+use crate::czi::CziFile;
+use crate::defects::DefectMap;
+use crate::detector::DetectorRange;
+use crate::disk;
+use crate::manifest;
+use crate::ome;
+use crate::parallel;
+use crate::progress::ProgressEvent;
+use crate::slices::{self, Shard};
+use crate::stats;
 use tiff::encoder::{colortype::Gray16, TiffEncoder};
+use tiff::tags::Tag;
 
 #[derive(Args, Clone)]
 pub struct ConvertArgs {
-    /// Path to the .nd2 file to convert
+    /// Path to the .nd2 or .czi file to convert (format from the extension; CZI scenes
+    /// become positions)
     #[arg(long)]
     pub input: String,
 
@@ -21,6 +40,17 @@ pub struct ConvertArgs {
     #[arg(long)]
     pub time: String,
 
+    /// Channels to convert: "all" or comma-separated indices/slices/names from the file's
+    /// metadata, e.g. "0,GFP"; they are numbered from 0 in the output (channels.json keeps
+    /// their names)
+    #[arg(long)]
+    pub channel: String,
+
+    /// Z-slices to convert: "all" or comma-separated indices/slices, e.g. "3"; numbered
+    /// from 0 in the output
+    #[arg(long)]
+    pub z: String,
+
     /// Output directory (will contain Pos*/... TIFF folders)
     #[arg(long)]
     pub output: String,
@@ -28,16 +58,277 @@ pub struct ConvertArgs {
     /// Skip confirmation prompt
     #[arg(long)]
     pub yes: bool,
+
+    /// Never read stdin: fail instead of prompting when --yes is not given
+    #[arg(long)]
+    pub no_input: bool,
+
+    /// Write the conversion plan (counts, positions, timepoints, estimated size) as JSON to this path
+    #[arg(long)]
+    pub summary_json: Option<String>,
+
+    /// Write even when the estimated output does not fit in the free disk space
+    #[arg(long)]
+    pub force: bool,
+
+    /// Pixel size in µm, recorded in calibration.json for downstream commands (default: the
+    /// input file's calibration)
+    #[arg(long)]
+    pub pixel_size_um: Option<f64>,
+
+    /// Acquisition frame interval in seconds, recorded in calibration.json and, with
+    /// --format ome-tiff, as the OME TimeIncrement/DeltaT (not read from the input file)
+    #[arg(long)]
+    pub frame_interval_s: Option<f64>,
+
+    /// Camera bit depth, recorded in detector.json (default: from the file metadata)
+    #[arg(long)]
+    pub bit_depth: Option<u32>,
+
+    /// Camera dark offset in counts, recorded in detector.json
+    #[arg(long)]
+    pub detector_offset: Option<u16>,
+
+    /// Output layout: "flat" (Pos{N}/img_channel…tif) or "by-channel"
+    /// (Pos{N}/{channel_name}/img_time…tif, channel names from the ND2/CZI metadata)
+    #[arg(long, default_value = "flat")]
+    pub layout: String,
+
+    /// File format: "tiff" (bare 16-bit frames) or "ome-tiff" (each frame carries OME-XML
+    /// with its channel name, pixel size, timing from --frame-interval-s and the stage
+    /// position of CZI scenes, for Fiji/Bio-Formats); file names and layout are the same
+    /// either way
+    #[arg(long, default_value = "tiff")]
+    pub format: String,
+
+    /// Defect map from `defects` (e.g. made on an earlier conversion with the same camera):
+    /// listed hot/dead pixels are replaced by the median of their neighbours
+    #[arg(long)]
+    pub fix_defects: Option<String>,
+
+    /// Convert up to this many positions at once on separate threads, each position
+    /// reading through its own file handle (bounded by --max-memory)
+    #[arg(long, default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Convert only share i of n of the selected positions (every n-th, starting at i,
+    /// 0-based), e.g. "$SLURM_ARRAY_TASK_ID/8"; shards write disjoint Pos* folders
+    #[arg(long)]
+    pub shard: Option<String>,
+}
+
+/// Rough per-file cost of the TIFF header and IFD on top of the pixel data.
+const TIFF_OVERHEAD_BYTES: u64 = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    Flat,
+    ByChannel,
+}
+
+impl Layout {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "flat" => Ok(Self::Flat),
+            "by-channel" => Ok(Self::ByChannel),
+            _ => Err(format!("Unknown layout {s:?}. Use flat or by-channel.").into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Tiff,
+    OmeTiff,
+}
+
+impl Format {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "tiff" => Ok(Self::Tiff),
+            "ome-tiff" => Ok(Self::OmeTiff),
+            _ => Err(format!("Unknown format {s:?}. Use tiff or ome-tiff.").into()),
+        }
+    }
+}
+
+/// Rough size of the OME-XML description written into each OME-TIFF frame.
+const OME_XML_BYTES: u64 = 1024;
+
+/// Channel names from the ND2 metadata, empty where missing.
+pub(crate) fn metadata_channel_names(nd2: &mut Nd2File, n_chan: usize) -> ChannelNames {
+    let meta_names: Vec<String> = nd2
+        .metadata()
+        .ok()
+        .and_then(|m| m.channels)
+        .map(|channels| {
+            channels
+                .iter()
+                .map(|ch| ch.channel.name.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    ChannelNames(
+        (0..n_chan)
+            .map(|c| meta_names.get(c).cloned().unwrap_or_default())
+            .collect(),
+    )
+}
+
+/// Significant bits per pixel from the ND2 metadata (first channel), if recorded.
+pub(crate) fn metadata_bit_depth(nd2: &mut Nd2File) -> Option<u32> {
+    let channels = nd2.metadata().ok()?.channels?;
+    let bits = channels.first()?.volume.bits_per_component_significant as u32;
+    (bits > 0).then_some(bits)
+}
+
+/// Pixel size in µm from the ND2 metadata (first channel's X calibration), if recorded.
+pub(crate) fn metadata_pixel_size(nd2: &mut Nd2File) -> Option<f64> {
+    let channels = nd2.metadata().ok()?.channels?;
+    let um = channels.first()?.volume.axes_calibration[0];
+    (um > 0.0).then_some(um)
+}
+
+/// The input file: ND2, or CZI when the path ends in .czi.
+enum Source {
+    Nd2(Nd2File),
+    Czi(CziFile),
+}
+
+impl Source {
+    fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let is_czi = Path::new(path)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("czi"));
+        Ok(if is_czi {
+            Self::Czi(CziFile::open(path)?)
+        } else {
+            Self::Nd2(Nd2File::open(path)?)
+        })
+    }
+
+    /// Axis sizes keyed P, T, C, Z, Y, X.
+    fn sizes(&mut self) -> Result<HashMap<String, usize>, Box<dyn std::error::Error>> {
+        match self {
+            Self::Nd2(nd2) => Ok(nd2
+                .sizes()?
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect()),
+            Self::Czi(czi) => Ok(czi.sizes().clone()),
+        }
+    }
+
+    fn read_frame_2d(
+        &mut self,
+        p: usize,
+        t: usize,
+        c: usize,
+        z: usize,
+    ) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
+        match self {
+            Self::Nd2(nd2) => Ok(nd2.read_frame_2d(p, t, c, z)?),
+            Self::Czi(czi) => czi.read_frame_2d(p, t, c, z),
+        }
+    }
+
+    fn channel_names(&mut self, n_chan: usize) -> ChannelNames {
+        match self {
+            Self::Nd2(nd2) => metadata_channel_names(nd2, n_chan),
+            Self::Czi(czi) => {
+                let names = czi.channel_names();
+                let name = |c: usize| names.get(c).cloned().unwrap_or_default();
+                ChannelNames((0..n_chan).map(name).collect())
+            }
+        }
+    }
+
+    fn bit_depth(&mut self) -> Option<u32> {
+        match self {
+            Self::Nd2(nd2) => metadata_bit_depth(nd2),
+            Self::Czi(czi) => czi.bit_depth(),
+        }
+    }
+
+    fn pixel_size_um(&mut self) -> Option<f64> {
+        match self {
+            Self::Nd2(nd2) => metadata_pixel_size(nd2),
+            Self::Czi(czi) => czi.pixel_size_um(),
+        }
+    }
+
+    /// Stage position (x, y) in µm of position `p`. nd2-rs does not expose the ND2
+    /// experiment loops, so ND2 input has none (`run` warns about it).
+    fn stage_position_um(&self, p: usize) -> Option<(f64, f64)> {
+        match self {
+            Self::Nd2(_) => None,
+            Self::Czi(czi) => czi.scene_position_um(p),
+        }
+    }
+}
+
+/// Channel indices for `spec`: slice segments as for the other axes, or channel names
+/// from the file's metadata (case-insensitive), e.g. "0,GFP".
+fn channel_indices(
+    spec: &str,
+    names: &ChannelNames,
+    n_chan: usize,
+) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+    let mut indices = Vec::new();
+    for segment in spec.split(',') {
+        match slices::parse_slice_string(segment, n_chan) {
+            Ok(found) => indices.extend(found),
+            Err(e) => {
+                let name = segment.trim();
+                match names.0.iter().position(|n| n.eq_ignore_ascii_case(name)) {
+                    Some(c) => indices.push(c),
+                    None if names.0.iter().all(|n| n.is_empty()) => return Err(e.into()),
+                    None => return Err(format!("{} (channels: {})", e, names.0.join(", ")).into()),
+                }
+            }
+        }
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    Ok(indices)
+}
+
+/// Folder-safe versions of `meta_names`, `channel{c:03}` where missing. Duplicates get
+/// the channel index appended so every channel has its own folder.
+fn channel_folder_names(meta_names: &ChannelNames) -> Vec<String> {
+    let mut names: Vec<String> = Vec::with_capacity(meta_names.0.len());
+    for (c, raw) in meta_names.0.iter().enumerate() {
+        let mut name: String = raw
+            .chars()
+            .map(|ch| {
+                if ch.is_ascii_alphanumeric() || "-_.".contains(ch) {
+                    ch
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if name.trim_matches(['_', '.']).is_empty() {
+            name = format!("channel{:03}", c);
+        }
+        if names.contains(&name) {
+            name = format!("{}_{:03}", name, c);
+        }
+        names.push(name);
+    }
+    names
 }
 
 pub fn run(
     args: ConvertArgs,
-    progress: impl Fn(f64, &str),
+    progress: impl Fn(ProgressEvent) + Sync,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let output_path = Path::new(&args.output);
+    let layout = Layout::parse(&args.layout)?;
+    let format = Format::parse(&args.format)?;
 
-    let mut nd2 = Nd2File::open(&args.input)?;
-    let sizes = nd2.sizes()?;
+    let mut source = Source::open(&args.input)?;
+    let sizes = source.sizes()?;
 
     let n_pos = *sizes.get("P").unwrap_or(&1);
     let n_time = *sizes.get("T").unwrap_or(&1);
@@ -46,35 +337,145 @@ pub fn run(
     let height = *sizes.get("Y").unwrap_or(&1);
     let width = *sizes.get("X").unwrap_or(&1);
 
-    let pos_indices = slices::parse_slice_string(&args.pos, n_pos)?;
+    let mut pos_indices = slices::parse_slice_string(&args.pos, n_pos)?;
     let time_indices = slices::parse_slice_string(&args.time, n_time)?;
+    // Names of all channels in the file; --channel may select by them.
+    let all_names = source.channel_names(n_chan);
+    let chan_indices = channel_indices(&args.channel, &all_names, n_chan)?;
+    let z_indices = slices::parse_slice_string(&args.z, n_z)?;
+    let shard = args.shard.as_deref().map(Shard::parse).transpose()?;
+    if let Some(shard) = shard {
+        let selected = pos_indices.len();
+        pos_indices = shard.select(&pos_indices);
+        console::info(&format!(
+            "Shard {}/{}: {} of {} selected positions",
+            shard.index,
+            shard.count,
+            pos_indices.len(),
+            selected
+        ));
+    }
 
-    let total = pos_indices.len() * time_indices.len() * n_chan * n_z;
+    let total = pos_indices.len() * time_indices.len() * chan_indices.len() * z_indices.len();
+    // Names of the converted channels, in output order.
+    let meta_names = ChannelNames(
+        chan_indices
+            .iter()
+            .map(|&c| all_names.0[c].clone())
+            .collect(),
+    );
+    let detector = DetectorRange {
+        bit_depth: source.bit_depth(),
+        offset: None,
+    }
+    .merged_with(DetectorRange {
+        bit_depth: args.bit_depth,
+        offset: args.detector_offset,
+    });
+    detector.validate()?;
+    // Pixel size: the flag, else the input file's own calibration.
+    let um_per_px = args.pixel_size_um.or_else(|| source.pixel_size_um());
+    let ome_um_per_px = match format {
+        Format::OmeTiff => um_per_px,
+        Format::Tiff => None,
+    };
+    if format == Format::OmeTiff {
+        if args.frame_interval_s.is_none() {
+            console::warn(
+                "--format ome-tiff without --frame-interval-s: the OME-XML gets no \
+                 TimeIncrement or DeltaT (frame timing is not read from the input file)",
+            );
+        }
+        if matches!(source, Source::Nd2(_)) {
+            console::warn(
+                "--format ome-tiff: stage positions are not read from ND2 files; the \
+                 OME-XML planes get no PositionX/PositionY",
+            );
+        }
+    }
+    let channel_names = match layout {
+        Layout::Flat => Vec::new(),
+        Layout::ByChannel => channel_folder_names(&meta_names),
+    };
 
-    eprintln!("ND2: {} positions, T={}, C={}, Z={}", n_pos, n_time, n_chan, n_z);
-    eprintln!();
-    eprintln!(
-        "Selected {}/{} positions, {}/{} timepoints, {} channels, {} z-slices",
+    let mut plan = format!(
+        "Input: {} positions, T={}, C={}, Z={}\n\n\
+         Selected {}/{} positions, {}/{} timepoints, {}/{} channels, {}/{} z-slices\n\
+         Total frames to write: {}\n\n\
+         Positions:\n  {}\n\n\
+         Timepoints (original indices):\n  {:?}\n\n\
+         Channels and z-slices (original indices):\n  {:?}, {:?}\n",
+        n_pos,
+        n_time,
+        n_chan,
+        n_z,
         pos_indices.len(),
         n_pos,
         time_indices.len(),
         n_time,
+        chan_indices.len(),
         n_chan,
-        n_z
+        z_indices.len(),
+        n_z,
+        total,
+        pos_indices
+            .iter()
+            .map(|i| format!("Pos{}", i))
+            .collect::<Vec<_>>()
+            .join(", "),
+        time_indices,
+        chan_indices,
+        z_indices
     );
-    eprintln!("Total frames to write: {}", total);
-    eprintln!();
-    eprintln!("Positions:");
-    eprintln!("  {}", pos_indices.iter().map(|i| format!("Pos{}", i)).collect::<Vec<_>>().join(", "));
-    eprintln!();
-    eprintln!("Timepoints (original indices):");
-    eprintln!("  {:?}", time_indices);
-    eprintln!();
+    if layout == Layout::ByChannel {
+        plan.push_str(&format!(
+            "\nChannel folders:\n  {}\n",
+            channel_names.join(", ")
+        ));
+    }
+    console::info(&plan);
+
+    let overhead = match format {
+        Format::Tiff => TIFF_OVERHEAD_BYTES,
+        Format::OmeTiff => TIFF_OVERHEAD_BYTES + OME_XML_BYTES,
+    };
+    let estimated_bytes = total as u64 * (width as u64 * height as u64 * 2 + overhead);
+    if let Some(path) = &args.summary_json {
+        let summary = serde_json::json!({
+            "input": args.input,
+            "output": args.output,
+            "layout": args.layout,
+            "format": args.format,
+            "sizes": {"P": n_pos, "T": n_time, "C": n_chan, "Z": n_z, "Y": height, "X": width},
+            "positions": pos_indices,
+            "shard": args.shard,
+            "timepoints": time_indices,
+            "channels": if layout == Layout::ByChannel {
+                serde_json::json!(channel_names)
+            } else {
+                serde_json::json!(chan_indices.len())
+            },
+            "channel_indices": chan_indices,
+            "z_indices": z_indices,
+            "frames": total,
+            "estimated_bytes": estimated_bytes,
+        });
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&summary)?)?;
+    }
+
+    disk::ensure_free_space(output_path, estimated_bytes, args.force)?;
 
+    if !args.yes && args.no_input {
+        return Err("Confirmation required: pass --yes to convert without prompting".into());
+    }
     if !args.yes {
+        // The prompt goes to stderr like every other log line; stdout is kept for the manifest.
         eprint!("Proceed with conversion? [y/N]: ");
         use std::io::{self, Write};
-        io::stdout().flush()?;
+        io::stderr().flush()?;
         let mut line = String::new();
         io::stdin().read_line(&mut line)?;
         if !line.trim().eq_ignore_ascii_case("y") && !line.trim().eq_ignore_ascii_case("yes") {
@@ -84,8 +485,35 @@ pub fn run(
 
     fs::create_dir_all(output_path)?;
 
-    let mut done: usize = 0;
-    for &p_idx in &pos_indices {
+    let calibration = PixelCalibration {
+        um_per_px,
+        frame_interval_s: args.frame_interval_s,
+    };
+    if !calibration.is_empty() {
+        calibration.write_sidecar(output_path)?;
+    }
+    if !meta_names.is_empty() {
+        meta_names.write_sidecar(output_path)?;
+    }
+    if !detector.is_empty() {
+        detector.write_sidecar(output_path)?;
+    }
+
+    let defect_map = args
+        .fix_defects
+        .as_deref()
+        .map(DefectMap::load)
+        .transpose()?;
+
+    stats::stage("write");
+    let done = AtomicUsize::new(0);
+    let throughput = Mutex::new(stats::Throughput::new());
+    let per_pos = time_indices.len() * chan_indices.len() * z_indices.len();
+    let frame_bytes = width as u64 * height as u64 * 2;
+    // Each position opens its own file handle (the ND2/CZI header is parsed again per
+    // position, cheap next to the frames); positions are written independently.
+    parallel::for_each(&pos_indices, args.jobs, frame_bytes, |pi, &p_idx| {
+        let mut source = Source::open(&args.input)?;
         let pos_dir = output_path.join(format!("Pos{}", p_idx));
         fs::create_dir_all(&pos_dir)?;
 
@@ -98,30 +526,124 @@ pub fn run(
         }
         csv.flush()?;
 
+        if layout == Layout::ByChannel {
+            // Folder order is not recoverable from the names; crop reads it from here.
+            let mut channels_csv =
+                BufWriter::new(fs::File::create(pos_dir.join(crop::CHANNELS_CSV))?);
+            writeln!(channels_csv, "channel,name")?;
+            for (c, name) in channel_names.iter().enumerate() {
+                writeln!(channels_csv, "{},{}", c, name)?;
+                fs::create_dir_all(pos_dir.join(name))?;
+            }
+            channels_csv.flush()?;
+        }
+
+        let position_um = match format {
+            Format::OmeTiff => source.stage_position_um(p_idx),
+            Format::Tiff => None,
+        };
+        let mut pos_done = 0;
         for (t_new, &t_orig) in time_indices.iter().enumerate() {
-            for c in 0..n_chan {
-                for z in 0..n_z {
-                    let channel_data = nd2.read_frame_2d(p_idx, t_orig, c, z)?;
-
-                    let fname = format!(
-                        "img_channel{:03}_position{:03}_time{:09}_z{:03}.tif",
-                        c, p_idx, t_new, z
-                    );
-                    let tiff_path = pos_dir.join(&fname);
+            for (c, &c_orig) in chan_indices.iter().enumerate() {
+                for (z, &z_orig) in z_indices.iter().enumerate() {
+                    let mut channel_data = source.read_frame_2d(p_idx, t_orig, c_orig, z_orig)?;
+                    if let Some(map) = &defect_map {
+                        // Defect maps index the camera's channels, not the output's.
+                        map.repair(
+                            c_orig as u32,
+                            &mut channel_data,
+                            width as u32,
+                            height as u32,
+                        );
+                    }
+
+                    let tiff_path = match layout {
+                        Layout::Flat => pos_dir.join(format!(
+                            "img_channel{:03}_position{:03}_time{:09}_z{:03}.tif",
+                            c, p_idx, t_new, z
+                        )),
+                        Layout::ByChannel => pos_dir
+                            .join(&channel_names[c])
+                            .join(format!("img_time{:09}_z{:03}.tif", t_new, z)),
+                    };
                     let file = fs::File::create(&tiff_path)?;
                     let mut writer = BufWriter::new(file);
                     let mut encoder = TiffEncoder::new(&mut writer)?;
-                    encoder.write_image::<Gray16>(width as u32, height as u32, &channel_data)?;
+                    match format {
+                        Format::Tiff => encoder.write_image::<Gray16>(
+                            width as u32,
+                            height as u32,
+                            &channel_data,
+                        )?,
+                        Format::OmeTiff => {
+                            let name = format!("Pos{}", p_idx);
+                            let channel = match meta_names.0[c].as_str() {
+                                "" => format!("Channel {}", c),
+                                named => named.to_string(),
+                            };
+                            let xml = ome::plane_xml(&ome::Plane {
+                                name: &name,
+                                width,
+                                height,
+                                channel: &channel,
+                                um_per_px: ome_um_per_px,
+                                frame_interval_s: args.frame_interval_s,
+                                t: t_orig,
+                                position_um,
+                            });
+                            let mut image =
+                                encoder.new_image::<Gray16>(width as u32, height as u32)?;
+                            image
+                                .encoder()
+                                .write_tag(Tag::ImageDescription, xml.as_str())?;
+                            image.write_data(&channel_data)?;
+                        }
+                    }
 
-                    done += 1;
+                    stats::add_frames(1);
+                    stats::add_bytes_written(channel_data.len() as u64 * 2);
+                    pos_done += 1;
+                    let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    let (rate, eta) = {
+                        let mut throughput = throughput.lock().unwrap();
+                        throughput.tick(1);
+                        (throughput.rate(), throughput.eta((total - done) as u64))
+                    };
+                    cancel::check()?;
                     if total > 0 {
-                        progress(done as f64 / total as f64, &format!("Writing TIFFs {}/{}", done, total));
+                        let mut msg = format!(
+                            "Writing TIFFs {}/{} (Pos{} {}/{}, position {}/{})",
+                            done,
+                            total,
+                            p_idx,
+                            pos_done,
+                            per_pos,
+                            pi + 1,
+                            pos_indices.len()
+                        );
+                        if let (Some(rate), Some(eta)) = (rate, eta) {
+                            msg.push_str(&format!(
+                                ", {:.1} frames/s, ETA {}",
+                                rate,
+                                stats::format_duration(eta)
+                            ));
+                        }
+                        progress(
+                            ProgressEvent::new("write", done as u64, total as u64, &msg)
+                                .with_rate(rate, eta)
+                                .with_position(p_idx, pos_done as u64, per_pos as u64),
+                        );
                     }
                 }
             }
         }
-    }
+        Ok(())
+    })?;
 
-    progress(1.0, &format!("Wrote {}", output_path.display()));
+    manifest::tiff_folder(output_path, total as u64);
+    progress(ProgressEvent::done(&format!(
+        "Wrote {}",
+        output_path.display()
+    )));
     Ok(())
 }
@@ -1,33 +1,696 @@
 use clap::Args;
+use nd2_rs::Nd2File;
+use rayon::prelude::*;
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
+use crate::calibration::PixelCalibration;
+use crate::cancel;
+use crate::channels::{self, ChannelNames};
+use crate::checksum::{self, FrameDigest};
+use crate::console;
+use crate::convert;
+use crate::crop_index;
+use crate::defects::DefectMap;
+use crate::detector::DetectorRange;
+use crate::disk;
+use crate::lock::WriteLock;
+use crate::manifest;
+use crate::progress::ProgressEvent;
+use crate::slices::{self, Shard};
+use crate::stats;
+use crate::tiff_cache;
 use crate::zarr;
 
 #[derive(Args, Clone)]
 pub struct CropArgs {
+    /// TIFF root from `convert` (Pos{N} folders)
+    #[arg(long, required_unless_present = "nd2")]
+    pub input: Option<String>,
+    /// Read frames straight from this ND2 file instead of converted TIFFs (channel names,
+    /// bit depth and pixel size come from its metadata)
+    #[arg(long, conflicts_with = "input")]
+    pub nd2: Option<String>,
+    /// Positions: a number, or "all" / slices over the Pos{N} folders in --input (the ND2
+    /// positions with --nd2), e.g. "0:10, 150"
     #[arg(long)]
-    pub input: String,
-    #[arg(long)]
-    pub pos: u32,
+    pub pos: String,
+    /// Bbox CSV: crop plus x,y,w,h in pixels or x_um,y_um,w_um,h_um in µm ({pos} is
+    /// replaced by the position number)
     #[arg(long)]
     pub bbox: String,
+    /// Output store ({pos} is replaced by the position number; shards running at the
+    /// same time need separate stores, as a store takes one writer at a time)
     #[arg(long)]
     pub output: String,
+    /// Crop only share i of n of the selected positions (every n-th, starting at i, 0-based)
+    #[arg(long)]
+    pub shard: Option<String>,
     #[arg(long, default_value_t = false)]
     pub background: bool,
+    /// Also store a coarse per-frame background image (`pos/{pos}/background_image`):
+    /// medians of the pixels outside all boxes on a --background-grid square grid
+    #[arg(long)]
+    pub background_image: bool,
+    /// Cells per side of the --background-image grid
+    #[arg(long, default_value_t = 32)]
+    pub background_grid: u32,
+    /// Frames per chunk along t: crops are chunked as (N, 1, 1, H, W)
+    #[arg(long, default_value_t = 1)]
+    pub time_chunk: u64,
+    /// Pixel size in µm (overrides calibration.json from convert)
+    #[arg(long)]
+    pub pixel_size_um: Option<f64>,
+    /// Frame interval in seconds (overrides calibration.json from convert)
+    #[arg(long)]
+    pub frame_interval_s: Option<f64>,
+    /// Camera bit depth (overrides detector.json from convert); used by
+    /// `movie --contrast detector` and `kill --normalize detector`
+    #[arg(long)]
+    pub bit_depth: Option<u32>,
+    /// Camera dark offset in counts (overrides detector.json from convert)
+    #[arg(long)]
+    pub detector_offset: Option<u16>,
+    /// Channel names in channel order, e.g. "Phase,GFP" (overrides channels.json from
+    /// convert); channel arguments of later commands accept these names
+    #[arg(long)]
+    pub channel_names: Option<String>,
+    /// Store per-array content checksums in the array attributes (check with `validate --checksums`)
+    #[arg(long)]
+    pub checksums: bool,
+    /// Filename regex replacing the default naming, with named groups channel, pos,
+    /// time and z (missing groups read as 0), e.g. "^(?P<pos>\d+)_t(?P<time>\d+)_c(?P<channel>\d+)\.tiff?$"
+    #[arg(long)]
+    pub pattern: Option<String>,
+    /// Overlapping boxes: "warn" (report and keep), "error" (abort) or "merge"
+    /// (replace each overlapping group by its union box)
+    #[arg(long, default_value = "warn")]
+    pub overlap: String,
+    /// Gaps in the (c, t, z) grid: "error", "skip" (drop incomplete timepoints),
+    /// "fill-nan" (blank frames that analysis reports as missing) or "fill-previous"
+    /// (repeat the nearest earlier frame; a gap with no earlier frame stays blank as with
+    /// fill-nan). Filled frames are listed in the `missing_frames` attribute.
+    #[arg(long, default_value = "error")]
+    pub missing: String,
+    /// TIFFs that cannot be decoded (or differ in frame size): "error", "skip" (log the file
+    /// and write a blank frame listed in `missing_frames`) or "quarantine" (skip, and move
+    /// the file to {input}/quarantine/Pos{N}/ so later runs see it as missing)
+    #[arg(long, default_value = "error")]
+    pub on_corrupt: String,
+    /// 8-bit frames: "none" (store values as-is) or "to-u16-range" (multiply by 257 so 255
+    /// maps to 65535). One value for all channels or a comma list per channel, e.g. "none,to-u16-range"
+    #[arg(long, default_value = "none")]
+    pub scale_u8: String,
+    /// Write even when the estimated output does not fit in the free disk space
+    #[arg(long)]
+    pub force: bool,
+    /// Pixels at or above this value count as saturated (default: 255 for unscaled 8-bit
+    /// channels, 65535 otherwise); counts per frame go to the `saturated_px` attribute
+    #[arg(long)]
+    pub saturation_level: Option<u16>,
+    /// Defect map from `defects`: listed hot/dead pixels are replaced by the median of
+    /// their neighbours before cropping
+    #[arg(long)]
+    pub fix_defects: Option<String>,
 }
 
-struct Bbox {
-    x: u32,
-    y: u32,
-    w: u32,
-    h: u32,
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OverlapPolicy {
+    Warn,
+    Error,
+    Merge,
+}
+
+impl OverlapPolicy {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            "merge" => Ok(Self::Merge),
+            _ => Err(format!("Unknown overlap policy {s:?}. Use warn, error or merge.").into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Bbox {
+    /// Crop directory name, from the CSV `crop` column.
+    pub(crate) id: String,
+    /// Raw `crop` value as written in the CSV.
+    pub(crate) label: String,
+    /// Data row in the CSV (0-based).
+    pub(crate) row: usize,
+    pub(crate) x: u32,
+    pub(crate) y: u32,
+    pub(crate) w: u32,
+    pub(crate) h: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MissingPolicy {
+    Error,
+    Skip,
+    FillNan,
+    FillPrevious,
+}
+
+impl MissingPolicy {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "error" => Ok(Self::Error),
+            "skip" => Ok(Self::Skip),
+            "fill-nan" => Ok(Self::FillNan),
+            "fill-previous" => Ok(Self::FillPrevious),
+            _ => Err(format!(
+                "Unknown missing-frame policy {s:?}. Use error, skip, fill-nan or fill-previous."
+            )
+            .into()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Skip => "skip",
+            Self::FillNan => "fill-nan",
+            Self::FillPrevious => "fill-previous",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CorruptPolicy {
+    Error,
+    Skip,
+    Quarantine,
+}
+
+impl CorruptPolicy {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "error" => Ok(Self::Error),
+            "skip" => Ok(Self::Skip),
+            "quarantine" => Ok(Self::Quarantine),
+            _ => Err(
+                format!("Unknown corrupt-file policy {s:?}. Use error, skip or quarantine.").into(),
+            ),
+        }
+    }
+}
+
+/// Pixels with width and height, as returned by `read_tiff_frame`.
+type Decoded = (FrameData, u32, u32);
+
+/// TIFFs of a position that failed to decode, handled per `--on-corrupt`.
+struct CorruptFiles {
+    policy: CorruptPolicy,
+    pos_dir: std::path::PathBuf,
+    quarantine_dir: std::path::PathBuf,
+    paths: std::collections::HashSet<std::path::PathBuf>,
+}
+
+impl CorruptFiles {
+    fn new(policy: CorruptPolicy, input: &Path, pos: u32) -> Self {
+        Self {
+            policy,
+            pos_dir: input.join(format!("Pos{}", pos)),
+            quarantine_dir: input.join("quarantine").join(format!("Pos{}", pos)),
+            paths: std::collections::HashSet::new(),
+        }
+    }
+
+    /// Decode `path`, expecting `size` (width, height) when given. A file that fails is an
+    /// error under the "error" policy; otherwise it is logged (and moved aside under
+    /// "quarantine") and `None` returned, now and for every later frame that reads it.
+    fn read(
+        &mut self,
+        path: &Path,
+        size: Option<(u32, u32)>,
+    ) -> Result<Option<Decoded>, Box<dyn std::error::Error>> {
+        if self.paths.contains(path) {
+            return Ok(None);
+        }
+        let error = match read_tiff_frame(path) {
+            Ok((_, w, h)) if size.is_some_and(|s| s != (w, h)) => {
+                let (ew, eh) = size.unwrap_or_default();
+                format!("frame is {w}x{h}, expected {ew}x{eh}")
+            }
+            Ok(frame) => return Ok(Some(frame)),
+            Err(e) => e.to_string(),
+        };
+        if self.policy == CorruptPolicy::Error {
+            return Err(format!(
+                "Corrupt TIFF {}: {} (choose an --on-corrupt policy to continue without it)",
+                path.display(),
+                error
+            )
+            .into());
+        }
+        console::warn(&format!(
+            "crop: corrupt TIFF {}: {}; writing a blank frame",
+            path.display(),
+            error
+        ));
+        if self.policy == CorruptPolicy::Quarantine {
+            // Keep the path below Pos{N} (channel folders) so files cannot collide.
+            let rel = match path.strip_prefix(&self.pos_dir) {
+                Ok(rel) => rel,
+                Err(_) => Path::new(path.file_name().unwrap_or_default()),
+            };
+            let dest = self.quarantine_dir.join(rel);
+            let moved = dest
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::rename(path, &dest));
+            let (from, to) = (path.display(), dest.display());
+            match moved {
+                Ok(()) => console::info(&format!("crop: moved {} to {}", from, to)),
+                Err(e) => console::warn(&format!("crop: could not quarantine {}: {}", from, e)),
+            }
+        }
+        self.paths.insert(path.to_path_buf());
+        Ok(None)
+    }
+}
+
+/// Where the frames of a position are read from.
+enum Source {
+    /// Pos{N} TIFFs under --input, decoded under the --on-corrupt policy.
+    Tiffs(CorruptFiles),
+    /// One position of the --nd2 file.
+    Nd2 { file: Nd2File, pos: usize },
+}
+
+/// What convert records next to the TIFFs (sidecars), or the ND2 metadata equivalent.
+struct SourceMeta {
+    calibration: PixelCalibration,
+    detector: DetectorRange,
+    channel_names: Option<ChannelNames>,
+    /// Frame (width, height) when known without decoding a frame.
+    size: Option<(u32, u32)>,
+    /// What was found, for the discover message, e.g. "120 TIFFs".
+    found: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum U8Scaling {
+    None,
+    ToU16Range,
+}
+
+impl U8Scaling {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s.trim() {
+            "none" => Ok(Self::None),
+            "to-u16-range" => Ok(Self::ToU16Range),
+            other => {
+                Err(format!("Unknown --scale-u8 value {other:?}. Use none or to-u16-range.").into())
+            }
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::ToU16Range => "to-u16-range",
+        }
+    }
+}
+
+/// Expand `--scale-u8` to one entry per channel.
+fn parse_u8_scaling(
+    s: &str,
+    n_channels: usize,
+) -> Result<Vec<U8Scaling>, Box<dyn std::error::Error>> {
+    let values = s
+        .split(',')
+        .map(U8Scaling::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    match values.len() {
+        1 => Ok(vec![values[0]; n_channels]),
+        n if n == n_channels => Ok(values),
+        n => Err(
+            format!("--scale-u8 lists {n} values but the data has {n_channels} channels").into(),
+        ),
+    }
+}
+
+/// Array attribute listing frames that were not on disk, as
+/// `{"policy": "...", "frames": [[t, c, z], ...]}` in array coordinates.
+pub(crate) const MISSING_FRAMES_ATTR: &str = "missing_frames";
+
+/// Frames of a crop or background array that `crop` had to fill; analysis skips them.
+pub(crate) fn missing_frames(arr: &zarr::StoreArray) -> std::collections::HashSet<(u64, u64, u64)> {
+    let frames = arr
+        .attributes()
+        .get(MISSING_FRAMES_ATTR)
+        .and_then(|m| m.get("frames"))
+        .and_then(|f| f.as_array());
+    frames
+        .into_iter()
+        .flatten()
+        .filter_map(|f| {
+            let f = f.as_array()?;
+            Some((
+                f.first()?.as_u64()?,
+                f.get(1)?.as_u64()?,
+                f.get(2)?.as_u64()?,
+            ))
+        })
+        .collect()
+}
+
+/// Timepoints whose channel `c` frame (z 0) `crop` had to fill. Commands add them to their
+/// skip set for every channel they read, so a blank frame is never measured or classified.
+pub(crate) fn missing_times(arr: &zarr::StoreArray, c: u64) -> std::collections::HashSet<u64> {
+    missing_frames(arr)
+        .into_iter()
+        .filter(|&(_, fc, z)| fc == c && z == 0)
+        .map(|(t, _, _)| t)
+        .collect()
+}
+
+/// Array attribute with the saturation level per channel and the number of saturated
+/// pixels per frame, as `{"levels": [...], "counts": [[t, c, z, n], ...]}` (frames
+/// without saturated pixels are left out).
+pub(crate) const SATURATED_ATTR: &str = "saturated_px";
+
+pub(crate) struct Saturation {
+    levels: Vec<u16>,
+    counts: HashMap<(u64, u64, u64), u64>,
+}
+
+impl Saturation {
+    pub(crate) fn count(&self, t: u64, c: u64, z: u64) -> u64 {
+        self.counts.get(&(t, c, z)).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn level(&self, c: u64) -> u16 {
+        self.levels.get(c as usize).copied().unwrap_or(u16::MAX)
+    }
+}
+
+/// Saturation recorded by `crop`; `None` for arrays written before it was recorded.
+pub(crate) fn saturation(arr: &zarr::StoreArray) -> Option<Saturation> {
+    let attr = arr.attributes().get(SATURATED_ATTR)?;
+    let levels = attr
+        .get("levels")?
+        .as_array()?
+        .iter()
+        .map(|v| v.as_u64().map(|l| l.min(u16::MAX as u64) as u16))
+        .collect::<Option<_>>()?;
+    let counts = attr
+        .get("counts")?
+        .as_array()?
+        .iter()
+        .filter_map(|f| {
+            let f = f.as_array()?;
+            let at = |i: usize| f.get(i)?.as_u64();
+            Some(((at(0)?, at(1)?, at(2)?), at(3)?))
+        })
+        .collect();
+    Some(Saturation { levels, counts })
+}
+
+/// Append `[t, c, z, n]` when `n > 0` pixels of `data` reach `level`.
+fn record_saturation(
+    out: &mut Vec<[u64; 4]>,
+    (t, c, z): (u64, u64, u64),
+    data: &[u16],
+    level: u16,
+) {
+    let n = data.iter().filter(|&&v| v >= level).count() as u64;
+    if n > 0 {
+        out.push([t, c, z, n]);
+    }
+}
+
+/// One output frame: array coordinates and the TIFF to read (None = not on disk, or read
+/// from the ND2).
+struct PlannedFrame<'a> {
+    t: u64,
+    c: u64,
+    z: u64,
+    path: Option<&'a std::path::PathBuf>,
+}
+
+struct FramePlan<'a> {
+    /// Frames in write order.
+    frames: Vec<PlannedFrame<'a>>,
+    /// Source timepoint of each output t.
+    times: Vec<u32>,
+    /// Grid positions with no TIFF, as source (c, t, z).
+    gaps: Vec<(u32, u32, u32)>,
+}
+
+/// Lay out the full (t, c, z) grid spanned by `index` and apply `policy` to its gaps.
+fn plan_frames(
+    index: &TiffIndex,
+    policy: MissingPolicy,
+) -> Result<FramePlan<'_>, Box<dyn std::error::Error>> {
+    let extent =
+        |axis: fn(&(u32, u32, u32)) -> u32| index.keys().map(axis).max().map_or(0, |m| m + 1);
+    let (n_c, n_t, n_z) = (extent(|k| k.0), extent(|k| k.1), extent(|k| k.2));
+    let mut gaps = Vec::new();
+    for c in 0..n_c {
+        for t in 0..n_t {
+            for z in 0..n_z {
+                if !index.contains_key(&(c, t, z)) {
+                    gaps.push((c, t, z));
+                }
+            }
+        }
+    }
+    if !gaps.is_empty() && policy == MissingPolicy::Error {
+        let shown: Vec<String> = gaps
+            .iter()
+            .take(5)
+            .map(|(c, t, z)| format!("c={c} t={t} z={z}"))
+            .collect();
+        return Err(format!(
+            "{} frame(s) missing from the (c, t, z) grid: {}{} (choose a --missing policy)",
+            gaps.len(),
+            shown.join(", "),
+            if gaps.len() > shown.len() {
+                ", ..."
+            } else {
+                ""
+            }
+        )
+        .into());
+    }
+    let times: Vec<u32> = (0..n_t)
+        .filter(|&t| policy != MissingPolicy::Skip || !gaps.iter().any(|g| g.1 == t))
+        .collect();
+
+    let mut frames = Vec::with_capacity(times.len() * (n_c * n_z) as usize);
+    for (t_out, &t) in times.iter().enumerate() {
+        for c in 0..n_c {
+            for z in 0..n_z {
+                let mut path = index.get(&(c, t, z));
+                // Only earlier frames fill a gap; leading gaps stay blank and listed.
+                if path.is_none() && policy == MissingPolicy::FillPrevious {
+                    path = (0..t).rev().find_map(|src| index.get(&(c, src, z)));
+                }
+                frames.push(PlannedFrame {
+                    t: t_out as u64,
+                    c: c as u64,
+                    z: z as u64,
+                    path,
+                });
+            }
+        }
+    }
+    Ok(FramePlan {
+        frames,
+        times,
+        gaps,
+    })
+}
+
+/// Every (t, c, z) frame of an ND2 position; none can be missing.
+fn nd2_plan<'a>(n_t: u32, n_c: u32, n_z: u32) -> FramePlan<'a> {
+    let mut frames = Vec::with_capacity((n_t * n_c * n_z) as usize);
+    for t in 0..n_t {
+        for c in 0..n_c {
+            for z in 0..n_z {
+                frames.push(PlannedFrame {
+                    t: t as u64,
+                    c: c as u64,
+                    z: z as u64,
+                    path: None,
+                });
+            }
+        }
+    }
+    FramePlan {
+        frames,
+        times: (0..n_t).collect(),
+        gaps: Vec::new(),
+    }
+}
+
+/// Overlapping box pairs (i, j, intersection area in px), i < j.
+fn find_overlaps(bboxes: &[Bbox]) -> Vec<(usize, usize, u64)> {
+    let mut out = Vec::new();
+    for i in 0..bboxes.len() {
+        for j in i + 1..bboxes.len() {
+            let (a, b) = (&bboxes[i], &bboxes[j]);
+            let w = (a.x + a.w).min(b.x + b.w).saturating_sub(a.x.max(b.x));
+            let h = (a.y + a.h).min(b.y + b.h).saturating_sub(a.y.max(b.y));
+            if w > 0 && h > 0 {
+                out.push((i, j, w as u64 * h as u64));
+            }
+        }
+    }
+    out
 }
 
-fn parse_bbox_csv(path: &Path) -> Result<Vec<Bbox>, Box<dyn std::error::Error>> {
+/// Replace every group of transitively overlapping boxes by its union box, kept at the
+/// position (and crop ID) of the group's first box.
+fn merge_overlapping(bboxes: &[Bbox], overlaps: &[(usize, usize, u64)]) -> Vec<Bbox> {
+    let mut group: Vec<usize> = (0..bboxes.len()).collect();
+    fn root(group: &mut [usize], mut i: usize) -> usize {
+        while group[i] != i {
+            group[i] = group[group[i]];
+            i = group[i];
+        }
+        i
+    }
+    for &(i, j, _) in overlaps {
+        let (ri, rj) = (root(&mut group, i), root(&mut group, j));
+        group[ri.max(rj)] = ri.min(rj);
+    }
+    let mut merged: Vec<(usize, Bbox)> = Vec::new();
+    for (i, bb) in bboxes.iter().enumerate() {
+        let r = root(&mut group, i);
+        match merged.iter_mut().find(|(g, _)| *g == r) {
+            Some((_, u)) => {
+                let (x1, y1) = ((u.x + u.w).max(bb.x + bb.w), (u.y + u.h).max(bb.y + bb.h));
+                u.x = u.x.min(bb.x);
+                u.y = u.y.min(bb.y);
+                u.w = x1 - u.x;
+                u.h = y1 - u.y;
+            }
+            None => merged.push((r, bb.clone())),
+        }
+    }
+    merged.into_iter().map(|(_, bb)| bb).collect()
+}
+
+/// Report overlapping boxes and apply `policy`. Overlaps double-count pixels in
+/// downstream per-crop sums (expression).
+fn check_overlaps(
+    bboxes: Vec<Bbox>,
+    policy: OverlapPolicy,
+) -> Result<Vec<Bbox>, Box<dyn std::error::Error>> {
+    let overlaps = find_overlaps(&bboxes);
+    if overlaps.is_empty() {
+        return Ok(bboxes);
+    }
+    let total: u64 = overlaps.iter().map(|o| o.2).sum();
+    for &(i, j, area) in &overlaps {
+        console::info(&format!(
+            "crop: bboxes {} and {} overlap by {} px",
+            bboxes[i].label, bboxes[j].label, area
+        ));
+    }
+    let summary = format!(
+        "{} overlapping bbox pair(s), {} px overlap in total",
+        overlaps.len(),
+        total
+    );
+    match policy {
+        OverlapPolicy::Warn => {
+            console::warn(&summary);
+            Ok(bboxes)
+        }
+        OverlapPolicy::Error => Err(format!("{} (use --overlap warn or merge)", summary).into()),
+        OverlapPolicy::Merge => {
+            let merged = merge_overlapping(&bboxes, &overlaps);
+            console::info(&format!(
+                "crop: {}; merged {} boxes into {}",
+                summary,
+                bboxes.len(),
+                merged.len()
+            ));
+            Ok(merged)
+        }
+    }
+}
+
+/// Clip boxes to the `width`×`height` frame. Boxes that stick out are clamped and boxes
+/// with nothing left inside the frame are dropped, each with a warning; it is an error
+/// only when no box remains.
+pub(crate) fn clamp_bboxes(
+    bboxes: Vec<Bbox>,
+    width: u32,
+    height: u32,
+) -> Result<Vec<Bbox>, Box<dyn std::error::Error>> {
+    let mut kept = Vec::with_capacity(bboxes.len());
+    for mut bb in bboxes {
+        let x1 = bb.x.saturating_add(bb.w).min(width);
+        let y1 = bb.y.saturating_add(bb.h).min(height);
+        if bb.x >= x1 || bb.y >= y1 {
+            console::warn(&format!(
+                "bbox {} ({},{} {}x{}) is empty inside the {}x{} frame; skipped",
+                bb.label, bb.x, bb.y, bb.w, bb.h, width, height
+            ));
+            continue;
+        }
+        if (x1 - bb.x, y1 - bb.y) != (bb.w, bb.h) {
+            console::warn(&format!(
+                "bbox {} ({},{} {}x{}) extends past the {}x{} frame; clamped to {}x{}",
+                bb.label,
+                bb.x,
+                bb.y,
+                bb.w,
+                bb.h,
+                width,
+                height,
+                x1 - bb.x,
+                y1 - bb.y
+            ));
+            (bb.w, bb.h) = (x1 - bb.x, y1 - bb.y);
+        }
+        kept.push(bb);
+    }
+    if kept.is_empty() {
+        return Err(format!("No bbox lies inside the {}x{} frame", width, height).into());
+    }
+    Ok(kept)
+}
+
+/// Crop directory name for a `crop` value: integers are zero-padded to three digits
+/// (7 → "007", matching row-numbered CSVs); other IDs (e.g. chip coordinates "A01")
+/// are kept as written and must be safe as a path component.
+pub(crate) fn crop_dir_name(value: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let value = value.trim();
+    if let Ok(n) = value.parse::<u32>() {
+        return Ok(format!("{:03}", n));
+    }
+    let safe = !value.is_empty()
+        && !value.starts_with('.')
+        && value
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "-_.".contains(ch));
+    if !safe {
+        return Err(
+            format!("Invalid crop ID {value:?} (use letters, digits, '-', '_' or '.')").into(),
+        );
+    }
+    Ok(value.to_string())
+}
+
+/// Parse a bbox CSV with pixel columns (x, y, w, h) or physical columns
+/// (x_um, y_um, w_um, h_um), the latter converted with `um_per_px`.
+/// Crop IDs come from the `crop` column (see `crop_dir_name`) and must be unique.
+pub(crate) fn parse_bbox_csv(
+    path: &Path,
+    um_per_px: Option<f64>,
+) -> Result<Vec<Bbox>, Box<dyn std::error::Error>> {
     let s = fs::read_to_string(path)?;
     let lines: Vec<&str> = s.trim().lines().collect();
     if lines.len() < 2 {
@@ -35,51 +698,163 @@ fn parse_bbox_csv(path: &Path) -> Result<Vec<Bbox>, Box<dyn std::error::Error>>
     }
     let header = lines[0].to_lowercase();
     let cols: Vec<&str> = header.split(',').map(|c| c.trim()).collect();
-    let crop_idx = cols
-        .iter()
-        .position(|c| *c == "crop")
-        .ok_or("Missing crop column")?;
-    let x_idx = cols
-        .iter()
-        .position(|c| *c == "x")
-        .ok_or("Missing x column")?;
-    let y_idx = cols
-        .iter()
-        .position(|c| *c == "y")
-        .ok_or("Missing y column")?;
-    let w_idx = cols
-        .iter()
-        .position(|c| *c == "w")
-        .ok_or("Missing w column")?;
-    let h_idx = cols
-        .iter()
-        .position(|c| *c == "h")
-        .ok_or("Missing h column")?;
+    let col = |name: &str| cols.iter().position(|c| *c == name);
+    let crop_idx = col("crop").ok_or("Missing crop column")?;
 
-    let mut out = Vec::new();
-    for line in lines.iter().skip(1) {
+    // Pixel columns take precedence; µm columns need a pixel size.
+    let (idx, scale) = match (col("x"), col("y"), col("w"), col("h")) {
+        (Some(x), Some(y), Some(w), Some(h)) => ([x, y, w, h], None),
+        _ => {
+            let (Some(x), Some(y), Some(w), Some(h)) =
+                (col("x_um"), col("y_um"), col("w_um"), col("h_um"))
+            else {
+                return Err("Bbox CSV needs x, y, w, h or x_um, y_um, w_um, h_um columns".into());
+            };
+            let um_per_px = um_per_px.ok_or(
+                "Bbox CSV is in µm but no pixel size is known (pass --pixel-size-um or set it in convert)",
+            )?;
+            ([x, y, w, h], Some(um_per_px))
+        }
+    };
+    let [x_idx, y_idx, w_idx, h_idx] = idx;
+
+    let parse = |value: &str| -> Result<u32, Box<dyn std::error::Error>> {
+        match scale {
+            None => Ok(value.trim().parse()?),
+            Some(um_per_px) => {
+                let px = (value.trim().parse::<f64>()? / um_per_px).round();
+                if px < 0.0 {
+                    return Err(format!("Negative bbox coordinate: {value}").into());
+                }
+                Ok(px as u32)
+            }
+        }
+    };
+
+    let mut out: Vec<Bbox> = Vec::new();
+    for (row, line) in lines.iter().skip(1).enumerate() {
         let parts: Vec<&str> = line.split(',').collect();
         if parts.len() <= *[crop_idx, x_idx, y_idx, w_idx, h_idx].iter().max().unwrap() {
             continue;
         }
+        let label = parts[crop_idx].trim().to_string();
+        let id = crop_dir_name(&label)?;
+        if let Some(prev) = out.iter().find(|b| b.id == id) {
+            return Err(format!(
+                "Duplicate crop ID {:?} in bbox CSV (rows {} and {})",
+                id, prev.row, row
+            )
+            .into());
+        }
         out.push(Bbox {
-            x: parts[x_idx].trim().parse()?,
-            y: parts[y_idx].trim().parse()?,
-            w: parts[w_idx].trim().parse()?,
-            h: parts[h_idx].trim().parse()?,
+            id,
+            label,
+            row,
+            x: parse(parts[x_idx])?,
+            y: parse(parts[y_idx])?,
+            w: parse(parts[w_idx])?,
+            h: parse(parts[h_idx])?,
         });
     }
     Ok(out)
 }
 
-const TIFF_RE: &str = r"^img_channel(\d+)_position(\d+)_time(\d+)_z(\d+)\.tif$";
+const TIFF_RE: &str =
+    r"^img_channel(?P<channel>\d+)_position(?P<pos>\d+)_time(?P<time>\d+)_z(?P<z>\d+)\.tif$";
+const CHANNEL_TIFF_RE: &str = r"^img_time(\d+)_z(\d+)\.tif$";
+
+/// TIFF paths of one position keyed by (c, t, z).
+pub(crate) type TiffIndex = HashMap<(u32, u32, u32), std::path::PathBuf>;
+
+/// Channel index → folder name table written by `convert --layout by-channel`.
+pub(crate) const CHANNELS_CSV: &str = "channels.csv";
 
-fn discover_tiffs(
+/// Index the TIFFs of one position by (c, t, z). Reads the flat layout
+/// (`img_channel…_position…_time…_z….tif`) and falls back to per-channel folders
+/// (`{channel_name}/img_time…_z….tif`) ordered by channels.csv, or by folder name
+/// when that table is missing.
+///
+/// `pattern` replaces the flat-layout regex (`--pattern`). It is matched against file
+/// names in `pos_dir` and may use the named groups `channel`, `pos`, `time` and `z`;
+/// absent groups read as 0, and without a `pos` group every file belongs to `pos`.
+pub(crate) fn discover_tiffs(
     pos_dir: &Path,
     pos: u32,
-) -> Result<HashMap<(u32, u32, u32), std::path::PathBuf>, Box<dyn std::error::Error>> {
-    let re = Regex::new(TIFF_RE)?;
+    pattern: Option<&str>,
+) -> Result<TiffIndex, Box<dyn std::error::Error>> {
+    if let Some(pattern) = pattern {
+        let re = Regex::new(pattern).map_err(|e| format!("Invalid --pattern: {e}"))?;
+        let known = ["channel", "pos", "time", "z"];
+        if !re.capture_names().flatten().any(|n| known.contains(&n)) {
+            return Err("--pattern needs at least one named group: channel, pos, time or z".into());
+        }
+        return discover_flat_tiffs(pos_dir, pos, &re);
+    }
+    let index = discover_flat_tiffs(pos_dir, pos, &Regex::new(TIFF_RE)?)?;
+    if !index.is_empty() {
+        return Ok(index);
+    }
+    discover_channel_folder_tiffs(pos_dir)
+}
+
+fn discover_channel_folder_tiffs(pos_dir: &Path) -> Result<TiffIndex, Box<dyn std::error::Error>> {
+    let table = pos_dir.join(CHANNELS_CSV);
+    let folders: Vec<String> = if table.exists() {
+        let mut rows: Vec<(u32, String)> = Vec::new();
+        for line in fs::read_to_string(&table)?.lines().skip(1) {
+            let Some((c, name)) = line.trim().split_once(',') else {
+                continue;
+            };
+            rows.push((c.trim().parse()?, name.trim().to_string()));
+        }
+        rows.sort();
+        rows.into_iter().map(|(_, name)| name).collect()
+    } else {
+        let mut names: Vec<String> = fs::read_dir(pos_dir)?
+            .filter_map(|e| {
+                let e = e.ok()?;
+                if e.file_type().ok()?.is_dir() {
+                    e.file_name().to_str().map(String::from)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        names.sort();
+        names
+    };
+
+    let re = Regex::new(CHANNEL_TIFF_RE)?;
     let mut index = HashMap::new();
+    for (c, folder) in folders.iter().enumerate() {
+        let dir = pos_dir.join(folder);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let Some(cap) = re.captures(&name) else {
+                continue;
+            };
+            let t: u32 = cap[1].parse()?;
+            let z: u32 = cap[2].parse()?;
+            index.insert((c as u32, t, z), entry.path());
+        }
+    }
+    Ok(index)
+}
+
+fn discover_flat_tiffs(
+    pos_dir: &Path,
+    pos: u32,
+    re: &Regex,
+) -> Result<TiffIndex, Box<dyn std::error::Error>> {
+    let mut index = TiffIndex::new();
     for entry in fs::read_dir(pos_dir)? {
         let entry = entry?;
         if !entry.file_type()?.is_file() {
@@ -91,24 +866,48 @@ fn discover_tiffs(
             Some(c) => c,
             None => continue,
         };
-        let file_pos: u32 = cap[2].parse()?;
-        if file_pos != pos {
+        let group = |g: &str| -> Result<Option<u32>, Box<dyn std::error::Error>> {
+            match cap.name(g) {
+                Some(m) => Ok(Some(m.as_str().parse().map_err(|_| {
+                    format!("{}: group {} is not a number ({:?})", name, g, m.as_str())
+                })?)),
+                None => Ok(None),
+            }
+        };
+        if group("pos")?.is_some_and(|file_pos| file_pos != pos) {
             continue;
         }
-        let c: u32 = cap[1].parse()?;
-        let t: u32 = cap[3].parse()?;
-        let z: u32 = cap[4].parse()?;
-        index.insert((c, t, z), entry.path());
+        let c = group("channel")?.unwrap_or(0);
+        let t = group("time")?.unwrap_or(0);
+        let z = group("z")?.unwrap_or(0);
+        if let Some(prev) = index.insert((c, t, z), entry.path()) {
+            return Err(format!(
+                "{} and {} both map to channel {}, time {}, z {}",
+                prev.display(),
+                entry.path().display(),
+                c,
+                t,
+                z
+            )
+            .into());
+        }
     }
     Ok(index)
 }
 
-enum FrameData {
+#[derive(Clone)]
+pub(crate) enum FrameData {
     U16(Vec<u16>),
     U8(Vec<u8>),
 }
 
-fn read_tiff_frame(path: &Path) -> Result<(FrameData, u32, u32), Box<dyn std::error::Error>> {
+/// Decode one TIFF frame (through `tiff_cache` when `--tiff-cache-mb` is set).
+pub(crate) fn read_tiff_frame(
+    path: &Path,
+) -> Result<(FrameData, u32, u32), Box<dyn std::error::Error>> {
+    if let Some(frame) = tiff_cache::get(path) {
+        return Ok(frame);
+    }
     let file = fs::File::open(path)?;
     let mut decoder = tiff::decoder::Decoder::new(file)?;
     let (width, height) = decoder.dimensions()?;
@@ -118,10 +917,22 @@ fn read_tiff_frame(path: &Path) -> Result<(FrameData, u32, u32), Box<dyn std::er
         tiff::decoder::DecodingResult::U16(v) => FrameData::U16(v),
         _ => return Err("Unsupported TIFF pixel format (need u8 or u16)".into()),
     };
+    stats::add_bytes_read(match &data {
+        FrameData::U16(v) => v.len() as u64 * 2,
+        FrameData::U8(v) => v.len() as u64,
+    });
+    tiff_cache::insert(path, &data, width, height);
     Ok((data, width, height))
 }
 
-fn extract_crop_u16(frame: &[u16], frame_width: u32, x: u32, y: u32, w: u32, h: u32) -> Vec<u16> {
+pub(crate) fn extract_crop_u16(
+    frame: &[u16],
+    frame_width: u32,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+) -> Vec<u16> {
     let mut out = vec![0u16; (w * h) as usize];
     for r in 0..h {
         let src_start = ((y + r) * frame_width + x) as usize;
@@ -132,7 +943,51 @@ fn extract_crop_u16(frame: &[u16], frame_width: u32, x: u32, y: u32, w: u32, h:
     out
 }
 
-fn median_outside_mask_u16(frame: &[u16], width: u32, height: u32, mask: &[bool]) -> u16 {
+/// Cut every box out of `frame` and store it at `(t, c, z)` of its crop array. Boxes are
+/// independent and the frame is only read, so they are extracted, compressed and stored
+/// in parallel; the crops come back in box order.
+fn store_crops(
+    frame: &[u16],
+    width: u32,
+    crop_arrays: &[zarr::StoreArray],
+    bboxes: &[Bbox],
+    (t, c, z): (u64, u64, u64),
+) -> Result<Vec<Vec<u16>>, Box<dyn std::error::Error>> {
+    crop_arrays
+        .par_iter()
+        .zip(bboxes)
+        .map(|(arr, bb)| {
+            let crop_data = extract_crop_u16(frame, width, bb.x, bb.y, bb.w, bb.h);
+            zarr::store_frame_u16(arr, &[t, c, z], &crop_data)
+                .map_err(|e| format!("crop {}: {}", bb.id, e))?;
+            Ok(crop_data)
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(Into::into)
+}
+
+/// Frame-sized mask that is true inside any of `bboxes`.
+pub(crate) fn box_mask(bboxes: &[Bbox], width: u32, height: u32) -> Vec<bool> {
+    let mut m = vec![false; (width * height) as usize];
+    for bb in bboxes {
+        for dy in 0..bb.h {
+            for dx in 0..bb.w {
+                let idx = ((bb.y + dy) * width + (bb.x + dx)) as usize;
+                if idx < m.len() {
+                    m[idx] = true;
+                }
+            }
+        }
+    }
+    m
+}
+
+pub(crate) fn median_outside_mask_u16(
+    frame: &[u16],
+    width: u32,
+    height: u32,
+    mask: &[bool],
+) -> u16 {
     let mut values = Vec::new();
     let n = (width * height) as usize;
     for i in 0..n {
@@ -156,6 +1011,75 @@ fn median_outside_mask_u8(frame: &[u8], width: u32, height: u32, mask: &[bool])
     median_u16_in_place(&mut values)
 }
 
+/// Array with the coarse background image of each frame (`--background-image`).
+pub(crate) const BACKGROUND_IMAGE: &str = "background_image";
+
+/// Rows or columns of cell `i` when `len` pixels are split into `n` cells.
+fn grid_span(i: u32, n: u32, len: u32) -> std::ops::Range<u32> {
+    (i * len / n)..((i + 1) * len / n)
+}
+
+/// `grid`×`grid` medians of the pixels outside `mask`, row-major. Cells without such
+/// pixels take the value of the nearest cell that has some (0 if none has).
+fn background_grid(frame: &[u16], width: u32, height: u32, mask: &[bool], grid: u32) -> Vec<u16> {
+    let mut cells: Vec<Option<u16>> = Vec::with_capacity((grid * grid) as usize);
+    for gy in 0..grid {
+        for gx in 0..grid {
+            let mut values = Vec::new();
+            for y in grid_span(gy, grid, height) {
+                for x in grid_span(gx, grid, width) {
+                    let i = (y * width + x) as usize;
+                    if !mask[i] {
+                        values.push(frame[i]);
+                    }
+                }
+            }
+            cells.push((!values.is_empty()).then(|| median_u16_in_place(&mut values)));
+        }
+    }
+    let filled: Vec<(i64, i64, u16)> = cells
+        .iter()
+        .enumerate()
+        .filter_map(|(i, v)| Some(((i as u32 / grid) as i64, (i as u32 % grid) as i64, (*v)?)))
+        .collect();
+    cells
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            v.unwrap_or_else(|| {
+                let (gy, gx) = ((i as u32 / grid) as i64, (i as u32 % grid) as i64);
+                filled
+                    .iter()
+                    .min_by_key(|(y, x, _)| (y - gy).pow(2) + (x - gx).pow(2))
+                    .map_or(0, |c| c.2)
+            })
+        })
+        .collect()
+}
+
+/// Background at frame pixel (y, x), bilinearly interpolated between the centers of the
+/// cells of a `--background-image` grid (`grid_h`×`grid_w`) over a `frame_h`×`frame_w` frame.
+pub(crate) fn interpolate_background(
+    grid: &[u16],
+    (grid_h, grid_w): (u32, u32),
+    (frame_h, frame_w): (u32, u32),
+    (y, x): (f64, f64),
+) -> f64 {
+    // Fractional cell coordinate, cell centers at integers.
+    let cell = |v: f64, len: u32, n: u32| (v + 0.5) * n as f64 / len as f64 - 0.5;
+    let at = |v: f64, n: u32| {
+        let v = v.clamp(0.0, (n - 1) as f64);
+        let i = (v.floor() as u32).min(n.saturating_sub(2));
+        (i, (i + 1).min(n - 1), v - i as f64)
+    };
+    let (y0, y1, fy) = at(cell(y, frame_h, grid_h), grid_h);
+    let (x0, x1, fx) = at(cell(x, frame_w, grid_w), grid_w);
+    let g = |gy: u32, gx: u32| grid[(gy * grid_w + gx) as usize] as f64;
+    let top = g(y0, x0) * (1.0 - fx) + g(y0, x1) * fx;
+    let bottom = g(y1, x0) * (1.0 - fx) + g(y1, x1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
 /// O(n) average median via select_nth_unstable. Mutates slice.
 fn median_u16_in_place(values: &mut [u16]) -> u16 {
     if values.is_empty() {
@@ -172,75 +1096,318 @@ fn median_u16_in_place(values: &mut [u16]) -> u16 {
     }
 }
 
-pub fn run(args: CropArgs, progress: impl Fn(f64, &str)) -> Result<(), Box<dyn std::error::Error>> {
-    let pos_dir = Path::new(&args.input).join(format!("Pos{}", args.pos));
-    if !pos_dir.exists() {
-        return Err(format!("Position directory not found: {}", pos_dir.display()).into());
+/// Positions matching `spec`: a single number as-is, else "all" / slices over the
+/// Pos{N} folders in `input`.
+fn select_positions(input: &Path, spec: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    if let Ok(pos) = spec.trim().parse() {
+        return Ok(vec![pos]);
     }
+    let mut available: Vec<u32> = fs::read_dir(input)?
+        .filter_map(|e| {
+            let e = e.ok()?;
+            let name = e.file_name().to_str()?.to_string();
+            e.file_type().ok()?.is_dir().then_some(())?;
+            name.strip_prefix("Pos")?.parse().ok()
+        })
+        .collect();
+    available.sort_unstable();
+    let Some(&max) = available.last() else {
+        return Err(format!("No Pos* folders in {}", input.display()).into());
+    };
+    let selected: Vec<u32> = slices::parse_slice_string(spec, max as usize + 1)?
+        .into_iter()
+        .map(|p| p as u32)
+        .filter(|p| available.contains(p))
+        .collect();
+    if selected.is_empty() {
+        return Err(format!("No positions in {} match {:?}", input.display(), spec).into());
+    }
+    Ok(selected)
+}
+
+/// `--input`, which clap requires unless `--nd2` is given.
+fn tiff_root(args: &CropArgs) -> Result<&Path, Box<dyn std::error::Error>> {
+    Ok(Path::new(
+        args.input.as_deref().ok_or("crop needs --input or --nd2")?,
+    ))
+}
 
-    let bboxes = parse_bbox_csv(Path::new(&args.bbox))?;
+/// ND2 positions matching `spec` ("all" / slices over the file's positions).
+fn nd2_positions(path: &str, spec: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let n_pos = *Nd2File::open(path)?.sizes()?.get("P").unwrap_or(&1);
+    Ok(slices::parse_slice_string(spec, n_pos)?
+        .into_iter()
+        .map(|p| p as u32)
+        .collect())
+}
+
+pub fn run(
+    args: CropArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut positions = match &args.nd2 {
+        Some(path) => nd2_positions(path, &args.pos)?,
+        None => select_positions(tiff_root(&args)?, &args.pos)?,
+    };
+    if let Some(shard) = args.shard.as_deref().map(Shard::parse).transpose()? {
+        positions = shard.select(&positions);
+        console::info(&format!(
+            "Shard {}/{}: positions {:?}",
+            shard.index, shard.count, positions
+        ));
+    }
+    for pos in positions {
+        crop_position(&args, pos, &progress)?;
+    }
+    Ok(())
+}
+
+/// Crop one position into `--output` (with `{pos}` filled in).
+fn crop_position(
+    args: &CropArgs,
+    pos: u32,
+    progress: &impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let bbox_path = args.bbox.replace("{pos}", &pos.to_string());
+    let output = args.output.replace("{pos}", &pos.to_string());
+
+    let overlap_policy = OverlapPolicy::parse(&args.overlap)?;
+    let missing_policy = MissingPolicy::parse(&args.missing)?;
+    let corrupt_policy = CorruptPolicy::parse(&args.on_corrupt)?;
+
+    stats::stage("discover");
+    let index;
+    let (mut source, plan, meta) = match &args.nd2 {
+        Some(path) => {
+            let mut file = Nd2File::open(path)?;
+            let sizes = file.sizes()?;
+            let [n_pos, n_t, n_c, n_z, height, width] =
+                ["P", "T", "C", "Z", "Y", "X"].map(|axis| *sizes.get(axis).unwrap_or(&1));
+            if pos as usize >= n_pos {
+                return Err(format!("Position {} not in {} (P={})", pos, path, n_pos).into());
+            }
+            let names = convert::metadata_channel_names(&mut file, n_c);
+            let meta = SourceMeta {
+                calibration: PixelCalibration {
+                    um_per_px: convert::metadata_pixel_size(&mut file),
+                    frame_interval_s: None,
+                },
+                detector: DetectorRange {
+                    bit_depth: convert::metadata_bit_depth(&mut file),
+                    offset: None,
+                },
+                channel_names: (!names.is_empty()).then_some(names),
+                size: Some((width as u32, height as u32)),
+                found: format!("ND2 position {}", pos),
+            };
+            let plan = nd2_plan(n_t as u32, n_c as u32, n_z as u32);
+            (
+                Source::Nd2 {
+                    file,
+                    pos: pos as usize,
+                },
+                plan,
+                meta,
+            )
+        }
+        None => {
+            let input = tiff_root(args)?;
+            let pos_dir = input.join(format!("Pos{}", pos));
+            if !pos_dir.exists() {
+                return Err(format!("Position directory not found: {}", pos_dir.display()).into());
+            }
+            index = discover_tiffs(&pos_dir, pos, args.pattern.as_deref())?;
+            if index.is_empty() {
+                return Err(format!("No TIFFs found in {}", pos_dir.display()).into());
+            }
+            let meta = SourceMeta {
+                calibration: PixelCalibration::read_sidecar(input)?.unwrap_or_default(),
+                detector: DetectorRange::read_sidecar(input)?.unwrap_or_default(),
+                channel_names: ChannelNames::read_sidecar(input)?,
+                size: None,
+                found: format!("{} TIFFs", index.len()),
+            };
+            let plan = plan_frames(&index, missing_policy)?;
+            (
+                Source::Tiffs(CorruptFiles::new(corrupt_policy, input, pos)),
+                plan,
+                meta,
+            )
+        }
+    };
+
+    let calibration = meta.calibration.merged_with(PixelCalibration {
+        um_per_px: args.pixel_size_um,
+        frame_interval_s: args.frame_interval_s,
+    });
+    let detector = meta.detector.merged_with(DetectorRange {
+        bit_depth: args.bit_depth,
+        offset: args.detector_offset,
+    });
+    detector.validate()?;
+    let bboxes = parse_bbox_csv(Path::new(&bbox_path), calibration.um_per_px)?;
     if bboxes.is_empty() {
         return Err("No valid bounding boxes in bbox CSV".into());
     }
+    let bboxes = check_overlaps(bboxes, overlap_policy)?;
 
-    let index = discover_tiffs(&pos_dir, args.pos)?;
-    if index.is_empty() {
-        return Err(format!("No TIFFs found in {}", pos_dir.display()).into());
+    let FramePlan {
+        frames,
+        times,
+        gaps,
+    } = plan;
+    let n_times = times.len();
+    let n_channels = frames.iter().map(|f| f.c).max().map_or(0, |m| m + 1) as usize;
+    let n_z = frames.iter().map(|f| f.z).max().map_or(0, |m| m + 1) as usize;
+    if n_times == 0 {
+        return Err("Every timepoint has missing frames; nothing left to crop".into());
     }
-
-    let mut keys: Vec<_> = index.keys().copied().collect();
-    keys.sort();
-    let n_channels = keys
-        .iter()
-        .map(|k| k.0)
-        .collect::<std::collections::HashSet<_>>()
-        .len();
-    let n_times = keys
-        .iter()
-        .map(|k| k.1)
-        .collect::<std::collections::HashSet<_>>()
-        .len();
-    let n_z = keys
-        .iter()
-        .map(|k| k.2)
-        .collect::<std::collections::HashSet<_>>()
-        .len();
-    progress(
-        0.0,
+    let channel_names = match &args.channel_names {
+        Some(s) => {
+            let names = ChannelNames::parse(s);
+            if names.0.len() != n_channels {
+                return Err(format!(
+                    "--channel-names lists {} names but the data has {} channels",
+                    names.0.len(),
+                    n_channels
+                )
+                .into());
+            }
+            Some(names)
+        }
+        None => meta.channel_names.filter(|names| {
+            let fits = names.0.len() == n_channels;
+            if !fits {
+                console::warn(&format!(
+                    "crop: {} lists {} channels but the data has {}; not recording channel names",
+                    channels::SIDECAR_NAME,
+                    names.0.len(),
+                    n_channels
+                ));
+            }
+            fits
+        }),
+    };
+    progress(ProgressEvent::new(
+        "discover",
+        1,
+        1,
         &format!(
-            "Discovered {} TIFFs: T={}, C={}, Z={}",
-            index.len(),
+            "Discovered {}: T={}, C={}, Z={}{}",
+            meta.found,
             n_times,
             n_channels,
-            n_z
+            n_z,
+            if gaps.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    " ({} missing, --missing {})",
+                    gaps.len(),
+                    missing_policy.name()
+                )
+            }
         ),
-    );
+    ));
+    let missing_attr = (!gaps.is_empty()).then(|| {
+        if missing_policy == MissingPolicy::Skip {
+            let skipped: std::collections::BTreeSet<u32> = gaps.iter().map(|g| g.1).collect();
+            serde_json::json!({"policy": missing_policy.name(), "frames": [], "skipped_t": skipped})
+        } else {
+            let frames: Vec<[u32; 3]> = gaps.iter().map(|&(c, t, z)| [t, c, z]).collect();
+            serde_json::json!({"policy": missing_policy.name(), "frames": frames})
+        }
+    });
 
-    let output_root = Path::new(&args.output);
-    let pos_id = format!("{:03}", args.pos);
+    let output_root = Path::new(&output);
+    let pos_id = format!("{:03}", pos);
+    let _lock = WriteLock::acquire(output_root, "crop")?;
     let store = zarr::open_store(output_root)?;
     zarr::ensure_pos_crop_groups(&store, &pos_id)?;
+    crop_index::remove(&store, &pos_id)?;
+
+    if !calibration.is_empty() {
+        calibration.write_to_store(&store)?;
+    }
+    if let Some(names) = &channel_names {
+        names.write_to_store(&store)?;
+    }
+    if !detector.is_empty() {
+        detector.write_to_store(&store)?;
+    }
+
+    let u8_scaling = parse_u8_scaling(&args.scale_u8, n_channels)?;
+    // Source sample type per channel, filled in as frames are read.
+    let mut source_dtypes: Vec<Option<&'static str>> = vec![None; n_channels];
+    let mut saturation_levels = vec![args.saturation_level.unwrap_or(u16::MAX); n_channels];
+    let defect_map = args
+        .fix_defects
+        .as_deref()
+        .map(DefectMap::load)
+        .transpose()?;
 
-    let first_path = index.get(&keys[0]).unwrap();
-    let (_first_frame, width, height) = read_tiff_frame(first_path)?;
+    // Frame size from the ND2 header, or the first TIFF that decodes.
+    let mut size = meta.size;
+    if let Source::Tiffs(corrupt) = &mut source {
+        for path in frames.iter().filter_map(|f| f.path) {
+            if let Some((_, w, h)) = corrupt.read(path, None)? {
+                size = Some((w, h));
+                break;
+            }
+        }
+    }
+    let (width, height) = size.ok_or("None of the TIFFs of this position could be read")?;
+    let bboxes = clamp_bboxes(bboxes, width, height)?;
 
     let n_times_u = n_times as u64;
     let n_channels_u = n_channels as u64;
     let n_z_u = n_z as u64;
 
+    if args.time_chunk == 0 {
+        return Err("--time-chunk must be at least 1".into());
+    }
+    let time_chunk = args.time_chunk.min(n_times_u.max(1));
+
+    let crop_shape = |bb: &Bbox| vec![n_times_u, n_channels_u, n_z_u, bb.h as u64, bb.w as u64];
+    let crop_chunk = |bb: &Bbox| vec![time_chunk, 1, 1, bb.h as u64, bb.w as u64];
+    let mut estimated_bytes: u64 = bboxes
+        .iter()
+        .map(|bb| disk::zarr_u16_bytes(&crop_shape(bb), &crop_chunk(bb)))
+        .sum();
+    if args.background {
+        let shape = [n_times_u, n_channels_u, n_z_u];
+        estimated_bytes += disk::zarr_u16_bytes(&shape, &[1, 1, 1]);
+    }
+    let grid = args.background_grid as u64;
+    if args.background_image {
+        if args.background_grid == 0 || args.background_grid > width.min(height) {
+            return Err(format!(
+                "--background-grid must be between 1 and {}",
+                width.min(height)
+            )
+            .into());
+        }
+        let shape = [n_times_u, n_channels_u, n_z_u, grid, grid];
+        estimated_bytes += disk::zarr_u16_bytes(&shape, &[1, 1, 1, grid, grid]);
+    }
+    disk::ensure_free_space(output_root, estimated_bytes, args.force)?;
+
     let mut crop_arrays: Vec<zarr::StoreArray> = Vec::new();
-    for (i, bb) in bboxes.iter().enumerate() {
-        let crop_id = format!("{:03}", i);
-        let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
-        let shape = vec![n_times_u, n_channels_u, n_z_u, bb.h as u64, bb.w as u64];
-        let chunk_shape = vec![1, 1, 1, bb.h as u64, bb.w as u64];
-        let shard_shape = zarr::shard_shape_t_first(&shape);
-        let attrs = serde_json::json!({
+    for bb in &bboxes {
+        let array_path = format!("/pos/{}/crop/{}", pos_id, bb.id);
+        let shape = crop_shape(bb);
+        let chunk_shape = crop_chunk(bb);
+        let shard_shape = zarr::shard_shape_t_chunked(&shape, time_chunk);
+        let mut attrs = serde_json::json!({
             "axis_names": ["t", "c", "z", "y", "x"],
-            "bbox": {"x": bb.x, "y": bb.y, "w": bb.w, "h": bb.h}
+            "bbox": {"x": bb.x, "y": bb.y, "w": bb.w, "h": bb.h},
+            "bbox_csv": {"crop": bb.label, "row": bb.row}
         })
         .as_object()
         .cloned();
+        if let (Some(a), Some(m)) = (attrs.as_mut(), &missing_attr) {
+            a.insert(MISSING_FRAMES_ATTR.to_string(), m.clone());
+        }
         let arr =
             zarr::create_array_u16(&store, &array_path, shape, chunk_shape, shard_shape, attrs)?;
         crop_arrays.push(arr);
@@ -251,12 +1418,15 @@ pub fn run(args: CropArgs, progress: impl Fn(f64, &str)) -> Result<(), Box<dyn s
         let shape = vec![n_times_u, n_channels_u, n_z_u];
         let chunk_shape = vec![1, 1, 1];
         let shard_shape = zarr::shard_shape_t_first(&shape);
-        let attrs = serde_json::json!({
+        let mut attrs = serde_json::json!({
             "axis_names": ["t", "c", "z"],
             "description": "Median of pixels outside all crop bounding boxes"
         })
         .as_object()
         .cloned();
+        if let (Some(a), Some(m)) = (attrs.as_mut(), &missing_attr) {
+            a.insert(MISSING_FRAMES_ATTR.to_string(), m.clone());
+        }
         Some(zarr::create_array_u16(
             &store,
             &bg_path,
@@ -269,62 +1439,399 @@ pub fn run(args: CropArgs, progress: impl Fn(f64, &str)) -> Result<(), Box<dyn s
         None
     };
 
-    let mask: Vec<bool> = if args.background {
-        let mut m = vec![false; (width * height) as usize];
-        for bb in &bboxes {
-            for dy in 0..bb.h {
-                for dx in 0..bb.w {
-                    let idx = ((bb.y + dy) * width + (bb.x + dx)) as usize;
-                    if idx < m.len() {
-                        m[idx] = true;
-                    }
-                }
-            }
+    let bg_image_array: Option<zarr::StoreArray> = if args.background_image {
+        let path = format!("/pos/{}/{}", pos_id, BACKGROUND_IMAGE);
+        let shape = vec![n_times_u, n_channels_u, n_z_u, grid, grid];
+        let shard_shape = zarr::shard_shape_t_first(&shape);
+        let mut attrs = serde_json::json!({
+            "axis_names": ["t", "c", "z", "gy", "gx"],
+            "description": "Median of pixels outside all crop bounding boxes per grid cell",
+            "frame_shape": [height, width]
+        })
+        .as_object()
+        .cloned();
+        if let (Some(a), Some(m)) = (attrs.as_mut(), &missing_attr) {
+            a.insert(MISSING_FRAMES_ATTR.to_string(), m.clone());
         }
-        m
+        Some(zarr::create_array_u16(
+            &store,
+            &path,
+            shape,
+            vec![1, 1, 1, grid, grid],
+            shard_shape,
+            attrs,
+        )?)
+    } else {
+        None
+    };
+
+    let mask: Vec<bool> = if args.background || args.background_image {
+        box_mask(&bboxes, width, height)
     } else {
         vec![]
     };
 
-    let total = keys.len();
-    for (i, &(c, t, z)) in keys.iter().enumerate() {
-        let path = index.get(&(c, t, z)).unwrap();
-        let (frame_data, _w, _h) = read_tiff_frame(path)?;
+    // One digest per crop plus one for the background, indexed (t, c, z) in C order.
+    let n_frames = (n_times_u * n_channels_u * n_z_u) as usize;
+    let mut digests: Vec<FrameDigest> = if args.checksums {
+        (0..crop_arrays.len() + 1)
+            .map(|_| FrameDigest::new(n_frames))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let bg_digest = crop_arrays.len();
+    let mut saturated: Vec<Vec<[u64; 4]>> = vec![Vec::new(); crop_arrays.len()];
+    // Frames written blank because their TIFF was corrupt, as [t, c, z].
+    let mut corrupt_frames: Vec<[u64; 3]> = Vec::new();
+
+    stats::stage("extract");
+    let total = frames.len();
+    for (i, &PlannedFrame { t, c, z, path }) in frames.iter().enumerate() {
+        let frame_idx = ((t * n_channels_u + c) * n_z_u + z) as usize;
+        let read = match (&mut source, path) {
+            (Source::Nd2 { file, pos }, _) => {
+                let data = file.read_frame_2d(*pos, t as usize, c as usize, z as usize)?;
+                stats::add_bytes_read(data.len() as u64 * 2);
+                Some(FrameData::U16(data))
+            }
+            (Source::Tiffs(corrupt), Some(path)) => {
+                let read = corrupt.read(path, Some((width, height)))?;
+                if read.is_none() {
+                    corrupt_frames.push([t, c, z]);
+                }
+                read.map(|frame| frame.0)
+            }
+            (Source::Tiffs(_), None) => None,
+        };
+        let on_disk = read.is_some();
+        let mut frame_data = match read {
+            Some(data) => {
+                let dtype = match data {
+                    FrameData::U16(_) => "uint16",
+                    FrameData::U8(_) => "uint8",
+                };
+                if let Some(seen) = source_dtypes[c as usize]
+                    .replace(dtype)
+                    .filter(|&s| s != dtype)
+                {
+                    let at = path.map_or_else(|| format!("t={t}"), |p| p.display().to_string());
+                    return Err(
+                        format!("Channel {c} mixes {seen} and {dtype} frames ({at})").into(),
+                    );
+                }
+                match (data, u8_scaling[c as usize]) {
+                    (FrameData::U8(v), U8Scaling::ToU16Range) => {
+                        FrameData::U16(v.iter().map(|&p| p as u16 * 257).collect())
+                    }
+                    (data, _) => data,
+                }
+            }
+            // Not on disk (or corrupt): write an explicit blank so the frame is deterministic.
+            None => FrameData::U16(vec![0; (width * height) as usize]),
+        };
+        if let Some(map) = &defect_map {
+            map.repair_frame(c as u32, &mut frame_data, width, height);
+        }
+        if on_disk {
+            saturation_levels[c as usize] = args.saturation_level.unwrap_or(match frame_data {
+                FrameData::U16(_) => u16::MAX,
+                FrameData::U8(_) => u8::MAX as u16,
+            });
+        }
+        let level = saturation_levels[c as usize];
 
         match &frame_data {
             FrameData::U16(frame) => {
-                for (arr, bb) in crop_arrays.iter().zip(bboxes.iter()) {
-                    let crop_data = extract_crop_u16(frame, width, bb.x, bb.y, bb.w, bb.h);
-                    let chunk_indices = [t as u64, c as u64, z as u64, 0, 0];
-                    zarr::store_chunk_u16(arr, &chunk_indices, &crop_data)?;
+                let crops = store_crops(frame, width, &crop_arrays, &bboxes, (t, c, z))?;
+                for (ci, crop_data) in crops.iter().enumerate() {
+                    record_saturation(&mut saturated[ci], (t, c, z), crop_data, level);
+                    if let Some(d) = digests.get_mut(ci) {
+                        d.record(frame_idx, crop_data);
+                    }
                 }
                 if let Some(ref bg) = bg_array {
                     let val = median_outside_mask_u16(frame, width, height, &mask);
-                    let chunk_indices = [t as u64, c as u64, z as u64];
-                    zarr::store_chunk_u16(bg, &chunk_indices, &[val])?;
+                    zarr::store_frame_u16(bg, &[t, c, z], &[val])?;
+                    if let Some(d) = digests.get_mut(bg_digest) {
+                        d.record(frame_idx, &[val]);
+                    }
+                }
+                if let Some(ref bg_image) = bg_image_array {
+                    let cells = background_grid(frame, width, height, &mask, args.background_grid);
+                    zarr::store_frame_u16(bg_image, &[t, c, z], &cells)?;
                 }
             }
             FrameData::U8(frame) => {
                 let frame_u16: Vec<u16> = frame.iter().map(|&v| v as u16).collect();
-                for (arr, bb) in crop_arrays.iter().zip(bboxes.iter()) {
-                    let crop_data = extract_crop_u16(&frame_u16, width, bb.x, bb.y, bb.w, bb.h);
-                    let chunk_indices = [t as u64, c as u64, z as u64, 0, 0];
-                    zarr::store_chunk_u16(arr, &chunk_indices, &crop_data)?;
+                let crops = store_crops(&frame_u16, width, &crop_arrays, &bboxes, (t, c, z))?;
+                for (ci, crop_data) in crops.iter().enumerate() {
+                    record_saturation(&mut saturated[ci], (t, c, z), crop_data, level);
+                    if let Some(d) = digests.get_mut(ci) {
+                        d.record(frame_idx, crop_data);
+                    }
                 }
                 if let Some(ref bg) = bg_array {
                     let val = median_outside_mask_u8(frame, width, height, &mask);
-                    let chunk_indices = [t as u64, c as u64, z as u64];
-                    zarr::store_chunk_u16(bg, &chunk_indices, &[val])?;
+                    zarr::store_frame_u16(bg, &[t, c, z], &[val])?;
+                    if let Some(d) = digests.get_mut(bg_digest) {
+                        d.record(frame_idx, &[val]);
+                    }
+                }
+                if let Some(ref bg_image) = bg_image_array {
+                    let cells =
+                        background_grid(&frame_u16, width, height, &mask, args.background_grid);
+                    zarr::store_frame_u16(bg_image, &[t, c, z], &cells)?;
                 }
             }
         }
 
-        progress(
-            (i + 1) as f64 / total as f64,
+        stats::add_frames(1);
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "extract",
+            (i + 1) as u64,
+            total as u64,
             &format!("Reading frames {}/{}", i + 1, total),
+        ));
+    }
+
+    // Record how each channel's samples map onto the stored u16 values.
+    let channel_scaling: Vec<serde_json::Value> = source_dtypes
+        .iter()
+        .zip(&u8_scaling)
+        .enumerate()
+        .map(|(c, (dtype, scaling))| {
+            let dtype = dtype.unwrap_or("uint16");
+            let scaling = if dtype == "uint8" {
+                scaling.name()
+            } else {
+                "none"
+            };
+            serde_json::json!({"channel": c, "source_dtype": dtype, "scaling": scaling})
+        })
+        .collect();
+    let mut scaling_attrs = serde_json::Map::new();
+    scaling_attrs.insert("channel_scaling".to_string(), channel_scaling.into());
+    if !corrupt_frames.is_empty() {
+        // Corrupt frames join the missing ones, so analysis skips them too.
+        let mut missing = missing_attr
+            .clone()
+            .unwrap_or_else(|| serde_json::json!({"policy": missing_policy.name(), "frames": []}));
+        if let Some(listed) = missing["frames"].as_array_mut() {
+            for frame in &corrupt_frames {
+                let frame = serde_json::json!(frame);
+                if !listed.contains(&frame) {
+                    listed.push(frame);
+                }
+            }
+        }
+        missing["corrupt"] = serde_json::json!(corrupt_frames);
+        scaling_attrs.insert(MISSING_FRAMES_ATTR.to_string(), missing);
+    }
+    for (bb, counts) in bboxes.iter().zip(&saturated) {
+        let array_path = format!("/pos/{}/crop/{}", pos_id, bb.id);
+        let mut attrs = scaling_attrs.clone();
+        attrs.insert(
+            SATURATED_ATTR.to_string(),
+            serde_json::json!({"levels": saturation_levels, "counts": counts}),
         );
+        zarr::update_array_attrs(&store, &array_path, attrs)?;
+    }
+    if bg_array.is_some() {
+        let bg_path = format!("/pos/{}/background", pos_id);
+        zarr::update_array_attrs(&store, &bg_path, scaling_attrs.clone())?;
+    }
+    if bg_image_array.is_some() {
+        let path = format!("/pos/{}/{}", pos_id, BACKGROUND_IMAGE);
+        zarr::update_array_attrs(&store, &path, scaling_attrs)?;
+    }
+
+    if args.checksums {
+        for (digest, bb) in digests.iter().zip(bboxes.iter()) {
+            let array_path = format!("/pos/{}/crop/{}", pos_id, bb.id);
+            checksum::write(&store, &array_path, digest, 3)?;
+        }
+        if bg_array.is_some() {
+            let bg_path = format!("/pos/{}/background", pos_id);
+            checksum::write(&store, &bg_path, &digests[bg_digest], 3)?;
+        }
     }
 
-    progress(1.0, &format!("Wrote {}", args.output));
+    let registry = crop_index::PosCrops {
+        channels: n_channels_u,
+        crops: bboxes
+            .iter()
+            .map(|bb| crop_index::CropEntry {
+                id: bb.id.clone(),
+                shape: crop_shape(bb),
+                bbox: crop_index::CropBox {
+                    x: bb.x,
+                    y: bb.y,
+                    w: bb.w,
+                    h: bb.h,
+                },
+            })
+            .collect(),
+    };
+    crop_index::write(&store, &pos_id, &registry)?;
+
+    for (arr, bb) in crop_arrays.iter().zip(&bboxes) {
+        manifest::array(
+            output_root,
+            &format!("/pos/{}/crop/{}", pos_id, bb.id),
+            arr.shape(),
+        );
+    }
+    if let Some(bg) = &bg_array {
+        manifest::array(
+            output_root,
+            &format!("/pos/{}/background", pos_id),
+            bg.shape(),
+        );
+    }
+    if let Some(bg_image) = &bg_image_array {
+        let path = format!("/pos/{}/{}", pos_id, BACKGROUND_IMAGE);
+        manifest::array(output_root, &path, bg_image.shape());
+    }
+
+    progress(ProgressEvent::done(&format!(
+        "Wrote Pos{} to {}",
+        pos, output
+    )));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_image_fills_boxes_and_interpolates() {
+        // 4x4 frame brightening to the right; the top-left 2x2 quadrant is a crop.
+        let frame: Vec<u16> = (0..16).map(|i| 100 + (i % 4) * 10).collect();
+        let mask: Vec<bool> = (0..16).map(|i| i % 4 < 2 && i / 4 < 2).collect();
+        let grid = background_grid(&frame, 4, 4, &mask, 2);
+        // Bottom-left cell: median of 100, 110, 100, 110; top-left is filled from a neighbour.
+        assert_eq!(grid, vec![125, 125, 105, 125]);
+        let mid = interpolate_background(&grid, (2, 2), (4, 4), (1.5, 1.5));
+        assert_eq!(mid, 120.0);
+        assert_eq!(
+            interpolate_background(&grid, (2, 2), (4, 4), (3.0, 0.0)),
+            105.0
+        );
+    }
+
+    fn bb(x: u32, y: u32, w: u32, h: u32) -> Bbox {
+        let label = format!("{x}_{y}");
+        Bbox {
+            id: label.clone(),
+            label,
+            row: 0,
+            x,
+            y,
+            w,
+            h,
+        }
+    }
+
+    #[test]
+    fn overlapping_boxes_are_reported_and_merged_transitively() {
+        // 0 and 1 overlap, 1 and 2 overlap, 3 only touches 2 along an edge.
+        let boxes = vec![
+            bb(0, 0, 10, 10),
+            bb(5, 5, 10, 10),
+            bb(12, 12, 5, 5),
+            bb(17, 12, 5, 5),
+        ];
+        let overlaps = find_overlaps(&boxes);
+        assert_eq!(overlaps, vec![(0, 1, 25), (1, 2, 9)]);
+
+        let merged = merge_overlapping(&boxes, &overlaps);
+        assert_eq!(merged, vec![bb(0, 0, 17, 17), bb(17, 12, 5, 5)]);
+    }
+
+    #[test]
+    fn boxes_are_clamped_to_the_frame() {
+        let boxes = vec![
+            bb(0, 0, 10, 10),
+            bb(15, 5, 10, 10),
+            bb(20, 0, 5, 5),
+            bb(3, 3, 0, 4),
+        ];
+        let kept = clamp_bboxes(boxes, 20, 12).unwrap();
+        assert_eq!(kept, vec![bb(0, 0, 10, 10), bb(15, 5, 5, 7)]);
+        assert!(clamp_bboxes(vec![bb(30, 30, 5, 5)], 20, 12).is_err());
+    }
+    #[test]
+    fn gaps_follow_the_missing_policy() {
+        // Two channels, three timepoints; c=1 t=1 is missing.
+        let mut index = TiffIndex::new();
+        for (c, t) in [(0, 0), (0, 1), (0, 2), (1, 0), (1, 2)] {
+            index.insert((c, t, 0), format!("c{c}t{t}.tif").into());
+        }
+        assert!(plan_frames(&index, MissingPolicy::Error).is_err());
+
+        let plan = plan_frames(&index, MissingPolicy::Skip).unwrap();
+        assert_eq!((plan.times, plan.gaps), (vec![0, 2], vec![(1, 1, 0)]));
+        assert_eq!(plan.frames.len(), 4);
+
+        let frames = plan_frames(&index, MissingPolicy::FillPrevious)
+            .unwrap()
+            .frames;
+        let filled = frames.iter().find(|f| (f.t, f.c) == (1, 1)).unwrap();
+        assert_eq!(filled.path, index.get(&(1, 0, 0)));
+
+        // A gap at t=0 has no earlier frame and is not filled from a later one.
+        index.remove(&(0, 0, 0));
+        let plan = plan_frames(&index, MissingPolicy::FillPrevious).unwrap();
+        assert!(plan.gaps.contains(&(0, 0, 0)));
+        let first = plan.frames.iter().find(|f| (f.t, f.c) == (0, 0)).unwrap();
+        assert!(first.path.is_none());
+
+        let frames = plan_frames(&index, MissingPolicy::FillNan).unwrap().frames;
+        assert!(frames
+            .iter()
+            .any(|f| (f.t, f.c) == (1, 1) && f.path.is_none()));
+    }
+
+    #[test]
+    fn corrupt_tiffs_follow_the_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel_dir = dir.path().join("Pos3").join("GFP");
+        fs::create_dir_all(&channel_dir).unwrap();
+        let bad = channel_dir.join("img_000.tif");
+        fs::write(&bad, b"not a tiff").unwrap();
+
+        let mut strict = CorruptFiles::new(CorruptPolicy::Error, dir.path(), 3);
+        assert!(strict.read(&bad, None).is_err());
+
+        let mut files = CorruptFiles::new(CorruptPolicy::Quarantine, dir.path(), 3);
+        assert!(files.read(&bad, None).unwrap().is_none());
+        assert!(!bad.exists());
+        assert!(dir.path().join("quarantine/Pos3/GFP/img_000.tif").exists());
+        // Frames filled from the same file are blank too, without another read.
+        assert!(files.read(&bad, None).unwrap().is_none());
+        assert!(CorruptPolicy::parse("ignore").is_err());
+    }
+
+    #[test]
+    fn crop_ids_come_from_the_crop_column() {
+        assert_eq!(crop_dir_name("7").unwrap(), "007");
+        assert_eq!(crop_dir_name(" B07 ").unwrap(), "B07");
+        assert!(crop_dir_name("../x").is_err());
+        assert!(crop_dir_name("").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("bbox.csv");
+        fs::write(&csv, "crop,x,y,w,h\nA01,0,0,4,4\nB07,8,0,4,4\n").unwrap();
+        let boxes = parse_bbox_csv(&csv, None).unwrap();
+        assert_eq!(
+            boxes.iter().map(|b| b.id.as_str()).collect::<Vec<_>>(),
+            ["A01", "B07"]
+        );
+
+        fs::write(&csv, "crop,x,y,w,h\n1,0,0,4,4\n001,8,0,4,4\n").unwrap();
+        assert!(parse_bbox_csv(&csv, None).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Crop registry of a store. `crop` records every position's crops (IDs in bbox CSV row
+//! order, array shapes, bboxes, channel count) in the root attrs under "crops", and
+//! downstream commands read it instead of listing `pos/{pos}/crop`, so stray directories
+//! are ignored, crops stay aligned with the bbox rows and remote stores need no listing.
+//! Stores without a registry (masks.zarr, stores from other tools) fall back to the crop
+//! folders in natural order ("2" before "10"). Crop IDs compare equal with or without
+//! zero padding.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+use crate::zarr;
+
+const ATTRS_KEY: &str = "crops";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CropBox {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CropEntry {
+    pub id: String,
+    /// Array shape (t, c, z, y, x)
+    pub shape: Vec<u64>,
+    /// Bbox in the full frame
+    pub bbox: CropBox,
+}
+
+/// Registry entry of one position.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PosCrops {
+    pub channels: u64,
+    pub crops: Vec<CropEntry>,
+}
+
+impl PosCrops {
+    pub fn ids(&self) -> Vec<String> {
+        self.crops.iter().map(|c| c.id.clone()).collect()
+    }
+}
+
+/// Leading digit run of `s` without its zero padding, and the rest of `s`.
+fn split_number(s: &[u8]) -> (&[u8], &[u8]) {
+    let len = s.iter().take_while(|c| c.is_ascii_digit()).count();
+    let zeros = s[..len].iter().take_while(|&&c| c == b'0').count();
+    (&s[zeros..len], &s[len..])
+}
+
+/// Natural order: digit runs compare as numbers, other text byte by byte. IDs equal as
+/// numbers ("7", "007") fall back to plain comparison so the order stays total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut x, mut y) = (a.as_bytes(), b.as_bytes());
+    while let (Some(&cx), Some(&cy)) = (x.first(), y.first()) {
+        let ord = if cx.is_ascii_digit() && cy.is_ascii_digit() {
+            let (nx, rest_x) = split_number(x);
+            let (ny, rest_y) = split_number(y);
+            (x, y) = (rest_x, rest_y);
+            nx.len().cmp(&ny.len()).then_with(|| nx.cmp(ny))
+        } else {
+            (x, y) = (&x[1..], &y[1..]);
+            cx.cmp(&cy)
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    x.len().cmp(&y.len()).then_with(|| a.cmp(b))
+}
+
+/// Whether two crop IDs name the same crop ("7" and "007" do).
+pub fn same_crop(a: &str, b: &str) -> bool {
+    match (a.trim().parse::<u64>(), b.trim().parse::<u64>()) {
+        (Ok(x), Ok(y)) => x == y,
+        _ => a.trim() == b.trim(),
+    }
+}
+
+/// The entry of `available` naming crop `id`, padded or not.
+pub fn find<'a>(available: &'a [String], id: &str) -> Option<&'a String> {
+    available.iter().find(|a| same_crop(a, id))
+}
+
+/// Registry entry of `pos_id` in root attrs, if recorded.
+fn from_attrs(
+    attrs: &serde_json::Map<String, serde_json::Value>,
+    pos_id: &str,
+) -> Result<Option<PosCrops>, Box<dyn std::error::Error>> {
+    match attrs.get(ATTRS_KEY).and_then(|all| all.get(pos_id)) {
+        Some(v) => serde_json::from_value(v.clone())
+            .map(Some)
+            .map_err(|e| format!("Invalid crop registry for pos {}: {}", pos_id, e).into()),
+        None => Ok(None),
+    }
+}
+
+/// Record (or with `None`, drop) the registry entry of `pos_id`, keeping other positions.
+fn set(
+    store: &zarr::Store,
+    pos_id: &str,
+    entry: Option<&PosCrops>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut all = zarr::read_root_attrs(store)
+        .remove(ATTRS_KEY)
+        .and_then(|v| v.as_object().cloned())
+        .unwrap_or_default();
+    match entry {
+        Some(e) => {
+            all.insert(pos_id.to_string(), serde_json::to_value(e)?);
+        }
+        None => {
+            if all.remove(pos_id).is_none() {
+                return Ok(());
+            }
+        }
+    }
+    let mut attrs = serde_json::Map::new();
+    attrs.insert(ATTRS_KEY.to_string(), serde_json::Value::Object(all));
+    zarr::update_root_attrs(store, attrs)
+}
+
+/// Record the crops of a position, in the given order.
+pub fn write(
+    store: &zarr::Store,
+    pos_id: &str,
+    entry: &PosCrops,
+) -> Result<(), Box<dyn std::error::Error>> {
+    set(store, pos_id, Some(entry))
+}
+
+/// Drop the entry before rewriting a position, so an interrupted `crop` does not leave
+/// the registry of the previous run behind.
+pub fn remove(store: &zarr::Store, pos_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    set(store, pos_id, None)
+}
+
+/// Registry entry of `pos_id` in the store at `root`, if `crop` recorded one.
+pub fn registry(root: &Path, pos_id: &str) -> Result<Option<PosCrops>, Box<dyn std::error::Error>> {
+    if !root.exists() {
+        return Ok(None);
+    }
+    let store = zarr::open_store(root)?;
+    from_attrs(&zarr::read_root_attrs(&store), pos_id)
+}
+
+/// Crop folders under `crop_root` in natural order; empty when the folder does not exist.
+fn scan(crop_root: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if !crop_root.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names: Vec<String> = fs::read_dir(crop_root)?
+        .filter_map(|e| {
+            let e = e.ok()?;
+            if e.file_type().ok()?.is_dir() {
+                e.file_name().to_str().map(String::from)
+            } else {
+                None
+            }
+        })
+        .collect();
+    names.sort_by(|a, b| natural_cmp(a, b));
+    Ok(names)
+}
+
+/// Crop IDs of `pos_id` in the store at `root`: the registry when there is one, else the
+/// crop folders in natural order.
+pub fn list(root: &Path, pos_id: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    match registry(root, pos_id)? {
+        Some(entry) => Ok(entry.ids()),
+        None => scan(&root.join("pos").join(pos_id).join("crop")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn registry_wins_over_natural_folder_order() {
+        let mut ids = vec!["10", "2", "1", "B10", "B9", "007", "A01"];
+        ids.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(ids, ["1", "2", "007", "10", "A01", "B9", "B10"]);
+        assert!(same_crop("7", "007") && !same_crop("A01", "A1"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("crop");
+        assert!(scan(&root).unwrap().is_empty());
+        for name in ["10", "2", "1"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+        }
+        assert_eq!(scan(&root).unwrap(), ["1", "2", "10"]);
+
+        let attrs = json!({"crops": {"000": {"channels": 2, "crops": [
+            {"id": "2", "shape": [5, 2, 1, 8, 9], "bbox": {"x": 1, "y": 2, "w": 9, "h": 8}},
+            {"id": "1", "shape": [5, 2, 1, 8, 8], "bbox": {"x": 20, "y": 2, "w": 8, "h": 8}}
+        ]}}});
+        let attrs = attrs.as_object().unwrap();
+        let entry = from_attrs(attrs, "000").unwrap().unwrap();
+        assert_eq!(entry.ids(), ["2", "1"]);
+        assert_eq!(entry.crops[0].bbox.w, 9);
+        assert!(from_attrs(attrs, "001").unwrap().is_none());
+        let bad = json!({"crops": {"000": {"crops": 3}}});
+        assert!(from_attrs(bad.as_object().unwrap(), "000").is_err());
+    }
+}
@@ -0,0 +1,436 @@
+//! Minimal reader for Zeiss CZI (ZISRAW) files, enough for `convert`: the subblock
+//! directory, 16-bit grayscale planes (uncompressed or zstd) and channel names, bit depth,
+//! pixel size and scene stage positions from the XML metadata. Scenes (S) are positions, like ND2 positions.
+//! Pyramid levels are skipped; mosaic tiles and JPEG-XR compressed files are rejected.
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SEGMENT_HEADER: usize = 32;
+const PIXEL_GRAY16: i32 = 1;
+const COMPRESSION_NONE: i32 = 0;
+const COMPRESSION_ZSTD0: i32 = 5;
+const COMPRESSION_ZSTD1: i32 = 6;
+
+/// Plane axes, in the order of the `planes` keys.
+const PLANE_DIMS: [&str; 4] = ["S", "T", "C", "Z"];
+
+struct Entry {
+    pixel_type: i32,
+    file_position: u64,
+    compression: i32,
+    pyramid: bool,
+    /// Dimension name -> (start, size, stored size).
+    dims: HashMap<String, (i32, i32, i32)>,
+}
+
+#[derive(Clone, Copy)]
+struct Plane {
+    file_position: u64,
+    compression: i32,
+}
+
+pub struct CziFile {
+    reader: BufReader<File>,
+    /// P (scenes), T, C, Z, Y, X.
+    sizes: HashMap<String, usize>,
+    planes: HashMap<[usize; 4], Plane>,
+    xml: String,
+}
+
+fn le_i32(b: &[u8], at: usize) -> i32 {
+    i32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn le_i64(b: &[u8], at: usize) -> i64 {
+    i64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+/// Read the segment at `pos`: (id, data).
+fn read_segment<R: Read + Seek>(
+    reader: &mut R,
+    pos: u64,
+) -> Result<(String, Vec<u8>), Box<dyn std::error::Error>> {
+    reader.seek(SeekFrom::Start(pos))?;
+    let mut header = [0u8; SEGMENT_HEADER];
+    reader.read_exact(&mut header)?;
+    let id = String::from_utf8_lossy(&header[..16])
+        .trim_end_matches('\0')
+        .to_string();
+    let used = le_i64(&header, 24).max(0) as usize;
+    let mut data = vec![0u8; used];
+    reader.read_exact(&mut data)?;
+    Ok((id, data))
+}
+
+fn expect_segment<R: Read + Seek>(
+    reader: &mut R,
+    pos: u64,
+    id: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (found, data) = read_segment(reader, pos)?;
+    if found != id {
+        return Err(
+            format!("Not a CZI file: expected {id} segment at {pos}, found {found:?}").into(),
+        );
+    }
+    Ok(data)
+}
+
+/// Parse the "DV" directory entry at `at`: (entry, its length in bytes).
+fn parse_entry(b: &[u8], at: usize) -> Result<(Entry, usize), Box<dyn std::error::Error>> {
+    if b.get(at..at + 2) != Some(b"DV") {
+        return Err("Malformed CZI directory entry".into());
+    }
+    let n_dims = le_i32(b, at + 28).max(0) as usize;
+    let len = 32 + 20 * n_dims;
+    if b.len() < at + len {
+        return Err("Truncated CZI directory entry".into());
+    }
+    let dims = (0..n_dims)
+        .map(|i| {
+            let d = at + 32 + 20 * i;
+            let name = String::from_utf8_lossy(&b[d..d + 4])
+                .trim_end_matches('\0')
+                .to_string();
+            (
+                name,
+                (le_i32(b, d + 4), le_i32(b, d + 8), le_i32(b, d + 16)),
+            )
+        })
+        .collect();
+    let entry = Entry {
+        pixel_type: le_i32(b, at + 2),
+        file_position: le_i64(b, at + 6) as u64,
+        compression: le_i32(b, at + 18),
+        pyramid: b[at + 22] != 0,
+        dims,
+    };
+    Ok((entry, len))
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+impl CziFile {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header = expect_segment(&mut reader, 0, "ZISRAWFILE")?;
+        if header.len() < 68 {
+            return Err("Truncated CZI file header".into());
+        }
+        let directory_pos = le_i64(&header, 52) as u64;
+        let metadata_pos = le_i64(&header, 60) as u64;
+
+        let directory = expect_segment(&mut reader, directory_pos, "ZISRAWDIRECTORY")?;
+        let n_entries = le_i32(&directory, 0).max(0) as usize;
+        let mut entries = Vec::with_capacity(n_entries);
+        let mut at = 128;
+        for _ in 0..n_entries {
+            let (entry, len) = parse_entry(&directory, at)?;
+            at += len;
+            // Pyramid levels are downsampled copies of level-0 planes.
+            let downsampled = entry
+                .dims
+                .get("X")
+                .is_some_and(|&(_, size, stored)| size != stored);
+            if !entry.pyramid && !downsampled {
+                entries.push(entry);
+            }
+        }
+        if entries.is_empty() {
+            return Err("CZI file has no image planes".into());
+        }
+
+        // Coordinates start anywhere; index them from the smallest start of each axis.
+        let min_start = |dim: &str| {
+            entries
+                .iter()
+                .map(|e| e.dims.get(dim).map_or(0, |d| d.0))
+                .min()
+                .unwrap_or(0)
+        };
+        let origin = PLANE_DIMS.map(min_start);
+        let (width, height) = {
+            let first = &entries[0].dims;
+            let size = |dim: &str| first.get(dim).map_or(0, |d| d.1.max(0) as usize);
+            (size("X"), size("Y"))
+        };
+        let mut planes = HashMap::new();
+        let mut extent = [0usize; 4];
+        for entry in &entries {
+            if entry.pixel_type != PIXEL_GRAY16 {
+                return Err(format!(
+                    "CZI pixel type {} is not supported (only 16-bit grayscale)",
+                    entry.pixel_type
+                )
+                .into());
+            }
+            let size = |dim: &str| entry.dims.get(dim).map_or(0, |d| d.1.max(0) as usize);
+            if (size("X"), size("Y")) != (width, height) {
+                let msg = "CZI planes differ in size (mosaics are not supported)";
+                return Err(msg.into());
+            }
+            let mut key = [0usize; 4];
+            for (i, dim) in PLANE_DIMS.iter().enumerate() {
+                key[i] = (entry.dims.get(*dim).map_or(0, |d| d.0) - origin[i]) as usize;
+                extent[i] = extent[i].max(key[i] + 1);
+            }
+            let plane = Plane {
+                file_position: entry.file_position,
+                compression: entry.compression,
+            };
+            if planes.insert(key, plane).is_some() {
+                return Err(format!(
+                    "CZI has several tiles for scene {} t={} c={} z={} (mosaics are not supported)",
+                    key[0], key[1], key[2], key[3]
+                )
+                .into());
+            }
+        }
+
+        let xml = if metadata_pos > 0 {
+            let metadata = expect_segment(&mut reader, metadata_pos, "ZISRAWMETADATA")?;
+            let xml_size = le_i32(&metadata, 0).max(0) as usize;
+            let end = (256 + xml_size).min(metadata.len());
+            String::from_utf8_lossy(&metadata[256.min(end)..end]).into_owned()
+        } else {
+            String::new()
+        };
+
+        let sizes = [
+            ("P", extent[0]),
+            ("T", extent[1]),
+            ("C", extent[2]),
+            ("Z", extent[3]),
+            ("Y", height),
+            ("X", width),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        Ok(Self {
+            reader,
+            sizes,
+            planes,
+            xml,
+        })
+    }
+
+    /// Axis sizes keyed like ND2 sizes: P, T, C, Z, Y, X.
+    pub fn sizes(&self) -> &HashMap<String, usize> {
+        &self.sizes
+    }
+
+    /// Plane (scene `p`, t, c, z) as row-major u16 pixels.
+    pub fn read_frame_2d(
+        &mut self,
+        p: usize,
+        t: usize,
+        c: usize,
+        z: usize,
+    ) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
+        let plane = *self
+            .planes
+            .get(&[p, t, c, z])
+            .ok_or_else(|| format!("CZI has no plane for scene {p} t={t} c={c} z={z}"))?;
+        let segment = expect_segment(&mut self.reader, plane.file_position, "ZISRAWSUBBLOCK")?;
+        let metadata_size = le_i32(&segment, 0).max(0) as usize;
+        let data_size = le_i64(&segment, 8).max(0) as usize;
+        let (_, entry_len) = parse_entry(&segment, 16)?;
+        let start = (16 + entry_len).max(256) + metadata_size;
+        let data = segment
+            .get(start..start + data_size)
+            .ok_or("Truncated CZI subblock")?;
+
+        let n_px = self.sizes["X"] * self.sizes["Y"];
+        let pixels: Vec<u16> = match plane.compression {
+            COMPRESSION_NONE => data
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+            COMPRESSION_ZSTD0 => zstd::decode_all(data)?
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+            COMPRESSION_ZSTD1 => {
+                // 1-byte header size, then optional chunk type 1: bit 0 = low bytes of all
+                // pixels stored before the high bytes.
+                let header_len = *data.first().ok_or("Empty CZI subblock")? as usize;
+                let header = data.get(1..header_len).ok_or("Truncated CZI zstd header")?;
+                let hi_lo = header.first() == Some(&1) && header.get(1).is_some_and(|b| b & 1 == 1);
+                let raw = zstd::decode_all(&data[header_len..])?;
+                if hi_lo {
+                    let (lo, hi) = raw.split_at(raw.len() / 2);
+                    lo.iter()
+                        .zip(hi)
+                        .map(|(&l, &h)| u16::from_le_bytes([l, h]))
+                        .collect()
+                } else {
+                    raw.chunks_exact(2)
+                        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                        .collect()
+                }
+            }
+            other => {
+                return Err(format!(
+                    "CZI compression {other} is not supported (use uncompressed or zstd CZI files)"
+                )
+                .into())
+            }
+        };
+        if pixels.len() != n_px {
+            return Err(format!("CZI plane has {} pixels, expected {}", pixels.len(), n_px).into());
+        }
+        Ok(pixels)
+    }
+
+    /// Channel names from Information/Image/Dimensions/Channels, in channel order.
+    pub fn channel_names(&self) -> Vec<String> {
+        let Some(info) = self.xml.find("<Information>").map(|i| &self.xml[i..]) else {
+            return Vec::new();
+        };
+        let Some(channels) = info
+            .find("<Channels>")
+            .and_then(|i| info[i..].find("</Channels>").map(|j| &info[i..i + j]))
+        else {
+            return Vec::new();
+        };
+        let tag = Regex::new(r"<Channel\b[^>]*>").unwrap();
+        let name = Regex::new(r#"\bName="([^"]*)""#).unwrap();
+        tag.find_iter(channels)
+            .map(|m| {
+                name.captures(m.as_str())
+                    .map(|c| unescape_xml(c[1].trim()))
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Significant bits per pixel (ComponentBitCount), if recorded.
+    pub fn bit_depth(&self) -> Option<u32> {
+        let re = Regex::new(r"<ComponentBitCount>\s*(\d+)\s*</ComponentBitCount>").unwrap();
+        let bits: u32 = re.captures(&self.xml)?[1].parse().ok()?;
+        (bits > 0).then_some(bits)
+    }
+
+    /// Stage position (x, y) in µm of the `index`-th scene (Dimensions/S/Scenes, Scene
+    /// CenterPosition "x,y"), if recorded.
+    pub fn scene_position_um(&self, index: usize) -> Option<(f64, f64)> {
+        let scene = Regex::new(r"(?s)<Scene\b[^>]*?(?:/>|>.*?</Scene>)").unwrap();
+        let center =
+            Regex::new(r"<CenterPosition>\s*([^,<\s]+)\s*,\s*([^<\s]+)\s*</CenterPosition>")
+                .unwrap();
+        let found = center.captures(scene.find_iter(&self.xml).nth(index)?.as_str())?;
+        Some((found[1].parse().ok()?, found[2].parse().ok()?))
+    }
+
+    /// Pixel size in µm (Scaling/Items/Distance X, stored in metres), if recorded.
+    pub fn pixel_size_um(&self) -> Option<f64> {
+        let re = Regex::new(r#"<Distance Id="X">\s*<Value>\s*([^<\s]+)\s*</Value>"#).unwrap();
+        let metres: f64 = re.captures(&self.xml)?[1].parse().ok()?;
+        (metres > 0.0).then_some(metres * 1e6)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn segment(id: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; SEGMENT_HEADER];
+        out[..id.len()].copy_from_slice(id.as_bytes());
+        out[16..24].copy_from_slice(&(data.len() as i64).to_le_bytes());
+        out[24..32].copy_from_slice(&(data.len() as i64).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn entry(file_position: u64, dims: &[(&str, i32, i32)]) -> Vec<u8> {
+        let mut out = b"DV".to_vec();
+        out.extend_from_slice(&PIXEL_GRAY16.to_le_bytes());
+        out.extend_from_slice(&(file_position as i64).to_le_bytes());
+        out.extend_from_slice(&0i32.to_le_bytes());
+        out.extend_from_slice(&COMPRESSION_NONE.to_le_bytes());
+        out.extend_from_slice(&[0u8; 6]);
+        out.extend_from_slice(&(dims.len() as i32).to_le_bytes());
+        for &(name, start, size) in dims {
+            let mut dim = [0u8; 20];
+            dim[..name.len()].copy_from_slice(name.as_bytes());
+            dim[4..8].copy_from_slice(&start.to_le_bytes());
+            dim[8..12].copy_from_slice(&size.to_le_bytes());
+            dim[16..20].copy_from_slice(&size.to_le_bytes());
+            out.extend_from_slice(&dim);
+        }
+        out
+    }
+
+    #[test]
+    fn reads_scenes_channels_and_metadata() {
+        // Two scenes (starting at S=1) × two channels of 3×2 pixels.
+        let (w, h) = (3, 2);
+        let mut file = segment("ZISRAWFILE", &[0u8; 80]);
+        let mut entries = Vec::new();
+        for s in 1..3 {
+            for c in 0..2 {
+                let dims = [("X", 0, w), ("Y", 0, h), ("C", c, 1), ("S", s, 1)];
+                let pixels: Vec<u8> = (0..(w * h) as u16)
+                    .flat_map(|i| (i + 100 * s as u16 + 10 * c as u16).to_le_bytes())
+                    .collect();
+                let dv = entry(file.len() as u64, &dims);
+                let mut data = Vec::new();
+                data.extend_from_slice(&0i32.to_le_bytes());
+                data.extend_from_slice(&0i32.to_le_bytes());
+                data.extend_from_slice(&(pixels.len() as i64).to_le_bytes());
+                data.extend_from_slice(&dv);
+                data.resize(256, 0);
+                data.extend_from_slice(&pixels);
+                file.extend(segment("ZISRAWSUBBLOCK", &data));
+                entries.push(dv);
+            }
+        }
+        let directory_pos = file.len() as i64;
+        let mut directory = (entries.len() as i32).to_le_bytes().to_vec();
+        directory.resize(128, 0);
+        directory.extend(entries.concat());
+        file.extend(segment("ZISRAWDIRECTORY", &directory));
+        let metadata_pos = file.len() as i64;
+        let xml = r#"<ImageDocument><Metadata><Information><Image><ComponentBitCount>12</ComponentBitCount><Dimensions><Channels><Channel Id="Channel:0" Name="Phase"/><Channel Name="GFP &amp; RFP" Id="Channel:1"></Channel></Channels><S><Scenes><Scene Index="0"><CenterPosition>100.5,-20</CenterPosition></Scene><Scene Index="1"/></Scenes></S></Dimensions></Image></Information><Scaling><Items><Distance Id="X"><Value>6.5E-07</Value></Distance></Items></Scaling></Metadata></ImageDocument>"#;
+        let mut metadata = (xml.len() as i32).to_le_bytes().to_vec();
+        metadata.resize(256, 0);
+        metadata.extend_from_slice(xml.as_bytes());
+        file.extend(segment("ZISRAWMETADATA", &metadata));
+        file[SEGMENT_HEADER + 52..SEGMENT_HEADER + 60]
+            .copy_from_slice(&directory_pos.to_le_bytes());
+        file[SEGMENT_HEADER + 60..SEGMENT_HEADER + 68].copy_from_slice(&metadata_pos.to_le_bytes());
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&file).unwrap();
+        let mut czi = CziFile::open(tmp.path()).unwrap();
+        let sizes = czi.sizes();
+        assert_eq!(
+            (sizes["P"], sizes["T"], sizes["C"], sizes["Z"]),
+            (2, 1, 2, 1)
+        );
+        assert_eq!((sizes["Y"], sizes["X"]), (2, 3));
+        assert_eq!(
+            czi.read_frame_2d(1, 0, 1, 0).unwrap(),
+            [210, 211, 212, 213, 214, 215]
+        );
+        assert!(czi.read_frame_2d(2, 0, 0, 0).is_err());
+        assert_eq!(czi.channel_names(), ["Phase", "GFP & RFP"]);
+        assert_eq!(czi.bit_depth(), Some(12));
+        assert!((czi.pixel_size_um().unwrap() - 0.65).abs() < 1e-9);
+        assert_eq!(czi.scene_position_um(0), Some((100.5, -20.0)));
+        assert_eq!(czi.scene_position_um(1), None);
+    }
+}
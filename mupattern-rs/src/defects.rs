@@ -0,0 +1,302 @@
+//! Hot and dead camera pixels. `defects` averages every frame of a channel over time and
+//! flags pixels that stand out from their 3×3 neighbourhood by more than `--threshold`
+//! robust standard deviations; the map is a small CSV (`channel,x,y,kind,residual`).
+//! `crop --fix-defects` and `convert --fix-defects` replace the listed pixels by the
+//! median of their non-defective neighbours.
+
+use clap::Args;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::cancel;
+use crate::crop::{self, FrameData};
+use crate::output::{ColumnType, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::stats;
+
+const SCHEMA: Schema = Schema {
+    table: "defects",
+    version: 1,
+    columns: &[
+        ("channel", ColumnType::Integer),
+        ("x", ColumnType::Integer),
+        ("y", ColumnType::Integer),
+        ("kind", ColumnType::Text),
+        ("residual", ColumnType::Real),
+    ],
+};
+
+#[derive(Args, Clone)]
+pub struct DefectsArgs {
+    /// Root folder with Pos{N} TIFF folders (as for `crop`)
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Defect map CSV to write
+    #[arg(long)]
+    pub output: String,
+    /// Robust standard deviations from the neighbourhood median that make a pixel defective
+    #[arg(long, default_value_t = 8.0)]
+    pub threshold: f64,
+    /// Use at most this many frames per channel, evenly spaced (default: all)
+    #[arg(long)]
+    pub frames: Option<usize>,
+    /// Filename regex with named groups channel, pos, time and z (as for `crop --pattern`)
+    #[arg(long)]
+    pub pattern: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {
+    Hot,
+    Dead,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Hot => "hot",
+            Self::Dead => "dead",
+        }
+    }
+}
+
+/// Offsets of the 8 neighbours of a pixel.
+const NEIGHBOURS: [(i64, i64); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+fn neighbours(x: u32, y: u32, w: u32, h: u32) -> impl Iterator<Item = (u32, u32)> {
+    NEIGHBOURS.iter().filter_map(move |&(dy, dx)| {
+        let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+        (nx >= 0 && ny >= 0 && nx < w as i64 && ny < h as i64).then_some((nx as u32, ny as u32))
+    })
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let m = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[m]
+    } else {
+        (values[m - 1] + values[m]) / 2.0
+    }
+}
+
+/// Pixels of the temporal mean image `mean` (`w`×`h`) whose difference from the median of
+/// their neighbours exceeds `threshold` robust standard deviations of that difference.
+fn find_defects(mean: &[f64], w: u32, h: u32, threshold: f64) -> Vec<(u32, u32, Kind, f64)> {
+    let residual: Vec<f64> = (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let mut around: Vec<f64> = neighbours(x, y, w, h)
+                .map(|(nx, ny)| mean[(ny * w + nx) as usize])
+                .collect();
+            mean[(y * w + x) as usize] - median(&mut around)
+        })
+        .collect();
+    let center = median(&mut residual.clone());
+    let mut spread: Vec<f64> = residual.iter().map(|r| (r - center).abs()).collect();
+    // 1.4826 * MAD estimates the standard deviation; the floor keeps flat images sane.
+    let sigma = (1.4826 * median(&mut spread)).max(1.0);
+    residual
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &r)| {
+            let (x, y) = (i as u32 % w, i as u32 / w);
+            let z = (r - center) / sigma;
+            if z > threshold {
+                Some((x, y, Kind::Hot, r))
+            } else if z < -threshold {
+                Some((x, y, Kind::Dead, r))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Defective pixels per channel, read from a `defects` CSV.
+pub(crate) struct DefectMap {
+    by_channel: HashMap<u32, HashSet<(u32, u32)>>,
+}
+
+impl DefectMap {
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+        let col = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| format!("Defect map {} needs a {} column", path, name))
+        };
+        let (c_idx, x_idx, y_idx) = (col("channel")?, col("x")?, col("y")?);
+        let mut by_channel: HashMap<u32, HashSet<(u32, u32)>> = HashMap::new();
+        for record in rdr.records() {
+            let record = record?;
+            let field = |i: usize| record.get(i).unwrap_or("").parse::<u32>();
+            by_channel
+                .entry(field(c_idx)?)
+                .or_default()
+                .insert((field(x_idx)?, field(y_idx)?));
+        }
+        Ok(Self { by_channel })
+    }
+
+    /// Replace the defective pixels of channel `c` in `frame` (`w`×`h`) by the median of
+    /// their non-defective neighbours. Pixels outside the frame are ignored.
+    pub(crate) fn repair<T: Copy + Into<f64> + TryFrom<u64>>(
+        &self,
+        c: u32,
+        frame: &mut [T],
+        w: u32,
+        h: u32,
+    ) {
+        let Some(defects) = self.by_channel.get(&c) else {
+            return;
+        };
+        for &(x, y) in defects {
+            if x >= w || y >= h {
+                continue;
+            }
+            let mut around: Vec<f64> = neighbours(x, y, w, h)
+                .filter(|n| !defects.contains(n))
+                .map(|(nx, ny)| frame[(ny * w + nx) as usize].into())
+                .collect();
+            if around.is_empty() {
+                continue;
+            }
+            if let Ok(v) = T::try_from(median(&mut around).round() as u64) {
+                frame[(y * w + x) as usize] = v;
+            }
+        }
+    }
+
+    pub(crate) fn repair_frame(&self, c: u32, frame: &mut FrameData, w: u32, h: u32) {
+        match frame {
+            FrameData::U16(v) => self.repair(c, v, w, h),
+            FrameData::U8(v) => self.repair(c, v, w, h),
+        }
+    }
+}
+
+pub fn run(
+    args: DefectsArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pos_dir = Path::new(&args.input).join(format!("Pos{}", args.pos));
+    if !pos_dir.exists() {
+        return Err(format!("Position directory not found: {}", pos_dir.display()).into());
+    }
+    let index = crop::discover_tiffs(&pos_dir, args.pos, args.pattern.as_deref())?;
+    let mut by_channel: HashMap<u32, Vec<&Path>> = HashMap::new();
+    let mut keys: Vec<&(u32, u32, u32)> = index.keys().collect();
+    keys.sort();
+    for key in keys {
+        by_channel
+            .entry(key.0)
+            .or_default()
+            .push(index[key].as_path());
+    }
+    let mut channels: Vec<u32> = by_channel.keys().copied().collect();
+    channels.sort();
+    if channels.is_empty() {
+        return Err(format!("No TIFFs found in {}", pos_dir.display()).into());
+    }
+
+    let mut table = TableWriter::create(&args.output, &SCHEMA, args.pos)?;
+    stats::stage("analyze");
+    let frames_to_take = |paths: &[&Path]| args.frames.unwrap_or(paths.len()).clamp(1, paths.len());
+    let total: usize = channels
+        .iter()
+        .map(|c| frames_to_take(&by_channel[c]))
+        .sum();
+    let mut done = 0;
+    for &c in &channels {
+        let paths = &by_channel[&c];
+        let take = frames_to_take(paths);
+        let mut sum: Vec<f64> = Vec::new();
+        let (mut w, mut h) = (0, 0);
+        for k in 0..take {
+            let path = paths[k * paths.len() / take];
+            let (data, fw, fh) = crop::read_tiff_frame(path)?;
+            if sum.is_empty() {
+                (w, h) = (fw, fh);
+                sum = vec![0.0; (w * h) as usize];
+            } else if (fw, fh) != (w, h) {
+                return Err(
+                    format!("{} is {}x{}, expected {}x{}", path.display(), fw, fh, w, h).into(),
+                );
+            }
+            match data {
+                FrameData::U16(v) => sum.iter_mut().zip(v).for_each(|(s, p)| *s += p as f64),
+                FrameData::U8(v) => sum.iter_mut().zip(v).for_each(|(s, p)| *s += p as f64),
+            }
+            stats::add_frames(1);
+            cancel::check()?;
+            done += 1;
+            progress(ProgressEvent::new(
+                "analyze",
+                done as u64,
+                total as u64,
+                &format!("Channel {} frame {}/{}", c, k + 1, take),
+            ));
+        }
+        let mean: Vec<f64> = sum.iter().map(|s| s / take as f64).collect();
+        for (x, y, kind, residual) in find_defects(&mean, w, h, args.threshold) {
+            table.write_row(&[
+                Value::from(c),
+                x.into(),
+                y.into(),
+                kind.name().into(),
+                residual.into(),
+            ])?;
+        }
+    }
+    let n_rows = table.finish()?;
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} defective pixels to {}",
+        n_rows, args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outliers_are_found_and_repaired() {
+        let (w, h) = (6u32, 5u32);
+        // Gentle gradient with noise-like ripple, one hot and one dead pixel.
+        let mut mean: Vec<f64> = (0..w * h)
+            .map(|i| 100.0 + (i % w) as f64 * 2.0 + (i % 3) as f64)
+            .collect();
+        mean[(2 * w + 3) as usize] = 5000.0;
+        mean[(4 * w) as usize] = 0.0;
+        let found = find_defects(&mean, w, h, 8.0);
+        let kinds: Vec<(u32, u32, Kind)> = found.iter().map(|d| (d.0, d.1, d.2)).collect();
+        assert_eq!(kinds, vec![(3, 2, Kind::Hot), (0, 4, Kind::Dead)]);
+
+        let map = DefectMap {
+            by_channel: HashMap::from([(0, HashSet::from([(3, 2), (0, 4)]))]),
+        };
+        let mut frame: Vec<u16> = mean.iter().map(|&v| v as u16).collect();
+        map.repair(0, &mut frame, w, h);
+        assert!((105..=113).contains(&frame[(2 * w + 3) as usize]));
+        assert!((100..=105).contains(&frame[(4 * w) as usize]));
+        map.repair(1, &mut frame, w, h);
+    }
+}
@@ -0,0 +1,120 @@
+//! Detector range: camera bit depth and dark offset.
+//!
+//! `convert` writes it as `detector.json` next to the Pos* folders (bit depth from the ND2
+//! metadata unless `--bit-depth` is given); `crop` copies it (or values from flags) into
+//! the crops.zarr root attrs under "detector". `movie --contrast detector` and
+//! `kill --normalize detector` map [offset, 2^bits - 1] to the display / model range, so
+//! the mapping does not change with the content of each frame.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::zarr;
+
+pub const SIDECAR_NAME: &str = "detector.json";
+const ATTRS_KEY: &str = "detector";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DetectorRange {
+    /// Significant bits per pixel
+    pub bit_depth: Option<u32>,
+    /// Dark offset (black level) in counts
+    pub offset: Option<u16>,
+}
+
+impl DetectorRange {
+    pub fn is_empty(&self) -> bool {
+        self.bit_depth.is_none() && self.offset.is_none()
+    }
+
+    /// Values from `other` take precedence where set.
+    pub fn merged_with(self, other: DetectorRange) -> DetectorRange {
+        DetectorRange {
+            bit_depth: other.bit_depth.or(self.bit_depth),
+            offset: other.offset.or(self.offset),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.bit_depth {
+            Some(bits) if !(1..=16).contains(&bits) => {
+                Err(format!("Bit depth {} is not between 1 and 16", bits).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// (offset, full scale) in counts; None without a bit depth.
+    pub fn bounds(&self) -> Option<(u16, u16)> {
+        let bits = self.bit_depth?.clamp(1, 16);
+        let max = ((1u32 << bits) - 1) as u16;
+        Some((self.offset.unwrap_or(0).min(max - 1), max))
+    }
+
+    /// Like `bounds`, failing with a hint for `flag` when no bit depth is recorded.
+    pub fn require_bounds(&self, flag: &str) -> Result<(u16, u16), Box<dyn std::error::Error>> {
+        self.bounds().ok_or_else(|| {
+            format!(
+                "{} needs the detector bit depth in crops.zarr (convert from ND2, or crop --bit-depth)",
+                flag
+            )
+            .into()
+        })
+    }
+
+    /// Read `detector.json` from a convert output directory, if present.
+    pub fn read_sidecar(dir: &Path) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let path = dir.join(SIDECAR_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)?;
+        Ok(Some(serde_json::from_str(&text)?))
+    }
+
+    pub fn write_sidecar(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(dir.join(SIDECAR_NAME), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Read from the store root attrs; unknown if missing.
+    pub fn from_store(store: &zarr::Store) -> Self {
+        zarr::read_root_attrs(store)
+            .get(ATTRS_KEY)
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write_to_store(&self, store: &zarr::Store) -> Result<(), Box<dyn std::error::Error>> {
+        let mut attrs = serde_json::Map::new();
+        attrs.insert(ATTRS_KEY.to_string(), serde_json::to_value(self)?);
+        zarr::update_root_attrs(store, attrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_follow_bit_depth_and_offset() {
+        assert_eq!(DetectorRange::default().bounds(), None);
+        let camera = DetectorRange {
+            bit_depth: Some(12),
+            offset: Some(100),
+        };
+        assert_eq!(camera.bounds(), Some((100, 4095)));
+        let flags = DetectorRange {
+            bit_depth: Some(16),
+            offset: None,
+        };
+        assert_eq!(camera.merged_with(flags).bounds(), Some((100, 65535)));
+        assert!(DetectorRange {
+            bit_depth: Some(20),
+            offset: None
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Free-space check before long writes (`convert`, `crop`): the estimated output size is
+//! compared with the space available on the target filesystem so a run fails up front
+//! instead of hours in. `--force` turns the failure into a warning.
+
+use std::path::Path;
+
+use crate::console;
+use crate::memory;
+
+/// Bytes of a Zarr array of u16 `shape` with inner chunks of `chunk_shape`. Arrays are
+/// written uncompressed (sharding + bytes codec), so this is the pixel data plus the
+/// shard index (offset and length per inner chunk).
+pub fn zarr_u16_bytes(shape: &[u64], chunk_shape: &[u64]) -> u64 {
+    let n_chunks: u64 = shape
+        .iter()
+        .zip(chunk_shape)
+        .map(|(&n, &c)| n.div_ceil(c.max(1)))
+        .product();
+    shape.iter().product::<u64>() * 2 + n_chunks * 16
+}
+
+/// Fail if the filesystem holding `target` (or its nearest existing ancestor) has less
+/// than `needed` bytes available; with `force`, warn and continue instead.
+pub fn ensure_free_space(
+    target: &Path,
+    needed: u64,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let existing = target
+        .ancestors()
+        .find(|p| p.exists())
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let available = match fs4::available_space(existing) {
+        Ok(n) => n,
+        Err(e) => {
+            console::warn(&format!(
+                "cannot check free space on {}: {}",
+                existing.display(),
+                e
+            ));
+            return Ok(());
+        }
+    };
+    if available >= needed {
+        return Ok(());
+    }
+    let msg = format!(
+        "{} needs about {} but only {} is free",
+        target.display(),
+        memory::format_bytes(needed),
+        memory::format_bytes(available)
+    );
+    if force {
+        console::warn(&format!("{} (continuing because of --force)", msg));
+        Ok(())
+    } else {
+        Err(format!("{} (pass --force to write anyway)", msg).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zarr_estimate_counts_pixels_and_shard_index() {
+        // 10 frames of 4x5 in (1, 1, 1, 4, 5) chunks: 400 bytes of data, 10 index entries.
+        assert_eq!(
+            zarr_u16_bytes(&[10, 1, 1, 4, 5], &[1, 1, 1, 4, 5]),
+            400 + 160
+        );
+        assert_eq!(zarr_u16_bytes(&[3, 2, 1], &[2, 1, 1]), 12 + 4 * 16);
+    }
+}
@@ -0,0 +1,142 @@
+//! Exclusion lists (`--exclude <csv>`): crops and frames flagged by QC that analysis
+//! commands skip.
+//!
+//! CSV columns: `pos`, `crop`, `t`. An empty cell matches everything, so a row with only
+//! `pos,crop` drops the whole crop. `t` takes slice syntax ("5", "10:20", "0:100:2");
+//! frames past the end of a crop are ignored, so one rule can cover crops of any length.
+//! Crop IDs match numerically when both sides are integers ("7" == "007").
+
+use std::collections::HashSet;
+
+use crate::crop_index;
+use crate::slices;
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pos: Option<u32>,
+    crop: Option<String>,
+    t: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExclusionList {
+    rules: Vec<Rule>,
+}
+
+fn non_empty(field: Option<&str>) -> Option<String> {
+    field
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(String::from)
+}
+
+impl ExclusionList {
+    /// Load from `path`; an absent path gives an empty list.
+    pub fn load(path: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let headers: Vec<String> = reader.headers()?.iter().map(|h| h.to_lowercase()).collect();
+        let col = |name: &str| headers.iter().position(|h| h == name);
+        let (pos_idx, crop_idx, t_idx) = (col("pos"), col("crop"), col("t"));
+        if pos_idx.is_none() && crop_idx.is_none() && t_idx.is_none() {
+            return Err(format!("Exclusion list {} needs pos, crop or t columns", path).into());
+        }
+
+        let mut rules = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            let field = |idx: Option<usize>| non_empty(idx.and_then(|i| record.get(i)));
+            let pos = match field(pos_idx) {
+                Some(p) => Some(
+                    p.parse()
+                        .map_err(|_| format!("Invalid pos {:?} in {}", p, path))?,
+                ),
+                None => None,
+            };
+            rules.push(Rule {
+                pos,
+                crop: field(crop_idx),
+                t: field(t_idx),
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    fn matching<'a>(&'a self, pos: u32, crop: &'a str) -> impl Iterator<Item = &'a Rule> + 'a {
+        self.rules.iter().filter(move |r| {
+            r.pos.iter().all(|&p| p == pos) && r.crop.iter().all(|c| crop_index::same_crop(c, crop))
+        })
+    }
+
+    /// True if every frame of the crop is excluded.
+    pub fn crop_excluded(&self, pos: u32, crop: &str) -> bool {
+        self.matching(pos, crop).any(|r| r.t.is_none())
+    }
+
+    /// Frame indices of the crop to skip, given its number of frames.
+    pub fn excluded_frames(
+        &self,
+        pos: u32,
+        crop: &str,
+        n_t: u64,
+    ) -> Result<HashSet<u64>, Box<dyn std::error::Error>> {
+        let mut out = HashSet::new();
+        for rule in self.matching(pos, crop) {
+            match &rule.t {
+                None => return Ok((0..n_t).collect()),
+                Some(t) => out.extend(
+                    slices::parse_slice_string_within(t, n_t as usize)?
+                        .into_iter()
+                        .map(|i| i as u64),
+                ),
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn rules_match_by_pos_crop_and_time() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("exclude.csv");
+        fs::write(&path, "pos,crop,t\n1,7,\n1,,0:2\n,003,5\n")?;
+        let list = ExclusionList::load(path.to_str())?;
+
+        assert!(list.crop_excluded(1, "007"));
+        assert!(!list.crop_excluded(2, "007"));
+        assert!(!list.crop_excluded(1, "003"));
+
+        let mut frames: Vec<u64> = list.excluded_frames(1, "003", 10)?.into_iter().collect();
+        frames.sort_unstable();
+        assert_eq!(frames, vec![0, 1, 5]);
+        assert_eq!(list.excluded_frames(2, "003", 10)?.len(), 1);
+        assert!(list.excluded_frames(2, "004", 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn frames_past_the_crop_end_are_ignored() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("exclude.csv");
+        fs::write(&path, "pos,crop,t\n0,,\"2, 8-12, 20\"\n")?;
+        let list = ExclusionList::load(path.to_str())?;
+
+        let mut frames: Vec<u64> = list.excluded_frames(0, "001", 10)?.into_iter().collect();
+        frames.sort_unstable();
+        assert_eq!(frames, vec![2, 8, 9]);
+        assert!(list.excluded_frames(0, "002", 2)?.is_empty());
+        Ok(())
+    }
+}
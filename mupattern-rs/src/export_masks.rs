@@ -0,0 +1,136 @@
+//! Export masks.zarr label frames as one image per frame (`{output}/{crop}/{t:04}.tif` or
+//! `.png`) for hand correction in napari or labelme; `import-masks --merge` reads the same
+//! layout back and rewrites only the frames that changed.
+
+use clap::Args;
+use std::fs;
+use std::path::Path;
+
+use crate::cancel;
+use crate::crop_index;
+use crate::exclude::ExclusionList;
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::zarr;
+
+#[derive(Args, Clone)]
+pub struct ExportMasksArgs {
+    /// Path to masks.zarr
+    #[arg(long)]
+    pub masks: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Folder to write {crop}/{t:04}.{format} label images into
+    #[arg(long)]
+    pub output: String,
+    /// tif (32-bit labels) | png (16-bit, for labelme; fails on labels above 65535)
+    #[arg(long, default_value = "tif")]
+    pub format: String,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Format {
+    Tif,
+    Png,
+}
+
+impl Format {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "tif" | "tiff" => Ok(Self::Tif),
+            "png" => Ok(Self::Png),
+            _ => Err(format!("Unknown format {s:?}. Use tif or png.").into()),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Tif => "tif",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// Write one `w`×`h` label frame to `path`.
+pub(crate) fn write_frame(
+    path: &Path,
+    format: Format,
+    labels: Vec<u32>,
+    w: u32,
+    h: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        Format::Tif => {
+            let mut encoder = tiff::encoder::TiffEncoder::new(fs::File::create(path)?)?;
+            encoder.write_image::<tiff::encoder::colortype::Gray32>(w, h, &labels)?;
+            stats::add_bytes_written(labels.len() as u64 * 4);
+        }
+        Format::Png => {
+            let narrowed: Vec<u16> = labels
+                .into_iter()
+                .map(u16::try_from)
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("{}: labels above 65535; use --format tif", path.display()))?;
+            stats::add_bytes_written(narrowed.len() as u64 * 2);
+            image::ImageBuffer::<image::Luma<u16>, _>::from_raw(w, h, narrowed)
+                .ok_or("label frame size mismatch")?
+                .save(path)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(
+    args: ExportMasksArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = Format::parse(&args.format)?;
+    let masks_zarr = Path::new(&args.masks);
+    let pos_id = format!("{:03}", args.pos);
+    let crop_root = masks_zarr.join("pos").join(&pos_id).join("crop");
+    if !crop_root.exists() {
+        return Err(format!("No masks for position {} in {}", args.pos, args.masks).into());
+    }
+
+    let mut crop_ids = crop_index::list(masks_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
+
+    let store = zarr::open_store(masks_zarr)?;
+    stats::stage("write");
+    let total = crop_ids.len();
+    let mut written = 0u64;
+
+    for (i, crop_id) in crop_ids.iter().enumerate() {
+        let arr = zarr::open_array(&store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
+        let shape = arr.shape();
+        let (n_t, h, w) = (shape[0], shape[1] as u32, shape[2] as u32);
+        let skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+        let dir = Path::new(&args.output).join(crop_id);
+        fs::create_dir_all(&dir)?;
+
+        for t in (0..n_t).filter(|t| !skip.contains(t)) {
+            let path = dir.join(format!("{:04}.{}", t, format.extension()));
+            write_frame(&path, format, zarr::read_labels(&arr, t)?, w, h)?;
+            written += 1;
+            stats::add_frames(1);
+        }
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "write",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Exported crop {}/{}", i + 1, total),
+        ));
+    }
+
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} label images to {}",
+        written, args.output
+    )));
+    Ok(())
+}
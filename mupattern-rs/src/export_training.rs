@@ -0,0 +1,254 @@
+//! Kill classifier training data: labelled crop frames rendered exactly as `kill` feeds them
+//! to the model (same normalization, resize filter and size) and written as PNGs in
+//! `absent/` and `present/` class folders, or as a webdataset tar (`{key}.png` +
+//! `{key}.cls`) when `--output` ends in `.tar`.
+
+use clap::Args;
+use image::GrayImage;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::cancel;
+use crate::detector::DetectorRange;
+use crate::kill::{self, Normalize};
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::zarr;
+
+const CLASSES: [&str; 2] = ["absent", "present"];
+
+#[derive(Args, Clone)]
+pub struct ExportTrainingArgs {
+    /// Path to crops.zarr
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Annotations CSV (t,crop,label with label true/false or 1/0, empty = not yet
+    /// annotated; a pos column, if present, selects rows of --pos), e.g. kill output or
+    /// a filled-in `select-uncertain` sheet
+    #[arg(long)]
+    pub labels: String,
+    /// Output folder with absent/ and present/ subfolders, or a webdataset .tar
+    #[arg(long)]
+    pub output: String,
+    /// Edge length of the square images (the model input size)
+    #[arg(long, default_value_t = kill::DEFAULT_IMAGE_SIZE)]
+    pub size: u32,
+    /// Per-frame intensity normalization, as for `kill --normalize`
+    #[arg(long, default_value = "minmax")]
+    pub normalize: String,
+    /// Resize filter, as for `kill --resize-filter`
+    #[arg(long, default_value = "triangle")]
+    pub resize_filter: String,
+}
+
+/// Labelled frames per crop: t -> present.
+pub(crate) fn load_labels(
+    path: &str,
+    pos: u32,
+) -> Result<BTreeMap<String, BTreeMap<u64, bool>>, Box<dyn std::error::Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let col = |name: &str| headers.iter().position(|h| h == name);
+    let need =
+        |name: &str| col(name).ok_or_else(|| format!("Labels {} need a {} column", path, name));
+    let (t_idx, crop_idx, label_idx) = (need("t")?, need("crop")?, need("label")?);
+    let pos_idx = col("pos");
+
+    let mut labels: BTreeMap<String, BTreeMap<u64, bool>> = BTreeMap::new();
+    for record in rdr.records() {
+        let record = record?;
+        let field = |i: usize| record.get(i).unwrap_or("");
+        if pos_idx.is_some_and(|i| field(i).parse::<u32>().ok() != Some(pos)) {
+            continue;
+        }
+        let present = match field(label_idx).to_lowercase().as_str() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            "" => continue,
+            other => return Err(format!("Unknown label {:?} in {}", other, path).into()),
+        };
+        labels
+            .entry(field(crop_idx).to_string())
+            .or_default()
+            .insert(field(t_idx).parse()?, present);
+    }
+    Ok(labels)
+}
+
+/// File stem of one crop-frame sample, shared with `select-uncertain`.
+pub(crate) fn sample_key(pos_id: &str, crop_id: &str, t: u64) -> String {
+    format!("pos{}_crop{}_t{:04}", pos_id, crop_id, t)
+}
+
+fn png_bytes(img: &GrayImage) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, image::ImageFormat::Png)?;
+    Ok(buf.into_inner())
+}
+
+/// Minimal ustar writer for webdataset shards (regular files only, names < 100 bytes).
+struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        if name.len() >= 100 {
+            return Err(format!("tar entry name too long: {name}").into());
+        }
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let octal = |field: &mut [u8], value: u64| {
+            let text = format!("{:0width$o}\0", value, width = field.len() - 1);
+            field.copy_from_slice(text.as_bytes());
+        };
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], 0);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // The checksum is computed with its own field filled with spaces.
+        header[148..156].fill(b' ');
+        let sum: u64 = header.iter().map(|&b| b as u64).sum();
+        let text = format!("{:06o}\0 ", sum);
+        header[148..156].copy_from_slice(text.as_bytes());
+
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+        let pad = (512 - data.len() % 512) % 512;
+        self.out.write_all(&vec![0u8; pad])?;
+        stats::add_bytes_written((512 + data.len() + pad) as u64);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.out.write_all(&[0u8; 1024])?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+pub fn run(
+    args: ExportTrainingArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = kill::parse_filter(&args.resize_filter)?;
+    let labels = load_labels(&args.labels, args.pos)?;
+    if labels.is_empty() {
+        return Err(format!("No labels for position {} in {}", args.pos, args.labels).into());
+    }
+
+    let crops_zarr = Path::new(&args.input);
+    let pos_id = format!("{:03}", args.pos);
+    let store = zarr::open_store(crops_zarr)?;
+    let normalize = Normalize::parse(&args.normalize, DetectorRange::from_store(&store))?;
+    let out = Path::new(&args.output);
+    let mut tar = if args.output.ends_with(".tar") {
+        fs::create_dir_all(out.parent().unwrap_or(Path::new(".")))?;
+        Some(TarWriter {
+            out: std::io::BufWriter::new(fs::File::create(out)?),
+        })
+    } else {
+        for class in CLASSES {
+            fs::create_dir_all(out.join(class))?;
+        }
+        None
+    };
+
+    stats::stage("write");
+    let total = labels.len();
+    let mut counts = [0u64; 2];
+    for (i, (crop_id, frames)) in labels.iter().enumerate() {
+        let arr = zarr::open_array(&store, &format!("/pos/{}/crop/{}", pos_id, crop_id))
+            .map_err(|e| format!("Labelled crop {} not in {}: {}", crop_id, args.input, e))?;
+        let shape = arr.shape();
+        let (n_t, h, w) = (shape[0], shape[3] as u32, shape[4] as u32);
+        for (&t, &present) in frames {
+            if t >= n_t {
+                return Err(format!(
+                    "Label for crop {} t={} but the crop has {} frames",
+                    crop_id, t, n_t
+                )
+                .into());
+            }
+            let data = zarr::read_frame_u16(&arr, &[t, 0, 0])?;
+            let img = kill::model_image(&data, w, h, normalize, (args.size, args.size), filter);
+            let key = sample_key(&pos_id, crop_id, t);
+            let class = present as usize;
+            match &mut tar {
+                Some(tar) => {
+                    tar.append(&format!("{key}.png"), &png_bytes(&img)?)?;
+                    tar.append(&format!("{key}.cls"), class.to_string().as_bytes())?;
+                }
+                None => {
+                    let path = out.join(CLASSES[class]).join(format!("{key}.png"));
+                    img.save(&path)?;
+                    stats::add_bytes_written(fs::metadata(&path)?.len());
+                }
+            }
+            counts[class] += 1;
+            stats::add_frames(1);
+        }
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "write",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Exporting crop {}/{}", i + 1, total),
+        ));
+    }
+    if let Some(tar) = tar {
+        tar.finish()?;
+    }
+
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} absent and {} present images to {}",
+        counts[0], counts[1], args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tar_entries_are_valid_ustar_blocks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tar = TarWriter { out: Vec::new() };
+        tar.append("a.cls", b"1")?;
+        tar.append("a.png", &[7u8; 600])?;
+        let TarWriter { out } = tar;
+        assert_eq!(out.len(), 512 + 512 + 512 + 1024);
+
+        let header = &out[..512];
+        assert_eq!(&header[..5], b"a.cls");
+        assert_eq!(&header[124..136], b"00000000001\0");
+        assert_eq!(&header[257..262], b"ustar");
+        let stored = u64::from_str_radix(std::str::from_utf8(&header[148..154])?, 8)?;
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    b as u64
+                }
+            })
+            .sum();
+        assert_eq!(stored, sum);
+        assert_eq!(out[512], b'1');
+        Ok(())
+    }
+}
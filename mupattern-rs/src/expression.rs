@@ -1,53 +1,423 @@
 use clap::Args;
-use std::fs;
+use std::collections::HashMap;
 use std::path::Path;
 
+use crate::calibration::PixelCalibration;
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::crop::{self, FrameData};
+use crate::crop_index;
+use crate::exclude::ExclusionList;
+use crate::output::{ColumnType, OutputFormat, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::slices;
+use crate::stats;
 use crate::zarr;
 
+const SCHEMA: Schema = Schema {
+    table: "expression",
+    version: 2,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("intensity", ColumnType::Integer),
+        ("area", ColumnType::Integer),
+        ("background", ColumnType::Integer),
+        ("saturated_px", ColumnType::Integer),
+    ],
+};
+
+/// `SCHEMA` plus `intensity - background * area` (`--subtract-background`).
+const CORRECTED_SCHEMA: Schema = Schema {
+    table: "expression_corrected",
+    version: 2,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("intensity", ColumnType::Integer),
+        ("area", ColumnType::Integer),
+        ("background", ColumnType::Integer),
+        ("intensity_corrected", ColumnType::Real),
+        ("saturated_px", ColumnType::Integer),
+    ],
+};
+
+/// `SCHEMA` plus intensity and area on and off the adhesive pattern (`--pattern-mask` or
+/// `--pattern-channel`).
+const PATTERN_SCHEMA: Schema = Schema {
+    table: "expression_pattern",
+    version: 2,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("intensity", ColumnType::Integer),
+        ("area", ColumnType::Integer),
+        ("background", ColumnType::Integer),
+        ("intensity_on", ColumnType::Integer),
+        ("area_on", ColumnType::Integer),
+        ("intensity_off", ColumnType::Integer),
+        ("area_off", ColumnType::Integer),
+        ("saturated_px", ColumnType::Integer),
+    ],
+};
+
+/// `CORRECTED_SCHEMA` plus the on/off-pattern columns.
+const CORRECTED_PATTERN_SCHEMA: Schema = Schema {
+    table: "expression_corrected_pattern",
+    version: 2,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("intensity", ColumnType::Integer),
+        ("area", ColumnType::Integer),
+        ("background", ColumnType::Integer),
+        ("intensity_corrected", ColumnType::Real),
+        ("intensity_on", ColumnType::Integer),
+        ("area_on", ColumnType::Integer),
+        ("intensity_off", ColumnType::Integer),
+        ("area_off", ColumnType::Integer),
+        ("saturated_px", ColumnType::Integer),
+    ],
+};
+
+/// `--whole-frame`: one row per frame and channel for the full field of view.
+const WHOLE_FRAME_SCHEMA: Schema = Schema {
+    table: "whole_frame",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("channel", ColumnType::Integer),
+        ("mean", ColumnType::Real),
+        ("total", ColumnType::Integer),
+        ("source", ColumnType::Text),
+    ],
+};
+
+/// Where the on-pattern pixels of a crop come from.
+enum PatternSource {
+    /// Full-frame mask image (nonzero = pattern), cut to each crop's bbox.
+    Mask {
+        mask: Vec<bool>,
+        width: u32,
+        height: u32,
+    },
+    /// Pixels of this channel above the threshold, per frame.
+    Channel { channel: u64, threshold: u16 },
+}
+
+impl PatternSource {
+    fn from_args(
+        args: &ExpressionArgs,
+        names: &ChannelNames,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        match (
+            &args.pattern_mask,
+            &args.pattern_channel,
+            args.pattern_threshold,
+        ) {
+            (None, None, None) => Ok(None),
+            (Some(path), None, None) => {
+                let img = image::open(path)
+                    .map_err(|e| format!("Cannot read pattern mask {}: {}", path, e))?
+                    .into_luma16();
+                let (width, height) = img.dimensions();
+                let mask = img.pixels().map(|p| p.0[0] > 0).collect();
+                Ok(Some(Self::Mask {
+                    mask,
+                    width,
+                    height,
+                }))
+            }
+            (None, Some(channel), Some(threshold)) => Ok(Some(Self::Channel {
+                channel: names.resolve(channel)? as u64,
+                threshold,
+            })),
+            (None, Some(_), None) | (None, None, Some(_)) => {
+                Err("--pattern-channel and --pattern-threshold go together".into())
+            }
+            _ => Err("Use either --pattern-mask or --pattern-channel, not both".into()),
+        }
+    }
+
+    /// Crop-sized mask from a full-frame mask, using the `bbox` attribute written by crop.
+    fn crop_mask(
+        &self,
+        arr: &zarr::StoreArray,
+    ) -> Result<Option<Vec<bool>>, Box<dyn std::error::Error>> {
+        if !matches!(self, Self::Mask { .. }) {
+            return Ok(None);
+        }
+        let bbox = arr
+            .attributes()
+            .get("bbox")
+            .ok_or("Crop has no bbox attribute")?;
+        let field = |k: &str| {
+            bbox.get(k)
+                .and_then(|v| v.as_u64())
+                .ok_or("Malformed bbox attribute")
+        };
+        let (x, y, w, h) = (field("x")?, field("y")?, field("w")?, field("h")?);
+        self.mask_in(x, y, w, h)
+    }
+
+    /// The full-frame mask cut to the box at (`x`, `y`) of size `w`×`h`.
+    fn mask_in(
+        &self,
+        x: u64,
+        y: u64,
+        w: u64,
+        h: u64,
+    ) -> Result<Option<Vec<bool>>, Box<dyn std::error::Error>> {
+        let Self::Mask {
+            mask,
+            width,
+            height,
+        } = self
+        else {
+            return Ok(None);
+        };
+        if x + w > *width as u64 || y + h > *height as u64 {
+            return Err(format!("Crop bbox exceeds the {}x{} pattern mask", width, height).into());
+        }
+        let mut out = Vec::with_capacity((w * h) as usize);
+        for row in y..y + h {
+            let start = (row * *width as u64 + x) as usize;
+            out.extend_from_slice(&mask[start..start + w as usize]);
+        }
+        Ok(Some(out))
+    }
+}
+
+/// (intensity_on, area_on, intensity_off, area_off) of `data` split by `on_pattern`.
+fn split_by_pattern(data: &[u16], on_pattern: impl Iterator<Item = bool>) -> [u64; 4] {
+    let mut sums = [0u64; 4];
+    for (&v, on) in data.iter().zip(on_pattern) {
+        let i = if on { 0 } else { 2 };
+        sums[i] += v as u64;
+        sums[i + 1] += 1;
+    }
+    sums
+}
+
+/// One table row for the quantified pixels `data` of a crop at frame `t`, in the column
+/// order of the schema `args` select.
+fn expression_row(
+    args: &ExpressionArgs,
+    t: u64,
+    crop_id: &str,
+    data: &[u16],
+    background: u16,
+    split: Option<[u64; 4]>,
+    saturated: Option<u64>,
+) -> Vec<Value> {
+    let intensity: u64 = data.iter().map(|&v| v as u64).sum();
+    let area = data.len() as u64;
+    let mut row: Vec<Value> = vec![
+        t.into(),
+        crop_id.into(),
+        intensity.into(),
+        area.into(),
+        background.into(),
+    ];
+    if args.subtract_background {
+        row.push((intensity as f64 - background as f64 * area as f64).into());
+    }
+    if let Some(split) = split {
+        row.extend(split.map(Value::from));
+    }
+    row.push(saturated.into());
+    row
+}
+
+fn determinant(m: &[Vec<f64>]) -> f64 {
+    match m.len() {
+        2 => m[0][0] * m[1][1] - m[0][1] * m[1][0],
+        3 => {
+            m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+        }
+        _ => f64::NAN,
+    }
+}
+
+/// Cross-talk correction: the quantified channel is replaced, pixel by pixel, by a linear
+/// combination of the measured channels (one row of the unmixing matrix).
+struct Unmixing {
+    channels: Vec<u64>,
+    weights: Vec<f64>,
+}
+
+impl Unmixing {
+    /// `matrix` is row-major with rows separated by ';' (e.g. "1,-0.15;0,1"); rows and
+    /// columns follow `channels` (indices or names), and the row of `channel` is kept.
+    fn parse(
+        matrix: &str,
+        channels: &str,
+        channel: u32,
+        names: &ChannelNames,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let channels: Vec<u64> = names
+            .resolve_list(channels)
+            .map_err(|e| format!("Invalid --unmix-channels '{}': {}", channels, e))?
+            .into_iter()
+            .map(u64::from)
+            .collect();
+        let rows: Vec<Vec<f64>> = matrix
+            .split(';')
+            .map(|row| row.split(',').map(|v| v.trim().parse::<f64>()).collect())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid --unmix matrix '{}': {}", matrix, e))?;
+        let n = channels.len();
+        if !(2..=3).contains(&n) {
+            return Err(format!("--unmix-channels must list 2 or 3 channels ({n} given)").into());
+        }
+        if rows.len() != n || rows.iter().any(|r| r.len() != n) {
+            return Err(
+                format!("--unmix must be a {n}x{n} matrix matching --unmix-channels").into(),
+            );
+        }
+        if determinant(&rows).abs() < 1e-9 {
+            return Err(format!("--unmix matrix '{}' is singular", matrix).into());
+        }
+        let k = channels
+            .iter()
+            .position(|&c| c == channel as u64)
+            .ok_or("--channel must be one of --unmix-channels")?;
+        Ok(Self {
+            channels,
+            weights: rows[k].clone(),
+        })
+    }
+
+    /// Unmixed values from one value per channel, rounded and clamped to u16.
+    fn apply(&self, per_channel: &[&[u16]]) -> Vec<u16> {
+        (0..per_channel[0].len())
+            .map(|i| {
+                let v: f64 = self
+                    .weights
+                    .iter()
+                    .zip(per_channel)
+                    .map(|(w, data)| w * data[i] as f64)
+                    .sum();
+                v.round().clamp(0.0, u16::MAX as f64) as u16
+            })
+            .collect()
+    }
+}
+
 #[derive(Args, Clone)]
 pub struct ExpressionArgs {
+    /// Path to crops.zarr, or with --bbox the root folder with Pos{N} TIFF folders
     #[arg(long)]
     pub input: String,
     #[arg(long)]
     pub pos: u32,
+    /// Channel to quantify: index or name (see `crop --channel-names`)
     #[arg(long)]
-    pub channel: u32,
+    pub channel: String,
+    /// Output path: CSV, SQLite when ending in .sqlite/.db, or Arrow IPC stream when ending in .arrows
     #[arg(long)]
     pub output: String,
+    /// Table backend: auto (from the output extension) | csv | sqlite | arrow-stream (record
+    /// batches appended as results come in, readable while the run continues)
+    #[arg(long, default_value = "auto")]
+    pub output_format: String,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+    /// Frames to analyze: "all" or comma-separated indices/slices, e.g. "0:100", "every:5";
+    /// the others are skipped like --exclude frames
+    #[arg(long)]
+    pub time: String,
+    /// Add intensity_corrected = intensity - background * area
+    #[arg(long)]
+    pub subtract_background: bool,
+    /// Take the background from the coarse background image (`crop --background-image`),
+    /// interpolated at each crop's center, instead of the position-wide median
+    #[arg(long)]
+    pub local_background: bool,
+    /// Full-frame pattern mask image (nonzero = adhesive pattern); adds
+    /// intensity_on, area_on, intensity_off, area_off
+    #[arg(long)]
+    pub pattern_mask: Option<String>,
+    /// Channel showing the pattern; pixels above --pattern-threshold count as on-pattern
+    #[arg(long)]
+    pub pattern_channel: Option<String>,
+    #[arg(long)]
+    pub pattern_threshold: Option<u16>,
+    /// 2x2 or 3x3 unmixing matrix applied to the frames (and backgrounds) of --unmix-channels
+    /// before quantification, row-major with rows separated by ';', e.g. "1,-0.15;0,1".
+    /// Negative results are clamped to 0.
+    #[arg(long, requires = "unmix_channels")]
+    pub unmix: Option<String>,
+    /// Channels the --unmix rows and columns refer to, e.g. "1,2" or "GFP,mCherry"; must
+    /// include --channel
+    #[arg(long, requires = "unmix")]
+    pub unmix_channels: Option<String>,
+    /// Bbox CSV as for `crop`: quantify straight from the TIFFs under --input, cutting the
+    /// boxes out of each frame in memory (no crops.zarr needed). The background column is
+    /// the median outside all boxes, as `crop --background` stores it.
+    #[arg(long)]
+    pub bbox: Option<String>,
+    /// Instead of per-crop rows, report the mean and total intensity of the whole field per
+    /// frame and channel (illumination stability, global bleaching). Reads the TIFFs when
+    /// --input holds a Pos{N} folder; from crops.zarr only the stored background is left,
+    /// so mean is that of the --background-image grid (or the background median) and total
+    /// is empty
+    #[arg(
+        long,
+        conflicts_with_all = ["bbox", "pattern_mask", "pattern_channel", "unmix", "subtract_background", "local_background"]
+    )]
+    pub whole_frame: bool,
 }
 
 pub fn run(
     args: ExpressionArgs,
-    progress: impl Fn(f64, &str),
+    progress: impl Fn(ProgressEvent),
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if args.whole_frame {
+        return run_whole_frame(&args, &progress);
+    }
+    let names = ChannelNames::load(Path::new(&args.input))?;
+    let channel = names.resolve(&args.channel)?;
+    let format = OutputFormat::parse(&args.output_format)?;
+    let pattern = PatternSource::from_args(&args, &names)?;
+    let unmixing = match (&args.unmix, &args.unmix_channels) {
+        (Some(matrix), Some(channels)) => Some(Unmixing::parse(matrix, channels, channel, &names)?),
+        _ => None,
+    };
+    let channels = match &unmixing {
+        Some(u) => u.channels.clone(),
+        None => vec![channel as u64],
+    };
+    let schema = match (args.subtract_background, pattern.is_some()) {
+        (false, false) => &SCHEMA,
+        (true, false) => &CORRECTED_SCHEMA,
+        (false, true) => &PATTERN_SCHEMA,
+        (true, true) => &CORRECTED_PATTERN_SCHEMA,
+    };
+    if let Some(bbox) = &args.bbox {
+        return run_tiffs(
+            &args,
+            bbox,
+            schema,
+            &channels,
+            unmixing.as_ref(),
+            pattern.as_ref(),
+            &progress,
+        );
+    }
     let crops_zarr = Path::new(&args.input);
     let pos_id = format!("{:03}", args.pos);
-    let crop_root = crops_zarr.join("pos").join(&pos_id).join("crop");
-
-    if !crop_root.exists() {
-        if !args.output.is_empty() {
-            fs::create_dir_all(Path::new(&args.output).parent().unwrap_or(Path::new(".")))?;
-            fs::write(&args.output, "t,crop,intensity,area,background\n")?;
-        }
-        return Ok(());
-    }
 
-    let mut crop_ids: Vec<String> = fs::read_dir(&crop_root)?
-        .filter_map(|e| {
-            let e = e.ok()?;
-            if e.file_type().ok()?.is_dir() {
-                e.file_name().to_str().map(String::from)
-            } else {
-                None
-            }
-        })
-        .collect();
-    crop_ids.sort();
+    let mut crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
 
     if crop_ids.is_empty() {
         if !args.output.is_empty() {
-            fs::create_dir_all(Path::new(&args.output).parent().unwrap_or(Path::new(".")))?;
-            fs::write(&args.output, "t,crop,intensity,area,background\n")?;
+            TableWriter::create_as(&args.output, schema, args.pos, format)?.finish()?;
         }
         return Ok(());
     }
@@ -55,57 +425,737 @@ pub fn run(
     let store = zarr::open_store(&crops_zarr)?;
 
     let bg_path = format!("/pos/{}/background", pos_id);
-    let mut backgrounds: Vec<u16> = Vec::new();
+    let mut per_channel_backgrounds: Vec<Vec<u16>> = Vec::new();
     if let Ok(bg_arr) = zarr::open_array(&store, &bg_path) {
         let shape = bg_arr.shape();
-        if shape.len() >= 2 && args.channel < shape[1] as u32 {
+        if shape.len() >= 2 && channels.iter().all(|&c| c < shape[1]) {
             let n_t = shape[0];
-            for t in 0..n_t {
-                let chunk_indices = vec![t, args.channel as u64, 0];
-                backgrounds.push(
-                    zarr::read_chunk_u16(&bg_arr, &chunk_indices)
-                        .ok()
-                        .and_then(|d| d.first().copied())
-                        .unwrap_or(0),
-                );
+            for &c in &channels {
+                let mut backgrounds = Vec::new();
+                for t in 0..n_t {
+                    let frame_indices = [t, c, 0];
+                    backgrounds.push(
+                        zarr::read_frame_u16(&bg_arr, &frame_indices)
+                            .ok()
+                            .and_then(|d| d.first().copied())
+                            .unwrap_or(0),
+                    );
+                }
+                per_channel_backgrounds.push(backgrounds);
             }
         }
     }
+    // One background series per channel in `channels` -> the quantified channel's series.
+    let combine = |mut per_channel: Vec<Vec<u16>>| -> Vec<u16> {
+        match (&unmixing, per_channel.len()) {
+            (_, 0) => Vec::new(),
+            (Some(u), _) => {
+                let refs: Vec<&[u16]> = per_channel.iter().map(Vec::as_slice).collect();
+                u.apply(&refs)
+            }
+            (None, _) => per_channel.swap_remove(0),
+        }
+    };
+    let backgrounds = combine(per_channel_backgrounds);
+
+    let bg_image = if args.local_background {
+        let path = format!("/pos/{}/{}", pos_id, crop::BACKGROUND_IMAGE);
+        let arr = zarr::open_array(&store, &path)
+            .map_err(|_| "--local-background needs a store cropped with --background-image")?;
+        let frame_shape = arr
+            .attributes()
+            .get("frame_shape")
+            .and_then(|v| Some((v.get(0)?.as_u64()? as u32, v.get(1)?.as_u64()? as u32)))
+            .ok_or("background_image has no frame_shape attribute")?;
+        Some((arr, frame_shape))
+    } else {
+        None
+    };
 
+    stats::stage("analyze");
     let total = crop_ids.len();
-    let mut rows: Vec<String> = vec!["t,crop,intensity,area,background".to_string()];
+    // Rows go out crop by crop, so an arrow-stream output can be read during the run.
+    let mut table = if args.output.is_empty() {
+        None
+    } else {
+        Some(TableWriter::create_as(
+            &args.output,
+            schema,
+            args.pos,
+            format,
+        )?)
+    };
+    let mut rows: Vec<Vec<Value>> = Vec::new();
 
     for (i, crop_id) in crop_ids.iter().enumerate() {
         let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
         let arr = zarr::open_array(&store, &array_path)?;
         let shape = arr.shape();
         let n_t = shape[0];
-        let h = shape[3];
-        let w = shape[4];
-        let area = h * w;
+        let mut skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+        skip.extend(slices::frames_outside(&args.time, n_t)?);
+        let missing = crop::missing_frames(&arr);
+        let saturation = crop::saturation(&arr);
+        let local_backgrounds;
+        let crop_backgrounds: &[u16] = match &bg_image {
+            Some((bg_arr, frame_shape)) => {
+                let bbox = arr
+                    .attributes()
+                    .get("bbox")
+                    .ok_or("Crop has no bbox attribute")?;
+                let field = |k: &str| bbox.get(k).and_then(|v| v.as_f64()).unwrap_or(0.0);
+                let center = (field("y") + field("h") / 2.0, field("x") + field("w") / 2.0);
+                let grid_shape = (bg_arr.shape()[3] as u32, bg_arr.shape()[4] as u32);
+                let mut per_channel = Vec::new();
+                for &c in &channels {
+                    let mut series = Vec::new();
+                    for t in 0..bg_arr.shape()[0] {
+                        let grid = zarr::read_frame_u16(bg_arr, &[t, c, 0])?;
+                        let v =
+                            crop::interpolate_background(&grid, grid_shape, *frame_shape, center);
+                        series.push(v.round() as u16);
+                    }
+                    per_channel.push(series);
+                }
+                local_backgrounds = combine(per_channel);
+                &local_backgrounds
+            }
+            None => &backgrounds,
+        };
+        let crop_pattern = match &pattern {
+            Some(p) => p.crop_mask(&arr)?,
+            None => None,
+        };
 
         for t in 0..n_t {
-            let chunk_indices = vec![t, args.channel as u64, 0, 0, 0];
-            let data = zarr::read_chunk_u16(&arr, &chunk_indices)?;
-            let intensity: u64 = data.iter().map(|&v| v as u64).sum();
-            let background = if (t as usize) < backgrounds.len() {
-                backgrounds[t as usize]
+            if skip.contains(&t) || channels.iter().any(|&c| missing.contains(&(t, c, 0))) {
+                continue;
+            }
+            let data = match &unmixing {
+                Some(u) => {
+                    let frames = channels
+                        .iter()
+                        .map(|&c| zarr::read_frame_u16(&arr, &[t, c, 0]))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let refs: Vec<&[u16]> = frames.iter().map(Vec::as_slice).collect();
+                    u.apply(&refs)
+                }
+                None => zarr::read_frame_u16(&arr, &[t, channels[0], 0])?,
+            };
+            let background = if (t as usize) < crop_backgrounds.len() {
+                crop_backgrounds[t as usize]
             } else {
                 0
             };
-            rows.push(format!("{},{},{},{},{}", t, crop_id, intensity, area, background));
+            let split = match (&pattern, &crop_pattern) {
+                (_, Some(mask)) => Some(split_by_pattern(&data, mask.iter().copied())),
+                (Some(PatternSource::Channel { channel, threshold }), None) => {
+                    let pattern_data = zarr::read_frame_u16(&arr, &[t, *channel, 0])?;
+                    Some(split_by_pattern(
+                        &data,
+                        pattern_data.iter().map(|&v| v > *threshold),
+                    ))
+                }
+                _ => None,
+            };
+            // Saturated samples in the quantified (or unmixed) channels; empty when the
+            // crop predates saturation counting.
+            let saturated = saturation
+                .as_ref()
+                .map(|s| channels.iter().map(|&c| s.count(t, c, 0)).sum::<u64>());
+            rows.push(expression_row(
+                &args, t, crop_id, &data, background, split, saturated,
+            ));
+            stats::add_frames(1);
+        }
+        if let Some(table) = &mut table {
+            for row in rows.drain(..) {
+                table.write_row(&row)?;
+            }
         }
 
-        progress(
-            (i + 1) as f64 / total as f64,
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "analyze",
+            (i + 1) as u64,
+            total as u64,
             &format!("Processing crop {}/{}", i + 1, total),
+        ));
+    }
+
+    if let Some(table) = table {
+        let n_rows = table.finish()?;
+        progress(ProgressEvent::done(&format!(
+            "Wrote {} rows to {}",
+            n_rows, args.output
+        )));
+    }
+    Ok(())
+}
+
+/// `--bbox`: the same table computed from the Pos{N} TIFF folder, one timepoint at a time.
+/// Rows come out in the order of the crops.zarr path (crops in bbox CSV row order, as
+/// `crop` registers them, then t), and timepoints with a missing TIFF in a needed channel
+/// are skipped.
+fn run_tiffs(
+    args: &ExpressionArgs,
+    bbox: &str,
+    schema: &Schema,
+    channels: &[u64],
+    unmixing: Option<&Unmixing>,
+    pattern: Option<&PatternSource>,
+    progress: &impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.local_background {
+        return Err(
+            "--local-background needs crops.zarr (crop --background-image), not --bbox".into(),
         );
     }
+    let root = Path::new(&args.input);
+    let pos_dir = root.join(format!("Pos{}", args.pos));
+    if !pos_dir.exists() {
+        return Err(format!("Position directory not found: {}", pos_dir.display()).into());
+    }
+    let um_per_px = PixelCalibration::read_sidecar(root)?
+        .unwrap_or_default()
+        .um_per_px;
+    let mut bboxes = crop::parse_bbox_csv(Path::new(bbox), um_per_px)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    bboxes.retain(|bb| !exclusions.crop_excluded(args.pos, &bb.id));
+    let index = crop::discover_tiffs(&pos_dir, args.pos, None)?;
+
+    let mut needed = channels.to_vec();
+    if let Some(PatternSource::Channel { channel, .. }) = pattern {
+        needed.push(*channel);
+    }
+    needed.sort();
+    needed.dedup();
+    let mut times: Vec<u32> = index
+        .keys()
+        .filter(|k| k.2 == 0 && needed.contains(&(k.0 as u64)))
+        .map(|k| k.1)
+        .collect();
+    times.sort();
+    times.dedup();
+    let first = times
+        .iter()
+        .find_map(|&t| needed.iter().find_map(|&c| index.get(&(c as u32, t, 0))));
+    let (Some(first), false) = (first, bboxes.is_empty()) else {
+        if !args.output.is_empty() {
+            let format = OutputFormat::parse(&args.output_format)?;
+            TableWriter::create_as(&args.output, schema, args.pos, format)?.finish()?;
+        }
+        return Ok(());
+    };
+    let (_, width, height) = crop::read_tiff_frame(first)?;
+    let bboxes = crop::clamp_bboxes(bboxes, width, height)?;
+    let outside = crop::box_mask(&bboxes, width, height);
+    let n_t = times.last().map_or(0, |&t| t as u64 + 1);
+    let mut skip = Vec::with_capacity(bboxes.len());
+    let mut crop_patterns = Vec::with_capacity(bboxes.len());
+    let unselected = slices::frames_outside(&args.time, n_t)?;
+    times.retain(|&t| !unselected.contains(&(t as u64)));
+    for bb in &bboxes {
+        let mut frames = exclusions.excluded_frames(args.pos, &bb.id, n_t)?;
+        frames.extend(&unselected);
+        skip.push(frames);
+        crop_patterns.push(match pattern {
+            Some(p) => p.mask_in(bb.x as u64, bb.y as u64, bb.w as u64, bb.h as u64)?,
+            None => None,
+        });
+    }
+
+    stats::stage("analyze");
+    let cut = |frame: &[u16], bb: &crop::Bbox| {
+        crop::extract_crop_u16(frame, width, bb.x, bb.y, bb.w, bb.h)
+    };
+    let mut rows: Vec<Vec<Vec<Value>>> = vec![Vec::new(); bboxes.len()];
+    for (i, &t) in times.iter().enumerate() {
+        // Channel -> (pixels, saturation level of its source dtype).
+        let mut frames: HashMap<u64, (Vec<u16>, u16)> = HashMap::new();
+        for &c in &needed {
+            let Some(path) = index.get(&(c as u32, t, 0)) else {
+                break;
+            };
+            let (data, w, h) = crop::read_tiff_frame(path)?;
+            if (w, h) != (width, height) {
+                return Err(format!(
+                    "{} is {}x{}, expected {}x{}",
+                    path.display(),
+                    w,
+                    h,
+                    width,
+                    height
+                )
+                .into());
+            }
+            frames.insert(
+                c,
+                match data {
+                    FrameData::U16(v) => (v, u16::MAX),
+                    FrameData::U8(v) => (v.into_iter().map(u16::from).collect(), u8::MAX as u16),
+                },
+            );
+            stats::add_frames(1);
+        }
+        if frames.len() < needed.len() {
+            continue;
+        }
+        let per_channel: Vec<&[u16]> = channels.iter().map(|c| frames[c].0.as_slice()).collect();
+        let backgrounds: Vec<u16> = per_channel
+            .iter()
+            .map(|f| crop::median_outside_mask_u16(f, width, height, &outside))
+            .collect();
+        let unmixed;
+        let (frame, background) = match unmixing {
+            Some(u) => {
+                unmixed = u.apply(&per_channel);
+                let refs: Vec<&[u16]> = backgrounds.iter().map(std::slice::from_ref).collect();
+                (unmixed.as_slice(), u.apply(&refs)[0])
+            }
+            None => (per_channel[0], backgrounds[0]),
+        };
+
+        let t = t as u64;
+        for (k, bb) in bboxes.iter().enumerate() {
+            if skip[k].contains(&t) {
+                continue;
+            }
+            let data = cut(frame, bb);
+            let split = match (pattern, &crop_patterns[k]) {
+                (_, Some(mask)) => Some(split_by_pattern(&data, mask.iter().copied())),
+                (Some(PatternSource::Channel { channel, threshold }), None) => {
+                    let pattern_data = cut(&frames[channel].0, bb);
+                    Some(split_by_pattern(
+                        &data,
+                        pattern_data.iter().map(|&v| v > *threshold),
+                    ))
+                }
+                _ => None,
+            };
+            let saturated: u64 = channels
+                .iter()
+                .map(|c| {
+                    let (f, level) = &frames[c];
+                    cut(f, bb).iter().filter(|&&v| v >= *level).count() as u64
+                })
+                .sum();
+            rows[k].push(expression_row(
+                args,
+                t,
+                &bb.id,
+                &data,
+                background,
+                split,
+                Some(saturated),
+            ));
+        }
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "analyze",
+            (i + 1) as u64,
+            times.len() as u64,
+            &format!("Processing timepoint {}/{}", i + 1, times.len()),
+        ));
+    }
 
     if !args.output.is_empty() {
-        fs::create_dir_all(Path::new(&args.output).parent().unwrap_or(Path::new(".")))?;
-        fs::write(&args.output, rows.join("\n"))?;
-        progress(1.0, &format!("Wrote {} rows to {}", rows.len() - 1, args.output));
+        let format = OutputFormat::parse(&args.output_format)?;
+        let mut table = TableWriter::create_as(&args.output, schema, args.pos, format)?;
+        for row in rows.iter().flatten() {
+            table.write_row(row)?;
+        }
+        let n_rows = table.finish()?;
+        progress(ProgressEvent::done(&format!(
+            "Wrote {} rows to {}",
+            n_rows, args.output
+        )));
     }
     Ok(())
 }
+
+/// `--whole-frame` from the Pos{N} TIFFs under `--input`, or from the background arrays of
+/// crops.zarr when there is no such folder.
+fn run_whole_frame(
+    args: &ExpressionArgs,
+    progress: &impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = Path::new(&args.input);
+    let pos_dir = root.join(format!("Pos{}", args.pos));
+    let format = OutputFormat::parse(&args.output_format)?;
+    let mut table = TableWriter::create_as(&args.output, &WHOLE_FRAME_SCHEMA, args.pos, format)?;
+    stats::stage("analyze");
+    if pos_dir.is_dir() {
+        let index = crop::discover_tiffs(&pos_dir, args.pos, None)?;
+        let mut keys: Vec<&(u32, u32, u32)> = index.keys().filter(|k| k.2 == 0).collect();
+        let n_t = keys.iter().map(|k| k.1 as u64 + 1).max().unwrap_or(0);
+        let unselected = slices::frames_outside(&args.time, n_t)?;
+        keys.retain(|k| !unselected.contains(&(k.1 as u64)));
+        keys.sort_by_key(|&&(c, t, _)| (t, c));
+        for (i, &&(c, t, z)) in keys.iter().enumerate() {
+            let (total, n) = match crop::read_tiff_frame(&index[&(c, t, z)])?.0 {
+                FrameData::U16(v) => (v.iter().map(|&p| p as u64).sum::<u64>(), v.len()),
+                FrameData::U8(v) => (v.iter().map(|&p| p as u64).sum::<u64>(), v.len()),
+            };
+            table.write_row(&[
+                Value::from(t),
+                c.into(),
+                (total as f64 / n.max(1) as f64).into(),
+                total.into(),
+                "frame".into(),
+            ])?;
+            stats::add_frames(1);
+            cancel::check()?;
+            progress(ProgressEvent::new(
+                "analyze",
+                (i + 1) as u64,
+                keys.len() as u64,
+                &format!("Reading frame {}/{}", i + 1, keys.len()),
+            ));
+        }
+    } else {
+        let store = zarr::open_store(root)?;
+        let pos_id = format!("{:03}", args.pos);
+        let image_path = format!("/pos/{}/{}", pos_id, crop::BACKGROUND_IMAGE);
+        let (arr, source) = match zarr::open_array(&store, &image_path) {
+            Ok(arr) => (arr, "background_image"),
+            Err(_) => {
+                let arr = zarr::open_array(&store, &format!("/pos/{}/background", pos_id))
+                    .map_err(|_| {
+                        format!(
+                            "{} has no background for position {}: crop with --background or \
+                         --background-image, or point --input at the TIFF folder",
+                            args.input, args.pos
+                        )
+                    })?;
+                (arr, "background")
+            }
+        };
+        let missing = crop::missing_frames(&arr);
+        let (n_t, n_c) = (arr.shape()[0], arr.shape()[1]);
+        let unselected = slices::frames_outside(&args.time, n_t)?;
+        for t in (0..n_t).filter(|t| !unselected.contains(t)) {
+            for c in 0..n_c {
+                if missing.contains(&(t, c, 0)) {
+                    continue;
+                }
+                let values = zarr::read_frame_u16(&arr, &[t, c, 0])?;
+                let sum: f64 = values.iter().map(|&v| v as f64).sum();
+                table.write_row(&[
+                    Value::from(t),
+                    c.into(),
+                    (sum / values.len().max(1) as f64).into(),
+                    Value::from(None::<u64>),
+                    source.into(),
+                ])?;
+            }
+            cancel::check()?;
+            progress(ProgressEvent::new(
+                "analyze",
+                t + 1,
+                n_t,
+                &format!("Reading frame {}/{}", t + 1, n_t),
+            ));
+        }
+    }
+    let n_rows = table.finish()?;
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} rows to {}",
+        n_rows, args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::fs;
+    use tiff::encoder::{colortype::Gray16, TiffEncoder};
+
+    #[derive(Parser)]
+    struct CropCli {
+        #[command(flatten)]
+        args: crop::CropArgs,
+    }
+
+    #[derive(Parser)]
+    struct ExpressionCli {
+        #[command(flatten)]
+        args: ExpressionArgs,
+    }
+
+    fn crop(args: &[&str]) {
+        let cli = CropCli::parse_from(std::iter::once("crop").chain(args.iter().copied()));
+        crop::run(cli.args, |_| {}).unwrap();
+    }
+
+    fn expression(args: &[&str]) {
+        let cli =
+            ExpressionCli::parse_from(std::iter::once("expression").chain(args.iter().copied()));
+        run(cli.args, |_| {}).unwrap();
+    }
+
+    /// Write `data` as the 16-bit TIFF for channel `c`, time `t` of a Pos folder.
+    fn write_tiff(pos_dir: &Path, c: u32, t: u32, width: u32, height: u32, data: &[u16]) {
+        let name = format!("img_channel{c:03}_position000_time{t:09}_z000.tif");
+        let mut encoder = TiffEncoder::new(fs::File::create(pos_dir.join(name)).unwrap()).unwrap();
+        encoder.write_image::<Gray16>(width, height, data).unwrap();
+    }
+
+    /// Data rows of a CSV table as column name -> field.
+    fn rows(path: &str) -> Vec<HashMap<String, String>> {
+        let text = fs::read_to_string(path).unwrap();
+        let mut lines = text.lines().filter(|l| !l.starts_with('#'));
+        let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+        lines
+            .map(|l| {
+                header
+                    .iter()
+                    .zip(l.split(','))
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// (t, crop) of every data row of a CSV table.
+    fn row_keys(path: &str) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .skip(1)
+            .map(|l| l.splitn(3, ',').take(2).collect::<Vec<_>>().join(","))
+            .collect()
+    }
+
+    #[test]
+    fn bbox_and_crops_zarr_rows_follow_bbox_csv_order() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let pos_dir = dir.path().join("Pos0");
+        fs::create_dir_all(&pos_dir).unwrap();
+        for t in 0..2u16 {
+            let data: Vec<u16> = (0..48).map(|i| 100 + i + t).collect();
+            write_tiff(&pos_dir, 0, t as u32, 8, 6, &data);
+        }
+        let root = dir.path().to_str().unwrap();
+        let (bbox, zarr) = (format!("{root}/bbox.csv"), format!("{root}/crops.zarr"));
+        // Row order differs from ID order.
+        fs::write(&bbox, "crop,x,y,w,h\nb,0,0,3,3\na,4,2,3,3\n").unwrap();
+        crop(&[
+            "--input", root, "--pos", "0", "--bbox", &bbox, "--output", &zarr,
+        ]);
+
+        let (from_zarr, from_tiffs) = (format!("{root}/zarr.csv"), format!("{root}/tiffs.csv"));
+        let common = ["--pos", "0", "--channel", "0", "--time", "all"];
+        expression(&[&common[..], &["--input", &zarr, "--output", &from_zarr]].concat());
+        expression(
+            &[
+                &common[..],
+                &["--input", root, "--bbox", &bbox, "--output", &from_tiffs],
+            ]
+            .concat(),
+        );
+
+        let keys = row_keys(&from_zarr);
+        assert_eq!(keys, ["0,b", "1,b", "0,a", "1,a"]);
+        assert_eq!(row_keys(&from_tiffs), keys);
+    }
+
+    #[test]
+    fn subtract_background_corrects_by_area() {
+        let args = |extra: &str| {
+            let line = format!(
+                "expression --input x --pos 0 --channel 0 --output o.csv --time all {extra}"
+            );
+            ExpressionCli::parse_from(line.split_whitespace()).args
+        };
+        let corrected = args("--subtract-background");
+        let row = expression_row(&corrected, 3, "A", &[10, 20, 30], 5, None, Some(1));
+        let expected: Vec<Value> = vec![
+            3u64.into(),
+            "A".into(),
+            60u64.into(),
+            3u64.into(),
+            5u16.into(),
+            45.0.into(),
+            Some(1u64).into(),
+        ];
+        assert_eq!(row, expected);
+        // Dim crops on a bright background go negative rather than clamping.
+        let row = expression_row(&corrected, 0, "A", &[1, 1], 10, None, None);
+        assert_eq!(row[5], Value::from(-18.0));
+        let plain = expression_row(&args(""), 3, "A", &[10, 20, 30], 5, None, Some(1));
+        assert_eq!(plain.len(), SCHEMA.columns.len());
+        assert_eq!(plain[5], Value::from(Some(1u64)));
+
+        // End to end through --bbox: the background is the median outside both boxes.
+        let dir = tempfile::TempDir::new().unwrap();
+        let pos_dir = dir.path().join("Pos0");
+        fs::create_dir_all(&pos_dir).unwrap();
+        let data: Vec<u16> = (0..48).map(|i| 100 + i).collect();
+        write_tiff(&pos_dir, 0, 0, 8, 6, &data);
+        let root = dir.path().to_str().unwrap();
+        let (bbox, out) = (format!("{root}/bbox.csv"), format!("{root}/out.csv"));
+        fs::write(&bbox, "crop,x,y,w,h\nb,0,0,3,3\na,4,2,3,3\n").unwrap();
+        let mut argv: Vec<&str> = "--pos 0 --channel 0 --time all --subtract-background"
+            .split(' ')
+            .collect();
+        argv.extend(["--input", root, "--bbox", &bbox, "--output", &out]);
+        expression(&argv);
+        let table = rows(&out);
+        assert_eq!(table.len(), 2);
+        for row in &table {
+            let field = |k: &str| row[k].parse::<f64>().unwrap();
+            assert_eq!(field("area"), 9.0);
+            assert_eq!(field("background"), 126.0);
+            assert_eq!(
+                field("intensity_corrected"),
+                field("intensity") - field("background") * field("area")
+            );
+        }
+    }
+
+    #[test]
+    fn whole_frame_reports_each_frame_and_channel() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let pos_dir = dir.path().join("Pos0");
+        fs::create_dir_all(&pos_dir).unwrap();
+        for (c, t) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            let data: Vec<u16> = (0..8).map(|i| i + 100 * c + t).collect();
+            write_tiff(&pos_dir, c as u32, t as u32, 4, 2, &data);
+        }
+        let root = dir.path().to_str().unwrap();
+        let run_whole = |input: &str, time: &str, out: &str| {
+            let flags = format!("--pos 0 --channel 0 --time {time} --whole-frame");
+            let mut argv: Vec<&str> = flags.split(' ').collect();
+            argv.extend(["--input", input, "--output", out]);
+            expression(&argv);
+            rows(out)
+        };
+        let field = |row: &HashMap<String, String>, k: &str| row[k].parse::<f64>().unwrap();
+
+        // From the TIFFs: sorted by t then channel, mean and total over all 8 pixels.
+        let table = run_whole(root, "all", &format!("{root}/frames.csv"));
+        let keys: Vec<(f64, f64)> = table
+            .iter()
+            .map(|r| (field(r, "t"), field(r, "channel")))
+            .collect();
+        assert_eq!(keys, [(0.0, 0.0), (0.0, 1.0), (1.0, 0.0), (1.0, 1.0)]);
+        for row in &table {
+            let offset = 100.0 * field(row, "channel") + field(row, "t");
+            assert_eq!(field(row, "mean"), 3.5 + offset);
+            assert_eq!(field(row, "total"), 28.0 + 8.0 * offset);
+            assert_eq!(row["source"], "frame");
+        }
+        let selected = run_whole(root, "1", &format!("{root}/t1.csv"));
+        assert_eq!(selected.len(), 2);
+        assert!(selected.iter().all(|r| r["t"] == "1"));
+
+        // From crops.zarr only the stored background is left: mean is its value, no total.
+        let (bbox, zarr) = (format!("{root}/bbox.csv"), format!("{root}/crops.zarr"));
+        fs::write(&bbox, "crop,x,y,w,h\nA,0,0,2,2\n").unwrap();
+        let mut argv = vec!["--pos", "0", "--background", "--input", root];
+        argv.extend(["--bbox", &bbox, "--output", &zarr]);
+        crop(&argv);
+        let table = run_whole(&zarr, "all", &format!("{root}/background.csv"));
+        assert_eq!(table.len(), 4);
+        let per_crop = format!("{root}/crops.csv");
+        let mut argv = vec!["--pos", "0", "--channel", "0", "--time", "all"];
+        argv.extend(["--input", &zarr, "--output", &per_crop]);
+        expression(&argv);
+        let backgrounds: Vec<&str> = table
+            .iter()
+            .filter(|r| r["channel"] == "0")
+            .map(|r| r["mean"].as_str())
+            .collect();
+        let expected: Vec<String> = rows(&per_crop)
+            .into_iter()
+            .map(|r| r["background"].clone())
+            .collect();
+        assert_eq!(backgrounds, expected);
+        assert!(table
+            .iter()
+            .all(|r| r["total"].is_empty() && r["source"] == "background"));
+    }
+
+    #[test]
+    fn pattern_mask_splits_crop_pixels() {
+        // 4x3 frame mask; the pattern covers the two middle columns.
+        #[rustfmt::skip]
+        let mask = [
+            0, 1, 1, 0,
+            0, 1, 1, 0,
+            0, 1, 1, 0,
+        ];
+        let source = PatternSource::Mask {
+            mask: mask.iter().map(|&m| m > 0).collect(),
+            width: 4,
+            height: 3,
+        };
+        // 3x2 crop at (1, 1): columns 1-3 of rows 1-2.
+        let crop_mask = source.mask_in(1, 1, 3, 2).unwrap().unwrap();
+        assert_eq!(crop_mask, [true, true, false, true, true, false]);
+        let data = [10u16, 20, 5, 30, 40, 7];
+        // on: 10 + 20 + 30 + 40 over 4 px; off: 5 + 7 over 2 px.
+        assert_eq!(
+            split_by_pattern(&data, crop_mask.into_iter()),
+            [100, 4, 12, 2]
+        );
+
+        assert!(source.mask_in(2, 1, 3, 2).is_err());
+        let channel = PatternSource::Channel {
+            channel: 1,
+            threshold: 50,
+        };
+        assert_eq!(channel.mask_in(0, 0, 1, 1).unwrap(), None);
+        let pattern = [60u16, 10, 51, 50];
+        let split = split_by_pattern(&[1, 2, 3, 4], pattern.iter().map(|&p| p > 50));
+        assert_eq!(split, [4, 2, 6, 2]);
+    }
+
+    #[test]
+    fn unmixing_rejects_malformed_matrices() {
+        let names = ChannelNames::parse("GFP,YFP,mCherry,DAPI");
+        let err = |matrix: &str, channels: &str, channel: u32| {
+            Unmixing::parse(matrix, channels, channel, &names)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert!(err("1,0;0", "0,1", 1).contains("2x2"));
+        assert!(err("1,0,0;0,1,0", "0,1,2", 1).contains("3x3"));
+        assert!(err("1,x;0,1", "0,1", 1).contains("Invalid --unmix matrix"));
+        assert!(err("1,2;0.5,1", "0,1", 1).contains("singular"));
+        assert!(err("1,0,0;0,1,0;1,1,0", "0,1,2", 1).contains("singular"));
+        assert!(err("1", "1", 1).contains("2 or 3"));
+        assert!(err("1,0,0,0;0,1,0,0;0,0,1,0;0,0,0,1", "0,1,2,3", 1).contains("2 or 3"));
+        assert!(err("1,0;0,1", "0,1", 2).contains("--channel"));
+        assert!(err("1,0;0,1", "GFP,CFP", 1).contains("--unmix-channels"));
+    }
+
+    #[test]
+    fn unmixing_removes_bleed_through() {
+        // YFP picks up 15% of the GFP signal: measured YFP = YFP + 0.15 * GFP.
+        let (gfp, yfp) = ([1000u16, 0, 200], [400u16, 300, 0]);
+        let measured_yfp: Vec<u16> = gfp
+            .iter()
+            .zip(&yfp)
+            .map(|(&g, &y)| y + g * 15 / 100)
+            .collect();
+        let names = ChannelNames::parse("GFP,YFP");
+        let matrix = "1,0;-0.15,1";
+
+        let unmix_yfp = Unmixing::parse(matrix, "GFP,YFP", 1, &names).unwrap();
+        assert_eq!(unmix_yfp.channels, [0, 1]);
+        assert_eq!(unmix_yfp.apply(&[&gfp, &measured_yfp]), yfp);
+        let unmix_gfp = Unmixing::parse(matrix, "0,1", 0, &names).unwrap();
+        assert_eq!(unmix_gfp.apply(&[&gfp, &measured_yfp]), gfp);
+
+        // Over-correction clamps at 0 instead of wrapping.
+        let strong = Unmixing::parse("1,0;-2,1", "0,1", 1, &names).unwrap();
+        assert_eq!(strong.apply(&[&[1000], &[100]]), [0]);
+    }
+}
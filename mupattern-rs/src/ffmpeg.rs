@@ -0,0 +1,163 @@
+//! ffmpeg discovery and the raw-video encoder pipe used by `movie`.
+//!
+//! The binary is taken from `--ffmpeg`, then `MUPATTERN_FFMPEG`, then `ffmpeg = "..."` in
+//! mupattern.toml, then PATH. It is checked for the requested encoder before any frame is
+//! streamed, and its stderr is kept so a failed encode reports ffmpeg's own message.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::JoinHandle;
+
+use crate::config;
+
+pub const ENV_VAR: &str = "MUPATTERN_FFMPEG";
+
+/// Lines of ffmpeg stderr quoted in error messages.
+const STDERR_TAIL_LINES: usize = 20;
+
+fn search_path() -> Option<PathBuf> {
+    let exe = if cfg!(windows) {
+        "ffmpeg.exe"
+    } else {
+        "ffmpeg"
+    };
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(exe))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Resolve the ffmpeg binary (see module docs for the order).
+pub fn locate(explicit: Option<&str>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let configured = explicit
+        .map(|p| (p.to_string(), "--ffmpeg"))
+        .or_else(|| std::env::var(ENV_VAR).ok().map(|p| (p, ENV_VAR)))
+        .or_else(|| config::get_str("ffmpeg").map(|p| (p.to_string(), config::FILE_NAME)))
+        .filter(|(p, _)| !p.trim().is_empty());
+    if let Some((path, source)) = configured {
+        let path = PathBuf::from(path.trim());
+        if !path.is_file() {
+            return Err(format!("ffmpeg from {} not found: {}", source, path.display()).into());
+        }
+        return Ok(path);
+    }
+    search_path().ok_or_else(|| {
+        format!(
+            "ffmpeg not found: pass --ffmpeg, set {}, add ffmpeg = \"/path/to/ffmpeg\" to {}, or put ffmpeg on PATH",
+            ENV_VAR,
+            config::FILE_NAME
+        )
+        .into()
+    })
+}
+
+/// True if `ffmpeg -encoders` output lists `encoder`.
+fn lists_encoder(encoders: &str, encoder: &str) -> bool {
+    encoders
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(encoder))
+}
+
+/// Fail early if `bin` cannot run or was built without `encoder` (e.g. libx264).
+pub fn verify_encoder(bin: &Path, encoder: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new(bin)
+        .args(["-hide_banner", "-encoders"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Cannot run {}: {}", bin.display(), e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} -encoders failed: {}",
+            bin.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    if !lists_encoder(&String::from_utf8_lossy(&output.stdout), encoder) {
+        return Err(format!("{} does not support the {} encoder", bin.display(), encoder).into());
+    }
+    Ok(())
+}
+
+/// A running ffmpeg reading raw frames on stdin.
+pub struct Encoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl Encoder {
+    /// Spawn `bin` with `args`; stderr is collected on a thread so it cannot block the pipe.
+    pub fn spawn(bin: &Path, args: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut child = Command::new(bin)
+            .args(["-hide_banner", "-nostats", "-loglevel", "error"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Cannot start {}: {}", bin.display(), e))?;
+        let stdin = child.stdin.take().ok_or("Failed to open ffmpeg stdin")?;
+        let mut pipe = child.stderr.take().ok_or("Failed to open ffmpeg stderr")?;
+        let stderr = std::thread::spawn(move || {
+            let mut text = String::new();
+            let _ = pipe.read_to_string(&mut text);
+            text
+        });
+        Ok(Self {
+            child,
+            stdin: Some(stdin),
+            stderr: Some(stderr),
+        })
+    }
+
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let stdin = self.stdin.as_mut().ok_or("ffmpeg stdin already closed")?;
+        if let Err(e) = stdin.write_all(frame) {
+            // ffmpeg exited early; its stderr says why.
+            return Err(self.failure(&format!("ffmpeg stopped reading frames ({})", e)));
+        }
+        Ok(())
+    }
+
+    /// Close stdin and wait for ffmpeg to finish writing the file.
+    pub fn finish(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if !status.success() {
+            let msg = format!("ffmpeg exited with code {}", status.code().unwrap_or(-1));
+            return Err(self.failure(&msg));
+        }
+        Ok(())
+    }
+
+    fn failure(&mut self, msg: &str) -> Box<dyn std::error::Error> {
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|h| h.join().ok())
+            .unwrap_or_default();
+        let lines: Vec<&str> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+        let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n");
+        if tail.is_empty() {
+            msg.into()
+        } else {
+            format!("{}:\n{}", msg, tail).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoder_list_is_matched_by_name_column() {
+        let listing = "Encoders:\n V..... = Video\n ------\n V....D libx264              libx264 H.264\n V....D libx264rgb           libx264 H.264 RGB\n";
+        assert!(lists_encoder(listing, "libx264"));
+        assert!(lists_encoder(listing, "libx264rgb"));
+        assert!(!lists_encoder(listing, "libx265"));
+    }
+}
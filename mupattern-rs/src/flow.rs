@@ -0,0 +1,361 @@
+//! Collective motion by particle image velocimetry (PIV): interrogation windows of one
+//! frame are matched in the next by zero-normalized cross-correlation with sub-pixel peak
+//! refinement. Per frame pair we report mean speed, divergence and curl of the vector
+//! field; `--vectors` keeps the field itself for `movie --flow`.
+
+use clap::Args;
+use std::path::Path;
+
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::crop;
+use crate::crop_index;
+use crate::exclude::ExclusionList;
+use crate::output::{ColumnType, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::zarr;
+
+const SCHEMA: Schema = Schema {
+    table: "flow",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("vectors", ColumnType::Integer),
+        ("mean_speed", ColumnType::Real),
+        ("divergence", ColumnType::Real),
+        ("curl", ColumnType::Real),
+    ],
+};
+
+const VECTORS_SCHEMA: Schema = Schema {
+    table: "flow_vectors",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("y", ColumnType::Real),
+        ("x", ColumnType::Real),
+        ("dy", ColumnType::Real),
+        ("dx", ColumnType::Real),
+    ],
+};
+
+#[derive(Args, Clone)]
+pub struct FlowArgs {
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Channel to track (usually phase contrast): index or name
+    #[arg(long)]
+    pub channel: String,
+    /// Interrogation window size in pixels
+    #[arg(long)]
+    pub window: usize,
+    /// Largest displacement searched between consecutive frames, in pixels
+    #[arg(long)]
+    pub max_shift: usize,
+    /// Spacing of the vector grid in pixels (default: half the window)
+    #[arg(long)]
+    pub step: Option<usize>,
+    /// Per-frame summary: t,crop,vectors,mean_speed,divergence,curl (px/frame and 1/frame);
+    /// CSV, or SQLite when ending in .sqlite/.db
+    #[arg(long)]
+    pub output: String,
+    /// Also write every vector (t,crop,y,x,dy,dx) here, e.g. for `movie --flow`
+    #[arg(long)]
+    pub vectors: Option<String>,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+struct PivParams {
+    window: usize,
+    step: usize,
+    max_shift: usize,
+}
+
+/// Vectors on a regular grid; `None` where the window has no texture to match.
+struct VectorField {
+    rows: usize,
+    cols: usize,
+    /// Window centers (y, x) in pixels, row-major.
+    centers: Vec<(f64, f64)>,
+    /// Displacement (dy, dx) in pixels per frame.
+    vectors: Vec<Option<(f64, f64)>>,
+    step: usize,
+}
+
+/// Zero-normalized cross-correlation of the `n`×`n` windows of `a` at (ay, ax) and of
+/// `b` at (by, bx); `None` if either window is flat.
+fn zncc(
+    a: &[u16],
+    b: &[u16],
+    w: usize,
+    n: usize,
+    (ay, ax): (usize, usize),
+    (by, bx): (usize, usize),
+) -> Option<f64> {
+    let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0f64, 0f64, 0f64, 0f64, 0f64);
+    for r in 0..n {
+        let ra = &a[(ay + r) * w + ax..][..n];
+        let rb = &b[(by + r) * w + bx..][..n];
+        for (&va, &vb) in ra.iter().zip(rb) {
+            let (va, vb) = (va as f64, vb as f64);
+            sa += va;
+            sb += vb;
+            saa += va * va;
+            sbb += vb * vb;
+            sab += va * vb;
+        }
+    }
+    let m = (n * n) as f64;
+    let var_a = saa - sa * sa / m;
+    let var_b = sbb - sb * sb / m;
+    if var_a <= 0.0 || var_b <= 0.0 {
+        return None;
+    }
+    Some((sab - sa * sb / m) / (var_a * var_b).sqrt())
+}
+
+/// Sub-pixel offset of a peak from three samples around it (parabolic fit).
+fn peak_offset(left: f64, center: f64, right: f64) -> f64 {
+    let denom = left - 2.0 * center + right;
+    if denom >= 0.0 {
+        return 0.0;
+    }
+    (0.5 * (left - right) / denom).clamp(-0.5, 0.5)
+}
+
+/// Displacement field from `prev` to `next` (both `h`×`w`).
+fn piv(prev: &[u16], next: &[u16], h: usize, w: usize, p: PivParams) -> VectorField {
+    let s = p.max_shift;
+    let n = p.window;
+    let starts = |len: usize| -> Vec<usize> {
+        if len < n + 2 * s {
+            return Vec::new();
+        }
+        (s..=len - n - s).step_by(p.step).collect()
+    };
+    let (ys, xs) = (starts(h), starts(w));
+    let side = 2 * s + 1;
+    let mut field = VectorField {
+        rows: ys.len(),
+        cols: xs.len(),
+        centers: Vec::with_capacity(ys.len() * xs.len()),
+        vectors: Vec::with_capacity(ys.len() * xs.len()),
+        step: p.step,
+    };
+    let half = (n as f64 - 1.0) / 2.0;
+    let mut scores = vec![f64::NEG_INFINITY; side * side];
+    for &y0 in &ys {
+        for &x0 in &xs {
+            field.centers.push((y0 as f64 + half, x0 as f64 + half));
+            scores.fill(f64::NEG_INFINITY);
+            let mut best: Option<(usize, usize)> = None;
+            for i in 0..side {
+                for j in 0..side {
+                    let Some(c) = zncc(prev, next, w, n, (y0, x0), (y0 + i - s, x0 + j - s)) else {
+                        continue;
+                    };
+                    scores[i * side + j] = c;
+                    if best.iter().all(|&(bi, bj)| c > scores[bi * side + bj]) {
+                        best = Some((i, j));
+                    }
+                }
+            }
+            field.vectors.push(best.map(|(i, j)| {
+                let at = |i: usize, j: usize| scores[i * side + j];
+                let mut dy = i as f64 - s as f64;
+                let mut dx = j as f64 - s as f64;
+                if i > 0 && i + 1 < side && at(i - 1, j).is_finite() && at(i + 1, j).is_finite() {
+                    dy += peak_offset(at(i - 1, j), at(i, j), at(i + 1, j));
+                }
+                if j > 0 && j + 1 < side && at(i, j - 1).is_finite() && at(i, j + 1).is_finite() {
+                    dx += peak_offset(at(i, j - 1), at(i, j), at(i, j + 1));
+                }
+                (dy, dx)
+            }));
+        }
+    }
+    field
+}
+
+/// (vectors, mean speed, mean divergence, mean curl). Derivatives use central differences
+/// at grid points whose four neighbours all have a vector; curl is positive for
+/// counterclockwise rotation on screen.
+fn field_stats(f: &VectorField) -> (usize, f64, f64, f64) {
+    let valid: Vec<(f64, f64)> = f.vectors.iter().flatten().copied().collect();
+    let mean = |v: &[f64]| {
+        if v.is_empty() {
+            f64::NAN
+        } else {
+            v.iter().sum::<f64>() / v.len() as f64
+        }
+    };
+    let speeds: Vec<f64> = valid.iter().map(|(dy, dx)| dy.hypot(*dx)).collect();
+    let (mut divs, mut curls) = (Vec::new(), Vec::new());
+    let at = |r: usize, c: usize| f.vectors[r * f.cols + c];
+    let h = 2.0 * f.step as f64;
+    for r in 1..f.rows.saturating_sub(1) {
+        for c in 1..f.cols.saturating_sub(1) {
+            let (Some(up), Some(down), Some(left), Some(right)) =
+                (at(r - 1, c), at(r + 1, c), at(r, c - 1), at(r, c + 1))
+            else {
+                continue;
+            };
+            let ddx_dx = (right.1 - left.1) / h;
+            let ddy_dy = (down.0 - up.0) / h;
+            let ddx_dy = (down.1 - up.1) / h;
+            let ddy_dx = (right.0 - left.0) / h;
+            divs.push(ddx_dx + ddy_dy);
+            // Image y points down, so counterclockwise on screen is dx/dy - dy/dx.
+            curls.push(ddx_dy - ddy_dx);
+        }
+    }
+    (valid.len(), mean(&speeds), mean(&divs), mean(&curls))
+}
+
+pub fn run(
+    args: FlowArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.window < 2 {
+        return Err("--window must be at least 2 pixels".into());
+    }
+    let params = PivParams {
+        window: args.window,
+        step: args.step.unwrap_or(args.window / 2).max(1),
+        max_shift: args.max_shift,
+    };
+    let crops_zarr = Path::new(&args.input);
+    let pos_id = format!("{:03}", args.pos);
+    let channel = ChannelNames::load(crops_zarr)?.resolve(&args.channel)? as u64;
+
+    let mut crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
+
+    let mut table = TableWriter::create(&args.output, &SCHEMA, args.pos)?;
+    let mut vectors = match &args.vectors {
+        Some(path) => Some(TableWriter::create(path, &VECTORS_SCHEMA, args.pos)?),
+        None => None,
+    };
+    if crop_ids.is_empty() {
+        table.finish()?;
+        if let Some(out) = vectors {
+            out.finish()?;
+        }
+        return Ok(());
+    }
+
+    let store = zarr::open_store(crops_zarr)?;
+    stats::stage("analyze");
+    let total = crop_ids.len();
+
+    for (i, crop_id) in crop_ids.iter().enumerate() {
+        let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
+        let arr = zarr::open_array(&store, &array_path)?;
+        let shape = arr.shape();
+        let (n_t, h, w) = (shape[0], shape[3] as usize, shape[4] as usize);
+        let skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+        let missing = crop::missing_frames(&arr);
+        let usable = |t: u64| !skip.contains(&t) && !missing.contains(&(t, channel, 0));
+
+        // Each row describes the motion from t-1 to t.
+        let mut prev: Option<Vec<u16>> = None;
+        for t in 0..n_t {
+            if !usable(t) {
+                prev = None;
+                continue;
+            }
+            let next = zarr::read_frame_u16(&arr, &[t, channel, 0])?;
+            if let Some(prev) = &prev {
+                let field = piv(prev, &next, h, w, params);
+                let (n, speed, div, curl) = field_stats(&field);
+                table.write_row(&[
+                    Value::from(t),
+                    crop_id.as_str().into(),
+                    n.into(),
+                    speed.into(),
+                    div.into(),
+                    curl.into(),
+                ])?;
+                if let Some(out) = vectors.as_mut() {
+                    for (&(y, x), v) in field.centers.iter().zip(&field.vectors) {
+                        if let Some((dy, dx)) = v {
+                            out.write_row(&[
+                                Value::from(t),
+                                crop_id.as_str().into(),
+                                y.into(),
+                                x.into(),
+                                (*dy).into(),
+                                (*dx).into(),
+                            ])?;
+                        }
+                    }
+                }
+                stats::add_frames(1);
+            }
+            prev = Some(next);
+        }
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "analyze",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Processing crop {}/{}", i + 1, total),
+        ));
+    }
+
+    let n_rows = table.finish()?;
+    if let Some(out) = vectors {
+        out.finish()?;
+    }
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} rows to {}",
+        n_rows, args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifted_texture_is_recovered() {
+        let (h, w) = (40, 40);
+        let texture = |y: i64, x: i64| ((y * 7 + x * 13) ^ (x * y)).rem_euclid(251) as u16 * 100;
+        let frame = |dy: i64, dx: i64| -> Vec<u16> {
+            (0..h * w)
+                .map(|i| texture((i / w) as i64 - dy, (i % w) as i64 - dx))
+                .collect()
+        };
+        // Content moves 2 px down and 1 px left.
+        let (prev, next) = (frame(0, 0), frame(2, -1));
+        let params = PivParams {
+            window: 12,
+            step: 6,
+            max_shift: 4,
+        };
+        let field = piv(&prev, &next, h, w, params);
+        assert!(field.rows >= 3 && field.cols >= 3);
+        for v in &field.vectors {
+            let (dy, dx) = v.expect("textured window");
+            assert!(
+                (dy - 2.0).abs() < 0.1 && (dx + 1.0).abs() < 0.1,
+                "{dy} {dx}"
+            );
+        }
+        let (n, speed, div, curl) = field_stats(&field);
+        assert_eq!(n, field.rows * field.cols);
+        assert!((speed - 5f64.sqrt()).abs() < 0.1);
+        assert!(div.abs() < 0.01 && curl.abs() < 0.01);
+    }
+}
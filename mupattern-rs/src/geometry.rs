@@ -0,0 +1,133 @@
+//! Polar coordinates of crop pixels around the pattern center, shared by the angular
+//! (`sector`) and radial analyses, and the orientation of a frame's content (`rotation`).
+//! Angles are in degrees, counterclockwise from +x as seen on screen (image y points down).
+
+/// Where the pattern center of a crop is taken from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Center {
+    /// Geometric center of the crop (crops are cut around the pattern).
+    Crop,
+    /// Intensity-weighted centroid of each frame.
+    Centroid,
+}
+
+impl Center {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "crop" => Ok(Self::Crop),
+            "centroid" => Ok(Self::Centroid),
+            _ => Err(format!("Unknown center '{}' (expected crop or centroid)", s).into()),
+        }
+    }
+
+    /// (cy, cx) in pixel coordinates for an `h`x`w` frame.
+    pub fn locate(self, data: &[u16], h: usize, w: usize) -> (f64, f64) {
+        let crop_center = ((h as f64 - 1.0) / 2.0, (w as f64 - 1.0) / 2.0);
+        if self == Self::Crop {
+            return crop_center;
+        }
+        let (mut sum, mut sy, mut sx) = (0f64, 0f64, 0f64);
+        for (i, &v) in data.iter().enumerate() {
+            let v = v as f64;
+            sum += v;
+            sy += v * (i / w) as f64;
+            sx += v * (i % w) as f64;
+        }
+        if sum > 0.0 {
+            (sy / sum, sx / sum)
+        } else {
+            crop_center
+        }
+    }
+}
+
+/// Radius (pixels) and angle (degrees) of pixel (y, x) around `center`.
+pub fn polar(y: usize, x: usize, center: (f64, f64)) -> (f64, f64) {
+    let dy = center.0 - y as f64;
+    let dx = x as f64 - center.1;
+    let angle = dy.atan2(dx).to_degrees().rem_euclid(360.0);
+    (dy.hypot(dx), angle)
+}
+
+/// Index of the angular bin holding `angle` when the circle is split into `n` equal
+/// sectors starting at 0°.
+pub fn sector_of(angle: f64, n: usize) -> usize {
+    ((angle / 360.0 * n as f64) as usize).min(n - 1)
+}
+
+/// Orientation of the principal axis of `data` (an image `w` pixels wide, each pixel
+/// weighted by `weight`) from second-order central moments: (angle in (-90, 90] degrees,
+/// anisotropy in [0, 1], 0 = no preferred axis). `None` when the total weight is zero.
+pub fn orientation<T: Copy>(data: &[T], w: usize, weight: impl Fn(T) -> f64) -> Option<(f64, f64)> {
+    let (mut m, mut my, mut mx) = (0f64, 0f64, 0f64);
+    for (i, &v) in data.iter().enumerate() {
+        let k = weight(v);
+        m += k;
+        my += k * (i / w) as f64;
+        mx += k * (i % w) as f64;
+    }
+    if m <= 0.0 {
+        return None;
+    }
+    let (cy, cx) = (my / m, mx / m);
+    let (mut mu20, mut mu02, mut mu11) = (0f64, 0f64, 0f64);
+    for (i, &v) in data.iter().enumerate() {
+        let k = weight(v);
+        let (dy, dx) = ((i / w) as f64 - cy, (i % w) as f64 - cx);
+        mu20 += k * dx * dx;
+        mu02 += k * dy * dy;
+        mu11 += k * dx * dy;
+    }
+    // Flip y so the angle turns counterclockwise on screen.
+    let angle = 0.5 * (-2.0 * mu11).atan2(mu20 - mu02);
+    let spread = mu20 + mu02;
+    let anisotropy = if spread > 0.0 {
+        (mu20 - mu02).hypot(2.0 * mu11) / spread
+    } else {
+        0.0
+    };
+    let mut deg = angle.to_degrees();
+    if deg <= -90.0 {
+        deg += 180.0;
+    }
+    Some((deg, anisotropy))
+}
+
+/// Continue an axis angle (defined modulo 180°) from the previous unwrapped value, taking
+/// the smallest turn.
+pub fn unwrap_axis(previous: f64, angle: f64) -> f64 {
+    let turn = (angle - previous + 90.0).rem_euclid(180.0) - 90.0;
+    previous + turn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angles_run_counterclockwise_on_screen() {
+        let center = (1.0, 1.0);
+        // Right, up (smaller y), left, down of the center pixel in a 3x3 crop.
+        let angles: Vec<usize> = [(1, 2), (0, 1), (1, 0), (2, 1)]
+            .iter()
+            .map(|&(y, x)| sector_of(polar(y, x, center).1 + 45.0, 4))
+            .collect();
+        assert_eq!(angles, vec![0, 1, 2, 3]);
+        assert_eq!(
+            Center::Centroid.locate(&[0, 0, 0, 0, 0, 9], 2, 3),
+            (1.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn axis_orientation_and_unwrapping() {
+        // Diagonal from bottom-left to top-right of a 3x3 frame: 45° on screen.
+        let data = [0, 0, 1, 0, 1, 0, 1, 0, 0];
+        let (angle, anisotropy) = orientation(&data, 3, |v| v as f64).unwrap();
+        assert!((angle - 45.0).abs() < 1e-9 && (anisotropy - 1.0).abs() < 1e-9);
+        assert_eq!(orientation(&[0; 4], 2, |v| v as f64), None);
+        // 80° -> -80° is a 20° counterclockwise turn, not 160° clockwise.
+        assert_eq!(unwrap_axis(80.0, -80.0), 100.0);
+        assert_eq!(unwrap_axis(370.0, 5.0), 365.0);
+    }
+}
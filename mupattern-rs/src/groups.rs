@@ -0,0 +1,198 @@
+//! Commands grouped by pipeline stage (`mupattern analyze kill ...`). The flat names
+//! (`mupattern kill ...`) stay as hidden aliases, so scripts and the desktop app keep
+//! working; `--help-json` describes the whole tree for the GUI.
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use serde_json::{json, Value};
+
+/// (group, help, commands) in help order.
+const GROUPS: &[(&str, &str, &[&str])] = &[
+    (
+        "ingest",
+        "Bring data in: ND2 conversion, cropping, external masks",
+        &["convert", "crop", "import-masks"],
+    ),
+    (
+        "analyze",
+        "Per-crop measurements and classification",
+        &[
+            "defects",
+            "expression",
+            "flow",
+            "kill",
+            "kill-qc",
+            "mask-stats",
+            "rotation",
+            "sector",
+            "smooth",
+            "spot",
+            "survival",
+            "tissue",
+        ],
+    ),
+    (
+        "visualize",
+        "Movies, snapshots, reports and the zarr viewer server",
+        &["heatmap", "movie", "report", "serve-zarr", "snapshot"],
+    ),
+    (
+        "utils",
+        "Validation, mask round trips and classifier datasets",
+        &[
+            "audit-background",
+            "export-masks",
+            "export-training",
+            "select-uncertain",
+            "validate",
+        ],
+    ),
+];
+
+/// Add the stage groups to `cmd`, hide the flat commands they contain and declare
+/// `--help-json` (handled before parsing, see `help_json`).
+pub fn group(mut cmd: Command) -> Command {
+    cmd = cmd.arg(
+        Arg::new("help_json")
+            .long("help-json")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Print the command tree (or the command named before it) with every flag as JSON and exit"),
+    );
+    for (name, about, members) in GROUPS {
+        let leaves: Vec<Command> = members
+            .iter()
+            .filter_map(|m| cmd.find_subcommand(m).cloned())
+            .collect();
+        if leaves.is_empty() {
+            continue;
+        }
+        cmd = cmd.subcommand(
+            Command::new(*name)
+                .about(*about)
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommands(leaves),
+        );
+    }
+    for member in GROUPS.iter().flat_map(|(_, _, members)| members.iter()) {
+        if cmd.find_subcommand(member).is_some() {
+            cmd = cmd.mut_subcommand(member, |c| c.hide(true));
+        }
+    }
+    cmd
+}
+
+fn is_group(name: &str) -> bool {
+    GROUPS.iter().any(|(group, _, _)| *group == name)
+}
+
+fn is_grouped(name: &str) -> bool {
+    GROUPS.iter().any(|(_, _, members)| members.contains(&name))
+}
+
+/// Matches as if the flat name had been used: `analyze kill ...` becomes `kill ...`.
+/// Global flags are present at every level, so nothing is lost by dropping the group.
+pub fn flatten(mut matches: ArgMatches) -> ArgMatches {
+    if matches.subcommand_name().is_some_and(is_group) {
+        if let Some((_, inner)) = matches.remove_subcommand() {
+            return inner;
+        }
+    }
+    matches
+}
+
+fn arg_json(arg: &clap::Arg) -> Value {
+    json!({
+        "name": arg.get_long(),
+        "help": arg.get_help().map(|h| h.to_string()),
+        "required": arg.is_required_set(),
+        "takes_value": arg.get_action().takes_values(),
+        "default": arg
+            .get_default_values()
+            .iter()
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+        "env": arg.get_env().map(|e| e.to_string_lossy().into_owned()),
+        "global": arg.is_global_set(),
+    })
+}
+
+/// JSON for `cmd` and its visible subcommands; `alias` is the top-level name a grouped
+/// command also runs under.
+fn command_json(cmd: &Command) -> Value {
+    let subcommands: Vec<Value> = cmd
+        .get_subcommands()
+        .filter(|s| !s.is_hide_set())
+        .map(command_json)
+        .collect();
+    json!({
+        "name": cmd.get_name(),
+        "about": cmd.get_about().map(|a| a.to_string()),
+        "alias": is_grouped(cmd.get_name()).then(|| cmd.get_name()),
+        "args": cmd
+            .get_arguments()
+            .filter(|a| !a.is_hide_set() && a.get_long().is_some())
+            .map(arg_json)
+            .collect::<Vec<_>>(),
+        "subcommands": subcommands,
+    })
+}
+
+/// JSON description of the command addressed by the non-flag words in `argv` (the whole
+/// CLI if they name none), for `--help-json`.
+pub fn help_json(cmd: &Command, argv: &[String]) -> Value {
+    let mut target = cmd;
+    for word in argv.iter().skip(1).filter(|w| !w.starts_with('-')) {
+        if let Some(sub) = target.find_subcommand(word) {
+            target = sub;
+        }
+    }
+    command_json(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli() -> Command {
+        group(
+            Command::new("mupattern")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("quiet")
+                        .long("quiet")
+                        .global(true)
+                        .action(ArgAction::SetTrue),
+                )
+                .subcommand(Command::new("kill").arg(Arg::new("model").long("model")))
+                .subcommand(Command::new("movie")),
+        )
+    }
+
+    #[test]
+    fn grouped_and_flat_names_parse_alike() {
+        for argv in [
+            vec!["mupattern", "--quiet", "analyze", "kill", "--model", "m"],
+            vec!["mupattern", "kill", "--quiet", "--model", "m"],
+        ] {
+            let matches = flatten(cli().get_matches_from(argv));
+            assert!(matches.get_flag("quiet"));
+            let (name, kill) = matches.subcommand().unwrap();
+            assert_eq!(name, "kill");
+            assert_eq!(
+                kill.get_one::<String>("model").map(String::as_str),
+                Some("m")
+            );
+        }
+
+        let tree = help_json(&cli(), &["mupattern".to_string()]);
+        let names: Vec<&str> = tree["subcommands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["analyze", "visualize"]);
+        assert_eq!(tree["subcommands"][0]["subcommands"][0]["alias"], "kill");
+    }
+}
@@ -0,0 +1,312 @@
+//! Heatmap: one per-crop metric drawn on the chip layout. Every pattern of the bbox CSV is
+//! filled with its metric through a colormap (patterns without a value stay gray), with a
+//! labelled color bar underneath, so spatial artifacts such as edge effects or a bad
+//! pipetting corner stand out at a glance.
+
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::crop::{self, Bbox};
+use crate::manifest;
+use crate::movie;
+use crate::output;
+use crate::overlay::{self, Rgb, WHITE};
+use crate::progress::ProgressEvent;
+
+const BACKGROUND: Rgb = [24, 24, 24];
+const MISSING: Rgb = [80, 80, 80];
+
+#[derive(Args, Clone)]
+pub struct HeatmapArgs {
+    /// Per-crop metric CSV with a crop column (e.g. expression, kill-qc --summary, survival --crops)
+    #[arg(long)]
+    pub metrics: String,
+    /// Metric column to color by
+    #[arg(long)]
+    pub column: String,
+    /// Bbox CSV giving the pattern layout (crop,x,y,w,h)
+    #[arg(long)]
+    pub bbox: String,
+    /// Output PNG
+    #[arg(long)]
+    pub output: String,
+    /// Only rows of this position, when the metrics have a pos column
+    #[arg(long)]
+    pub pos: Option<u32>,
+    /// Only rows at this frame, when the metrics have a t column
+    #[arg(long)]
+    pub t: Option<u64>,
+    /// How crops with several rows are summarized: mean, median, min or max
+    #[arg(long, default_value = "mean")]
+    pub reduce: String,
+    /// Color range "lo,hi" (default: the metric's min and max)
+    #[arg(long)]
+    pub range: Option<String>,
+    /// grayscale, hot or viridis
+    #[arg(long, default_value = "viridis")]
+    pub colormap: String,
+    /// Output pixels per frame pixel
+    #[arg(long, default_value_t = 0.25)]
+    pub scale: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Reduce {
+    Mean,
+    Median,
+    Min,
+    Max,
+}
+
+fn parse_reduce(s: &str) -> Result<Reduce, Box<dyn std::error::Error>> {
+    match s.to_lowercase().as_str() {
+        "mean" => Ok(Reduce::Mean),
+        "median" => Ok(Reduce::Median),
+        "min" => Ok(Reduce::Min),
+        "max" => Ok(Reduce::Max),
+        _ => Err(format!("Unknown --reduce {:?} (use mean, median, min or max)", s).into()),
+    }
+}
+
+impl Reduce {
+    fn apply(self, values: &mut [f64]) -> f64 {
+        match self {
+            Reduce::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Reduce::Median => {
+                values.sort_by(|a, b| a.total_cmp(b));
+                let mid = values.len() / 2;
+                if values.len() % 2 == 1 {
+                    values[mid]
+                } else {
+                    (values[mid - 1] + values[mid]) / 2.0
+                }
+            }
+            Reduce::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Reduce::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+/// Metric per crop directory name. Empty and non-numeric cells are skipped, and rows of
+/// other positions or frames are dropped when `pos` / `t` are given and the CSV has that
+/// column.
+fn load_metric(
+    path: &str,
+    column: &str,
+    pos: Option<u32>,
+    t: Option<u64>,
+    reduce: Reduce,
+) -> Result<BTreeMap<String, f64>, Box<dyn std::error::Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let col = |name: &str| headers.iter().position(|h| h == name);
+    let crop_idx = col("crop").ok_or_else(|| format!("{} has no crop column", path))?;
+    let value_idx =
+        col(&column.to_lowercase()).ok_or_else(|| format!("{} has no {} column", path, column))?;
+    let filters: Vec<(usize, u64)> = [col("pos").zip(pos.map(u64::from)), col("t").zip(t)]
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let mut values: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for record in rdr.records() {
+        let record = record?;
+        let field = |i: usize| record.get(i).unwrap_or("");
+        if filters
+            .iter()
+            .any(|&(i, want)| field(i).parse::<u64>().ok() != Some(want))
+        {
+            continue;
+        }
+        let value = match field(value_idx) {
+            "true" | "True" => 1.0,
+            "false" | "False" => 0.0,
+            s => match s.parse::<f64>() {
+                Ok(v) if v.is_finite() => v,
+                _ => continue,
+            },
+        };
+        values
+            .entry(crop::crop_dir_name(field(crop_idx))?)
+            .or_default()
+            .push(value);
+    }
+    Ok(values
+        .into_iter()
+        .map(|(crop, mut v)| (crop, reduce.apply(&mut v)))
+        .collect())
+}
+
+fn parse_range(s: &str) -> Result<(f64, f64), Box<dyn std::error::Error>> {
+    let (lo, hi) = s
+        .split_once(',')
+        .ok_or_else(|| format!("Invalid --range {:?} (use lo,hi)", s))?;
+    let (lo, hi): (f64, f64) = (lo.trim().parse()?, hi.trim().parse()?);
+    if lo >= hi {
+        return Err(format!("Invalid --range {:?}: lo must be below hi", s).into());
+    }
+    Ok((lo, hi))
+}
+
+fn color(v: f64, (lo, hi): (f64, f64), colormap: &str) -> Rgb {
+    let norm = if hi > lo { (v - lo) / (hi - lo) } else { 0.5 };
+    let (r, g, b) = movie::apply_colormap(norm, colormap);
+    [r, g, b]
+}
+
+pub fn run(
+    args: HeatmapArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !args.scale.is_finite() || args.scale <= 0.0 {
+        return Err("--scale must be positive".into());
+    }
+    let reduce = parse_reduce(&args.reduce)?;
+    if !["grayscale", "hot", "viridis"].contains(&args.colormap.to_lowercase().as_str()) {
+        return Err(format!(
+            "Unknown --colormap {:?} (use grayscale, hot or viridis)",
+            args.colormap
+        )
+        .into());
+    }
+    let bboxes: Vec<Bbox> = crop::parse_bbox_csv(Path::new(&args.bbox), None)?;
+    if bboxes.is_empty() {
+        return Err(format!("No patterns in {}", args.bbox).into());
+    }
+    let metric = load_metric(&args.metrics, &args.column, args.pos, args.t, reduce)?;
+    let values: Vec<f64> = bboxes
+        .iter()
+        .filter_map(|b| metric.get(&b.id).copied())
+        .collect();
+    if values.is_empty() {
+        return Err(format!(
+            "No {} values in {} match the crops of {}",
+            args.column, args.metrics, args.bbox
+        )
+        .into());
+    }
+    let range = match &args.range {
+        Some(s) => parse_range(s)?,
+        None => (
+            values.iter().copied().fold(f64::INFINITY, f64::min),
+            values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        ),
+    };
+
+    let scaled = |v: u32| (v as f64 * args.scale).round() as i64;
+    let chip_w = bboxes
+        .iter()
+        .map(|b| scaled(b.x + b.w))
+        .max()
+        .unwrap_or(0)
+        .max(1) as u64;
+    let chip_h = bboxes
+        .iter()
+        .map(|b| scaled(b.y + b.h))
+        .max()
+        .unwrap_or(0)
+        .max(1) as u64;
+    let text_scale = overlay::text_scale(chip_h.max(256));
+    let line = overlay::line_height(text_scale) as i64;
+    let (w, h) = (chip_w, chip_h + 3 * line as u64);
+    let mut rgb = vec![0u8; (w * h * 3) as usize];
+    overlay::fill_rect(
+        &mut rgb,
+        w,
+        (w, h),
+        (0, 0),
+        (w as i64, h as i64),
+        BACKGROUND,
+    );
+
+    for b in &bboxes {
+        let fill = metric
+            .get(&b.id)
+            .map_or(MISSING, |&v| color(v, range, &args.colormap));
+        let (x0, y0) = (scaled(b.x), scaled(b.y));
+        // Keep every pattern at least one pixel wide so small scales do not drop any.
+        let (x1, y1) = (scaled(b.x + b.w).max(x0 + 1), scaled(b.y + b.h).max(y0 + 1));
+        overlay::fill_rect(&mut rgb, w, (w, h), (x0, y0), (x1, y1), fill);
+    }
+
+    // Color bar: gradient strip, then "lo ... column ... hi" underneath.
+    let bar_top = chip_h as i64 + line / 2;
+    for x in 0..w as i64 {
+        let v = range.0 + (range.1 - range.0) * x as f64 / (w as f64 - 1.0).max(1.0);
+        let fill = color(v, range, &args.colormap);
+        overlay::fill_rect(
+            &mut rgb,
+            w,
+            (w, h),
+            (x, bar_top),
+            (x + 1, bar_top + line),
+            fill,
+        );
+    }
+    let text_y = bar_top + line + line / 4;
+    let lo = output::format_float(range.0);
+    let hi = output::format_float(range.1);
+    let dims = (w, h);
+    overlay::draw_text(&mut rgb, w, dims, (0, text_y), &lo, WHITE, text_scale);
+    let hi_x = w as i64 - overlay::text_width(&hi, text_scale) as i64;
+    overlay::draw_text(&mut rgb, w, dims, (hi_x, text_y), &hi, WHITE, text_scale);
+    let mid_x = (w as i64 - overlay::text_width(&args.column, text_scale) as i64) / 2;
+    overlay::draw_text(
+        &mut rgb,
+        w,
+        dims,
+        (mid_x, text_y),
+        &args.column,
+        WHITE,
+        text_scale,
+    );
+
+    let out_path = Path::new(&args.output);
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    image::RgbImage::from_raw(w as u32, h as u32, rgb)
+        .ok_or("Heatmap buffer size mismatch")?
+        .save(out_path)?;
+    manifest::image(&args.output, w, h);
+    progress(ProgressEvent::done(&format!(
+        "Wrote heatmap of {}/{} patterns ({} {}..{}) to {}",
+        values.len(),
+        bboxes.len(),
+        args.column,
+        lo,
+        hi,
+        args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_filtered_and_reduced_per_crop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("expression.csv");
+        std::fs::write(
+            &path,
+            "# schema: mupattern-expression/1\nt,crop,intensity\n0,0,1\n1,0,3\n1,A01,5\n2,0,11\n1,7,\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        let mean = load_metric(path, "intensity", None, None, Reduce::Mean).unwrap();
+        assert_eq!(mean.get("000"), Some(&5.0));
+        assert_eq!(mean.get("A01"), Some(&5.0));
+        assert!(!mean.contains_key("007"));
+        let at_1 = load_metric(path, "intensity", Some(3), Some(1), Reduce::Max).unwrap();
+        assert_eq!(at_1.get("000"), Some(&3.0));
+        assert_eq!(Reduce::Median.apply(&mut [4.0, 1.0, 2.0, 3.0]), 2.5);
+        assert!(load_metric(path, "area", None, None, Reduce::Mean).is_err());
+        assert!(parse_range("5,1").is_err());
+    }
+}
@@ -0,0 +1,481 @@
+//! Import label images from other tools (Python Cellpose, napari annotations) into the
+//! masks.zarr layout written by `tissue`, so `tissue --stage analyze`, `spot --masks`,
+//! `mask-stats` and the viewers can use them. Each crop's labels come from
+//! `{crop}.tif`/`.tiff` (one page per frame), `{crop}.npy` or a `{crop}/` folder of
+//! per-frame images in the source folder and must match the crop's (T, H, W) from
+//! crops.zarr. Per-frame folders are what `export-masks` writes, so hand-corrected frames
+//! go back in with `--merge`, leaving every other frame untouched.
+
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cancel;
+use crate::console;
+use crate::crop_index;
+use crate::lock::WriteLock;
+use crate::manifest;
+use crate::outline;
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::tissue;
+use crate::zarr;
+
+#[derive(Args, Clone)]
+pub struct ImportMasksArgs {
+    /// Path to crops.zarr (crop shapes to align with)
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Folder with labels per crop: {crop}.tif/.tiff (one page per frame), {crop}.npy
+    /// ((T, H, W), or (H, W) for single-frame crops) or a {crop}/ folder of per-frame
+    /// {t}.tif/.png images (the `export-masks` layout)
+    #[arg(long)]
+    pub source: String,
+    /// masks.zarr to write; arrays of imported crops are replaced
+    #[arg(long)]
+    pub output: String,
+    /// Write masks as uint16 (fails on labels above 65535)
+    #[arg(long)]
+    pub u16_masks: bool,
+    /// Apply the labels as corrections to existing arrays in --output: frames whose labels
+    /// differ are rewritten, frames without a file or with identical labels are kept
+    #[arg(long)]
+    pub merge: bool,
+}
+
+/// Labels of one source file in C order with the shape they were stored in.
+struct Labels {
+    shape: Vec<u64>,
+    data: Vec<u32>,
+}
+
+fn label_u32<T: Copy + TryInto<u32>>(
+    values: Vec<T>,
+) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    values
+        .into_iter()
+        .map(|v| {
+            v.try_into()
+                .map_err(|_| "labels must be non-negative and fit in u32".into())
+        })
+        .collect()
+}
+
+/// Every page of a label TIFF, stacked along a leading t axis.
+fn read_tiff_stack(path: &Path) -> Result<Labels, Box<dyn std::error::Error>> {
+    use tiff::decoder::DecodingResult;
+
+    let mut decoder = tiff::decoder::Decoder::new(fs::File::open(path)?)?;
+    let (width, height) = decoder.dimensions()?;
+    let mut data = Vec::new();
+    let mut pages = 0u64;
+    loop {
+        if decoder.dimensions()? != (width, height) {
+            return Err(format!("{}: pages differ in size", path.display()).into());
+        }
+        data.extend(match decoder.read_image()? {
+            DecodingResult::U8(v) => label_u32(v)?,
+            DecodingResult::U16(v) => label_u32(v)?,
+            DecodingResult::U32(v) => v,
+            DecodingResult::U64(v) => label_u32(v)?,
+            DecodingResult::I8(v) => label_u32(v)?,
+            DecodingResult::I16(v) => label_u32(v)?,
+            DecodingResult::I32(v) => label_u32(v)?,
+            DecodingResult::I64(v) => label_u32(v)?,
+            _ => return Err(format!("{}: labels must be integers", path.display()).into()),
+        });
+        pages += 1;
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image()?;
+    }
+    stats::add_bytes_read(data.len() as u64 * 4);
+    Ok(Labels {
+        shape: vec![pages, height as u64, width as u64],
+        data,
+    })
+}
+
+/// Integer arrays in NumPy's `.npy` format (little-endian, C order).
+fn parse_npy(bytes: &[u8]) -> Result<Labels, Box<dyn std::error::Error>> {
+    if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+        return Err("not a .npy file".into());
+    }
+    let (header_len, start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        _ if bytes.len() >= 12 => (
+            u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+            12,
+        ),
+        _ => return Err("truncated .npy header".into()),
+    };
+    let header = std::str::from_utf8(
+        bytes
+            .get(start..start + header_len)
+            .ok_or("truncated .npy header")?,
+    )?;
+
+    let descr = regex::Regex::new(r"'descr':\s*'([<|=])([uib])(\d+)'")?
+        .captures(header)
+        .ok_or_else(|| format!("unsupported .npy dtype (need little-endian integers): {header}"))?;
+    let kind = &descr[2];
+    let size: usize = descr[3].parse()?;
+    if ![1, 2, 4, 8].contains(&size) {
+        return Err(format!("unsupported .npy element size {size}").into());
+    }
+    if regex::Regex::new(r"'fortran_order':\s*True")?.is_match(header) {
+        return Err(".npy arrays must be in C order".into());
+    }
+    let dims = regex::Regex::new(r"'shape':\s*\(([^)]*)\)")?
+        .captures(header)
+        .ok_or("missing .npy shape")?;
+    let shape: Vec<u64> = dims[1]
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+
+    let body = &bytes[start + header_len..];
+    let n = shape.iter().product::<u64>() as usize;
+    if body.len() < n * size {
+        return Err(format!(
+            ".npy data holds {} bytes, shape needs {}",
+            body.len(),
+            n * size
+        )
+        .into());
+    }
+    let data = body[..n * size]
+        .chunks_exact(size)
+        .map(|b| {
+            if kind == "i" && b[size - 1] & 0x80 != 0 {
+                return Err("labels must be non-negative".into());
+            }
+            let v = b
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &byte| (acc << 8) | byte as u64);
+            u32::try_from(v).map_err(|_| "labels must fit in u32".into())
+        })
+        .collect::<Result<Vec<u32>, Box<dyn std::error::Error>>>()?;
+    Ok(Labels { shape, data })
+}
+
+fn read_labels(path: &Path) -> Result<Labels, Box<dyn std::error::Error>> {
+    if path.extension().is_some_and(|e| e == "npy") {
+        let bytes = fs::read(path)?;
+        stats::add_bytes_read(bytes.len() as u64);
+        parse_npy(&bytes).map_err(|e| format!("{}: {}", path.display(), e).into())
+    } else {
+        read_tiff_stack(path)
+    }
+}
+
+/// Labels of a single-frame PNG (8 or 16-bit grayscale, as written by `export-masks`).
+fn read_png_frame(path: &Path) -> Result<Labels, Box<dyn std::error::Error>> {
+    let img = image::open(path)?;
+    let (width, height) = (img.width() as u64, img.height() as u64);
+    let data: Vec<u32> = match img {
+        image::DynamicImage::ImageLuma8(buf) => buf.into_raw().into_iter().map(u32::from).collect(),
+        image::DynamicImage::ImageLuma16(buf) => {
+            buf.into_raw().into_iter().map(u32::from).collect()
+        }
+        _ => {
+            return Err(format!(
+                "{}: PNG labels must be 8 or 16-bit grayscale",
+                path.display()
+            )
+            .into())
+        }
+    };
+    stats::add_bytes_read(data.len() as u64 * 2);
+    Ok(Labels {
+        shape: vec![height, width],
+        data,
+    })
+}
+
+/// Where a crop's labels come from.
+enum Source {
+    /// `{crop}.npy` / `{crop}.tif`: every frame in one file.
+    Stack(PathBuf),
+    /// `{crop}/{t}.tif|.tiff|.png` (the `export-masks` layout): one file per frame, sorted by t.
+    Frames(Vec<(u64, PathBuf)>),
+}
+
+/// Source for a crop, if any: a per-frame folder first, then `.npy`, `.tif`, `.tiff`.
+fn source_for(dir: &Path, crop_id: &str) -> Result<Option<Source>, Box<dyn std::error::Error>> {
+    // External tools often drop the zero padding ("7" for crop "007").
+    let mut names = vec![crop_id.to_string()];
+    if let Some(unpadded) = crop_id.parse::<u64>().ok().map(|n| n.to_string()) {
+        if unpadded != crop_id {
+            names.push(unpadded);
+        }
+    }
+    for name in &names {
+        let frame_dir = dir.join(name);
+        if frame_dir.is_dir() {
+            let mut frames: Vec<(u64, PathBuf)> = fs::read_dir(&frame_dir)?
+                .filter_map(|e| {
+                    let path = e.ok()?.path();
+                    let ext = path.extension()?.to_str()?.to_lowercase();
+                    if !["tif", "tiff", "png"].contains(&ext.as_str()) {
+                        return None;
+                    }
+                    let t = path.file_stem()?.to_str()?.parse().ok()?;
+                    Some((t, path))
+                })
+                .collect();
+            frames.sort();
+            return Ok(Some(Source::Frames(frames)));
+        }
+        let stack = ["npy", "tif", "tiff"]
+            .iter()
+            .map(|ext| dir.join(format!("{name}.{ext}")))
+            .find(|p| p.is_file());
+        if let Some(path) = stack {
+            return Ok(Some(Source::Stack(path)));
+        }
+    }
+    Ok(None)
+}
+
+/// (t, labels) of one frame.
+type Frame = (u64, Vec<u32>);
+
+/// Frames of a source, each checked against the crop's (T, H, W).
+fn load_frames(
+    source: &Source,
+    crop_id: &str,
+    shape: &[u64],
+) -> Result<Vec<Frame>, Box<dyn std::error::Error>> {
+    let mismatch = |path: &Path, got: &[u64]| -> Box<dyn std::error::Error> {
+        format!(
+            "{}: shape {:?} does not match crop {} (T, H, W) = {:?}",
+            path.display(),
+            got,
+            crop_id,
+            shape
+        )
+        .into()
+    };
+    match source {
+        Source::Stack(path) => {
+            let labels = read_labels(path)?;
+            if labels.shape != shape && !(shape[0] == 1 && labels.shape == shape[1..]) {
+                return Err(mismatch(path, &labels.shape));
+            }
+            let frame_len = (shape[1] * shape[2]) as usize;
+            Ok(labels
+                .data
+                .chunks_exact(frame_len)
+                .enumerate()
+                .map(|(t, frame)| (t as u64, frame.to_vec()))
+                .collect())
+        }
+        Source::Frames(files) => files
+            .iter()
+            .map(|(t, path)| {
+                if *t >= shape[0] {
+                    return Err(format!(
+                        "{}: crop {} has {} frames",
+                        path.display(),
+                        crop_id,
+                        shape[0]
+                    )
+                    .into());
+                }
+                let labels = if path
+                    .extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+                {
+                    read_png_frame(path)?
+                } else {
+                    read_tiff_stack(path)?
+                };
+                let single = labels.shape == shape[1..] || labels.shape == [1, shape[1], shape[2]];
+                if !single {
+                    return Err(mismatch(path, &labels.shape));
+                }
+                Ok((*t, labels.data))
+            })
+            .collect(),
+    }
+}
+
+pub fn run(
+    args: ImportMasksArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let crops_zarr = Path::new(&args.input);
+    let source = Path::new(&args.source);
+    let pos_id = format!("{:03}", args.pos);
+    let crop_root = crops_zarr.join("pos").join(&pos_id).join("crop");
+    if !crop_root.exists() {
+        return Err("No crops found. Run crop task first.".into());
+    }
+    if !source.is_dir() {
+        return Err(format!("Source folder not found: {}", source.display()).into());
+    }
+
+    let crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let mut matched: Vec<(String, Source)> = Vec::new();
+    for crop_id in &crop_ids {
+        if let Some(src) = source_for(source, crop_id)? {
+            matched.push((crop_id.clone(), src));
+        }
+    }
+    if matched.is_empty() {
+        return Err(format!(
+            "No {{crop}}.npy/.tif files or {{crop}}/ folders for position {} in {}",
+            args.pos,
+            source.display()
+        )
+        .into());
+    }
+    if matched.len() < crop_ids.len() {
+        console::warn(&format!(
+            "no labels for {} of {} crop(s); they are left out",
+            crop_ids.len() - matched.len(),
+            crop_ids.len()
+        ));
+    }
+
+    let masks_path = Path::new(&args.output);
+    let crop_store = zarr::open_store(crops_zarr)?;
+    let _lock = WriteLock::acquire(masks_path, "import-masks")?;
+    let mask_store = zarr::open_store(masks_path)?;
+    tissue::ensure_mask_groups(&mask_store, &pos_id)?;
+    stats::stage("write");
+    let total = matched.len();
+    let mut changed = 0u64;
+
+    for (i, (crop_id, src)) in matched.iter().enumerate() {
+        let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
+        let crop_shape = zarr::open_array(&crop_store, &array_path)?.shape().to_vec();
+        let shape = vec![crop_shape[0], crop_shape[3], crop_shape[4]];
+        let frames = load_frames(src, crop_id, &shape)?;
+
+        let existing = if args.merge {
+            zarr::open_array(&mask_store, &array_path)
+                .ok()
+                .filter(|a| a.shape() == shape.as_slice())
+        } else {
+            None
+        };
+        let written = changed;
+        match existing {
+            Some(mask_arr) => {
+                // Only frames whose labels differ are rewritten; the rest stay as they are.
+                let u16_masks = !zarr::is_u32(&mask_arr);
+                for (t, labels) in frames {
+                    if zarr::read_labels(&mask_arr, t)? != labels {
+                        tissue::store_masks(&mask_arr, t, labels, u16_masks)?;
+                        changed += 1;
+                    }
+                    stats::add_frames(1);
+                }
+            }
+            None => {
+                if frames.len() as u64 != shape[0] {
+                    let hint = if args.merge {
+                        "no existing masks of that shape to merge into"
+                    } else {
+                        "pass --merge to keep the other frames"
+                    };
+                    return Err(format!(
+                        "Crop {}: labels for {} of {} frames; {}",
+                        crop_id,
+                        frames.len(),
+                        shape[0],
+                        hint
+                    )
+                    .into());
+                }
+                let mask_arr =
+                    tissue::create_mask_array(&mask_store, &array_path, &shape, args.u16_masks)?;
+                for (t, labels) in frames {
+                    tissue::store_masks(&mask_arr, t, labels, args.u16_masks)?;
+                    changed += 1;
+                    stats::add_frames(1);
+                }
+            }
+        }
+        if changed > written {
+            // Stored outlines (tissue --outlines) would show the old labels.
+            outline::remove(&mask_store, &pos_id, crop_id)?;
+        }
+        manifest::array(masks_path, &array_path, &shape);
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "write",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Imported crop {}/{}", i + 1, total),
+        ));
+    }
+
+    progress(ProgressEvent::done(&format!(
+        "Imported masks for {} crop(s) into {} ({} frame(s) written)",
+        total, args.output, changed
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn npy(descr: &str, shape: &str, body: &[u8]) -> Vec<u8> {
+        let header =
+            format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}\n");
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn npy_labels_are_widened_and_validated() {
+        let labels = parse_npy(&npy("<u2", "(2, 1, 2)", &[1, 0, 0, 1, 7, 0, 0, 0])).unwrap();
+        assert_eq!(labels.shape, vec![2, 1, 2]);
+        assert_eq!(labels.data, vec![1, 256, 7, 0]);
+        let big = parse_npy(&npy("<i8", "(1,)", &70_000i64.to_le_bytes())).unwrap();
+        assert_eq!((big.shape, big.data), (vec![1], vec![70_000]));
+        assert!(parse_npy(&npy("<i4", "(1,)", &(-1i32).to_le_bytes())).is_err());
+        assert!(parse_npy(&npy("<f4", "(1,)", &[0; 4])).is_err());
+    }
+
+    #[test]
+    fn exported_frames_load_back() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::export_masks::{write_frame, Format};
+
+        let dir = tempfile::TempDir::new()?;
+        let crop = dir.path().join("007");
+        fs::create_dir_all(&crop)?;
+        write_frame(
+            &crop.join("0000.tif"),
+            Format::Tif,
+            vec![70_000, 0, 2, 2],
+            2,
+            2,
+        )?;
+        write_frame(&crop.join("0002.png"), Format::Png, vec![1, 1, 0, 3], 2, 2)?;
+        assert!(write_frame(&crop.join("x.png"), Format::Png, vec![70_000; 4], 2, 2).is_err());
+
+        let source = source_for(dir.path(), "007")?.expect("frame folder");
+        let frames = load_frames(&source, "007", &[3, 2, 2])?;
+        assert_eq!(
+            frames,
+            vec![(0, vec![70_000, 0, 2, 2]), (2, vec![1, 1, 0, 3])]
+        );
+        assert!(load_frames(&source, "007", &[3, 2, 3]).is_err());
+        Ok(())
+    }
+}
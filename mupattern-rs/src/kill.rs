@@ -1,25 +1,60 @@
 //! Kill predict: ONNX inference for binary cell presence (absent/present).
 //! Expects model dir with model.onnx.
-//! Input: NCHW float32 [N, 3, 224, 224], ImageNet normalization.
+//! Input: NCHW float32 [N, 3, H, W], ImageNet normalization. H and W come from the model's
+//! input shape (DEFAULT_IMAGE_SIZE when the export has dynamic spatial axes).
 
 use clap::Args;
 use image::{imageops::FilterType, GrayImage, ImageBuffer, Luma};
-use ndarray::{Array, ArrayViewD, Ix4};
-use ort::session::Session;
-use ort::value::Tensor;
-#[cfg(any(windows, target_os = "linux"))]
-use ort::ep::{CUDA, ExecutionProvider};
-use std::collections::HashMap;
-use std::fs;
+use ndarray::{Array, Ix4};
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::path::Path;
 
+use crate::cancel;
+use crate::console;
+use crate::crop;
+use crate::crop_index;
+use crate::detector::DetectorRange;
+use crate::exclude::ExclusionList;
+use crate::memory;
+use crate::output::{ColumnType, OutputFormat, Schema, TableWriter, Value};
+use crate::pixelmath;
+use crate::progress::ProgressEvent;
+use crate::provenance::Provenance;
+use crate::session::{self, Precision};
+use crate::slices;
+use crate::stats;
+use crate::treatment;
 use crate::zarr;
 
-const IMAGE_SIZE: u32 = 224;
+pub(crate) const DEFAULT_IMAGE_SIZE: u32 = 224;
 const IMAGENET_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
 const IMAGENET_STD: [f32; 3] = [0.229, 0.224, 0.225];
 
+const SCHEMA: Schema = Schema {
+    table: "kill",
+    version: 2,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("label", ColumnType::Integer),
+        ("probability", ColumnType::Real),
+    ],
+};
+
+/// `SCHEMA` plus `t_treatment` (`--treatment-frame` / `--treatments`).
+const TREATMENT_SCHEMA: Schema = Schema {
+    table: "kill_treatment",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("t_treatment", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("label", ColumnType::Integer),
+        ("probability", ColumnType::Real),
+    ],
+};
+
 #[derive(Args, Clone)]
 pub struct KillArgs {
     #[arg(long)]
@@ -28,13 +63,61 @@ pub struct KillArgs {
     pub pos: u32,
     #[arg(long)]
     pub model: String,
+    /// Output path: CSV, SQLite when ending in .sqlite/.db, or Arrow IPC stream when ending in .arrows
     #[arg(long)]
     pub output: String,
+    /// Table backend: auto (from the output extension) | csv | sqlite | arrow-stream (record
+    /// batches appended as results come in, readable while the run continues)
+    #[arg(long, default_value = "auto")]
+    pub output_format: String,
     #[arg(long, default_value_t = 256)]
     pub batch_size: usize,
     /// Force CPU (skip CUDA). Use if GPU path hangs.
     #[arg(long)]
     pub cpu: bool,
+    /// Model precision: auto | fp32 (model.onnx) | fp16 (model_fp16.onnx) | int8 (model_quantized.onnx)
+    #[arg(long, default_value = "auto")]
+    pub precision: String,
+    /// Per-frame intensity normalization before resizing: minmax | percentile:LO-HI (e.g. percentile:1-99) | zscore
+    /// | detector (camera offset to full scale, recorded by convert/crop)
+    #[arg(long, default_value = "minmax")]
+    pub normalize: String,
+    /// Resize filter to the model input size: nearest | triangle | catmullrom | gaussian | lanczos3
+    #[arg(long, default_value = "triangle")]
+    pub resize_filter: String,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+    /// Frames to analyze: "all" or comma-separated indices/slices, e.g. "0:100", "every:5";
+    /// the others are skipped like --exclude frames
+    #[arg(long)]
+    pub time: String,
+    /// Skip inference for frames whose tissue mask is empty and label them absent
+    #[arg(long)]
+    pub skip_empty: bool,
+    /// masks.zarr for --skip-empty (default: masks.zarr next to the input)
+    #[arg(long)]
+    pub masks: Option<String>,
+    /// Frame of drug addition: adds a t_treatment column (t minus this frame) so kill times
+    /// read relative to treatment
+    #[arg(long, conflicts_with = "treatments")]
+    pub treatment_frame: Option<u64>,
+    /// CSV with pos,treatment_frame rows (staggered pipetting); the row of --pos is used
+    /// like --treatment-frame
+    #[arg(long)]
+    pub treatments: Option<String>,
+}
+
+impl KillArgs {
+    pub fn masks_path(&self) -> std::path::PathBuf {
+        match &self.masks {
+            Some(p) => std::path::PathBuf::from(p),
+            None => Path::new(&self.input)
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join("masks.zarr"),
+        }
+    }
 }
 
 struct CropFrame {
@@ -45,6 +128,9 @@ struct CropFrame {
     width: u64,
 }
 
+/// Output row: (t, crop, label, present probability; None when inference was skipped).
+type Row = (u64, String, bool, Option<f64>);
+
 /// Lightweight index entry: (crop_id, t, height, width) - no pixel data.
 struct FrameIndex {
     crop_id: String,
@@ -53,169 +139,284 @@ struct FrameIndex {
     width: u64,
 }
 
-/// Build ONNX session. Tries CUDA if use_cuda; on CUDA failure falls back to CPU.
-fn build_kill_session(
-    model_path: &Path,
-    use_cuda: bool,
-) -> Result<Session, Box<dyn std::error::Error>> {
-    #[cfg(any(windows, target_os = "linux"))]
-    if use_cuda {
-        let mut builder = Session::builder()?;
-        let cuda = CUDA::default();
-        if cuda.is_available().unwrap_or(false) {
-            if cuda.register(&mut builder).is_ok() {
-                match builder.commit_from_file(model_path) {
-                    Ok(s) => {
-                        eprintln!("kill: using CUDA for GPU acceleration.");
-                        let _ = std::io::stderr().flush();
-                        return Ok(s);
-                    }
-                    Err(e) => {
-                        let msg = e.to_string();
-                        if msg.to_lowercase().contains("cuda")
-                            || msg.contains("no CUDA-capable device")
-                        {
-                            eprintln!("kill: CUDA failed ({}), falling back to CPU.", msg.lines().next().unwrap_or(&msg));
-                            let _ = std::io::stderr().flush();
-                            // Fall through to CPU path
-                        } else {
-                            return Err(e.into());
-                        }
-                    }
-                }
+/// Index the frames of one crop to classify. Frames in `skip` and frames `crop` had to fill
+/// (blank, so they would read as "absent") get no row; with `--skip-empty` masks, frames
+/// without cells are labelled absent in `rows` without inference.
+fn scan_crop(
+    arr: &zarr::StoreArray,
+    mask_arr: Option<&zarr::StoreArray>,
+    crop_id: &str,
+    skip: &HashSet<u64>,
+    indices: &mut Vec<FrameIndex>,
+    rows: &mut Vec<Row>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let shape = arr.shape();
+    let missing = crop::missing_times(arr, 0);
+    for t in (0..shape[0]).filter(|t| !skip.contains(t) && !missing.contains(t)) {
+        if let Some(mask_arr) = mask_arr {
+            let mask = zarr::read_labels(mask_arr, t)?;
+            if mask.iter().all(|&v| v == 0) {
+                rows.push((t, crop_id.to_string(), false, None));
+                continue;
             }
         }
+        indices.push(FrameIndex {
+            crop_id: crop_id.to_string(),
+            t,
+            height: shape[3],
+            width: shape[4],
+        });
     }
+    Ok(())
+}
 
-    eprintln!("kill: using CPU.");
-    let _ = std::io::stderr().flush();
-    Ok(Session::builder()?.commit_from_file(model_path)?)
+/// Frame normalization to 0-255 (`--normalize`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Normalize {
+    /// Frame min/max (the training default; sensitive to hot pixels).
+    MinMax,
+    /// Clip to the given lower/upper percentiles (0-100).
+    Percentile(f64, f64),
+    /// Map mean ± 3 standard deviations to 0-255.
+    ZScore,
+    /// Fixed window, the detector offset to full scale (`detector`).
+    Fixed(f64, f64),
+}
+
+impl Normalize {
+    /// `detector` is the range recorded in the store the frames come from.
+    pub(crate) fn parse(
+        s: &str,
+        detector: DetectorRange,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let t = s.trim().to_ascii_lowercase();
+        if t == "minmax" {
+            return Ok(Self::MinMax);
+        }
+        if t == "zscore" {
+            return Ok(Self::ZScore);
+        }
+        if t == "detector" {
+            let (lo, hi) = detector.require_bounds("--normalize detector")?;
+            return Ok(Self::Fixed(lo as f64, hi as f64));
+        }
+        if let Some(range) = t.strip_prefix("percentile:") {
+            let (lo, hi) = range
+                .split_once('-')
+                .ok_or_else(|| format!("Invalid percentile range {range:?}, expected LO-HI"))?;
+            let (lo, hi): (f64, f64) = (lo.trim().parse()?, hi.trim().parse()?);
+            if !(0.0..hi).contains(&lo) || hi > 100.0 {
+                return Err(format!(
+                    "Invalid percentile range {range:?}, need 0 <= LO < HI <= 100"
+                )
+                .into());
+            }
+            return Ok(Self::Percentile(lo, hi));
+        }
+        Err(format!(
+            "Unknown normalization {s:?}. Use minmax, percentile:LO-HI, zscore or detector."
+        )
+        .into())
+    }
+
+    /// Intensity window (low, high) mapped to 0 and 255.
+    fn window(self, data: &[u16]) -> (f64, f64) {
+        match self {
+            Self::MinMax => {
+                let (min, max) = pixelmath::min_max(data).unwrap_or((0, 0));
+                (min as f64, max as f64)
+            }
+            Self::Percentile(lo, hi) => {
+                let bounds = pixelmath::percentiles(data, &[lo, hi]);
+                (bounds[0] as f64, bounds[1] as f64)
+            }
+            Self::ZScore => {
+                let n = data.len() as f64;
+                let mean = data.iter().map(|&v| v as f64).sum::<f64>() / n;
+                let var = data.iter().map(|&v| (v as f64 - mean).powi(2)).sum::<f64>() / n;
+                let std = var.sqrt();
+                (mean - 3.0 * std, mean + 3.0 * std)
+            }
+            Self::Fixed(lo, hi) => (lo, hi),
+        }
+    }
 }
 
-/// Min-max normalize uint16 frame to 0-255.
-fn normalize_frame(data: &[u16]) -> Vec<u8> {
+/// Normalize uint16 frame to 0-255 over the window chosen by `mode`.
+fn normalize_frame(data: &[u16], mode: Normalize) -> Vec<u8> {
     if data.is_empty() {
         return vec![];
     }
-    let (min, max) = data
-        .iter()
-        .fold((data[0], data[0]), |(min, max), &v| {
-            (min.min(v), max.max(v))
-        });
-    let range = (max - min) as f64;
-    data.iter()
-        .map(|&v| {
-            if range > 0.0 {
-                (((v - min) as f64 / range) * 255.0).round() as u8
-            } else {
-                0
-            }
-        })
-        .collect()
+    let (lo, hi) = mode.window(data);
+    pixelmath::window_u8(data, lo, hi)
+}
+
+pub(crate) fn parse_filter(s: &str) -> Result<FilterType, Box<dyn std::error::Error>> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "nearest" => Ok(FilterType::Nearest),
+        "triangle" | "bilinear" => Ok(FilterType::Triangle),
+        "catmullrom" | "bicubic" => Ok(FilterType::CatmullRom),
+        "gaussian" => Ok(FilterType::Gaussian),
+        "lanczos3" | "lanczos" => Ok(FilterType::Lanczos3),
+        _ => Err(format!(
+            "Unknown resize filter {s:?}. Use nearest, triangle, catmullrom, gaussian or lanczos3."
+        )
+        .into()),
+    }
 }
 
-/// Resize grayscale (H,W) to 224x224.
-fn resize_to_224(data: &[u8], width: u32, height: u32) -> GrayImage {
+/// Resize grayscale (H,W) to the model input size (out_h, out_w).
+fn resize_to_input(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    (out_h, out_w): (u32, u32),
+    filter: FilterType,
+) -> GrayImage {
     let img = ImageBuffer::<Luma<u8>, Vec<u8>>::from_raw(width, height, data.to_vec())
-        .unwrap_or_else(|| ImageBuffer::from_raw(width, height, vec![0; (width * height) as usize]).unwrap());
-    image::imageops::resize(&img, IMAGE_SIZE, IMAGE_SIZE, FilterType::Triangle)
+        .unwrap_or_else(|| {
+            ImageBuffer::from_raw(width, height, vec![0; (width * height) as usize]).unwrap()
+        });
+    image::imageops::resize(&img, out_w, out_h, filter)
 }
 
-/// Convert 224x224 grayscale to NCHW float32 with ImageNet normalization.
+/// The 8-bit image the classifier sees for a crop frame (before ImageNet normalization):
+/// normalized with `normalize`, then resized to `size` (h, w). `export-training` writes
+/// the same images so retraining matches inference.
+pub(crate) fn model_image(
+    data: &[u16],
+    width: u32,
+    height: u32,
+    normalize: Normalize,
+    size: (u32, u32),
+    filter: FilterType,
+) -> GrayImage {
+    resize_to_input(
+        &normalize_frame(data, normalize),
+        width,
+        height,
+        size,
+        filter,
+    )
+}
+
+/// Convert resized grayscale to NCHW float32 with ImageNet normalization.
 fn to_nchw_normalized(gray: &GrayImage) -> Vec<f32> {
-    let n = (IMAGE_SIZE * IMAGE_SIZE) as usize;
-    let mut out = vec![0.0f32; 3 * n];
-    for (i, &v) in gray.as_raw().iter().enumerate() {
-        let normalized = v as f32 / 255.0;
-        for c in 0..3 {
-            out[c * n + i] = (normalized - IMAGENET_MEAN[c]) / IMAGENET_STD[c];
-        }
-    }
-    out
+    pixelmath::gray_to_chw(gray.as_raw(), IMAGENET_MEAN, IMAGENET_STD)
 }
 
 pub fn run(
     args: KillArgs,
-    progress: impl Fn(f64, &str),
+    progress: impl Fn(ProgressEvent),
 ) -> Result<(), Box<dyn std::error::Error>> {
-    eprintln!("kill: starting");
+    console::info("kill: starting");
     let _ = std::io::stderr().flush();
+    let treatment =
+        treatment::treatment_frame(args.treatment_frame, args.treatments.as_deref(), args.pos)?;
+    let format = OutputFormat::parse(&args.output_format)?;
     let crops_zarr = Path::new(&args.input);
     let pos_id = format!("{:03}", args.pos);
-    let crop_root = crops_zarr.join("pos").join(&pos_id).join("crop");
 
-    if !crop_root.exists() {
-        return Err("No crops found for position. Run crop task first.".into());
-    }
-
-    let mut crop_ids: Vec<String> = fs::read_dir(&crop_root)?
-        .filter_map(|e| {
-            let e = e.ok()?;
-            if e.file_type().ok()?.is_dir() {
-                e.file_name().to_str().map(String::from)
-            } else {
-                None
-            }
-        })
-        .collect();
-    crop_ids.sort();
+    let mut crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
 
     if crop_ids.is_empty() {
-        return Err("No crops found for position.".into());
+        return Err("No crops found for position. Run crop task first.".into());
     }
-    eprintln!("kill: loaded {} crop(s), opening zarr...", crop_ids.len());
+    console::info(&format!(
+        "kill: loaded {} crop(s), opening zarr...",
+        crop_ids.len()
+    ));
     let _ = std::io::stderr().flush();
 
     let store = zarr::open_store(&crops_zarr)?;
-    eprintln!("kill: zarr opened, scanning frame index...");
+    console::info("kill: zarr opened, scanning frame index...");
     let _ = std::io::stderr().flush();
 
+    let mask_store = if args.skip_empty {
+        let masks_path = args.masks_path();
+        if masks_path.exists() {
+            Some(zarr::open_store(&masks_path)?)
+        } else {
+            console::warn(&format!(
+                "--skip-empty: no masks at {}, running inference on all frames",
+                masks_path.display()
+            ));
+            None
+        }
+    } else {
+        None
+    };
+
     // Build lightweight index (metadata only, no pixel data)
+    stats::stage("scan");
     let mut indices: Vec<FrameIndex> = Vec::new();
+    let mut rows: Vec<Row> = Vec::new();
     for (i, crop_id) in crop_ids.iter().enumerate() {
         if i > 0 && i % 100 == 0 {
-            progress(i as f64 / crop_ids.len() as f64 * 0.2, &format!("Scanning {}/{} crops", i, crop_ids.len()));
+            cancel::check()?;
+            progress(ProgressEvent::new(
+                "scan",
+                i as u64,
+                crop_ids.len() as u64,
+                &format!("Scanning {}/{} crops", i, crop_ids.len()),
+            ));
         }
         let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
-        let arr = zarr::open_array(&store, &array_path)?;
-        let shape = arr.shape();
-        let n_t = shape[0];
-        let h = shape[3];
-        let w = shape[4];
-        for t in 0..n_t {
-            indices.push(FrameIndex {
-                crop_id: crop_id.clone(),
-                t,
-                height: h,
-                width: w,
-            });
-        }
+        let arr = zarr::open_array_cached(&store, &array_path)?;
+        let n_t = arr.shape()[0];
+        let mut skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+        skip.extend(slices::frames_outside(&args.time, n_t)?);
+        let mask_arr = mask_store
+            .as_ref()
+            .and_then(|s| zarr::open_array(s, &array_path).ok())
+            .filter(|m| m.shape()[0] == n_t);
+        scan_crop(
+            &arr,
+            mask_arr.as_ref(),
+            crop_id,
+            &skip,
+            &mut indices,
+            &mut rows,
+        )?;
     }
 
     let total = indices.len();
-    eprintln!("kill: {} frames to process, loading model...", total);
+    if mask_store.is_some() {
+        console::info(&format!(
+            "kill: --skip-empty: {} frame(s) with empty masks labeled absent",
+            rows.len()
+        ));
+    }
+    console::info(&format!(
+        "kill: {} frames to process, loading model...",
+        total
+    ));
     let _ = std::io::stderr().flush();
 
     if total == 0 {
-        fs::create_dir_all(Path::new(&args.output).parent().unwrap_or(Path::new(".")))?;
-        fs::write(&args.output, "t,crop,label\n")?;
-        progress(1.0, "No frames to predict, wrote empty CSV.");
+        let n_rows = write_rows(&args, None, treatment, format, &crop_ids, &mut rows)?;
+        progress(ProgressEvent::done(&format!(
+            "No frames to predict, wrote {} rows to {}",
+            n_rows, args.output
+        )));
         return Ok(());
     }
 
-    let model_path = Path::new(&args.model).join("model.onnx");
-    if !model_path.exists() {
-        return Err(format!(
-            "Model not found at {}. Export with: uv run optimum-cli export onnx --model keejkrej/mupattern-resnet18 {}",
-            model_path.display(),
-            args.model
+    let precision = Precision::parse(&args.precision)?;
+    let normalize = Normalize::parse(&args.normalize, DetectorRange::from_store(&store))?;
+    let model_path = session::resolve_model(Path::new(&args.model), precision).map_err(|e| {
+        format!(
+            "{}. Export with: uv run optimum-cli export onnx --model keejkrej/mupattern-resnet18 {}",
+            e, args.model
         )
-        .into());
-    }
+    })?;
 
-    let mut session = build_kill_session(&model_path, !args.cpu)?;
+    stats::stage("load_model");
+    let mut session = session::build_session(&model_path, !args.cpu, "kill")?;
 
-    eprintln!("kill: model loaded, running inference...");
+    console::info("kill: model loaded, running inference...");
     let _ = std::io::stderr().flush();
 
     let input_name = session
@@ -224,23 +425,48 @@ pub fn run(
         .ok_or("Model has no inputs")?
         .name()
         .to_string();
-
-    let mut rows: Vec<(u64, String, bool)> = Vec::new();
-    let batch_size = args.batch_size;
-    let mut array_cache: HashMap<String, zarr::StoreArray> = HashMap::new();
-
+    let input_type = session::input_type(&session)?;
+    let fixed_batch = session::fixed_batch(&session);
+    let (in_h, in_w) = session::input_hw(&session)
+        .map(|(h, w)| (h as u32, w as u32))
+        .unwrap_or((DEFAULT_IMAGE_SIZE, DEFAULT_IMAGE_SIZE));
+    let resize_filter = parse_filter(&args.resize_filter)?;
+    console::info(&format!("kill: model input {}x{}", in_w, in_h));
+    let input_px = in_h as usize * in_w as usize;
+
+    stats::stage("infer");
+    // Per frame: raw u16 crop plus the f32 NCHW tensor slot.
+    let max_crop_px = indices
+        .iter()
+        .map(|idx| idx.height * idx.width)
+        .max()
+        .unwrap_or(0);
+    let frame_bytes = max_crop_px * 2 + 3 * input_px as u64 * 4;
+    let batch_size = match fixed_batch {
+        Some(n) => {
+            if n != args.batch_size {
+                console::info(&format!(
+                    "kill: model has a fixed batch size of {}, using it",
+                    n
+                ));
+            }
+            n
+        }
+        None => memory::cap_items(args.batch_size, frame_bytes),
+    };
+    if fixed_batch.is_none() && batch_size < args.batch_size {
+        console::info(&format!(
+            "kill: --max-memory limits batch size to {} (requested {})",
+            batch_size, args.batch_size
+        ));
+    }
     for (batch_start, index_chunk) in indices.chunks(batch_size).enumerate() {
         // Load only this batch's pixel data
         let mut batch_frames: Vec<CropFrame> = Vec::with_capacity(index_chunk.len());
         for idx in index_chunk {
             let array_path = format!("/pos/{}/crop/{}", pos_id, idx.crop_id);
-            if !array_cache.contains_key(&idx.crop_id) {
-                let arr = zarr::open_array(&store, &array_path)?;
-                array_cache.insert(idx.crop_id.clone(), arr);
-            }
-            let arr = array_cache.get(&idx.crop_id).unwrap();
-            let chunk_indices = vec![idx.t, 0u64, 0, 0, 0];
-            let data = zarr::read_chunk_u16(arr, &chunk_indices)?;
+            let arr = zarr::open_array_cached(&store, &array_path)?;
+            let data = zarr::read_frame_u16(&arr, &[idx.t, 0, 0])?;
             batch_frames.push(CropFrame {
                 t: idx.t,
                 crop_id: idx.crop_id.clone(),
@@ -251,29 +477,31 @@ pub fn run(
         }
 
         let batch_len = batch_frames.len();
-        let mut batch_data = vec![0.0f32; batch_len * 3 * IMAGE_SIZE as usize * IMAGE_SIZE as usize];
+        // Static-batch models get the last batch zero-padded to their batch size.
+        let tensor_batch = fixed_batch.unwrap_or(batch_len);
+        let mut batch_data = vec![0.0f32; tensor_batch * 3 * input_px];
 
         for (i, frame) in batch_frames.iter().enumerate() {
-            let normalized = normalize_frame(&frame.data);
-            let resized = resize_to_224(&normalized, frame.width as u32, frame.height as u32);
+            let resized = model_image(
+                &frame.data,
+                frame.width as u32,
+                frame.height as u32,
+                normalize,
+                (in_h, in_w),
+                resize_filter,
+            );
             let nchw = to_nchw_normalized(&resized);
-            let offset = i * 3 * IMAGE_SIZE as usize * IMAGE_SIZE as usize;
+            let offset = i * 3 * input_px;
             batch_data[offset..offset + nchw.len()].copy_from_slice(&nchw);
         }
 
-        let shape: Ix4 = ndarray::Dim([
-            batch_len as usize,
-            3,
-            IMAGE_SIZE as usize,
-            IMAGE_SIZE as usize,
-        ]);
+        let shape: Ix4 = ndarray::Dim([tensor_batch, 3, in_h as usize, in_w as usize]);
         let arr = Array::from_shape_vec(shape, batch_data)?;
-        let input_tensor = Tensor::from_array(arr)?;
+        let input_tensor = session::float_input(arr.into_dyn(), input_type)?;
         let input = ort::inputs![input_name.as_str() => input_tensor];
 
         let outputs = session.run(input)?;
-        let output = &outputs[0];
-        let logits: ArrayViewD<f32> = output.try_extract_array()?;
+        let logits = session::extract_f32(&outputs[0])?;
 
         // Logits shape: [N, num_classes] (e.g. [batch, 2])
         let ndim = logits.ndim();
@@ -283,38 +511,232 @@ pub fn run(
             2
         };
         for (i, frame) in batch_frames.iter().enumerate() {
-            let mut max_idx = 0;
-            let mut max_val = if ndim == 2 {
-                logits[[i, 0]]
-            } else {
-                logits[[i, 0, 0, 0]]
-            };
-            for c in 1..num_classes {
-                let v = if ndim == 2 {
-                    logits[[i, c]]
-                } else {
-                    logits[[i, c, 0, 0]]
-                };
-                if v > max_val {
-                    max_val = v;
-                    max_idx = c;
-                }
-            }
-            rows.push((frame.t, frame.crop_id.clone(), max_idx == 1));
+            let scores: Vec<f32> = (0..num_classes)
+                .map(|c| {
+                    if ndim == 2 {
+                        logits[[i, c]]
+                    } else {
+                        logits[[i, c, 0, 0]]
+                    }
+                })
+                .collect();
+            let max_idx =
+                (1..num_classes).fold(0, |best, c| if scores[c] > scores[best] { c } else { best });
+            rows.push((
+                frame.t,
+                frame.crop_id.clone(),
+                max_idx == 1,
+                present_probability(&scores),
+            ));
         }
 
-        let processed = (batch_start + 1) * batch_size;
-        let prog = 0.2 + (processed.min(total) as f64 / total as f64) * 0.8; // 20% for scan, 80% for infer
-        progress(prog, &format!("Predicting {}/{}", processed.min(total), total));
+        stats::add_frames(batch_len as u64);
+        cancel::check()?;
+        let processed = ((batch_start + 1) * batch_size).min(total);
+        progress(ProgressEvent::new(
+            "infer",
+            processed as u64,
+            total as u64,
+            &format!("Predicting {}/{}", processed, total),
+        ));
     }
 
-    fs::create_dir_all(Path::new(&args.output).parent().unwrap_or(Path::new(".")))?;
-    let mut csv = "t,crop,label\n".to_string();
-    for (t, crop, label) in &rows {
-        csv.push_str(&format!("{},{},{}\n", t, crop, label.to_string().to_lowercase()));
-    }
-    fs::write(&args.output, csv)?;
-    progress(1.0, &format!("Wrote {} rows to {}", rows.len(), args.output));
+    stats::stage("write");
+    let n_rows = write_rows(
+        &args,
+        Some(Path::new(&model_path)),
+        treatment,
+        format,
+        &crop_ids,
+        &mut rows,
+    )?;
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} rows to {}",
+        n_rows, args.output
+    )));
 
     Ok(())
 }
+
+/// Softmax probability of the present class (index 1); None for single-output models.
+fn present_probability(scores: &[f32]) -> Option<f64> {
+    let present = *scores.get(1)? as f64;
+    let max = scores
+        .iter()
+        .fold(f64::NEG_INFINITY, |m, &v| m.max(v as f64));
+    let sum: f64 = scores.iter().map(|&v| (v as f64 - max).exp()).sum();
+    Some((present - max).exp() / sum)
+}
+
+/// Write (t, crop, label, probability) rows in crop, t order, with the provenance of
+/// `model` (None when no frame needed inference).
+/// With a `treatment` frame, `t_treatment` follows `t`.
+fn write_rows(
+    args: &KillArgs,
+    model: Option<&Path>,
+    treatment: Option<u64>,
+    format: OutputFormat,
+    crop_ids: &[String],
+    rows: &mut [Row],
+) -> Result<u64, Box<dyn std::error::Error>> {
+    sort_rows(rows, crop_ids);
+    let mut provenance = Provenance::new("kill")
+        .param("batch_size", args.batch_size)
+        .param("precision", args.precision.as_str())
+        .param("normalize", args.normalize.as_str())
+        .param("resize_filter", args.resize_filter.as_str())
+        .param("skip_empty", args.skip_empty)
+        .param("time", args.time.as_str())
+        .param("treatment_frame", treatment);
+    if let Some(model) = model {
+        provenance = provenance.model(model)?;
+    }
+    let schema = if treatment.is_some() {
+        &TREATMENT_SCHEMA
+    } else {
+        &SCHEMA
+    };
+    let mut table =
+        TableWriter::create_with_provenance(&args.output, schema, args.pos, format, &provenance)?;
+    for (t, crop, label, probability) in rows.iter() {
+        let mut row: Vec<Value> = vec![(*t).into()];
+        if let Some(frame) = treatment {
+            row.push(treatment::relative(*t, frame).into());
+        }
+        row.extend([crop.as_str().into(), (*label).into(), (*probability).into()]);
+        table.write_row(&row)?;
+    }
+    table.finish()
+}
+
+/// Order rows like the other tables: crops in `crop_ids` (crop registry) order, then by t.
+fn sort_rows(rows: &mut [Row], crop_ids: &[String]) {
+    let rank: HashMap<&str, usize> = crop_ids
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (c.as_str(), i))
+        .collect();
+    let rank_of = |row: &Row| rank.get(row.1.as_str()).copied().unwrap_or(usize::MAX);
+    rows.sort_by(|a, b| {
+        rank_of(a)
+            .cmp(&rank_of(b))
+            .then_with(|| crop_index::natural_cmp(&a.1, &b.1))
+            .then(a.0.cmp(&b.0))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filled_frames_get_no_kill_row() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::TempDir::new()?;
+        let store = zarr::open_store(dir.path())?;
+        let shape = vec![4, 2, 1, 2, 2];
+        let mut attrs = serde_json::Map::new();
+        attrs.insert(
+            crop::MISSING_FRAMES_ATTR.to_string(),
+            serde_json::json!({"policy": "fill-nan", "frames": [[1, 0, 0], [2, 1, 0]]}),
+        );
+        let arr = zarr::create_array_u16(
+            &store,
+            "/crop",
+            shape.clone(),
+            vec![1, 1, 1, 2, 2],
+            zarr::shard_shape_t_first(&shape),
+            Some(attrs),
+        )?;
+
+        let (mut indices, mut rows) = (Vec::new(), Vec::new());
+        let skip = HashSet::from([3]);
+        scan_crop(&arr, None, "000", &skip, &mut indices, &mut rows)?;
+        // t=1 was filled in channel 0 (the one kill reads); t=2 only in channel 1.
+        let ts: Vec<u64> = indices.iter().map(|idx| idx.t).collect();
+        assert_eq!(ts, [0, 2]);
+        assert!(rows.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn rows_follow_crop_registry_order() {
+        let crop_ids: Vec<String> = ["10", "2", "b"].map(String::from).to_vec();
+        let row = |t: u64, crop: &str| -> Row { (t, crop.to_string(), true, None) };
+        let mut rows = vec![
+            row(1, "b"),
+            row(0, "2"),
+            row(1, "10"),
+            row(0, "b"),
+            row(0, "10"),
+        ];
+        sort_rows(&mut rows, &crop_ids);
+        let order: Vec<(u64, &str)> = rows.iter().map(|r| (r.0, r.1.as_str())).collect();
+        assert_eq!(order, [(0, "10"), (1, "10"), (0, "2"), (0, "b"), (1, "b")]);
+    }
+
+    #[test]
+    fn normalize_parses_modes() {
+        let none = DetectorRange::default();
+        let twelve_bit = DetectorRange {
+            bit_depth: Some(12),
+            offset: Some(100),
+        };
+        assert_eq!(Normalize::parse("minmax", none).unwrap(), Normalize::MinMax);
+        assert_eq!(
+            Normalize::parse(" ZScore ", none).unwrap(),
+            Normalize::ZScore
+        );
+        assert_eq!(
+            Normalize::parse("percentile:1-99.5", none).unwrap(),
+            Normalize::Percentile(1.0, 99.5)
+        );
+        assert_eq!(
+            Normalize::parse("detector", twelve_bit).unwrap(),
+            Normalize::Fixed(100.0, 4095.0)
+        );
+        for bad in [
+            "percentile:99-1",
+            "percentile:5-5",
+            "percentile:1-101",
+            "percentile:-1-50",
+            "percentile:5",
+            "percentile:a-b",
+            "gamma",
+        ] {
+            assert!(Normalize::parse(bad, twelve_bit).is_err(), "{bad}");
+        }
+        assert!(Normalize::parse("detector", none).is_err());
+    }
+
+    #[test]
+    fn normalize_windows_on_synthetic_crop() {
+        // A 100..=199 ramp plus one hot pixel.
+        let mut crop: Vec<u16> = (100..200).collect();
+        crop.push(4095);
+
+        let minmax = normalize_frame(&crop, Normalize::MinMax);
+        assert_eq!((minmax[0], minmax[99], minmax[100]), (0, 6, 255));
+
+        let percentile = normalize_frame(&crop, Normalize::Percentile(0.0, 99.0));
+        assert_eq!(
+            Normalize::Percentile(0.0, 99.0).window(&crop),
+            (100.0, 199.0)
+        );
+        assert_eq!(
+            (percentile[0], percentile[99], percentile[100]),
+            (0, 255, 255)
+        );
+
+        let fixed = normalize_frame(&[0, 100, 2097, 4095], Normalize::Fixed(100.0, 4095.0));
+        assert_eq!(fixed, [0, 0, 127, 255]);
+
+        let (lo, hi) = Normalize::ZScore.window(&[10, 20, 30, 40]);
+        assert!((lo + hi - 50.0).abs() < 1e-9);
+        assert!(((hi - lo) / 6.0 - 125f64.sqrt()).abs() < 1e-9);
+        let zscore = normalize_frame(&[10, 20, 30, 40], Normalize::ZScore);
+        assert!(zscore.windows(2).all(|w| w[0] < w[1]));
+        assert!(zscore[0] > 0 && zscore[3] < 255);
+        assert_eq!(normalize_frame(&[7; 4], Normalize::ZScore), [0; 4]);
+        assert!(normalize_frame(&[], Normalize::MinMax).is_empty());
+    }
+}
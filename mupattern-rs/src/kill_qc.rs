@@ -0,0 +1,253 @@
+//! Kill vs segmentation QC: compare kill's present/absent labels with cell presence from a
+//! masks.zarr (any labelled pixels, or at least --min-area) per crop-frame. Writes the
+//! frames where they disagree, optionally per-crop agreement and Cohen's kappa, and logs
+//! the overall figures; used to vet a classifier update before it replaces the old model.
+
+use clap::Args;
+use std::path::Path;
+
+use crate::cancel;
+use crate::console;
+use crate::exclude::ExclusionList;
+use crate::export_training;
+use crate::mask_stats;
+use crate::output::{ColumnType, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::zarr;
+
+const SCHEMA: Schema = Schema {
+    table: "kill_disagreement",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("kill_label", ColumnType::Integer),
+        ("mask_labels", ColumnType::Integer),
+        ("mask_area", ColumnType::Integer),
+    ],
+};
+
+const SUMMARY_SCHEMA: Schema = Schema {
+    table: "kill_agreement",
+    version: 1,
+    columns: &[
+        ("crop", ColumnType::Text),
+        ("frames", ColumnType::Integer),
+        ("both_present", ColumnType::Integer),
+        ("both_absent", ColumnType::Integer),
+        ("kill_only", ColumnType::Integer),
+        ("mask_only", ColumnType::Integer),
+        ("agreement", ColumnType::Real),
+        ("kappa", ColumnType::Real),
+    ],
+};
+
+#[derive(Args, Clone)]
+pub struct KillQcArgs {
+    /// kill output CSV (t,crop,label)
+    #[arg(long)]
+    pub predictions: String,
+    /// Path to masks.zarr
+    #[arg(long)]
+    pub masks: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Disagreeing frames (t,crop,kill_label,mask_labels,mask_area): CSV, or SQLite when ending in .sqlite/.db
+    #[arg(long)]
+    pub output: String,
+    /// Per-crop agreement (crop,frames,both_present,both_absent,kill_only,mask_only,agreement,kappa): CSV, or SQLite when ending in .sqlite/.db
+    #[arg(long)]
+    pub summary: Option<String>,
+    /// Labelled pixels a frame needs for the masks to count as "present"
+    #[arg(long, default_value_t = 1)]
+    pub min_area: u64,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+}
+
+/// Kill (rows) vs mask (columns) presence counts.
+#[derive(Clone, Copy, Default, Debug)]
+struct Confusion {
+    both_present: u64,
+    both_absent: u64,
+    kill_only: u64,
+    mask_only: u64,
+}
+
+impl Confusion {
+    fn add(&mut self, kill: bool, mask: bool) {
+        match (kill, mask) {
+            (true, true) => self.both_present += 1,
+            (false, false) => self.both_absent += 1,
+            (true, false) => self.kill_only += 1,
+            (false, true) => self.mask_only += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &Confusion) {
+        self.both_present += other.both_present;
+        self.both_absent += other.both_absent;
+        self.kill_only += other.kill_only;
+        self.mask_only += other.mask_only;
+    }
+
+    fn frames(&self) -> u64 {
+        self.both_present + self.both_absent + self.kill_only + self.mask_only
+    }
+
+    fn agreement(&self) -> Option<f64> {
+        let n = self.frames();
+        (n > 0).then(|| (self.both_present + self.both_absent) as f64 / n as f64)
+    }
+
+    /// Cohen's kappa; None when undefined (no frames, or both raters constant and equal).
+    fn kappa(&self) -> Option<f64> {
+        let n = self.frames() as f64;
+        let observed = self.agreement()?;
+        let kill_present = (self.both_present + self.kill_only) as f64 / n;
+        let mask_present = (self.both_present + self.mask_only) as f64 / n;
+        let expected = kill_present * mask_present + (1.0 - kill_present) * (1.0 - mask_present);
+        (expected < 1.0).then(|| (observed - expected) / (1.0 - expected))
+    }
+}
+
+pub fn run(
+    args: KillQcArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let predictions = export_training::load_labels(&args.predictions, args.pos)?;
+    if predictions.is_empty() {
+        return Err(format!(
+            "No predictions for position {} in {}",
+            args.pos, args.predictions
+        )
+        .into());
+    }
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    let masks_zarr = Path::new(&args.masks);
+    let pos_id = format!("{:03}", args.pos);
+    let store = zarr::open_store(masks_zarr)?;
+
+    let mut table = TableWriter::create(&args.output, &SCHEMA, args.pos)?;
+    let mut summary = args
+        .summary
+        .as_deref()
+        .map(|path| TableWriter::create(path, &SUMMARY_SCHEMA, args.pos))
+        .transpose()?;
+
+    stats::stage("analyze");
+    let total = predictions.len();
+    let mut overall = Confusion::default();
+    let mut missing = Vec::new();
+    for (i, (crop_id, frames)) in predictions.iter().enumerate() {
+        if exclusions.crop_excluded(args.pos, crop_id) {
+            continue;
+        }
+        let Ok(arr) = zarr::open_array(&store, &format!("/pos/{}/crop/{}", pos_id, crop_id)) else {
+            missing.push(crop_id.as_str());
+            continue;
+        };
+        let n_t = arr.shape()[0];
+        let skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+        let mut confusion = Confusion::default();
+        for (&t, &kill_present) in frames.iter().filter(|(t, _)| !skip.contains(*t)) {
+            if t >= n_t {
+                return Err(format!(
+                    "Prediction for crop {} t={} but its masks have {} frames",
+                    crop_id, t, n_t
+                )
+                .into());
+            }
+            let (n_labels, area) = mask_stats::frame_stats(&zarr::read_labels(&arr, t)?);
+            let mask_present = area >= args.min_area.max(1);
+            confusion.add(kill_present, mask_present);
+            if kill_present != mask_present {
+                table.write_row(&[
+                    Value::from(t),
+                    crop_id.as_str().into(),
+                    kill_present.into(),
+                    n_labels.into(),
+                    area.into(),
+                ])?;
+            }
+            stats::add_frames(1);
+        }
+        if let Some(summary) = summary.as_mut() {
+            summary.write_row(&[
+                Value::from(crop_id.as_str()),
+                confusion.frames().into(),
+                confusion.both_present.into(),
+                confusion.both_absent.into(),
+                confusion.kill_only.into(),
+                confusion.mask_only.into(),
+                confusion.agreement().into(),
+                confusion.kappa().into(),
+            ])?;
+        }
+        overall.merge(&confusion);
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "analyze",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Processing crop {}/{}", i + 1, total),
+        ));
+    }
+    if !missing.is_empty() {
+        console::warn(&format!(
+            "no masks for {} of {} crop(s) ({}); they are left out",
+            missing.len(),
+            total,
+            missing.join(", ")
+        ));
+    }
+
+    let n_rows = table.finish()?;
+    if let Some(summary) = summary {
+        summary.finish()?;
+    }
+    let fmt = |v: Option<f64>| v.map_or("n/a".to_string(), |v| format!("{:.3}", v));
+    console::info(&format!(
+        "kill-qc: {} frames, agreement {}, kappa {}, kill-only {}, mask-only {}",
+        overall.frames(),
+        fmt(overall.agreement()),
+        fmt(overall.kappa()),
+        overall.kill_only,
+        overall.mask_only
+    ));
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} disagreeing frames to {}",
+        n_rows, args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kappa_discounts_chance_agreement() {
+        let c = Confusion {
+            both_present: 40,
+            both_absent: 45,
+            kill_only: 10,
+            mask_only: 5,
+        };
+        assert_eq!(c.frames(), 100);
+        assert!((c.agreement().unwrap() - 0.85).abs() < 1e-12);
+        // Expected agreement 0.5 * 0.45 + 0.5 * 0.55 = 0.5.
+        assert!((c.kappa().unwrap() - 0.7).abs() < 1e-12);
+
+        let constant = Confusion {
+            both_absent: 3,
+            ..Default::default()
+        };
+        assert_eq!(constant.agreement(), Some(1.0));
+        assert_eq!(constant.kappa(), None);
+        assert_eq!(Confusion::default().kappa(), None);
+    }
+}
@@ -0,0 +1,227 @@
+//! Per-position result layout (`--results-root`). With a results root, every command that
+//! works on one position gets its path flags defaulted to `{root}/pos{NNN}/{command}/...`
+//! (inputs point at the upstream step: `kill --input` at `crop/crops.zarr`, `kill-qc
+//! --predictions` at `kill/kill.csv`, ...), so a pipeline needs no hand-typed paths that
+//! could drift apart between steps. Flags given on the command line or in the environment
+//! still win. After a successful run the paths the command used are recorded in
+//! `{root}/pos{NNN}/layout.json` under the command name.
+
+use clap::{Arg, ArgMatches, Command};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_NAME: &str = "layout.json";
+
+const CROPS: &str = "crop/crops.zarr";
+const MASKS: &str = "tissue/masks.zarr";
+
+/// Path flags per command, relative to the position directory. Flags that switch a mode on
+/// when present (`spot --masks`, `rotation --masks`) are left alone.
+const LAYOUT: &[(&str, &[(&str, &str)])] = &[
+    ("audit-background", &[("input", CROPS)]),
+    ("crop", &[("output", CROPS)]),
+    ("defects", &[("output", "defects/defects.csv")]),
+    (
+        "export-masks",
+        &[("masks", MASKS), ("output", "export-masks")],
+    ),
+    (
+        "export-training",
+        &[
+            ("input", CROPS),
+            ("labels", "select-uncertain/labels.csv"),
+            ("output", "export-training"),
+        ],
+    ),
+    (
+        "expression",
+        &[("input", CROPS), ("output", "expression/expression.csv")],
+    ),
+    ("flow", &[("input", CROPS), ("output", "flow/flow.csv")]),
+    // Imported masks go where `tissue --stage analyze` reads them.
+    ("import-masks", &[("input", CROPS), ("output", MASKS)]),
+    (
+        "kill",
+        &[
+            ("input", CROPS),
+            ("output", "kill/kill.csv"),
+            ("masks", MASKS),
+        ],
+    ),
+    (
+        "kill-qc",
+        &[
+            ("predictions", "kill/kill.csv"),
+            ("masks", MASKS),
+            ("output", "kill-qc/kill-qc.csv"),
+        ],
+    ),
+    (
+        "mask-stats",
+        &[("masks", MASKS), ("output", "mask-stats/mask-stats.csv")],
+    ),
+    ("movie", &[("input", CROPS), ("output", "movie/movie.mp4")]),
+    (
+        "rotation",
+        &[("input", CROPS), ("output", "rotation/rotation.csv")],
+    ),
+    (
+        "sector",
+        &[("input", CROPS), ("output", "sector/sector.csv")],
+    ),
+    (
+        "select-uncertain",
+        &[
+            ("input", CROPS),
+            ("predictions", "kill/kill.csv"),
+            ("output", "select-uncertain"),
+        ],
+    ),
+    (
+        "snapshot",
+        &[("input", CROPS), ("output", "snapshot/snapshot.png")],
+    ),
+    ("spot", &[("input", CROPS), ("output", "spot/spot.csv")]),
+    (
+        "tissue",
+        &[("input", CROPS), ("output", "tissue/tissue.csv")],
+    ),
+];
+
+fn flags(command: &str) -> &'static [(&'static str, &'static str)] {
+    LAYOUT
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map(|(_, flags)| *flags)
+        .unwrap_or(&[])
+}
+
+/// Raw value of flag `id` in `matches`, if the flag exists and was set.
+fn raw(matches: &ArgMatches, id: &str) -> Option<String> {
+    let mut values = matches.try_get_raw(id).ok()??;
+    Some(values.next()?.to_string_lossy().into_owned())
+}
+
+/// Declare the global `--results-root` (read before parsing, see `Layout::detect`).
+pub fn declare(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("results_root")
+            .long("results-root")
+            .global(true)
+            .value_name("DIR")
+            .help("Default the path flags of per-position commands to DIR/pos{NNN}/{command}/... (recorded in pos{NNN}/layout.json)"),
+    )
+}
+
+/// Results root, command and position of one run.
+pub struct Layout {
+    root: PathBuf,
+    command: String,
+    pos: u32,
+}
+
+impl Layout {
+    /// The layout for `argv` when it has a results root and runs a command with a layout
+    /// on a single `--pos`. `cmd` is the full CLI; other flags are not validated here.
+    pub fn detect(cmd: &Command, argv: &[String]) -> Option<Self> {
+        let matches = cmd
+            .clone()
+            .ignore_errors(true)
+            .try_get_matches_from(argv)
+            .ok()?;
+        let matches = crate::groups::flatten(matches);
+        let (command, sub) = matches.subcommand()?;
+        if flags(command).is_empty() {
+            return None;
+        }
+        Some(Self {
+            root: PathBuf::from(raw(sub, "results_root")?),
+            command: command.to_string(),
+            pos: raw(sub, "pos")?.trim().parse().ok()?,
+        })
+    }
+
+    /// `{root}/pos{NNN}`
+    pub fn pos_dir(&self) -> PathBuf {
+        self.root.join(format!("pos{:03}", self.pos))
+    }
+
+    /// Default the command's path flags in `cmd` (before grouping) to the layout.
+    pub fn apply(&self, cmd: Command) -> Command {
+        let pos_dir = self.pos_dir();
+        cmd.mut_subcommand(&self.command, |mut sub| {
+            for &(id, rel) in flags(&self.command) {
+                let path = pos_dir.join(rel).display().to_string();
+                sub = sub.mut_arg(id, |a| a.default_value(path).required(false));
+            }
+            sub
+        })
+    }
+
+    /// Record the paths the command used (from the parsed, flattened `matches`) in the
+    /// position's `layout.json`, keeping the entries of other commands.
+    pub fn record(&self, matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((_, sub)) = matches.subcommand() else {
+            return Ok(());
+        };
+        let paths: Map<String, Value> = flags(&self.command)
+            .iter()
+            .filter_map(|&(id, _)| Some((id.to_string(), Value::String(raw(sub, id)?))))
+            .collect();
+        let path = self.pos_dir().join(MANIFEST_NAME);
+        let mut manifest = read_manifest(&path)?;
+        manifest.insert(self.command.clone(), Value::Object(paths));
+        fs::create_dir_all(self.pos_dir())?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&manifest)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<Map<String, Value>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+    match serde_json::from_str(&fs::read_to_string(path)?)? {
+        Value::Object(map) => Ok(map),
+        _ => Err(format!("{} is not a JSON object", path.display()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_default_to_the_layout_and_flags_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().display().to_string();
+        let cli = || {
+            declare(Command::new("mupattern")).subcommand(
+                Command::new("kill")
+                    .arg(Arg::new("input").long("input").required(true))
+                    .arg(Arg::new("pos").long("pos").required(true))
+                    .arg(Arg::new("output").long("output").required(true))
+                    .arg(Arg::new("masks").long("masks")),
+            )
+        };
+        let argv: Vec<String> = ["mupattern", "--results-root", &root, "kill", "--pos", "7"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let layout = Layout::detect(&cli(), &argv).unwrap();
+        let mut custom = argv.clone();
+        custom.extend(["--output".to_string(), "k.csv".to_string()]);
+        let matches = layout.apply(cli()).get_matches_from(&custom);
+        layout.record(&matches).unwrap();
+
+        let text = fs::read_to_string(dir.path().join("pos007").join(MANIFEST_NAME)).unwrap();
+        let manifest: Value = serde_json::from_str(&text).unwrap();
+        let crops = dir.path().join("pos007").join(CROPS);
+        assert_eq!(manifest["kill"]["input"], crops.display().to_string());
+        assert_eq!(manifest["kill"]["output"], "k.csv");
+        assert!(Layout::detect(&cli(), &argv[..4]).is_none());
+    }
+}
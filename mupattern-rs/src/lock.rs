@@ -0,0 +1,168 @@
+//! Advisory write locks on Zarr stores. A command that writes a store (`crop`,
+//! `import-masks`, `tissue`) holds `<store>/.mupattern.lock` while it runs; the file names
+//! the writer's pid and command. `zarr::open_store` refuses to open a store locked by
+//! another live process, so a concurrent `expression` or `kill` fails up front instead of
+//! reading half-written chunks. Locks left by a crashed writer are ignored with a warning.
+
+use serde_json::json;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+#[cfg(not(target_os = "linux"))]
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::console;
+
+pub const LOCK_FILE: &str = ".mupattern.lock";
+
+/// Held while writing a store; the lock file is removed on drop.
+pub struct WriteLock {
+    path: PathBuf,
+}
+
+impl WriteLock {
+    /// Lock `root` for `command`, creating the store directory if needed.
+    pub fn acquire(root: &Path, command: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(root)?;
+        let path = root.join(LOCK_FILE);
+        if let Some(holder) = holder(&path)? {
+            return Err(busy_message(root, &holder).into());
+        }
+        // A stale lock was reported by `holder`; take it over.
+        let _ = fs::remove_file(&path);
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(
+                    format!("{} was locked by another writer just now", root.display()).into(),
+                );
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let info = json!({"pid": std::process::id(), "command": command, "started": started});
+        writeln!(file, "{}", info)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Fail if `root` is being written by another process.
+pub fn check_readable(root: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    match holder(&root.join(LOCK_FILE))? {
+        Some(holder) => Err(busy_message(root, &holder).into()),
+        None => Ok(()),
+    }
+}
+
+/// Contents of a lock file held by another live process; `None` when there is no lock,
+/// it is ours, or its process is gone.
+fn holder(path: &Path) -> Result<Option<serde_json::Value>, Box<dyn std::error::Error>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let info: serde_json::Value = serde_json::from_str(text.trim()).unwrap_or_default();
+    let pid = info["pid"].as_u64();
+    if pid == Some(std::process::id() as u64) {
+        return Ok(None);
+    }
+    if let Some(pid) = pid {
+        if !process_alive(pid) {
+            console::warn(&format!(
+                "ignoring stale lock {} (pid {} is not running)",
+                path.display(),
+                pid
+            ));
+            return Ok(None);
+        }
+    }
+    Ok(Some(info))
+}
+
+/// Whether `pid` is running: /proc on Linux, `ps -p` on other Unix systems and `tasklist`
+/// on Windows. A lock whose process cannot be checked is assumed live.
+fn process_alive(pid: u64) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let proc = Path::new("/proc");
+        !proc.join("self").exists() || proc.join(pid.to_string()).exists()
+    }
+    #[cfg(all(unix, not(target_os = "linux")))]
+    {
+        let pid = pid.to_string();
+        match Command::new("ps").args(["-p", &pid]).output() {
+            Ok(output) => output.status.success(),
+            Err(_) => true,
+        }
+    }
+    #[cfg(windows)]
+    {
+        let filter = format!("PID eq {}", pid);
+        match Command::new("tasklist")
+            .args(["/FI", &filter, "/FO", "CSV", "/NH"])
+            .output()
+        {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)),
+            Err(_) => true,
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+fn busy_message(root: &Path, holder: &serde_json::Value) -> String {
+    format!(
+        "{} is being written by `{}` (pid {}); wait for it to finish, or delete {} if no writer is running",
+        root.display(),
+        holder["command"].as_str().unwrap_or("?"),
+        holder["pid"],
+        root.join(LOCK_FILE).display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_blocks_other_processes_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("crops.zarr");
+        let lock = WriteLock::acquire(&root, "crop").unwrap();
+        // Our own lock does not block us.
+        check_readable(&root).unwrap();
+
+        // Pid 1 is always running on Linux.
+        fs::write(root.join(LOCK_FILE), r#"{"pid": 1, "command": "crop"}"#).unwrap();
+        if process_alive(1) {
+            let err = check_readable(&root).unwrap_err().to_string();
+            assert!(err.contains("being written by `crop`"), "{err}");
+            assert!(WriteLock::acquire(&root, "import-masks").is_err());
+        }
+
+        drop(lock);
+        assert!(!root.join(LOCK_FILE).exists());
+        check_readable(&root).unwrap();
+    }
+
+    #[test]
+    fn process_alive_tells_running_from_gone() {
+        assert!(process_alive(std::process::id() as u64));
+        // Above every platform's pid range.
+        assert!(!process_alive(4_000_000_000));
+    }
+}
@@ -1,53 +1,310 @@
+mod audit_background;
+mod calibration;
+mod cancel;
+mod channels;
+mod checksum;
+mod config;
+mod console;
 mod convert;
 mod crop;
+mod crop_index;
+mod czi;
+mod defects;
+mod detector;
+mod disk;
+mod exclude;
+mod export_masks;
+mod export_training;
 mod expression;
+mod ffmpeg;
+mod flow;
+mod geometry;
+mod groups;
+mod heatmap;
+mod import_masks;
 mod kill;
+mod kill_qc;
+mod layout;
+mod lock;
+mod manifest;
+mod mask_stats;
+mod memory;
+mod mosaic;
 mod movie;
+mod notify;
+mod ome;
+mod outline;
+mod output;
+mod overlay;
+mod parallel;
+mod pixelmath;
+mod plot;
+mod progress;
+mod provenance;
+mod report;
+mod rotation;
+mod sector;
+mod seed;
+mod select_uncertain;
+mod serve_zarr;
+mod session;
 mod slices;
+mod smooth;
+mod snapshot;
 mod spot;
+mod stats;
+mod survival;
+mod tiff_cache;
 mod tissue;
+mod treatment;
+mod validate;
 mod zarr;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use std::io::{self, Write};
+use std::time::Instant;
 
 #[derive(Parser)]
-#[command(name = "mupattern", about = "mupattern CLI: crop, convert, expression, kill, movie, spot, tissue")]
+#[command(
+    name = "mupattern",
+    about = "mupattern CLI: audit-background, crop, convert, defects, export-masks, export-training, expression, flow, heatmap, import-masks, kill, kill-qc, mask-stats, movie, report, rotation, sector, select-uncertain, serve-zarr, smooth, snapshot, spot, survival, tissue, validate"
+)]
 struct Cli {
+    /// POST a JSON summary (status, duration, outputs, error) to this URL when the command finishes
+    #[arg(long, global = true)]
+    notify_url: Option<String>,
+
+    /// Cap in-flight frame buffers (movie frame cache, kill batches), e.g. "8G" or "512M"
+    #[arg(long, global = true, value_parser = memory::parse_bytes)]
+    max_memory: Option<u64>,
+
+    /// Directory for TensorRT engine caches (needs a `tensorrt` build); engines are built once per model/shape
+    #[arg(long, global = true)]
+    trt_engine_cache: Option<std::path::PathBuf>,
+
+    /// Open crop arrays kept per thread so repeated reads skip metadata parsing (0 disables)
+    #[arg(long, global = true, default_value_t = 256)]
+    array_cache: usize,
+
+    /// On a frame read miss, also fetch this many following chunks along t in one request
+    #[arg(long, global = true, default_value_t = 0)]
+    readahead: u64,
+
+    /// Decoded chunks kept in memory across stages and stores, in MB (0, the default,
+    /// disables it; helps commands that read the same chunks repeatedly, e.g. movie)
+    #[arg(long, global = true, default_value_t = 0)]
+    chunk_cache_mb: u64,
+
+    /// Decoded TIFF frames kept in memory so repeated passes skip decoding, in MB (0 disables)
+    #[arg(long, global = true, default_value_t = 0)]
+    tiff_cache_mb: u64,
+
+    /// Read JSON control lines from stdin; {"cmd":"cancel"} aborts the run cleanly
+    #[arg(long, global = true)]
+    control_stdin: bool,
+
+    /// Seed for random steps (e.g. `select-uncertain --random`); same inputs and seed give identical outputs
+    #[arg(long, global = true, default_value_t = 0)]
+    seed: u64,
+
+    /// Only print warnings and errors on stderr (no progress or status lines); the result manifest still goes to stdout
+    #[arg(long, global = true)]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    AuditBackground(audit_background::AuditBackgroundArgs),
     Convert(convert::ConvertArgs),
     Crop(crop::CropArgs),
+    Defects(defects::DefectsArgs),
+    ExportMasks(export_masks::ExportMasksArgs),
+    ExportTraining(export_training::ExportTrainingArgs),
     Expression(expression::ExpressionArgs),
+    Flow(flow::FlowArgs),
+    Heatmap(heatmap::HeatmapArgs),
+    ImportMasks(import_masks::ImportMasksArgs),
     Kill(kill::KillArgs),
+    KillQc(kill_qc::KillQcArgs),
+    MaskStats(mask_stats::MaskStatsArgs),
     Movie(movie::MovieArgs),
+    Report(report::ReportArgs),
+    Rotation(rotation::RotationArgs),
+    Sector(sector::SectorArgs),
+    SelectUncertain(select_uncertain::SelectUncertainArgs),
+    ServeZarr(serve_zarr::ServeZarrArgs),
+    Smooth(smooth::SmoothArgs),
+    Snapshot(snapshot::SnapshotArgs),
     Spot(spot::SpotArgs),
+    Survival(survival::SurvivalArgs),
     Tissue(tissue::TissueArgs),
+    Validate(validate::ValidateArgs),
 }
 
-fn progress(prog: f64, msg: &str) {
-    let _ = writeln!(
-        io::stderr(),
-        "{}",
-        serde_json::json!({"progress": prog, "message": msg})
-    );
-    let _ = io::stderr().flush();
+impl Commands {
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::AuditBackground(_) => "audit-background",
+            Commands::Convert(_) => "convert",
+            Commands::Crop(_) => "crop",
+            Commands::Defects(_) => "defects",
+            Commands::ExportMasks(_) => "export-masks",
+            Commands::ExportTraining(_) => "export-training",
+            Commands::Expression(_) => "expression",
+            Commands::Flow(_) => "flow",
+            Commands::Heatmap(_) => "heatmap",
+            Commands::ImportMasks(_) => "import-masks",
+            Commands::Kill(_) => "kill",
+            Commands::KillQc(_) => "kill-qc",
+            Commands::MaskStats(_) => "mask-stats",
+            Commands::Movie(_) => "movie",
+            Commands::Report(_) => "report",
+            Commands::Rotation(_) => "rotation",
+            Commands::Sector(_) => "sector",
+            Commands::SelectUncertain(_) => "select-uncertain",
+            Commands::ServeZarr(_) => "serve-zarr",
+            Commands::Smooth(_) => "smooth",
+            Commands::Snapshot(_) => "snapshot",
+            Commands::Spot(_) => "spot",
+            Commands::Survival(_) => "survival",
+            Commands::Tissue(_) => "tissue",
+            Commands::Validate(_) => "validate",
+        }
+    }
+
+    /// Paths the command writes to, as given on the command line.
+    fn outputs(&self) -> Vec<String> {
+        match self {
+            Commands::AuditBackground(_) => Vec::new(),
+            Commands::Convert(args) => vec![args.output.clone()],
+            Commands::Crop(args) => vec![args.output.clone()],
+            Commands::Defects(args) => vec![args.output.clone()],
+            Commands::ExportMasks(args) => vec![args.output.clone()],
+            Commands::ExportTraining(args) => vec![args.output.clone()],
+            Commands::Expression(args) => vec![args.output.clone()],
+            Commands::Flow(args) => std::iter::once(args.output.clone())
+                .chain(args.vectors.clone())
+                .collect(),
+            Commands::Heatmap(args) => vec![args.output.clone()],
+            Commands::ImportMasks(args) => vec![args.output.clone()],
+            Commands::Kill(args) => vec![args.output.clone()],
+            Commands::KillQc(args) => [args.output.clone()]
+                .into_iter()
+                .chain(args.summary.clone())
+                .collect(),
+            Commands::MaskStats(args) => vec![args.output.clone()],
+            Commands::Movie(args) => vec![args.output.clone()],
+            Commands::Report(args) => vec![args.output.clone()],
+            Commands::Rotation(args) => vec![args.output.clone()],
+            Commands::Sector(args) => vec![args.output.clone()],
+            Commands::SelectUncertain(args) => vec![args.output.clone()],
+            Commands::ServeZarr(_) => Vec::new(),
+            Commands::Smooth(args) => vec![args.output.clone()],
+            Commands::Snapshot(args) => vec![args.output.clone()],
+            Commands::Spot(args) => std::iter::once(args.output.clone())
+                .chain(args.cell_counts.clone())
+                .collect(),
+            Commands::Survival(args) => std::iter::once(args.output.clone())
+                .chain(args.crops.clone())
+                .collect(),
+            Commands::Tissue(args) => {
+                [args.output.clone(), args.masks_path().display().to_string()]
+                    .into_iter()
+                    .chain(args.summary.clone())
+                    .collect()
+            }
+            Commands::Validate(_) => Vec::new(),
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
-    match cli.command {
-        Commands::Convert(args) => convert::run(args, progress)?,
-        Commands::Crop(args) => crop::run(args, progress)?,
-        Commands::Expression(args) => expression::run(args, progress)?,
-        Commands::Kill(args) => kill::run(args, progress)?,
-        Commands::Movie(args) => movie::run(args, progress)?,
-        Commands::Spot(args) => spot::run(args, progress)?,
-        Commands::Tissue(args) => tissue::run(args, progress)?,
+fn run(command: Commands) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Commands::AuditBackground(args) => audit_background::run(args, progress::emit)?,
+        Commands::Convert(args) => convert::run(args, progress::emit)?,
+        Commands::Crop(args) => crop::run(args, progress::emit)?,
+        Commands::Defects(args) => defects::run(args, progress::emit)?,
+        Commands::ExportMasks(args) => export_masks::run(args, progress::emit)?,
+        Commands::ExportTraining(args) => export_training::run(args, progress::emit)?,
+        Commands::Expression(args) => expression::run(args, progress::emit)?,
+        Commands::Flow(args) => flow::run(args, progress::emit)?,
+        Commands::Heatmap(args) => heatmap::run(args, progress::emit)?,
+        Commands::ImportMasks(args) => import_masks::run(args, progress::emit)?,
+        Commands::Kill(args) => kill::run(args, progress::emit)?,
+        Commands::KillQc(args) => kill_qc::run(args, progress::emit)?,
+        Commands::MaskStats(args) => mask_stats::run(args, progress::emit)?,
+        Commands::Movie(args) => movie::run(args, progress::emit)?,
+        Commands::Report(args) => report::run(args, progress::emit)?,
+        Commands::Rotation(args) => rotation::run(args, progress::emit)?,
+        Commands::Sector(args) => sector::run(args, progress::emit)?,
+        Commands::SelectUncertain(args) => select_uncertain::run(args, progress::emit)?,
+        Commands::ServeZarr(args) => serve_zarr::run(args, progress::emit)?,
+        Commands::Smooth(args) => smooth::run(args, progress::emit)?,
+        Commands::Snapshot(args) => snapshot::run(args, progress::emit)?,
+        Commands::Spot(args) => spot::run(args, progress::emit)?,
+        Commands::Survival(args) => survival::run(args, progress::emit)?,
+        Commands::Tissue(args) => tissue::run(args, progress::emit)?,
+        Commands::Validate(args) => validate::run(args, progress::emit)?,
     }
     Ok(())
 }
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let base = config::apply_defaults(config::apply_env(layout::declare(Cli::command())));
+    let argv: Vec<String> = std::env::args().collect();
+    let layout = layout::Layout::detect(&groups::group(base.clone()), &argv);
+    let cmd = groups::group(match &layout {
+        Some(layout) => layout.apply(base),
+        None => base,
+    });
+    if argv.iter().any(|a| a == "--help-json") {
+        println!("{}", groups::help_json(&cmd, &argv));
+        return Ok(());
+    }
+    let matches = groups::flatten(cmd.get_matches());
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    console::set_quiet(cli.quiet);
+    memory::set_budget(cli.max_memory);
+    seed::set_seed(cli.seed);
+    zarr::configure_cache(cli.array_cache, cli.readahead, cli.chunk_cache_mb);
+    tiff_cache::configure(cli.tiff_cache_mb);
+    session::set_trt_engine_cache(cli.trt_engine_cache.clone())?;
+    if cli.control_stdin {
+        cancel::listen_stdin();
+    }
+    let command = cli.command.name();
+    let outputs = cli.command.outputs();
+    let started = Instant::now();
+
+    let result = run(cli.command);
+    if result.is_ok() {
+        if let Some(layout) = &layout {
+            if let Err(e) = layout.record(&matches) {
+                console::warn(&format!("layout: could not record paths: {}", e));
+            }
+        }
+        manifest::print(command);
+    }
+
+    if !cli.quiet {
+        let _ = writeln!(
+            io::stderr(),
+            "{}",
+            stats::summary(command, started.elapsed())
+        );
+        let _ = io::stderr().flush();
+    }
+
+    if let Some(url) = &cli.notify_url {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        if let Err(e) =
+            notify::post_summary(url, command, started.elapsed(), &outputs, error.as_deref())
+        {
+            console::warn(&format!("notify: failed to POST summary to {}: {}", url, e));
+        }
+    }
+    result
+}
@@ -0,0 +1,68 @@
+//! Result manifest: the artifacts a command produced, printed as one JSON line on stdout
+//! when it succeeds so workflow managers (Snakemake, Nextflow) can track outputs without
+//! guessing filenames. Progress and logs stay on stderr.
+//!
+//! `{"command": "crop", "status": "ok", "artifacts": [{"path": ..., "type": ..., ...}]}`
+//! where `type` is `table` (with `format`, `table`, `rows`), `zarr_array` (with `shape`),
+//! `tiff_folder` (with `files`), `movie` (with `frames`), `image` (with `width`, `height`) or `report`. `warnings` lists the
+//! messages passed to `console::warn` during the run.
+
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::console;
+use crate::output;
+
+static ARTIFACTS: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+fn record(artifact: Value) {
+    ARTIFACTS.lock().unwrap().push(artifact);
+}
+
+/// A CSV or SQLite table written by `output::TableWriter`.
+pub fn table(path: &str, table: &str, rows: u64) {
+    let format = if output::is_sqlite_path(path) {
+        "sqlite"
+    } else {
+        "csv"
+    };
+    record(json!({"path": path, "type": "table", "format": format, "table": table, "rows": rows}));
+}
+
+/// An array at `array_path` inside the Zarr store at `store_root`.
+pub fn array(store_root: &Path, array_path: &str, shape: &[u64]) {
+    let path = store_root.join(array_path.trim_start_matches('/'));
+    record(json!({"path": path.display().to_string(), "type": "zarr_array", "shape": shape}));
+}
+
+/// A directory of TIFFs written by `convert`.
+pub fn tiff_folder(path: &Path, files: u64) {
+    record(json!({"path": path.display().to_string(), "type": "tiff_folder", "files": files}));
+}
+
+pub fn movie(path: &str, frames: usize) {
+    record(json!({"path": path, "type": "movie", "frames": frames}));
+}
+
+pub fn image(path: &str, width: u64, height: u64) {
+    record(json!({"path": path, "type": "image", "width": width, "height": height}));
+}
+
+pub fn report(path: &str) {
+    record(json!({"path": path, "type": "report"}));
+}
+
+/// Print the manifest line for a successful `command` on stdout.
+pub fn print(command: &str) {
+    let artifacts = std::mem::take(&mut *ARTIFACTS.lock().unwrap());
+    let warnings = console::take_warnings();
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(
+        stdout,
+        "{}",
+        json!({"command": command, "status": "ok", "artifacts": artifacts, "warnings": warnings})
+    );
+    let _ = stdout.flush();
+}
@@ -0,0 +1,127 @@
+//! Occupancy QC straight from a masks.zarr: per frame and crop the number of labelled
+//! objects, the masked area and the mean object size. Works on masks from `tissue` or
+//! any other segmentation imported into the same layout; no fluorescence is read.
+
+use clap::Args;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::cancel;
+use crate::crop_index;
+use crate::exclude::ExclusionList;
+use crate::output::{ColumnType, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::zarr;
+
+const SCHEMA: Schema = Schema {
+    table: "mask_stats",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("labels", ColumnType::Integer),
+        ("area", ColumnType::Integer),
+        ("mean_area", ColumnType::Real),
+        ("coverage", ColumnType::Real),
+    ],
+};
+
+#[derive(Args, Clone)]
+pub struct MaskStatsArgs {
+    /// Path to masks.zarr
+    #[arg(long)]
+    pub masks: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Output path: CSV, or SQLite when ending in .sqlite/.db
+    #[arg(long)]
+    pub output: String,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+}
+
+/// (distinct labels, labelled pixels) of one label frame.
+pub(crate) fn frame_stats(labels: &[u32]) -> (u64, u64) {
+    let mut seen = HashSet::new();
+    let mut area = 0u64;
+    for &l in labels.iter().filter(|&&l| l > 0) {
+        seen.insert(l);
+        area += 1;
+    }
+    (seen.len() as u64, area)
+}
+
+pub fn run(
+    args: MaskStatsArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let masks_zarr = Path::new(&args.masks);
+    let pos_id = format!("{:03}", args.pos);
+    let crop_root = masks_zarr.join("pos").join(&pos_id).join("crop");
+    if !crop_root.exists() {
+        return Err(format!("No masks for position {} in {}", args.pos, args.masks).into());
+    }
+
+    let mut crop_ids = crop_index::list(masks_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
+
+    let mut table = TableWriter::create(&args.output, &SCHEMA, args.pos)?;
+    let store = zarr::open_store(masks_zarr)?;
+    stats::stage("analyze");
+    let total = crop_ids.len();
+
+    for (i, crop_id) in crop_ids.iter().enumerate() {
+        let arr = zarr::open_array(&store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
+        let shape = arr.shape();
+        let n_t = shape[0];
+        let pixels = shape[1..].iter().product::<u64>();
+        let skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+
+        for t in (0..n_t).filter(|t| !skip.contains(t)) {
+            let (n_labels, area) = frame_stats(&zarr::read_labels(&arr, t)?);
+            let mean_area = if n_labels > 0 {
+                Some(area as f64 / n_labels as f64)
+            } else {
+                None
+            };
+            table.write_row(&[
+                Value::from(t),
+                crop_id.as_str().into(),
+                n_labels.into(),
+                area.into(),
+                mean_area.into(),
+                (area as f64 / pixels as f64).into(),
+            ])?;
+            stats::add_frames(1);
+        }
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "analyze",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Processing crop {}/{}", i + 1, total),
+        ));
+    }
+
+    let n_rows = table.finish()?;
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} rows to {}",
+        n_rows, args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_counted_once_and_background_is_ignored() {
+        assert_eq!(frame_stats(&[0, 3, 3, 0, 70_000, 1]), (3, 4));
+        assert_eq!(frame_stats(&[0; 4]), (0, 0));
+    }
+}
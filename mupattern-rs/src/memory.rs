@@ -0,0 +1,72 @@
+//! Process-wide memory budget for in-flight frame buffers (`--max-memory`).
+//! Commands consult it to size frame caches and inference batches. While `--jobs` workers
+//! run, each of them gets an equal share of the budget.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+static BUDGET: OnceLock<Option<u64>> = OnceLock::new();
+static WORKERS: AtomicUsize = AtomicUsize::new(1);
+
+/// Parse a byte size like "512M", "16G" or "1048576". Suffixes are binary (K = 1024).
+pub fn parse_bytes(s: &str) -> Result<u64, String> {
+    let t = s.trim();
+    let upper = t.to_ascii_uppercase();
+    let digits = upper.trim_end_matches(['B', 'I']);
+    let (num, mult) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1u64 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1u64 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1u64 << 30),
+        Some('T') => (&digits[..digits.len() - 1], 1u64 << 40),
+        _ => (digits, 1u64),
+    };
+    let value: f64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid memory size: {:?}", t))?;
+    if value <= 0.0 {
+        return Err(format!("Memory size must be positive: {:?}", t));
+    }
+    Ok((value * mult as f64) as u64)
+}
+
+/// Set the budget once at startup. Later calls are ignored.
+pub fn set_budget(bytes: Option<u64>) {
+    let _ = BUDGET.set(bytes);
+}
+
+/// Budget available to one worker.
+pub fn budget() -> Option<u64> {
+    let workers = WORKERS.load(Ordering::Relaxed).max(1) as u64;
+    BUDGET.get().copied().flatten().map(|b| b / workers)
+}
+
+/// Split the budget between `n` concurrent workers (1 when they are done).
+pub fn set_workers(n: usize) {
+    WORKERS.store(n.max(1), Ordering::Relaxed);
+}
+
+/// Number of items of `item_bytes` each that fit in the budget, clamped to 1..=requested.
+/// Returns `requested` when no budget is set.
+pub fn cap_items(requested: usize, item_bytes: u64) -> usize {
+    match budget() {
+        Some(b) if item_bytes > 0 => ((b / item_bytes) as usize).clamp(1, requested.max(1)),
+        _ => requested,
+    }
+}
+
+/// Human-readable binary size, e.g. "1.5 GiB".
+pub fn format_bytes(n: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", n)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,175 @@
+//! Mosaics of equally sized crop frames for `tissue --frame-batch`: the frames are laid out
+//! on a grid with blank gaps so one Cellpose call (whose 256×256 tiles are batched on the
+//! GPU) segments all of them, and the label image is cut back into per-frame masks.
+//! Layout and renumbering are exact (a local segmenter gives the same labels as per
+//! frame), but Cellpose tiles and runs any image-level normalisation over the whole
+//! mosaic, so its labels are not guaranteed to match `--frame-batch 1` pixel for pixel.
+
+pub struct Mosaic {
+    n: usize,
+    cols: usize,
+    tile_h: usize,
+    tile_w: usize,
+    gap: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Mosaic {
+    /// Grid for `n` tiles of `tile_h`×`tile_w` separated by `gap` blank pixels.
+    pub fn new(n: usize, tile_h: usize, tile_w: usize, gap: usize) -> Self {
+        let cols = (n as f64).sqrt().ceil().max(1.0) as usize;
+        let rows = n.div_ceil(cols).max(1);
+        Self {
+            n,
+            cols,
+            tile_h,
+            tile_w,
+            gap,
+            height: rows * tile_h + (rows - 1) * gap,
+            width: cols * tile_w + (cols - 1) * gap,
+        }
+    }
+
+    /// Top-left corner (y, x) of tile `i`.
+    fn origin(&self, i: usize) -> (usize, usize) {
+        let (r, c) = (i / self.cols, i % self.cols);
+        (r * (self.tile_h + self.gap), c * (self.tile_w + self.gap))
+    }
+
+    /// Place CHW `images` (any number of channels, the same for all) into one CHW mosaic;
+    /// gaps and unused grid cells stay 0.
+    pub fn pack(&self, images: &[Vec<f32>]) -> Vec<f32> {
+        let plane = self.tile_h * self.tile_w;
+        let n_ch = images.first().map_or(0, |im| im.len() / plane.max(1));
+        let out_plane = self.height * self.width;
+        let mut out = vec![0.0f32; n_ch * out_plane];
+        for (i, image) in images.iter().enumerate().take(self.n) {
+            let (y0, x0) = self.origin(i);
+            for ch in 0..n_ch {
+                for y in 0..self.tile_h {
+                    let src = ch * plane + y * self.tile_w;
+                    let dst = ch * out_plane + (y0 + y) * self.width + x0;
+                    out[dst..dst + self.tile_w].copy_from_slice(&image[src..src + self.tile_w]);
+                }
+            }
+        }
+        out
+    }
+
+    /// Cut the mosaic `labels` back into one mask per tile. Each tile's labels are
+    /// renumbered 1..=k in the order of their mosaic IDs, so a single-tile mosaic of
+    /// consecutive labels comes back unchanged.
+    pub fn split(&self, labels: &[u32]) -> Vec<Vec<u32>> {
+        (0..self.n)
+            .map(|i| {
+                let (y0, x0) = self.origin(i);
+                let mut tile = Vec::with_capacity(self.tile_h * self.tile_w);
+                for y in 0..self.tile_h {
+                    let row = (y0 + y) * self.width + x0;
+                    tile.extend_from_slice(&labels[row..row + self.tile_w]);
+                }
+                let mut ids: Vec<u32> = tile.iter().copied().filter(|&v| v != 0).collect();
+                ids.sort_unstable();
+                ids.dedup();
+                for v in tile.iter_mut().filter(|v| **v != 0) {
+                    *v = ids.binary_search(v).unwrap() as u32 + 1;
+                }
+                tile
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_round_trip_through_the_mosaic() {
+        // Three 2×3 two-channel tiles on a 2×2 grid with a 1 px gap.
+        let mosaic = Mosaic::new(3, 2, 3, 1);
+        assert_eq!((mosaic.height, mosaic.width), (5, 7));
+        let images: Vec<Vec<f32>> = (0..3)
+            .map(|i| (0..12).map(|v| (i * 100 + v) as f32).collect())
+            .collect();
+        let packed = mosaic.pack(&images);
+        assert_eq!(packed.len(), 2 * 5 * 7);
+        assert_eq!(&packed[4..7], &[100.0, 101.0, 102.0]);
+        assert_eq!(packed[3], 0.0);
+        assert_eq!(&packed[35 + 3 * 7..35 + 3 * 7 + 3], &[206.0, 207.0, 208.0]);
+
+        // Tile 0 holds cells 7 and 9 (renumbered 1 and 2), tile 2 holds cell 12.
+        let mut labels = vec![0u32; 5 * 7];
+        labels[0] = 9;
+        labels[1] = 7;
+        labels[7 + 2] = 9;
+        labels[3 * 7 + 1] = 12;
+        let masks = mosaic.split(&labels);
+        assert_eq!(masks[0], [2, 1, 0, 0, 0, 2]);
+        assert_eq!(masks[1], [0; 6]);
+        assert_eq!(masks[2], [0, 1, 0, 0, 0, 0]);
+        assert_eq!(
+            Mosaic::new(1, 2, 3, 8).split(&[1, 2, 0, 0, 3, 2])[0],
+            [1, 2, 0, 0, 3, 2]
+        );
+    }
+
+    /// Stand-in for Cellpose: 4-connected components of the nonzero pixels of channel 0,
+    /// numbered in raster order. Purely local, unlike Cellpose.
+    fn label_components(image: &[f32], h: usize, w: usize) -> Vec<u32> {
+        let mut labels = vec![0u32; h * w];
+        let mut next = 0;
+        for start in 0..h * w {
+            if image[start] == 0.0 || labels[start] != 0 {
+                continue;
+            }
+            next += 1;
+            labels[start] = next;
+            let mut stack = vec![start];
+            while let Some(i) = stack.pop() {
+                let (y, x) = (i / w, i % w);
+                let neighbours = [
+                    (y > 0).then(|| i - w),
+                    (y + 1 < h).then(|| i + w),
+                    (x > 0).then(|| i - 1),
+                    (x + 1 < w).then(|| i + 1),
+                ];
+                for j in neighbours.into_iter().flatten() {
+                    if image[j] != 0.0 && labels[j] == 0 {
+                        labels[j] = next;
+                        stack.push(j);
+                    }
+                }
+            }
+        }
+        labels
+    }
+
+    #[test]
+    fn mosaic_labels_match_per_frame_labels() {
+        // Five 4×5 frames of several cells, some on the tile edges: --frame-batch 5 vs 1.
+        let (h, w) = (4, 5);
+        let frames: Vec<Vec<f32>> = (0..5u32)
+            .map(|i| {
+                (0..h * w)
+                    .map(|p| {
+                        let (y, x) = ((p / w) as u32, (p % w) as u32);
+                        let on = (x + i) % 3 != 1 && y != (i % h as u32);
+                        if on {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let mosaic = Mosaic::new(frames.len(), h, w, 2);
+        let labels = label_components(&mosaic.pack(&frames), mosaic.height, mosaic.width);
+        let batched = mosaic.split(&labels);
+        for (frame, mask) in frames.iter().zip(&batched) {
+            assert_eq!(*mask, label_components(frame, h, w));
+        }
+    }
+}
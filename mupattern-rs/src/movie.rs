@@ -1,23 +1,56 @@
 use clap::Args;
-use std::io::Write;
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use crate::calibration::PixelCalibration;
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::console;
+use crate::crop;
+use crate::crop_index;
+use crate::detector::DetectorRange;
+use crate::exclude::ExclusionList;
+use crate::ffmpeg;
+use crate::manifest;
+use crate::memory;
+use crate::outline::{self, Outlines};
+use crate::overlay::{self, Rgb};
+use crate::parallel;
+use crate::pixelmath;
+use crate::plot::{self, MetricTable};
+use crate::progress::ProgressEvent;
 use crate::slices;
+use crate::stats;
 use crate::zarr;
 
 #[derive(Args, Clone)]
 pub struct MovieArgs {
     #[arg(long)]
     pub input: String,
+    /// Positions: a number, or "all" / slices over position numbers, e.g. "0:10, 150"
     #[arg(long)]
-    pub pos: u32,
+    pub pos: String,
+    /// Crops: IDs as in the bbox CSV (e.g. "7" or "A01,B07"), or "all" / slices over the
+    /// crop list of each position (bbox order)
     #[arg(long)]
-    pub crop: u32,
+    pub crop: String,
+    /// Channel(s) to render by index or name, e.g. "1", "0,1" or "Phase,GFP" (one panel
+    /// per channel)
     #[arg(long)]
-    pub channel: u32,
+    pub channel: String,
+    /// Panel arrangement when several channels are rendered: hstack, vstack or grid. Each
+    /// panel is labelled with the channel's recorded name, else C{index}
+    #[arg(long, default_value = "hstack")]
+    pub layout: String,
+    /// Frames to render, e.g. "all", "0:100", "10-40", "every:5" or "::-1"
     #[arg(long)]
     pub time: String,
+    /// Render frames in the order --time lists them, repeats included (e.g. "::-1" plays
+    /// backwards, "0:50,49:0:-1" there and back) instead of ascending
+    #[arg(long)]
+    pub keep_order: bool,
+    /// Output path; with several movies a template using {pos} and {crop},
+    /// e.g. "movies/pos{pos}_crop{crop}.mp4"
     #[arg(long)]
     pub output: String,
     #[arg(long, default_value_t = 10)]
@@ -26,142 +59,1005 @@ pub struct MovieArgs {
     pub colormap: String,
     #[arg(long)]
     pub spots: Option<String>,
+    /// ffmpeg binary (default: MUPATTERN_FFMPEG, `ffmpeg` in mupattern.toml, then PATH)
+    #[arg(long)]
+    pub ffmpeg: Option<String>,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+    /// Intensity range: "per-movie" (each movie min–max), "shared" (one min–max over
+    /// every selected movie, so brightness is comparable across them) or "detector" (the
+    /// camera offset to full scale recorded by convert/crop, independent of the data)
+    #[arg(long, default_value = "per-movie")]
+    pub contrast: String,
+    /// Draw a scale bar of this length in µm (needs pixel calibration in crops.zarr)
+    #[arg(long)]
+    pub scale_bar_um: Option<f64>,
+    /// CSV of per-frame annotations (columns: t, text, x, y, color). Empty t = every frame;
+    /// empty x/y = stacked in the top-left corner. x/y are crop pixels.
+    #[arg(long)]
+    pub annotations: Option<String>,
+    /// CSV of key moments (columns: t, label), e.g. "drug added"; each becomes a chapter
+    /// marker starting at the first rendered frame at or after t
+    #[arg(long)]
+    pub events: Option<String>,
+    /// Also show each event label on a title card held for this many frames before the event
+    #[arg(long, default_value_t = 0)]
+    pub event_card_frames: u32,
+    /// Vector CSV from `flow --vectors` (columns: t, crop, y, x, dy, dx; optional pos);
+    /// each frame shows the motion since the previous one as arrows
     #[arg(long)]
-    pub ffmpeg: String,
+    pub flow: Option<String>,
+    /// Arrow length per pixel of displacement
+    #[arg(long, default_value_t = 4.0)]
+    pub flow_scale: f64,
+    /// masks.zarr (e.g. from `tissue`) whose cell outlines are drawn on every panel: the
+    /// stored outline arrays (`tissue --outlines`), else traced from the label frames
+    #[arg(long)]
+    pub masks: Option<String>,
+    /// Color of the --masks outlines: a name or #rrggbb
+    #[arg(long, default_value = "cyan")]
+    pub outline_color: String,
+    /// Line plot of a per-crop metric next to the image, "metric.csv:column" (columns t, crop
+    /// and that column; optional pos), with the current frame marked
+    #[arg(long)]
+    pub plot: Option<String>,
+    /// Quick check: render every Nth frame at half resolution with a fast encoder preset
+    #[arg(long, value_name = "N")]
+    pub preview: Option<usize>,
+    /// Render up to this many movies at once on separate threads (bounded by --max-memory)
+    #[arg(long, default_value_t = 1)]
+    pub jobs: usize,
+}
+
+pub(crate) struct Annotation {
+    t: Option<u64>,
+    text: String,
+    at: Option<(f64, f64)>,
+    color: Rgb,
+}
+
+pub(crate) fn load_annotations(path: &str) -> Result<Vec<Annotation>, Box<dyn std::error::Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let col = |name: &str| headers.iter().position(|h| h == name);
+    let t_idx = col("t").ok_or("Annotations CSV needs a t column")?;
+    let text_idx = col("text").ok_or("Annotations CSV needs a text column")?;
+    let (x_idx, y_idx, color_idx) = (col("x"), col("y"), col("color"));
+
+    let mut out = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        let field = |idx: Option<usize>| idx.and_then(|i| record.get(i)).unwrap_or("");
+        let t = match field(Some(t_idx)) {
+            "" => None,
+            v => Some(v.parse()?),
+        };
+        let at = match (field(x_idx), field(y_idx)) {
+            ("", _) | (_, "") => None,
+            (x, y) => Some((x.parse()?, y.parse()?)),
+        };
+        out.push(Annotation {
+            t,
+            text: field(Some(text_idx)).to_string(),
+            at,
+            color: overlay::parse_color(field(color_idx))?,
+        });
+    }
+    Ok(out)
+}
+
+/// Draw the annotations for frame `t`: markers with a label at their position, or
+/// stacked text lines in the top-left corner.
+fn draw_annotations(
+    rgb: &mut [u8],
+    stride: u64,
+    w: u64,
+    h: u64,
+    t: u64,
+    annotations: &[Annotation],
+) {
+    let scale = overlay::text_scale(h);
+    let line = overlay::line_height(scale) as i64;
+    let margin = 2 * scale as i64;
+    let mut corner_y = margin;
+    for ann in annotations.iter().filter(|a| a.t.iter().all(|&at| at == t)) {
+        match ann.at {
+            Some((x, y)) => {
+                let (x, y) = (x.round() as i64, y.round() as i64);
+                let r = 2 * scale as i64;
+                overlay::draw_marker(rgb, stride, (w, h), (x, y), r, ann.color);
+                // Label to the right of the marker, or to the left near the right edge.
+                let width = overlay::text_width(&ann.text, scale) as i64;
+                let mut label_x = x + r + scale as i64;
+                if label_x + width > w as i64 {
+                    label_x = x - r - scale as i64 - width;
+                }
+                let label = (label_x, y - line / 2);
+                overlay::draw_text(rgb, stride, (w, h), label, &ann.text, ann.color, scale);
+            }
+            None => {
+                overlay::draw_text(
+                    rgb,
+                    stride,
+                    (w, h),
+                    (margin, corner_y),
+                    &ann.text,
+                    ann.color,
+                    scale,
+                );
+                corner_y += line;
+            }
+        }
+    }
+}
+
+struct Event {
+    t: u64,
+    label: String,
+}
+
+fn load_events(path: &str) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let col = |name: &str| headers.iter().position(|h| h == name);
+    let t_idx = col("t").ok_or("Events CSV needs a t column")?;
+    let label_idx = col("label").ok_or("Events CSV needs a label column")?;
+    let mut events = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        let t = record.get(t_idx).unwrap_or("");
+        events.push(Event {
+            t: t.parse()
+                .map_err(|_| format!("Invalid event time {t:?} in {path}"))?,
+            label: record.get(label_idx).unwrap_or("").to_string(),
+        });
+    }
+    events.sort_by_key(|e| e.t);
+    Ok(events)
+}
+
+pub(crate) struct FlowVector {
+    pos: Option<u32>,
+    crop: String,
+    t: u64,
+    y: f64,
+    x: f64,
+    dy: f64,
+    dx: f64,
+}
+
+pub(crate) fn load_flow(path: &str) -> Result<Vec<FlowVector>, Box<dyn std::error::Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let col = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| format!("Flow CSV needs a {name} column"))
+    };
+    let idx = [col("t")?, col("y")?, col("x")?, col("dy")?, col("dx")?];
+    let crop_idx = col("crop")?;
+    let pos_idx = col("pos").ok();
+    let mut out = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        let field = |i: usize| record.get(i).unwrap_or("");
+        let [t, y, x, dy, dx] = idx.map(field);
+        out.push(FlowVector {
+            pos: pos_idx.map(|i| field(i).parse()).transpose()?,
+            crop: field(crop_idx).to_string(),
+            t: t.parse()?,
+            y: y.parse()?,
+            x: x.parse()?,
+            dy: dy.parse()?,
+            dx: dx.parse()?,
+        });
+    }
+    Ok(out)
+}
+
+/// The vectors of one crop (rows without a pos apply to every position).
+pub(crate) fn crop_flow<'a>(
+    flow: &'a [FlowVector],
+    pos: u32,
+    crop_id: &str,
+) -> Vec<&'a FlowVector> {
+    flow.iter()
+        .filter(|v| v.crop == crop_id && v.pos.iter().all(|&p| p == pos))
+        .collect()
+}
+
+/// Place events on the rendered frames: (index into `time_indices`, event) for each event
+/// at or before the last rendered frame, in time order.
+fn place_events<'a>(events: &'a [Event], time_indices: &[usize]) -> Vec<(usize, &'a Event)> {
+    events
+        .iter()
+        .filter_map(|e| {
+            let i = time_indices.iter().position(|&t| t as u64 >= e.t)?;
+            Some((i, e))
+        })
+        .collect()
+}
+
+/// Escape a value for ffmpeg's FFMETADATA format.
+fn escape_ffmetadata(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        if matches!(ch, '=' | ';' | '#' | '\\' | '\n') {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+    out
+}
+
+/// FFMETADATA with one chapter per `(start frame, title)`, each running to the next
+/// chapter or to `total_frames`.
+fn chapters_metadata(chapters: &[(u64, &str)], total_frames: u64, fps: u32) -> String {
+    let mut text = String::from(";FFMETADATA1\n");
+    for (i, &(start, title)) in chapters.iter().enumerate() {
+        let end = chapters.get(i + 1).map_or(total_frames, |c| c.0);
+        text.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/{}\nSTART={}\nEND={}\ntitle={}\n",
+            fps,
+            start,
+            end,
+            escape_ffmetadata(title)
+        ));
+    }
+    text
+}
+
+/// One movie of a batch.
+struct Job {
+    pos: u32,
+    crop_id: String,
+    output: String,
+}
+
+/// Settings shared by every movie of a run.
+struct Shared {
+    store: zarr::Store,
+    exclusions: ExclusionList,
+    annotations: Vec<Annotation>,
+    events: Vec<Event>,
+    flow: Vec<FlowVector>,
+    plot: Option<MetricTable>,
+    um_per_px: Option<f64>,
+    /// Channels rendered as panels, in `--channel` order.
+    channels: Vec<u64>,
+    /// Panel label of each of `channels` (`panel_labels`).
+    labels: Vec<String>,
+    layout: Layout,
+    /// Fixed (min, max) intensity range per channel for `--contrast shared` and `detector`.
+    contrast: Option<Vec<(u16, u16)>>,
+    ffmpeg: PathBuf,
+    /// `--masks` store and outline color.
+    masks: Option<(zarr::Store, Rgb)>,
+}
+
+/// Color of `--flow` arrows.
+const FLOW_COLOR: Rgb = [255, 255, 0];
+
+/// ffmpeg video encoder used for every movie.
+const VIDEO_ENCODER: &str = "libx264";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Layout {
+    HStack,
+    VStack,
+    Grid,
+}
+
+impl Layout {
+    pub(crate) fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "hstack" => Ok(Self::HStack),
+            "vstack" => Ok(Self::VStack),
+            "grid" => Ok(Self::Grid),
+            _ => Err(format!("Unknown layout {s:?}. Use hstack, vstack or grid.").into()),
+        }
+    }
+
+    /// (columns, rows) of panels for `n` channels.
+    pub(crate) fn grid(self, n: usize) -> (usize, usize) {
+        match self {
+            Self::HStack => (n, 1),
+            Self::VStack => (1, n),
+            Self::Grid => {
+                let cols = (n as f64).sqrt().ceil() as usize;
+                (cols, n.div_ceil(cols))
+            }
+        }
+    }
+}
+
+pub(crate) fn parse_channels(
+    s: &str,
+    names: &ChannelNames,
+) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    let channels = s
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| names.resolve(c).map(u64::from))
+        .collect::<Result<Vec<_>, _>>()?;
+    if channels.is_empty() {
+        return Err("--channel needs at least one channel index or name".into());
+    }
+    Ok(channels)
+}
+
+/// Label of each channel panel: the name recorded in crops.zarr, else `C{index}`.
+pub(crate) fn panel_labels(channels: &[u64], names: &ChannelNames) -> Vec<String> {
+    channels
+        .iter()
+        .map(|&c| match names.0.get(c as usize) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("C{c}"),
+        })
+        .collect()
+}
+
+/// One frame of every rendered channel.
+pub(crate) fn read_panels(
+    arr: &zarr::StoreArray,
+    t: usize,
+    channels: &[u64],
+) -> Result<Vec<Vec<u16>>, Box<dyn std::error::Error>> {
+    channels
+        .iter()
+        .map(|&c| zarr::read_frame_u16(arr, &[t as u64, c, 0]))
+        .collect()
+}
+
+/// Widen each channel's (min, max) in `ranges` to cover its panel in `panels`.
+pub(crate) fn widen_ranges(ranges: &mut [(u16, u16)], panels: &[Vec<u16>]) {
+    for (range, data) in ranges.iter_mut().zip(panels) {
+        if let Some((lo, hi)) = pixelmath::min_max(data) {
+            range.0 = range.0.min(lo);
+            range.1 = range.1.max(hi);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Contrast {
+    PerMovie,
+    Shared,
+    Detector,
+}
+
+impl Contrast {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "per-movie" => Ok(Self::PerMovie),
+            "shared" => Ok(Self::Shared),
+            "detector" => Ok(Self::Detector),
+            _ => Err(
+                format!("Unknown contrast mode {s:?}. Use per-movie, shared or detector.").into(),
+            ),
+        }
+    }
+}
+
+fn subdirs(dir: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|e| {
+            let e = e.ok()?;
+            if e.file_type().ok()?.is_dir() {
+                e.file_name().to_str().map(String::from)
+            } else {
+                None
+            }
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Positions in the store matching `spec` (slice syntax over position numbers).
+fn select_positions(root: &Path, spec: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let mut available: Vec<u32> = subdirs(&root.join("pos"))?
+        .iter()
+        .filter_map(|p| p.parse().ok())
+        .collect();
+    available.sort_unstable();
+    let Some(&max) = available.last() else {
+        return Err(format!("No positions in {}", root.display()).into());
+    };
+    let selected: Vec<u32> = slices::parse_slice_string(spec, max as usize + 1)?
+        .into_iter()
+        .map(|p| p as u32)
+        .filter(|p| available.contains(p))
+        .collect();
+    if selected.is_empty() {
+        return Err(format!("No positions in {} match {:?}", root.display(), spec).into());
+    }
+    Ok(selected)
+}
+
+/// Crops among `available` (see `crop_index::list`) matching `spec`: "all" and slices
+/// index that list, other tokens are crop IDs (with or without zero padding).
+fn select_crops(
+    available: &[String],
+    spec: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut wanted: Vec<&str> = Vec::new();
+    for token in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        if token.eq_ignore_ascii_case("all") || token.contains(':') {
+            for i in slices::parse_slice_string(token, available.len())? {
+                wanted.push(&available[i]);
+            }
+        } else {
+            let found = crop_index::find(available, token)
+                .ok_or_else(|| format!("Crop {} not found", token))?;
+            wanted.push(found);
+        }
+    }
+    Ok(available
+        .iter()
+        .filter(|a| wanted.contains(&a.as_str()))
+        .cloned()
+        .collect())
+}
+
+fn output_path(template: &str, pos: u32, crop_id: &str) -> String {
+    template
+        .replace("{pos}", &pos.to_string())
+        .replace("{crop}", crop_id)
 }
 
 pub fn run(
     args: MovieArgs,
-    progress: impl Fn(f64, &str),
+    progress: impl Fn(ProgressEvent) + Sync,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let zarr_path = Path::new(&args.input);
-    let crop_id = format!("{:03}", args.crop);
-    let pos_id = format!("{:03}", args.pos);
-
+    let contrast = Contrast::parse(&args.contrast)?;
+    let layout = Layout::parse(&args.layout)?;
     let store = zarr::open_store(zarr_path)?;
-    let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
-    let arr = zarr::open_array(&store, &array_path)?;
+    let names = ChannelNames::from_store(&store);
+    let channels = parse_channels(&args.channel, &names)?;
+    let labels = panel_labels(&channels, &names);
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+
+    let mut jobs: Vec<Job> = Vec::new();
+    for pos in select_positions(zarr_path, &args.pos)? {
+        let available = crop_index::list(zarr_path, &format!("{:03}", pos))?;
+        for crop_id in select_crops(&available, &args.crop)? {
+            jobs.push(Job {
+                pos,
+                output: output_path(&args.output, pos, &crop_id),
+                crop_id,
+            });
+        }
+    }
+    if jobs.len() > 1 {
+        jobs.retain(|job| !exclusions.crop_excluded(job.pos, &job.crop_id));
+        let mut outputs: Vec<&str> = jobs.iter().map(|j| j.output.as_str()).collect();
+        outputs.sort_unstable();
+        outputs.dedup();
+        if outputs.len() < jobs.len() {
+            return Err(
+                "Several movies selected: --output must contain {pos} and/or {crop}".into(),
+            );
+        }
+    }
+    if jobs.is_empty() {
+        return Err("No crops selected".into());
+    }
+
+    if args.preview == Some(0) {
+        return Err("--preview needs a frame step of at least 1".into());
+    }
+    let ffmpeg_bin = ffmpeg::locate(args.ffmpeg.as_deref())?;
+    ffmpeg::verify_encoder(&ffmpeg_bin, VIDEO_ENCODER)?;
+
+    let mut shared = Shared {
+        annotations: match &args.annotations {
+            Some(path) => load_annotations(path)?,
+            None => Vec::new(),
+        },
+        events: match &args.events {
+            Some(path) => load_events(path)?,
+            None => Vec::new(),
+        },
+        flow: match &args.flow {
+            Some(path) => load_flow(path)?,
+            None => Vec::new(),
+        },
+        plot: args.plot.as_deref().map(MetricTable::load).transpose()?,
+        um_per_px: PixelCalibration::from_store(&store).um_per_px,
+        store,
+        exclusions,
+        channels,
+        labels,
+        layout,
+        contrast: None,
+        ffmpeg: ffmpeg_bin,
+        masks: match &args.masks {
+            Some(path) => Some((
+                zarr::open_store(Path::new(path))?,
+                overlay::parse_color(&args.outline_color)?,
+            )),
+            None => None,
+        },
+    };
+    if args.scale_bar_um.is_some() && shared.um_per_px.is_none() {
+        return Err(
+            "--scale-bar-um needs a pixel calibration in crops.zarr (crop --pixel-size-um)".into(),
+        );
+    }
+
+    let n_jobs = jobs.len();
+    if contrast == Contrast::Detector {
+        let bounds =
+            DetectorRange::from_store(&shared.store).require_bounds("--contrast detector")?;
+        shared.contrast = Some(vec![bounds; shared.channels.len()]);
+    }
+    if contrast == Contrast::Shared {
+        stats::stage("contrast");
+        let mut ranges = vec![(u16::MAX, u16::MIN); shared.channels.len()];
+        for (j, job) in jobs.iter().enumerate() {
+            let (arr, time_indices) = open_job(&args, &shared, job)?;
+            for &t in &time_indices {
+                cancel::check()?;
+                widen_ranges(&mut ranges, &read_panels(&arr, t, &shared.channels)?);
+            }
+            progress(ProgressEvent::new(
+                "contrast",
+                (j + 1) as u64,
+                n_jobs as u64,
+                &format!("Shared contrast: scanned {}/{} movies", j + 1, n_jobs),
+            ));
+        }
+        for (c, (lo, hi)) in shared.channels.iter().zip(&ranges) {
+            console::info(&format!(
+                "movie: shared intensity range for channel {}: {}-{}",
+                c, lo, hi
+            ));
+        }
+        shared.contrast = Some(ranges);
+    }
+
+    // One rendered RGB frame of the largest crop is the least a worker needs in memory.
+    let mut frame_bytes = 0u64;
+    if args.jobs > 1 {
+        let (cols, rows) = shared.layout.grid(shared.channels.len());
+        for job in &jobs {
+            let (arr, _) = open_job(&args, &shared, job)?;
+            let (h, w) = (arr.shape()[3], arr.shape()[4]);
+            let plot_w = if shared.plot.is_some() {
+                plot::width(rows as u64 * h)
+            } else {
+                0
+            };
+            frame_bytes = frame_bytes.max((cols as u64 * w + plot_w) * rows as u64 * h * 3);
+        }
+    }
+    parallel::for_each(&jobs, args.jobs, frame_bytes, |j, job| {
+        let job_progress = |event: ProgressEvent| {
+            if n_jobs > 1 {
+                let msg = format!(
+                    "[{}/{}] Pos{} crop {}: {}",
+                    j + 1,
+                    n_jobs,
+                    job.pos,
+                    job.crop_id,
+                    event.message
+                );
+                progress(ProgressEvent {
+                    message: &msg,
+                    ..event
+                });
+            } else {
+                progress(event);
+            }
+        };
+        render_movie(&args, &shared, job, job_progress)
+    })?;
+
+    if n_jobs > 1 {
+        progress(ProgressEvent::done(&format!("Wrote {} movies", n_jobs)));
+    } else {
+        progress(ProgressEvent::done(&format!("Wrote {}", jobs[0].output)));
+    }
+    Ok(())
+}
+
+/// Open the crop array of `job` and list its frames to render (`--time` minus exclusions
+/// and frames `crop` filled in a shown channel).
+fn open_job(
+    args: &MovieArgs,
+    shared: &Shared,
+    job: &Job,
+) -> Result<(zarr::StoreArray, Vec<usize>), Box<dyn std::error::Error>> {
+    let pos_id = format!("{:03}", job.pos);
+    let array_path = format!("/pos/{}/crop/{}", pos_id, job.crop_id);
+    let arr = zarr::open_array(&shared.store, &array_path)?;
     let shape = arr.shape();
     let n_t = shape[0];
     let n_channels = shape[1];
-    if args.channel >= n_channels as u32 {
-        return Err(format!("Channel {} out of range (0-{})", args.channel, n_channels - 1).into());
+    if let Some(&c) = shared.channels.iter().find(|&&c| c >= n_channels) {
+        return Err(format!("Channel {} out of range (0-{})", c, n_channels - 1).into());
     }
-    let h = shape[3];
-    let w = shape[4];
 
-    let time_indices = slices::parse_slice_string(&args.time, n_t as usize)?;
+    let mut skip = shared
+        .exclusions
+        .excluded_frames(job.pos, &job.crop_id, n_t)?;
+    for &c in &shared.channels {
+        skip.extend(crop::missing_times(&arr, c));
+    }
+    let time_indices = if args.keep_order {
+        slices::parse_slice_order(&args.time, n_t as usize)?
+    } else {
+        slices::parse_slice_string(&args.time, n_t as usize)?
+    };
+    let time_indices: Vec<usize> = time_indices
+        .into_iter()
+        .filter(|&t| !skip.contains(&(t as u64)))
+        .step_by(args.preview.unwrap_or(1))
+        .collect();
     if time_indices.is_empty() {
         return Err("No frames to write".into());
     }
+    Ok((arr, time_indices))
+}
 
-    let mut frames_raw: Vec<Vec<f64>> = Vec::new();
-    for (i, &t) in time_indices.iter().enumerate() {
-        progress(
-            (i + 1) as f64 / time_indices.len() as f64 * 0.4,
+fn render_movie(
+    args: &MovieArgs,
+    shared: &Shared,
+    job: &Job,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (arr, time_indices) = open_job(args, shared, job)?;
+    let h = arr.shape()[3];
+    let w = arr.shape()[4];
+    let channels = &shared.channels;
+    let (cols, rows) = shared.layout.grid(channels.len());
+    let plot_w = if shared.plot.is_some() {
+        plot::width(rows as u64 * h)
+    } else {
+        0
+    };
+    let (frame_w, frame_h) = (cols as u64 * w + plot_w, rows as u64 * h);
+
+    // Keep raw frames for the encode pass only if they fit the memory budget;
+    // otherwise re-read them from the store after computing the global range.
+    // A shared contrast range is already known, so frames are read once while encoding.
+    let frame_bytes = h * w * 2 * channels.len() as u64;
+    let cache_frames = shared.contrast.is_none()
+        && memory::cap_items(time_indices.len(), frame_bytes) == time_indices.len();
+
+    stats::stage("read");
+    let mut frames_raw: Vec<Vec<Vec<u16>>> = Vec::new();
+    let mut ranges = shared
+        .contrast
+        .clone()
+        .unwrap_or_else(|| vec![(u16::MAX, u16::MIN); channels.len()]);
+    let read_pass: &[usize] = if shared.contrast.is_some() {
+        &[]
+    } else {
+        &time_indices
+    };
+    for (i, &t) in read_pass.iter().enumerate() {
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "read",
+            (i + 1) as u64,
+            time_indices.len() as u64,
             &format!("Reading frames {}/{}", i + 1, time_indices.len()),
-        );
-        let chunk_indices = vec![t as u64, args.channel as u64, 0, 0, 0];
-        let data = zarr::read_chunk_u16(&arr, &chunk_indices)?;
-        let f64_frame: Vec<f64> = data.iter().map(|&v| v as f64).collect();
-        frames_raw.push(f64_frame);
-    }
-
-    let mut global_min = f64::INFINITY;
-    let mut global_max = f64::NEG_INFINITY;
-    for f in &frames_raw {
-        for v in f {
-            if *v < global_min {
-                global_min = *v;
-            }
-            if *v > global_max {
-                global_max = *v;
-            }
+        ));
+        let panels = read_panels(&arr, t, channels)?;
+        widen_ranges(&mut ranges, &panels);
+        if cache_frames {
+            frames_raw.push(panels);
         }
     }
-    let range = global_max - global_min;
 
-    let colormap = &args.colormap;
-    let mut frames_rgb: Vec<Vec<u8>> = Vec::new();
-    for frame_raw in &frames_raw {
-        let mut rgb = Vec::with_capacity((w * h * 3) as usize);
-        for v in frame_raw {
-            let norm = if range > 0.0 {
-                ((*v - global_min) / range).clamp(0.0, 1.0)
-            } else {
-                0.0
-            };
-            let (r, g, b) = apply_colormap(norm, colormap);
-            rgb.push(r);
-            rgb.push(g);
-            rgb.push(b);
+    let flow = crop_flow(&shared.flow, job.pos, &job.crop_id);
+    let outlines = match &shared.masks {
+        Some((store, _)) => {
+            let found = Outlines::open(store, &format!("{:03}", job.pos), &job.crop_id)
+                .filter(|o| o.frame_size() == (w, h));
+            if found.is_none() {
+                console::warn(&format!(
+                    "no {}x{} masks for Pos{} crop {}; its movie has no outlines",
+                    w, h, job.pos, job.crop_id
+                ));
+            }
+            found
         }
-        frames_rgb.push(rgb);
+        None => None,
+    };
+
+    let series = shared
+        .plot
+        .as_ref()
+        .map(|p| p.series(job.pos, &job.crop_id));
+    if series.as_ref().is_some_and(|s| s.is_empty()) {
+        console::warn(&format!(
+            "no {} values for Pos{} crop {}; its plot stays empty",
+            shared.plot.as_ref().map_or("", |p| p.column.as_str()),
+            job.pos,
+            job.crop_id
+        ));
     }
+    let t_range = (
+        *time_indices.iter().min().unwrap() as u64,
+        *time_indices.iter().max().unwrap() as u64,
+    );
 
-    let pad_h = (16 - (h % 16)) % 16;
-    let pad_w = (16 - (w % 16)) % 16;
+    let scale_bar_px = match (args.scale_bar_um, shared.um_per_px) {
+        (Some(um), Some(um_per_px)) => Some(((um / um_per_px).round() as u64).clamp(1, w)),
+        _ => None,
+    };
+
+    let pad_h = (16 - (frame_h % 16)) % 16;
+    let pad_w = (16 - (frame_w % 16)) % 16;
     let (out_w, out_h) = if pad_h > 0 || pad_w > 0 {
-        (w + pad_w, h + pad_h)
+        (frame_w + pad_w, frame_h + pad_h)
+    } else {
+        (frame_w, frame_h)
+    };
+    fs::create_dir_all(Path::new(&job.output).parent().unwrap_or(Path::new(".")))?;
+
+    // Events become chapters; with title cards, each chapter starts at its card.
+    let events = place_events(&shared.events, &time_indices);
+    if events.len() < shared.events.len() {
+        console::warn(&format!(
+            "{} event(s) after the last rendered frame of {} were dropped",
+            shared.events.len() - events.len(),
+            job.output
+        ));
+    }
+    let card_frames = args.event_card_frames as u64;
+    let chapters: Vec<(u64, &str)> = events
+        .iter()
+        .enumerate()
+        .map(|(j, &(i, e))| (i as u64 + j as u64 * card_frames, e.label.as_str()))
+        .collect();
+    let total_frames = time_indices.len() as u64 + events.len() as u64 * card_frames;
+    let chapters_path = if chapters.is_empty() {
+        None
+    } else {
+        let path = std::env::temp_dir().join(format!(
+            "mupattern-{}-pos{}-crop{}.ffmetadata",
+            std::process::id(),
+            job.pos,
+            job.crop_id
+        ));
+        fs::write(&path, chapters_metadata(&chapters, total_frames, args.fps))?;
+        Some(path.display().to_string())
+    };
+
+    // Previews are encoded at half the rendered size (out_w and out_h are multiples of 16).
+    let preview = args.preview.is_some();
+    let size = if preview {
+        format!("{}x{}", out_w / 2, out_h / 2)
+    } else {
+        format!("{}x{}", out_w, out_h)
+    };
+    let (preset, crf) = if preview {
+        ("ultrafast", "28")
     } else {
-        (w, h)
+        ("slow", "15")
+    };
+    let fps = args.fps.to_string();
+    let mut ffmpeg_args: Vec<&str> = vec![
+        "-f", "rawvideo", "-pix_fmt", "rgb24", "-s", &size, "-r", &fps, "-i", "pipe:0",
+    ];
+    if let Some(path) = &chapters_path {
+        ffmpeg_args.extend([
+            "-f",
+            "ffmetadata",
+            "-i",
+            path,
+            "-map",
+            "0:v",
+            "-map_chapters",
+            "1",
+        ]);
+    }
+    ffmpeg_args.extend([
+        "-c:v",
+        VIDEO_ENCODER,
+        "-pix_fmt",
+        "yuv420p",
+        "-preset",
+        preset,
+        "-crf",
+        crf,
+        "-y",
+        &job.output,
+    ]);
+    let mut encoder = ffmpeg::Encoder::spawn(&shared.ffmpeg, &ffmpeg_args)?;
+    let mut write_frame = |frame: &[u8]| {
+        if preview {
+            encoder.write_frame(&pixelmath::half_rgb(frame, out_w as usize, out_h as usize))
+        } else {
+            encoder.write_frame(frame)
+        }
     };
 
-    let mut padded = Vec::new();
-    for rgb in &frames_rgb {
-        let mut p = vec![0u8; (out_w * out_h * 3) as usize];
-        for y in 0..h {
-            for x in 0..w {
-                let src = (y * w + x) as usize * 3;
-                let dst = (y * out_w + x) as usize * 3;
-                p[dst] = rgb[src];
-                p[dst + 1] = rgb[src + 1];
-                p[dst + 2] = rgb[src + 2];
+    stats::stage("encode");
+    // The intensity range is fixed for the whole movie: colormap every value once.
+    let style = PanelStyle {
+        cols,
+        luts: colormap_luts(&ranges, &args.colormap),
+        labels: &shared.labels,
+        scale_bar_px,
+        annotations: &shared.annotations,
+        flow: &flow,
+        flow_scale: args.flow_scale,
+    };
+    let n_frames = time_indices.len();
+    let mut padded = vec![0u8; (out_w * out_h * 3) as usize];
+    let card_scale = overlay::text_scale(out_h);
+    let mut events_iter = events.iter().copied().peekable();
+    for (i, &t) in time_indices.iter().enumerate() {
+        while let Some((_, event)) = events_iter.next_if(|(k, _)| *k == i) {
+            if card_frames == 0 {
+                continue;
             }
+            padded.fill(0);
+            let label_w = overlay::text_width(&event.label, card_scale) as i64;
+            let at = (
+                (out_w as i64 - label_w) / 2,
+                (out_h as i64 - overlay::line_height(card_scale) as i64) / 2,
+            );
+            overlay::draw_text(
+                &mut padded,
+                out_w,
+                (out_w, out_h),
+                at,
+                &event.label,
+                overlay::WHITE,
+                card_scale,
+            );
+            for _ in 0..card_frames {
+                write_frame(&padded)?;
+            }
+            // Clear the card so it does not show through padding or empty grid cells.
+            padded.fill(0);
         }
-        padded.push(p);
-    }
-
-    std::fs::create_dir_all(Path::new(&args.output).parent().unwrap_or(Path::new(".")))?;
-
-    let mut child = Command::new(&args.ffmpeg)
-        .args([
-            "-f", "rawvideo",
-            "-pix_fmt", "rgb24",
-            "-s", &format!("{}x{}", out_w, out_h),
-            "-r", &args.fps.to_string(),
-            "-i", "pipe:0",
-            "-c:v", "libx264",
-            "-pix_fmt", "yuv420p",
-            "-preset", "slow",
-            "-crf", "15",
-            "-y",
-            &args.output,
-        ])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()?;
-
-    let mut stdin = child.stdin.take().ok_or("Failed to open ffmpeg stdin")?;
-    for (i, frame) in padded.iter().enumerate() {
-        stdin.write_all(frame)?;
-        progress(
-            0.4 + (i + 1) as f64 / padded.len() as f64 * 0.6,
-            &format!("Encoding {}/{}", i + 1, padded.len()),
-        );
+        let reread;
+        let panels: &[Vec<u16>] = if cache_frames {
+            &frames_raw[i]
+        } else {
+            reread = read_panels(&arr, t, channels)?;
+            &reread
+        };
+        draw_panels(&mut padded, out_w, (w, h), panels, t as u64, &style);
+        if let (Some(outlines), Some((_, color))) = (&outlines, &shared.masks) {
+            if (t as u64) < outlines.n_frames() {
+                let outline = outlines.frame(t as u64, w as usize, h as usize)?;
+                for ci in 0..channels.len() {
+                    let (ox, oy) = ((ci % cols) as u64 * w, (ci / cols) as u64 * h);
+                    let panel = &mut padded[((oy * out_w + ox) * 3) as usize..];
+                    outline::draw(panel, out_w, (w, h), &outline, *color);
+                }
+            }
+        }
+        if let (Some(table), Some(series)) = (&shared.plot, &series) {
+            let panel = &mut padded[(cols as u64 * w * 3) as usize..];
+            plot::draw(
+                panel,
+                out_w,
+                (plot_w, frame_h),
+                &table.column,
+                series,
+                t_range,
+                t as u64,
+            );
+        }
+        write_frame(&padded)?;
+        stats::add_frames(1);
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "encode",
+            (i + 1) as u64,
+            n_frames as u64,
+            &format!("Encoding {}/{}", i + 1, n_frames),
+        ));
     }
-    drop(stdin);
-    let status = child.wait()?;
-    if !status.success() {
-        return Err(format!("ffmpeg exited with code {}", status.code().unwrap_or(-1)).into());
+    encoder.finish()?;
+    if let Some(path) = &chapters_path {
+        let _ = fs::remove_file(path);
     }
-
-    progress(1.0, &format!("Wrote {}", args.output));
+    manifest::movie(&job.output, total_frames as usize);
     Ok(())
 }
 
-fn apply_colormap(v: f64, colormap: &str) -> (u8, u8, u8) {
+/// For each channel's fixed (min, max) window, the window minimum and a lookup table from
+/// intensity to color.
+pub(crate) fn colormap_luts(ranges: &[(u16, u16)], colormap: &str) -> Vec<(u16, Vec<[u8; 3]>)> {
+    ranges
+        .iter()
+        .map(|&(lo, hi)| {
+            let lut = pixelmath::window_lut(lo, hi, |norm| {
+                let (r, g, b) = apply_colormap(norm, colormap);
+                [r, g, b]
+            });
+            (lo, lut)
+        })
+        .collect()
+}
+
+/// How the channel panels of a frame are laid out, colored and overlaid.
+pub(crate) struct PanelStyle<'a> {
+    /// Panel grid columns (see `Layout::grid`).
+    pub cols: usize,
+    /// Per channel, from `colormap_luts`.
+    pub luts: Vec<(u16, Vec<[u8; 3]>)>,
+    /// Label of each panel (`panel_labels`); drawn only when there are several.
+    pub labels: &'a [String],
+    pub scale_bar_px: Option<u64>,
+    pub annotations: &'a [Annotation],
+    /// Flow vectors of the rendered crop.
+    pub flow: &'a [&'a FlowVector],
+    pub flow_scale: f64,
+}
+
+/// Colormap one frame's `panels` (w×h each) into `rgb` (row stride `stride` pixels) and
+/// draw the overlays of frame `t` on each panel.
+pub(crate) fn draw_panels(
+    rgb: &mut [u8],
+    stride: u64,
+    (w, h): (u64, u64),
+    panels: &[Vec<u16>],
+    t: u64,
+    style: &PanelStyle,
+) {
+    let scale = overlay::text_scale(h);
+    let cols = style.cols;
+    for (ci, frame_raw) in panels.iter().enumerate() {
+        let (lo, lut) = (style.luts[ci].0, &style.luts[ci].1);
+        let (ox, oy) = ((ci % cols) as u64 * w, (ci / cols) as u64 * h);
+        for (y, src) in frame_raw.chunks_exact(w as usize).enumerate() {
+            let start = ((oy + y as u64) * stride + ox) as usize * 3;
+            let dst = &mut rgb[start..start + w as usize * 3];
+            for (px, &v) in dst.chunks_exact_mut(3).zip(src) {
+                px.copy_from_slice(&lut[pixelmath::lut_index(v, lo, lut.len())]);
+            }
+        }
+        // Overlays draw into the panel's sub-buffer, clipped to the panel.
+        let panel = &mut rgb[((oy * stride + ox) * 3) as usize..];
+        if let (Some(len), 0) = (style.scale_bar_px, ci) {
+            overlay::draw_scale_bar(panel, stride, w, h, len);
+        }
+        draw_annotations(panel, stride, w, h, t, style.annotations);
+        for v in style.flow.iter().filter(|v| v.t == t) {
+            let tip = (v.x + v.dx * style.flow_scale, v.y + v.dy * style.flow_scale);
+            overlay::draw_arrow(panel, stride, (w, h), (v.x, v.y), tip, FLOW_COLOR);
+        }
+        if style.labels.len() > 1 {
+            let margin = 2 * scale as i64;
+            let label_y = h as i64 - overlay::line_height(scale) as i64 - margin;
+            overlay::draw_text(
+                panel,
+                stride,
+                (w, h),
+                (margin, label_y),
+                &style.labels[ci],
+                overlay::WHITE,
+                scale,
+            );
+        }
+    }
+}
+
+pub(crate) fn apply_colormap(v: f64, colormap: &str) -> (u8, u8, u8) {
     let v = v.clamp(0.0, 1.0);
     match colormap.to_lowercase().as_str() {
         "grayscale" => {
@@ -182,9 +1078,18 @@ fn apply_colormap(v: f64, colormap: &str) -> (u8, u8, u8) {
         }
         "viridis" => {
             let t = v;
-            let r = (0.267 + 0.3244 * t + 2.6477 * t * t - 4.4098 * t * t * t + 2.0942 * t * t * t * t).clamp(0.0, 1.0) * 255.0;
-            let g = (0.0046 + 0.0495 * t + 2.5253 * t * t - 6.0613 * t * t * t + 3.7466 * t * t * t * t).clamp(0.0, 1.0) * 255.0;
-            let b = (0.3294 + 0.1002 * t + 2.3256 * t * t - 3.1356 * t * t * t + 1.5046 * t * t * t * t).clamp(0.0, 1.0) * 255.0;
+            let r = (0.267 + 0.3244 * t + 2.6477 * t * t - 4.4098 * t * t * t
+                + 2.0942 * t * t * t * t)
+                .clamp(0.0, 1.0)
+                * 255.0;
+            let g = (0.0046 + 0.0495 * t + 2.5253 * t * t - 6.0613 * t * t * t
+                + 3.7466 * t * t * t * t)
+                .clamp(0.0, 1.0)
+                * 255.0;
+            let b = (0.3294 + 0.1002 * t + 2.3256 * t * t - 3.1356 * t * t * t
+                + 1.5046 * t * t * t * t)
+                .clamp(0.0, 1.0)
+                * 255.0;
             (r.round() as u8, g.round() as u8, b.round() as u8)
         }
         _ => {
@@ -193,3 +1098,92 @@ fn apply_colormap(v: f64, colormap: &str) -> (u8, u8, u8) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contrast_modes_parse() {
+        assert_eq!(Contrast::parse("per-movie").unwrap(), Contrast::PerMovie);
+        assert_eq!(Contrast::parse("shared").unwrap(), Contrast::Shared);
+        assert_eq!(Contrast::parse("detector").unwrap(), Contrast::Detector);
+        assert!(Contrast::parse("global").is_err());
+    }
+
+    #[test]
+    fn shared_contrast_spans_every_movie() {
+        // Two movies of two channels; the second is brighter in both.
+        let movies = [
+            vec![vec![100, 150, 200], vec![0, 10, 5]],
+            vec![vec![150, 400, 300], vec![50, 5, 20]],
+        ];
+        let unset = vec![(u16::MAX, u16::MIN); 2];
+        let mut shared = unset.clone();
+        for panels in &movies {
+            widen_ranges(&mut shared, panels);
+        }
+        assert_eq!(shared, [(100, 400), (0, 50)]);
+        let mut first = unset;
+        widen_ranges(&mut first, &movies[0]);
+        assert_eq!(first, [(100, 200), (0, 10)]);
+
+        // The same intensity gets the same gray in every movie, unlike per-movie ranges.
+        let gray = |ranges: &[(u16, u16)], v: u16| {
+            let (lo, lut) = &colormap_luts(ranges, "grayscale")[0];
+            lut[pixelmath::lut_index(v, *lo, lut.len())]
+        };
+        assert_eq!(gray(&shared, 100), [0; 3]);
+        assert_eq!(gray(&shared, 400), [255; 3]);
+        assert_eq!(gray(&shared, 200), [85; 3]);
+        assert_eq!(gray(&first, 200), [255; 3]);
+    }
+
+    #[test]
+    fn layouts_place_one_panel_per_channel() {
+        assert_eq!(Layout::HStack.grid(3), (3, 1));
+        assert_eq!(Layout::VStack.grid(3), (1, 3));
+        assert_eq!(Layout::Grid.grid(1), (1, 1));
+        assert_eq!(Layout::Grid.grid(3), (2, 2));
+        assert_eq!(Layout::Grid.grid(5), (3, 2));
+        assert!(Layout::parse("mosaic").is_err());
+
+        // Three uniform 32x32 panels on a 2x2 grid; the fourth cell stays untouched.
+        let (w, h) = (32u64, 32u64);
+        let panels: Vec<Vec<u16>> = [0, 1000, 2000].map(|v| vec![v; 32 * 32]).to_vec();
+        let (cols, rows) = Layout::Grid.grid(panels.len());
+        let stride = cols as u64 * w;
+        let mut rgb = vec![7u8; (stride * rows as u64 * h * 3) as usize];
+        let style = PanelStyle {
+            cols,
+            luts: colormap_luts(&[(0, 2000); 3], "grayscale"),
+            labels: &["C0", "C1", "C2"].map(String::from),
+            scale_bar_px: None,
+            annotations: &[],
+            flow: &[],
+            flow_scale: 1.0,
+        };
+        draw_panels(&mut rgb, stride, (w, h), &panels, 0, &style);
+        let red = |x: u64, y: u64| rgb[((y * stride + x) * 3) as usize];
+        // Top-right corner of each cell, clear of the label in its bottom-left corner.
+        assert_eq!(red(w - 1, 0), 0);
+        assert_eq!(red(2 * w - 1, 0), 128);
+        assert_eq!(red(w - 1, h), 255);
+        assert_eq!(red(2 * w - 1, h), 7);
+        // With several channels each panel is labelled in white.
+        let label_rows = h - overlay::line_height(1) - 2..h;
+        assert!(label_rows
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .any(|(x, y)| red(x, y) == 255));
+    }
+
+    #[test]
+    fn panels_are_labelled_with_channel_names() {
+        let names = ChannelNames::parse("Phase,GFP,");
+        assert_eq!(
+            panel_labels(&[1, 0, 2, 3], &names),
+            ["GFP", "Phase", "C2", "C3"]
+        );
+        assert_eq!(panel_labels(&[1], &ChannelNames::default()), ["C1"]);
+    }
+}
@@ -0,0 +1,24 @@
+//! Completion webhook: POST a JSON summary when a command finishes.
+//! Body: {"command", "status", "duration_s", "outputs", "error"}.
+
+use std::time::Duration;
+
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn post_summary(
+    url: &str,
+    command: &str,
+    duration: Duration,
+    outputs: &[String],
+    error: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let body = serde_json::json!({
+        "command": command,
+        "status": if error.is_none() { "success" } else { "error" },
+        "duration_s": duration.as_secs_f64(),
+        "outputs": outputs,
+        "error": error,
+    });
+    ureq::post(url).timeout(NOTIFY_TIMEOUT).send_json(body)?;
+    Ok(())
+}
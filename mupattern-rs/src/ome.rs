@@ -0,0 +1,112 @@
+//! OME-XML for `convert --format ome-tiff`. Every TIFF still holds one plane, and its
+//! ImageDescription describes that plane as a 1×1×1 image (channel name, pixel size, frame
+//! interval, acquisition time and stage position), so Fiji/Bio-Formats and other OME-aware readers open
+//! each file calibrated while the Pos{N} layout stays what `crop` expects.
+
+/// One converted frame.
+pub struct Plane<'a> {
+    /// Image name, e.g. "Pos3".
+    pub name: &'a str,
+    pub width: usize,
+    pub height: usize,
+    pub channel: &'a str,
+    pub um_per_px: Option<f64>,
+    pub frame_interval_s: Option<f64>,
+    /// Source timepoint; with a frame interval, `DeltaT` is its time since the first frame.
+    pub t: usize,
+    /// Stage position (x, y) of the position in µm.
+    pub position_um: Option<(f64, f64)>,
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// OME-XML document for `plane`.
+pub fn plane_xml(plane: &Plane) -> String {
+    let mut pixel_attrs = format!(
+        r#"ID="Pixels:0" DimensionOrder="XYZCT" Type="uint16" SizeX="{}" SizeY="{}" SizeZ="1" SizeC="1" SizeT="1""#,
+        plane.width, plane.height
+    );
+    if let Some(um) = plane.um_per_px {
+        pixel_attrs.push_str(&format!(
+            r#" PhysicalSizeX="{um}" PhysicalSizeXUnit="µm" PhysicalSizeY="{um}" PhysicalSizeYUnit="µm""#
+        ));
+    }
+    let mut plane_attrs = String::from(r#"TheZ="0" TheC="0" TheT="0""#);
+    if let Some(dt) = plane.frame_interval_s {
+        pixel_attrs.push_str(&format!(r#" TimeIncrement="{dt}" TimeIncrementUnit="s""#));
+        plane_attrs.push_str(&format!(
+            r#" DeltaT="{}" DeltaTUnit="s""#,
+            plane.t as f64 * dt
+        ));
+    }
+    if let Some((x, y)) = plane.position_um {
+        plane_attrs.push_str(&format!(
+            r#" PositionX="{x}" PositionXUnit="µm" PositionY="{y}" PositionYUnit="µm""#
+        ));
+    }
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<OME xmlns="http://www.openmicroscopy.org/Schemas/OME/2016-06" "#,
+            r#"xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" "#,
+            r#"xsi:schemaLocation="http://www.openmicroscopy.org/Schemas/OME/2016-06 "#,
+            r#"http://www.openmicroscopy.org/Schemas/OME/2016-06/ome.xsd" "#,
+            r#"Creator="mupattern {}">"#,
+            r#"<Image ID="Image:0" Name="{}"><Pixels {}>"#,
+            r#"<Channel ID="Channel:0:0" Name="{}" SamplesPerPixel="1"/>"#,
+            r#"<TiffData IFD="0" PlaneCount="1"/><Plane {}/>"#,
+            r#"</Pixels></Image></OME>"#
+        ),
+        env!("CARGO_PKG_VERSION"),
+        escape(plane.name),
+        pixel_attrs,
+        escape(plane.channel),
+        plane_attrs
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_and_names_go_into_the_xml() {
+        let mut plane = Plane {
+            name: "Pos3",
+            width: 64,
+            height: 32,
+            channel: "GFP <488>",
+            um_per_px: Some(0.65),
+            frame_interval_s: Some(600.0),
+            t: 4,
+            position_um: Some((1250.5, -300.0)),
+        };
+        let xml = plane_xml(&plane);
+        assert!(xml.contains(r#"Name="GFP &lt;488&gt;""#));
+        assert!(xml.contains(r#"SizeX="64" SizeY="32""#));
+        assert!(xml.contains(r#"PhysicalSizeX="0.65""#));
+        assert!(xml.contains(r#"DeltaT="2400" DeltaTUnit="s""#));
+        assert!(xml.contains(r#"PositionX="1250.5" PositionXUnit="µm" PositionY="-300""#));
+
+        plane.um_per_px = None;
+        plane.frame_interval_s = None;
+        plane.position_um = None;
+        let xml = plane_xml(&plane);
+        assert!(!xml.contains("PhysicalSizeX") && !xml.contains("DeltaT"));
+        assert!(!xml.contains("PositionX"));
+        assert!(xml.ends_with(r#"<Plane TheZ="0" TheC="0" TheT="0"/></Pixels></Image></OME>"#));
+    }
+}
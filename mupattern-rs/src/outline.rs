@@ -0,0 +1,189 @@
+//! Cell outlines: the label boundaries of masks.zarr frames as compact uint8 arrays
+//! (1 = outline pixel) at `/pos/{p}/outline/{crop}`, next to the labels. `tissue --outlines`
+//! writes them so `movie --masks` can overlay cells without tracing every label frame;
+//! for masks without them (older or imported stores) the outlines are traced on the fly.
+//! Whatever rewrites a crop's labels without tracing them drops its outline array, so
+//! stored outlines never lag behind the labels.
+
+use crate::overlay::Rgb;
+use crate::zarr;
+
+pub fn array_path(pos_id: &str, crop_id: &str) -> String {
+    format!("/pos/{}/outline/{}", pos_id, crop_id)
+}
+
+/// 1 where a labelled pixel touches another label, the background or the frame edge
+/// (4-neighbourhood), else 0.
+pub fn trace(labels: &[u32], w: usize, h: usize) -> Vec<u8> {
+    let mut out = vec![0u8; labels.len()];
+    for y in 0..h {
+        for x in 0..w {
+            let v = labels[y * w + x];
+            if v == 0 {
+                continue;
+            }
+            let edge = x == 0 || y == 0 || x + 1 == w || y + 1 == h;
+            if edge
+                || labels[y * w + x - 1] != v
+                || labels[y * w + x + 1] != v
+                || labels[(y - 1) * w + x] != v
+                || labels[(y + 1) * w + x] != v
+            {
+                out[y * w + x] = 1;
+            }
+        }
+    }
+    out
+}
+
+/// Create the (T, H, W) outline array of one crop, with its `/pos/{p}/outline` group.
+pub fn create_array(
+    store: &zarr::Store,
+    pos_id: &str,
+    crop_id: &str,
+    shape: &[u64],
+) -> Result<zarr::StoreArray, Box<dyn std::error::Error>> {
+    use std::sync::Arc;
+    use zarrs::group::GroupBuilder;
+    use zarrs::storage::ReadableWritableListableStorageTraits;
+
+    let st: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
+    GroupBuilder::new()
+        .build(st, &format!("/pos/{}/outline", pos_id))?
+        .store_metadata()?;
+    let mut attrs = serde_json::Map::new();
+    attrs.insert("axis_names".to_string(), serde_json::json!(["t", "y", "x"]));
+    zarr::create_array_u8(
+        store,
+        &array_path(pos_id, crop_id),
+        shape.to_vec(),
+        vec![1, shape[1], shape[2]],
+        zarr::shard_shape_t_first(shape),
+        Some(attrs),
+    )
+}
+
+/// Delete the outline array of one crop, if there is one.
+pub fn remove(
+    store: &zarr::Store,
+    pos_id: &str,
+    crop_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use zarrs::storage::{StorePrefix, WritableStorageTraits};
+
+    let prefix = format!("{}/", array_path(pos_id, crop_id).trim_start_matches('/'));
+    store.erase_prefix(&StorePrefix::new(prefix)?)?;
+    Ok(())
+}
+
+/// Trace `labels` (frame `t`, w×h) into `array`.
+pub fn store(
+    array: &zarr::StoreArray,
+    t: u64,
+    labels: &[u32],
+    w: usize,
+    h: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    zarr::store_chunk_u8(array, &[t, 0, 0], &trace(labels, w, h))
+}
+
+/// Per-frame outlines of one crop in a masks.zarr.
+pub enum Outlines {
+    Stored(zarr::StoreArray),
+    /// No outline array: traced from the label array.
+    Traced(zarr::StoreArray),
+}
+
+impl Outlines {
+    /// The crop's outlines, or None when the store has no masks for it.
+    pub fn open(store: &zarr::Store, pos_id: &str, crop_id: &str) -> Option<Self> {
+        if let Ok(arr) = zarr::open_array(store, &array_path(pos_id, crop_id)) {
+            return Some(Self::Stored(arr));
+        }
+        let labels = format!("/pos/{}/crop/{}", pos_id, crop_id);
+        zarr::open_array(store, &labels).ok().map(Self::Traced)
+    }
+
+    fn array(&self) -> &zarr::StoreArray {
+        match self {
+            Self::Stored(arr) | Self::Traced(arr) => arr,
+        }
+    }
+
+    /// Number of frames.
+    pub fn n_frames(&self) -> u64 {
+        self.array().shape()[0]
+    }
+
+    /// Frame size (w, h).
+    pub fn frame_size(&self) -> (u64, u64) {
+        let shape = self.array().shape();
+        (shape[2], shape[1])
+    }
+
+    /// Outline of frame `t` (w×h).
+    pub fn frame(&self, t: u64, w: usize, h: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self {
+            Self::Stored(arr) => zarr::read_frame_u8(arr, &[t]),
+            Self::Traced(arr) => Ok(trace(&zarr::read_labels(arr, t)?, w, h)),
+        }
+    }
+}
+
+/// Paint the outline pixels of a w×h panel in `rgb` (row stride `stride` pixels).
+pub fn draw(rgb: &mut [u8], stride: u64, (w, h): (u64, u64), outline: &[u8], color: Rgb) {
+    for (y, row) in outline
+        .chunks_exact(w as usize)
+        .take(h as usize)
+        .enumerate()
+    {
+        for (x, _) in row.iter().enumerate().filter(|(_, &v)| v != 0) {
+            let dst = (y as u64 * stride + x as u64) as usize * 3;
+            rgb[dst..dst + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outlines_follow_label_borders() {
+        let labels: Vec<u32> = [
+            [0, 0, 0, 0, 0],
+            [0, 1, 1, 1, 0],
+            [0, 1, 1, 1, 2],
+            [0, 1, 1, 1, 2],
+            [0, 0, 0, 0, 2],
+        ]
+        .concat();
+        let expected: Vec<u8> = [
+            [0, 0, 0, 0, 0],
+            [0, 1, 1, 1, 0],
+            [0, 1, 0, 1, 1],
+            [0, 1, 1, 1, 1],
+            [0, 0, 0, 0, 1],
+        ]
+        .concat();
+        assert_eq!(trace(&labels, 5, 5), expected);
+
+        let mut rgb = vec![0u8; 2 * 6 * 3];
+        draw(&mut rgb, 3, (2, 2), &[1, 0, 0, 1], [9, 8, 7]);
+        assert_eq!(&rgb[..3], &[9, 8, 7]);
+        assert_eq!(&rgb[(3 + 1) * 3..(3 + 1) * 3 + 3], &[9, 8, 7]);
+        assert_eq!(rgb.iter().filter(|&&v| v != 0).count(), 6);
+    }
+
+    #[test]
+    fn remove_drops_stored_outlines() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = zarr::open_store(dir.path()).unwrap();
+        create_array(&store, "000", "000", &[2, 3, 3]).unwrap();
+        assert!(zarr::open_array(&store, &array_path("000", "000")).is_ok());
+        remove(&store, "000", "000").unwrap();
+        assert!(zarr::open_array(&store, &array_path("000", "000")).is_err());
+        // A crop without outlines is left alone.
+        remove(&store, "000", "001").unwrap();
+    }
+}
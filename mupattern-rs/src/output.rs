@@ -0,0 +1,741 @@
+//! Table output shared by the analysis commands (expression, kill, spot, tissue).
+//!
+//! The backend follows the output extension:
+//! - CSV (default): a `# schema: mupattern-{table}/{version}` comment line, csv-crate
+//!   quoting, and floats with at most 4 decimals (trailing zeros trimmed).
+//! - SQLite (`.sqlite`, `.sqlite3`, `.db`): one typed table per command with an extra
+//!   `pos` column and an index on (pos, crop, t). Re-running a position replaces its rows.
+//! - Arrow IPC stream (`.arrows`, or `--output-format arrow-stream`): typed columns, with
+//!   a record batch appended and flushed every `ARROW_BATCH_ROWS` rows, so readers can
+//!   follow a long run while it is still writing.
+//!
+//! Tables created with a `Provenance` carry it in each backend (see `provenance.rs`).
+
+use arrow_array::builder::{Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::manifest;
+use crate::provenance::Provenance;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    fn sql(self) -> &'static str {
+        match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+        }
+    }
+
+    fn arrow(self) -> DataType {
+        match self {
+            ColumnType::Integer => DataType::Int64,
+            ColumnType::Real => DataType::Float64,
+            ColumnType::Text => DataType::Utf8,
+        }
+    }
+}
+
+/// Table name and typed columns of one command's output. Every column layout has its own
+/// table name (e.g. `expression_corrected` for `expression --subtract-background`), so one
+/// SQLite file can hold the results of runs with different flags.
+pub struct Schema {
+    pub table: &'static str,
+    /// Layout version, bumped whenever the columns change: CSV headers carry it as
+    /// `mupattern-{table}/{version}` and a SQLite table keeps the version it was created with.
+    pub version: u32,
+    pub columns: &'static [(&'static str, ColumnType)],
+}
+
+impl Schema {
+    fn has_column(&self, name: &str) -> bool {
+        self.columns.iter().any(|(c, _)| *c == name)
+    }
+}
+
+pub fn is_sqlite_path(path: &str) -> bool {
+    matches!(
+        Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .as_deref(),
+        Some("sqlite" | "sqlite3" | "db")
+    )
+}
+
+/// Rows per record batch of the Arrow stream backend.
+const ARROW_BATCH_ROWS: usize = 1024;
+
+/// Table backend (`--output-format`); `Auto` follows the output extension.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Auto,
+    Csv,
+    Sqlite,
+    ArrowStream,
+}
+
+impl OutputFormat {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(OutputFormat::Auto),
+            "csv" => Ok(OutputFormat::Csv),
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            "arrow-stream" => Ok(OutputFormat::ArrowStream),
+            _ => Err(format!(
+                "Unknown output format {:?}. Use auto, csv, sqlite or arrow-stream.",
+                s
+            )
+            .into()),
+        }
+    }
+
+    /// The concrete backend for `path`.
+    fn resolve(self, path: &str) -> Self {
+        if self != OutputFormat::Auto {
+            return self;
+        }
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        if is_sqlite_path(path) {
+            OutputFormat::Sqlite
+        } else if ext.as_deref() == Some("arrows") {
+            OutputFormat::ArrowStream
+        } else {
+            OutputFormat::Csv
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    fn to_field(&self) -> String {
+        match self {
+            Value::Int(v) => v.to_string(),
+            Value::Float(v) => format_float(*v),
+            Value::Text(v) => v.clone(),
+            Value::Bool(v) => v.to_string(),
+            Value::Null => String::new(),
+        }
+    }
+
+    fn to_sql(&self) -> rusqlite::types::Value {
+        match self {
+            Value::Int(v) => rusqlite::types::Value::Integer(*v),
+            Value::Float(v) => rusqlite::types::Value::Real(*v),
+            Value::Text(v) => rusqlite::types::Value::Text(v.clone()),
+            Value::Bool(v) => rusqlite::types::Value::Integer(*v as i64),
+            Value::Null => rusqlite::types::Value::Null,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(v: u64) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(v: u32) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<u16> for Value {
+    fn from(v: u16) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(v: usize) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(v: f32) -> Self {
+        Value::Float(v as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Text(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Text(v)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+/// At most 4 decimals, trailing zeros trimmed: 12.0 -> "12", 0.30000001 -> "0.3".
+pub fn format_float(v: f64) -> String {
+    if !v.is_finite() {
+        return v.to_string();
+    }
+    let s = format!("{:.4}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" {
+        "0".to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+enum Backend {
+    Csv(Box<csv::Writer<BufWriter<fs::File>>>),
+    Sqlite {
+        conn: Connection,
+        insert_sql: String,
+    },
+    Arrow(Box<ArrowStream>),
+}
+
+enum ColumnBuilder {
+    Integer(Int64Builder),
+    Real(Float64Builder),
+    Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(ty: ColumnType) -> Self {
+        match ty {
+            ColumnType::Integer => ColumnBuilder::Integer(Int64Builder::new()),
+            ColumnType::Real => ColumnBuilder::Real(Float64Builder::new()),
+            ColumnType::Text => ColumnBuilder::Text(StringBuilder::new()),
+        }
+    }
+
+    fn append(&mut self, name: &str, value: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        match (self, value) {
+            (ColumnBuilder::Integer(b), Value::Null) => b.append_null(),
+            (ColumnBuilder::Real(b), Value::Null) => b.append_null(),
+            (ColumnBuilder::Text(b), Value::Null) => b.append_null(),
+            (ColumnBuilder::Integer(b), Value::Int(v)) => b.append_value(*v),
+            (ColumnBuilder::Integer(b), Value::Bool(v)) => b.append_value(*v as i64),
+            (ColumnBuilder::Real(b), Value::Float(v)) => b.append_value(*v),
+            (ColumnBuilder::Real(b), Value::Int(v)) => b.append_value(*v as f64),
+            (ColumnBuilder::Text(b), v) => b.append_value(v.to_field()),
+            (_, v) => return Err(format!("Column {} cannot hold {:?}", name, v).into()),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Integer(b) => Arc::new(b.finish()),
+            ColumnBuilder::Real(b) => Arc::new(b.finish()),
+            ColumnBuilder::Text(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Arrow IPC stream: rows are buffered per column and written as one record batch every
+/// `ARROW_BATCH_ROWS` rows.
+struct ArrowStream {
+    writer: StreamWriter<BufWriter<fs::File>>,
+    schema: Arc<arrow_schema::Schema>,
+    columns: Vec<ColumnBuilder>,
+    pending: usize,
+}
+
+impl ArrowStream {
+    fn create(
+        path: &Path,
+        schema: &Schema,
+        pos: u32,
+        provenance: Option<&Provenance>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let fields: Vec<Field> = schema
+            .columns
+            .iter()
+            .map(|(name, ty)| Field::new(*name, ty.arrow(), true))
+            .collect();
+        let mut metadata = HashMap::from([
+            (
+                "mupattern.schema".to_string(),
+                format!("mupattern-{}/{}", schema.table, schema.version),
+            ),
+            ("mupattern.pos".to_string(), pos.to_string()),
+        ]);
+        if let Some(provenance) = provenance {
+            metadata.insert("mupattern.provenance".to_string(), provenance.to_json());
+        }
+        let arrow_schema = Arc::new(arrow_schema::Schema::new_with_metadata(fields, metadata));
+        let file = BufWriter::new(fs::File::create(path)?);
+        let mut writer = StreamWriter::try_new(file, &arrow_schema)?;
+        // Readers can open the stream as soon as the schema is on disk.
+        writer.flush()?;
+        Ok(Self {
+            writer,
+            schema: arrow_schema,
+            columns: schema
+                .columns
+                .iter()
+                .map(|(_, ty)| ColumnBuilder::new(*ty))
+                .collect(),
+            pending: 0,
+        })
+    }
+
+    fn push(&mut self, row: &[Value]) -> Result<(), Box<dyn std::error::Error>> {
+        for ((column, field), value) in self.columns.iter_mut().zip(self.schema.fields()).zip(row) {
+            column.append(field.name(), value)?;
+        }
+        self.pending += 1;
+        if self.pending >= ARROW_BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Append the buffered rows as a record batch and flush it to disk.
+    fn write_batch(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.pending == 0 {
+            return Ok(());
+        }
+        let arrays: Vec<ArrayRef> = self.columns.iter_mut().map(ColumnBuilder::finish).collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        self.writer.write(&batch)?;
+        self.writer.flush()?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_batch()?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+pub struct TableWriter {
+    backend: Backend,
+    n_columns: usize,
+    pos: u32,
+    rows: u64,
+    path: String,
+    table: &'static str,
+}
+
+impl TableWriter {
+    /// Create the output for `schema` at `path` (and parent dirs), with the backend
+    /// following the extension.
+    pub fn create(
+        path: &str,
+        schema: &Schema,
+        pos: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::create_as(path, schema, pos, OutputFormat::Auto)
+    }
+
+    /// Like `create`, with the backend given by `format`. `pos` is only stored by the
+    /// SQLite backend (and in the Arrow schema metadata); CSV files hold a single position.
+    pub fn create_as(
+        path: &str,
+        schema: &Schema,
+        pos: u32,
+        format: OutputFormat,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::open(path, schema, pos, format, None)
+    }
+
+    /// Like `create_as`, recording `provenance` with the table.
+    pub fn create_with_provenance(
+        path: &str,
+        schema: &Schema,
+        pos: u32,
+        format: OutputFormat,
+        provenance: &Provenance,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::open(path, schema, pos, format, Some(provenance))
+    }
+
+    fn open(
+        path: &str,
+        schema: &Schema,
+        pos: u32,
+        format: OutputFormat,
+        provenance: Option<&Provenance>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let fs_path = Path::new(path);
+        fs::create_dir_all(fs_path.parent().unwrap_or(Path::new(".")))?;
+        let backend = match format.resolve(path) {
+            OutputFormat::Sqlite => create_sqlite(fs_path, schema, pos, provenance)?,
+            OutputFormat::ArrowStream => Backend::Arrow(Box::new(ArrowStream::create(
+                fs_path, schema, pos, provenance,
+            )?)),
+            _ => {
+                let mut file = BufWriter::new(fs::File::create(fs_path)?);
+                writeln!(
+                    file,
+                    "# schema: mupattern-{}/{}",
+                    schema.table, schema.version
+                )?;
+                if let Some(provenance) = provenance {
+                    writeln!(file, "# provenance: {}", provenance.to_json())?;
+                }
+                let mut writer = csv::Writer::from_writer(file);
+                writer.write_record(schema.columns.iter().map(|(name, _)| *name))?;
+                Backend::Csv(Box::new(writer))
+            }
+        };
+        Ok(Self {
+            backend,
+            n_columns: schema.columns.len(),
+            pos,
+            rows: 0,
+            path: path.to_string(),
+            table: schema.table,
+        })
+    }
+
+    pub fn write_row(&mut self, row: &[Value]) -> Result<(), Box<dyn std::error::Error>> {
+        if row.len() != self.n_columns {
+            return Err(format!(
+                "Row has {} values, expected {} columns",
+                row.len(),
+                self.n_columns
+            )
+            .into());
+        }
+        match &mut self.backend {
+            Backend::Csv(writer) => writer.write_record(row.iter().map(Value::to_field))?,
+            Backend::Sqlite { conn, insert_sql } => {
+                let params = std::iter::once(rusqlite::types::Value::Integer(self.pos as i64))
+                    .chain(row.iter().map(Value::to_sql));
+                conn.prepare_cached(insert_sql)?
+                    .execute(rusqlite::params_from_iter(params))?;
+            }
+            Backend::Arrow(stream) => stream.push(row)?,
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Flush (or commit) and return the number of data rows written.
+    pub fn finish(self) -> Result<u64, Box<dyn std::error::Error>> {
+        match self.backend {
+            Backend::Csv(mut writer) => writer.flush()?,
+            Backend::Sqlite { conn, .. } => conn.execute_batch("COMMIT")?,
+            Backend::Arrow(stream) => stream.finish()?,
+        }
+        manifest::table(&self.path, self.table, self.rows);
+        Ok(self.rows)
+    }
+}
+
+/// Create the table and indices if needed, drop previous rows for `pos`, and open a
+/// transaction that `finish` commits.
+fn create_sqlite(
+    path: &Path,
+    schema: &Schema,
+    pos: u32,
+    provenance: Option<&Provenance>,
+) -> Result<Backend, Box<dyn std::error::Error>> {
+    let conn = Connection::open(path)?;
+    let table = schema.table;
+    let column_defs: Vec<String> = std::iter::once("pos INTEGER NOT NULL".to_string())
+        .chain(
+            schema
+                .columns
+                .iter()
+                .map(|(name, ty)| format!("\"{}\" {}", name, ty.sql())),
+        )
+        .collect();
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS mupattern_schema (name TEXT PRIMARY KEY, version INTEGER NOT NULL);
+         CREATE TABLE IF NOT EXISTS \"{table}\" ({});",
+        column_defs.join(", ")
+    ))?;
+    let stored: Option<u32> = conn
+        .query_row(
+            "SELECT version FROM mupattern_schema WHERE name = ?1",
+            rusqlite::params![table],
+            |r| r.get(0),
+        )
+        .optional()?;
+    if let Some(version) = stored.filter(|&v| v != schema.version) {
+        return Err(format!(
+            "{}: table {} has schema version {} but this run writes version {}; use a new output file",
+            path.display(),
+            table,
+            version,
+            schema.version
+        )
+        .into());
+    }
+    check_columns(&conn, path, schema)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO mupattern_schema (name, version) VALUES (?1, ?2)",
+        rusqlite::params![table, schema.version],
+    )?;
+    let index_cols: Vec<&str> = ["pos", "crop", "t"]
+        .into_iter()
+        .filter(|c| *c == "pos" || schema.has_column(c))
+        .collect();
+    conn.execute_batch(&format!(
+        "CREATE INDEX IF NOT EXISTS \"idx_{table}_{}\" ON \"{table}\" ({});",
+        index_cols.join("_"),
+        index_cols.join(", ")
+    ))?;
+
+    conn.execute_batch("BEGIN")?;
+    conn.execute(
+        &format!("DELETE FROM \"{table}\" WHERE pos = ?1"),
+        rusqlite::params![pos],
+    )?;
+    if let Some(provenance) = provenance {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS mupattern_provenance (name TEXT NOT NULL, pos INTEGER NOT NULL, provenance TEXT NOT NULL, PRIMARY KEY (name, pos));",
+        )?;
+        conn.execute(
+            "INSERT OR REPLACE INTO mupattern_provenance (name, pos, provenance) VALUES (?1, ?2, ?3)",
+            rusqlite::params![table, pos, provenance.to_json()],
+        )?;
+    }
+
+    let names: Vec<String> = std::iter::once("pos")
+        .chain(schema.columns.iter().map(|(name, _)| *name))
+        .map(|name| format!("\"{}\"", name))
+        .collect();
+    let placeholders: Vec<String> = (1..=names.len()).map(|i| format!("?{}", i)).collect();
+    let insert_sql = format!(
+        "INSERT INTO \"{table}\" ({}) VALUES ({})",
+        names.join(", "),
+        placeholders.join(", ")
+    );
+    Ok(Backend::Sqlite { conn, insert_sql })
+}
+
+/// Error unless the table of `schema` in `conn` has exactly the columns this run writes
+/// (a table created by an older mupattern or edited by hand).
+fn check_columns(
+    conn: &Connection,
+    path: &Path,
+    schema: &Schema,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info(\"{}\")", schema.table))?;
+    let found: Vec<String> = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<_, _>>()?;
+    let expected: Vec<&str> = std::iter::once("pos")
+        .chain(schema.columns.iter().map(|(name, _)| *name))
+        .collect();
+    if found != expected {
+        return Err(format!(
+            "{}: table {} has columns ({}) but this run writes ({}); use a new output file",
+            path.display(),
+            schema.table,
+            found.join(", "),
+            expected.join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const TEST_SCHEMA: Schema = Schema {
+        table: "test",
+        version: 1,
+        columns: &[
+            ("t", ColumnType::Integer),
+            ("crop", ColumnType::Text),
+            ("value", ColumnType::Real),
+        ],
+    };
+
+    #[test]
+    fn floats_are_trimmed_to_four_decimals() {
+        assert_eq!(format_float(12.0), "12");
+        assert_eq!(format_float(0.30000001192092896), "0.3");
+        assert_eq!(format_float(1.23456), "1.2346");
+        assert_eq!(format_float(-0.00001), "0");
+    }
+
+    #[test]
+    fn fields_with_commas_are_quoted() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("out").join("table.csv");
+        let path = path.to_str().unwrap();
+        let mut table = TableWriter::create(path, &TEST_SCHEMA, 3)?;
+        table.write_row(&[0u64.into(), "A,1".into(), 2.5f64.into()])?;
+        table.write_row(&[1u64.into(), "B2".into(), Value::Null])?;
+        assert_eq!(table.finish()?, 2);
+
+        let text = fs::read_to_string(path)?;
+        assert_eq!(
+            text,
+            "# schema: mupattern-test/1\nt,crop,value\n0,\"A,1\",2.5\n1,B2,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sqlite_rows_are_replaced_per_position() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("results.sqlite");
+        let path = path.to_str().unwrap();
+        for pos in [1, 2, 1] {
+            let mut table = TableWriter::create(path, &TEST_SCHEMA, pos)?;
+            table.write_row(&[0u64.into(), "A01".into(), 1.5f64.into()])?;
+            table.write_row(&[1u64.into(), "A01".into(), Value::Null])?;
+            table.finish()?;
+        }
+
+        let conn = Connection::open(path)?;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM test", [], |r| r.get(0))?;
+        assert_eq!(count, 4);
+        let version: i64 = conn.query_row(
+            "SELECT version FROM mupattern_schema WHERE name = 'test'",
+            [],
+            |r| r.get(0),
+        )?;
+        assert_eq!(version, TEST_SCHEMA.version as i64);
+        let value: f64 =
+            conn.query_row("SELECT value FROM test WHERE pos = 2 AND t = 0", [], |r| {
+                r.get(0)
+            })?;
+        assert_eq!(value, 1.5);
+
+        // Nor into one created by another schema version.
+        conn.execute(
+            "UPDATE mupattern_schema SET version = 2 WHERE name = 'test'",
+            [],
+        )?;
+        let err = TableWriter::create(path, &TEST_SCHEMA, 1).err().unwrap();
+        assert!(err.to_string().contains("schema version 2"));
+        conn.execute(
+            "UPDATE mupattern_schema SET version = 1 WHERE name = 'test'",
+            [],
+        )?;
+
+        // A table with another column layout is not written into.
+        conn.execute_batch(
+            "DROP TABLE test; CREATE TABLE test (pos INTEGER, t INTEGER, value REAL, crop TEXT);",
+        )?;
+        let err = TableWriter::create(path, &TEST_SCHEMA, 1).err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("has columns (pos, t, value, crop)"));
+        Ok(())
+    }
+
+    #[test]
+    fn arrow_stream_is_readable_batch_by_batch() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("table.arrows");
+        let path = path.to_str().unwrap();
+        let mut table = TableWriter::create(path, &TEST_SCHEMA, 3)?;
+        for t in 0..ARROW_BATCH_ROWS as u64 + 1 {
+            table.write_row(&[t.into(), "A01".into(), Value::Null])?;
+        }
+        assert_eq!(table.finish()?, ARROW_BATCH_ROWS as u64 + 1);
+
+        let reader = arrow_ipc::reader::StreamReader::try_new(fs::File::open(path)?, None)?;
+        let schema = reader.schema();
+        assert_eq!(schema.metadata()["mupattern.schema"], "mupattern-test/1");
+        assert_eq!(schema.field(2).data_type(), &DataType::Float64);
+        let rows: Vec<usize> = reader
+            .map(|b| b.map(|b| b.num_rows()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(rows, [ARROW_BATCH_ROWS, 1]);
+        assert!(OutputFormat::parse("parquet").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn provenance_is_stored_by_every_backend() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new()?;
+        let model = dir.path().join("model.onnx");
+        fs::write(&model, b"weights")?;
+        let provenance = Provenance::new("test")
+            .model(&model)?
+            .param("tile_size", 512);
+        let json = provenance.to_json();
+        assert!(json.contains("\"model_xxh3\":") && json.contains("\"tile_size\":512"));
+
+        for name in ["table.csv", "table.sqlite", "table.arrows"] {
+            let path = dir.path().join(name);
+            let path = path.to_str().unwrap();
+            let format = OutputFormat::Auto;
+            TableWriter::create_with_provenance(path, &TEST_SCHEMA, 3, format, &provenance)?
+                .finish()?;
+        }
+        let csv = fs::read_to_string(dir.path().join("table.csv"))?;
+        assert_eq!(
+            csv.lines().nth(1),
+            Some(format!("# provenance: {json}").as_str())
+        );
+        let conn = Connection::open(dir.path().join("table.sqlite"))?;
+        let stored: String = conn.query_row(
+            "SELECT provenance FROM mupattern_provenance WHERE name = 'test' AND pos = 3",
+            [],
+            |r| r.get(0),
+        )?;
+        assert_eq!(stored, json);
+        let file = fs::File::open(dir.path().join("table.arrows"))?;
+        let reader = arrow_ipc::reader::StreamReader::try_new(file, None)?;
+        assert_eq!(reader.schema().metadata()["mupattern.provenance"], json);
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+//! Drawing on packed RGB24 frames (movie overlays): scale bar, markers and text in a
+//! built-in 5×7 bitmap font. Buffers are row-major with `stride` pixels per row; drawing
+//! is clipped to the (w, h) image region.
+
+pub type Rgb = [u8; 3];
+
+pub const WHITE: Rgb = [255, 255, 255];
+
+/// Glyph cell size in font pixels, including one column/row of spacing.
+const GLYPH_W: u64 = 6;
+const GLYPH_H: u64 = 8;
+
+/// Parse a color name (white, black, red, green, blue, yellow, cyan, magenta) or `#rrggbb`.
+pub fn parse_color(s: &str) -> Result<Rgb, Box<dyn std::error::Error>> {
+    let t = s.trim().to_ascii_lowercase();
+    let named = match t.as_str() {
+        "" | "white" => Some(WHITE),
+        "black" => Some([0, 0, 0]),
+        "red" => Some([255, 0, 0]),
+        "green" => Some([0, 255, 0]),
+        "blue" => Some([0, 0, 255]),
+        "yellow" => Some([255, 255, 0]),
+        "cyan" => Some([0, 255, 255]),
+        "magenta" => Some([255, 0, 255]),
+        _ => None,
+    };
+    if let Some(c) = named {
+        return Ok(c);
+    }
+    let hex = t.strip_prefix('#').unwrap_or(&t);
+    if hex.len() == 6 {
+        if let Ok(v) = u32::from_str_radix(hex, 16) {
+            return Ok([(v >> 16) as u8, (v >> 8) as u8, v as u8]);
+        }
+    }
+    Err(format!("Invalid color {s:?} (use a name or #rrggbb)").into())
+}
+
+/// Font scale for an image of height `h`: 1 up to 128 px, growing with the image.
+pub fn text_scale(h: u64) -> u64 {
+    (h / 128).max(1)
+}
+
+/// Pixel height of one text line at `scale`.
+pub fn line_height(scale: u64) -> u64 {
+    GLYPH_H * scale
+}
+
+fn put(rgb: &mut [u8], stride: u64, w: u64, h: u64, x: i64, y: i64, color: Rgb) {
+    if x < 0 || y < 0 || x as u64 >= w || y as u64 >= h {
+        return;
+    }
+    let dst = (y as u64 * stride + x as u64) as usize * 3;
+    rgb[dst..dst + 3].copy_from_slice(&color);
+}
+
+/// Filled rectangle [x0, x1) × [y0, y1), clipped to the image.
+pub fn fill_rect(
+    rgb: &mut [u8],
+    stride: u64,
+    (w, h): (u64, u64),
+    (x0, y0): (i64, i64),
+    (x1, y1): (i64, i64),
+    color: Rgb,
+) {
+    for y in y0.max(0)..y1.min(h as i64) {
+        for x in x0.max(0)..x1.min(w as i64) {
+            put(rgb, stride, w, h, x, y, color);
+        }
+    }
+}
+
+/// White bar in the bottom-right corner of the (w, h) image region.
+pub fn draw_scale_bar(rgb: &mut [u8], stride: u64, w: u64, h: u64, len: u64) {
+    let thickness = (h / 40).max(2);
+    let margin = (h / 20).max(2);
+    let y1 = h.saturating_sub(margin);
+    let y0 = y1.saturating_sub(thickness);
+    let x1 = w.saturating_sub(margin);
+    let x0 = x1.saturating_sub(len);
+    fill_rect(
+        rgb,
+        stride,
+        (w, h),
+        (x0 as i64, y0 as i64),
+        (x1 as i64, y1 as i64),
+        WHITE,
+    );
+}
+
+/// Cross marker centered on (x, y) with arm length `r`.
+pub fn draw_marker(
+    rgb: &mut [u8],
+    stride: u64,
+    (w, h): (u64, u64),
+    (x, y): (i64, i64),
+    r: i64,
+    color: Rgb,
+) {
+    for d in -r..=r {
+        put(rgb, stride, w, h, x + d, y, color);
+        put(rgb, stride, w, h, x, y + d, color);
+    }
+}
+
+/// Straight line from `from` to `to` (inclusive), one pixel wide.
+pub fn draw_line(
+    rgb: &mut [u8],
+    stride: u64,
+    (w, h): (u64, u64),
+    from: (i64, i64),
+    to: (i64, i64),
+    color: Rgb,
+) {
+    let (dx, dy) = ((to.0 - from.0).abs(), -(to.1 - from.1).abs());
+    let (sx, sy) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+    let (mut x, mut y, mut err) = (from.0, from.1, dx + dy);
+    loop {
+        put(rgb, stride, w, h, x, y, color);
+        if (x, y) == to {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+/// Arrow from `from` to `to` with a two-stroke head a third of the shaft long.
+pub fn draw_arrow(
+    rgb: &mut [u8],
+    stride: u64,
+    (w, h): (u64, u64),
+    from: (f64, f64),
+    to: (f64, f64),
+    color: Rgb,
+) {
+    let round = |p: (f64, f64)| (p.0.round() as i64, p.1.round() as i64);
+    draw_line(rgb, stride, (w, h), round(from), round(to), color);
+    let (vx, vy) = (to.0 - from.0, to.1 - from.1);
+    let len = vx.hypot(vy);
+    if len < 1.0 {
+        return;
+    }
+    let head = (len / 3.0).max(2.0);
+    let (ux, uy) = (vx / len, vy / len);
+    for side in [-1.0, 1.0] {
+        // Back along the shaft, rotated ±30°.
+        let (c, s) = (0.866, 0.5 * side);
+        let bx = -(ux * c - uy * s) * head;
+        let by = -(ux * s + uy * c) * head;
+        draw_line(
+            rgb,
+            stride,
+            (w, h),
+            round(to),
+            round((to.0 + bx, to.1 + by)),
+            color,
+        );
+    }
+}
+
+/// Draw `text` with its top-left corner at (x, y). Lowercase is drawn as uppercase;
+/// characters outside the font are drawn as a hollow box.
+pub fn draw_text(
+    rgb: &mut [u8],
+    stride: u64,
+    (w, h): (u64, u64),
+    (x, y): (i64, i64),
+    text: &str,
+    color: Rgb,
+    scale: u64,
+) {
+    let s = scale as i64;
+    for (i, ch) in text.chars().enumerate() {
+        let columns = glyph(ch);
+        let gx = x + i as i64 * GLYPH_W as i64 * s;
+        for (col, bits) in columns.iter().enumerate() {
+            for row in 0..7 {
+                if bits & (1 << row) == 0 {
+                    continue;
+                }
+                let px = gx + col as i64 * s;
+                let py = y + row * s;
+                fill_rect(rgb, stride, (w, h), (px, py), (px + s, py + s), color);
+            }
+        }
+    }
+}
+
+/// Pixel width of `text` at `scale`.
+pub fn text_width(text: &str, scale: u64) -> u64 {
+    text.chars().count() as u64 * GLYPH_W * scale
+}
+
+/// 5×7 glyph as five columns, bit 0 = top row.
+fn glyph(ch: char) -> [u8; 5] {
+    match ch.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00],
+        '!' => [0x00, 0x00, 0x5F, 0x00, 0x00],
+        '#' => [0x14, 0x7F, 0x14, 0x7F, 0x14],
+        '%' => [0x23, 0x13, 0x08, 0x64, 0x62],
+        '\'' => [0x00, 0x05, 0x03, 0x00, 0x00],
+        '(' => [0x00, 0x1C, 0x22, 0x41, 0x00],
+        ')' => [0x00, 0x41, 0x22, 0x1C, 0x00],
+        '*' => [0x08, 0x2A, 0x1C, 0x2A, 0x08],
+        '+' => [0x08, 0x08, 0x3E, 0x08, 0x08],
+        ',' => [0x00, 0x50, 0x30, 0x00, 0x00],
+        '-' => [0x08, 0x08, 0x08, 0x08, 0x08],
+        '.' => [0x00, 0x60, 0x60, 0x00, 0x00],
+        '/' => [0x20, 0x10, 0x08, 0x04, 0x02],
+        '0' => [0x3E, 0x51, 0x49, 0x45, 0x3E],
+        '1' => [0x00, 0x42, 0x7F, 0x40, 0x00],
+        '2' => [0x42, 0x61, 0x51, 0x49, 0x46],
+        '3' => [0x21, 0x41, 0x45, 0x4B, 0x31],
+        '4' => [0x18, 0x14, 0x12, 0x7F, 0x10],
+        '5' => [0x27, 0x45, 0x45, 0x45, 0x39],
+        '6' => [0x3C, 0x4A, 0x49, 0x49, 0x30],
+        '7' => [0x01, 0x71, 0x09, 0x05, 0x03],
+        '8' => [0x36, 0x49, 0x49, 0x49, 0x36],
+        '9' => [0x06, 0x49, 0x49, 0x29, 0x1E],
+        ':' => [0x00, 0x36, 0x36, 0x00, 0x00],
+        '<' => [0x08, 0x14, 0x22, 0x41, 0x00],
+        '=' => [0x14, 0x14, 0x14, 0x14, 0x14],
+        '>' => [0x00, 0x41, 0x22, 0x14, 0x08],
+        '?' => [0x02, 0x01, 0x51, 0x09, 0x06],
+        'A' => [0x7E, 0x11, 0x11, 0x11, 0x7E],
+        'B' => [0x7F, 0x49, 0x49, 0x49, 0x36],
+        'C' => [0x3E, 0x41, 0x41, 0x41, 0x22],
+        'D' => [0x7F, 0x41, 0x41, 0x22, 0x1C],
+        'E' => [0x7F, 0x49, 0x49, 0x49, 0x41],
+        'F' => [0x7F, 0x09, 0x09, 0x09, 0x01],
+        'G' => [0x3E, 0x41, 0x49, 0x49, 0x7A],
+        'H' => [0x7F, 0x08, 0x08, 0x08, 0x7F],
+        'I' => [0x00, 0x41, 0x7F, 0x41, 0x00],
+        'J' => [0x20, 0x40, 0x41, 0x3F, 0x01],
+        'K' => [0x7F, 0x08, 0x14, 0x22, 0x41],
+        'L' => [0x7F, 0x40, 0x40, 0x40, 0x40],
+        'M' => [0x7F, 0x02, 0x0C, 0x02, 0x7F],
+        'N' => [0x7F, 0x04, 0x08, 0x10, 0x7F],
+        'O' => [0x3E, 0x41, 0x41, 0x41, 0x3E],
+        'P' => [0x7F, 0x09, 0x09, 0x09, 0x06],
+        'Q' => [0x3E, 0x41, 0x51, 0x21, 0x5E],
+        'R' => [0x7F, 0x09, 0x19, 0x29, 0x46],
+        'S' => [0x46, 0x49, 0x49, 0x49, 0x31],
+        'T' => [0x01, 0x01, 0x7F, 0x01, 0x01],
+        'U' | 'µ' => [0x3F, 0x40, 0x40, 0x40, 0x3F],
+        'V' => [0x1F, 0x20, 0x40, 0x20, 0x1F],
+        'W' => [0x3F, 0x40, 0x38, 0x40, 0x3F],
+        'X' => [0x63, 0x14, 0x08, 0x14, 0x63],
+        'Y' => [0x07, 0x08, 0x70, 0x08, 0x07],
+        'Z' => [0x61, 0x51, 0x49, 0x45, 0x43],
+        '_' => [0x40, 0x40, 0x40, 0x40, 0x40],
+        _ => [0x7F, 0x41, 0x41, 0x41, 0x7F],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_parse_by_name_and_hex() {
+        assert_eq!(parse_color("Red").unwrap(), [255, 0, 0]);
+        assert_eq!(parse_color("#00ff80").unwrap(), [0, 255, 128]);
+        assert!(parse_color("chartreuse").is_err());
+    }
+
+    #[test]
+    fn text_is_clipped_to_the_image_region() {
+        // 8×8 image inside a 10-pixel-wide buffer; "I" at x=5 spills past w.
+        let mut rgb = vec![0u8; 10 * 8 * 3];
+        draw_text(&mut rgb, 10, (8, 8), (5, 0), "II", WHITE, 1);
+        let lit = |x: usize, y: usize| rgb[(y * 10 + x) * 3] == 255;
+        assert!(lit(7, 0));
+        assert!(!lit(8, 0) && !lit(9, 0));
+        assert_eq!(text_width("II", 1), 12);
+    }
+}
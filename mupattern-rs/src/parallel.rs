@@ -0,0 +1,94 @@
+//! `--jobs N`: process independent items (positions, movies) on up to N worker threads
+//! inside one process, so progress, cancellation and errors stay in one place. The worker
+//! count is capped so each worker's share of `--max-memory` still holds one item.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::console;
+use crate::memory;
+
+/// Run `work(i, &items[i])` for every item on up to `jobs` threads. `item_bytes` is the
+/// memory one item needs at least. Once an item fails no new items are started, and the
+/// error of the first failed item (in item order) is returned.
+pub fn for_each<T: Sync>(
+    items: &[T],
+    jobs: usize,
+    item_bytes: u64,
+    work: impl Fn(usize, &T) -> Result<(), Box<dyn std::error::Error>> + Sync,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if jobs == 0 {
+        return Err("--jobs must be at least 1".into());
+    }
+    let wanted = jobs.min(items.len());
+    let workers = memory::cap_items(wanted, item_bytes);
+    if workers < wanted {
+        console::info(&format!(
+            "--max-memory limits --jobs to {} (requested {})",
+            workers, jobs
+        ));
+    }
+    if workers <= 1 {
+        for (i, item) in items.iter().enumerate() {
+            work(i, item)?;
+        }
+        return Ok(());
+    }
+
+    memory::set_workers(workers);
+    let next = AtomicUsize::new(0);
+    let failed: Mutex<Option<(usize, String)>> = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                if failed.lock().unwrap().is_some() {
+                    break;
+                }
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                if let Err(e) = work(i, item) {
+                    let mut failed = failed.lock().unwrap();
+                    if failed.iter().all(|(j, _)| i < *j) {
+                        *failed = Some((i, e.to_string()));
+                    }
+                }
+            });
+        }
+    });
+    memory::set_workers(1);
+    match failed.into_inner().unwrap() {
+        Some((_, msg)) => Err(msg.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_item_runs_once_and_failures_surface() {
+        let items: Vec<u64> = (0..20).collect();
+        let sum = AtomicUsize::new(0);
+        for_each(&items, 4, 0, |_, &x| {
+            sum.fetch_add(x as usize, Ordering::Relaxed);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(sum.into_inner(), 190);
+
+        let err = for_each(&items, 3, 0, |i, _| {
+            if i == 5 {
+                Err(format!("item {i} failed").into())
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "item 5 failed");
+        assert!(for_each(&items, 0, 0, |_, _| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! Metric plot panel for `movie --plot metric.csv:column`: a small line plot of one
+//! column over time, drawn next to the image panels. The trace up to the current frame is
+//! bright and the rest dim, with a cursor at the current frame, so the quantification
+//! evolves with the images.
+
+use std::collections::BTreeMap;
+
+use crate::crop_index;
+use crate::output;
+use crate::overlay::{self, Rgb, WHITE};
+
+const BACKGROUND: Rgb = [16, 16, 16];
+const AXIS: Rgb = [96, 96, 96];
+const FUTURE: Rgb = [72, 72, 72];
+const TRACE: Rgb = [255, 200, 0];
+
+/// Panel width for image panels `frame_h` pixels high: square, but wide enough to read.
+pub fn width(frame_h: u64) -> u64 {
+    frame_h.max(128)
+}
+
+/// One metric column of a per-crop table (expression, spot, tissue, ...).
+pub struct MetricTable {
+    pub column: String,
+    /// (pos, crop, t, value); pos is None when the CSV has no pos column.
+    rows: Vec<(Option<u32>, String, u64, f64)>,
+}
+
+impl MetricTable {
+    /// Load `spec` = "path.csv:column". Rows with an empty or non-numeric value are skipped.
+    pub fn load(spec: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (path, column) = spec
+            .rsplit_once(':')
+            .filter(|(p, c)| !p.is_empty() && !c.is_empty())
+            .ok_or_else(|| format!("Invalid --plot {:?} (use metric.csv:column)", spec))?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+        let col = |name: &str| headers.iter().position(|h| h == name);
+        let need = |name: &str| col(name).ok_or_else(|| format!("{} has no {} column", path, name));
+        let (t_idx, crop_idx) = (need("t")?, need("crop")?);
+        let value_idx = need(&column.to_lowercase())?;
+        let pos_idx = col("pos");
+
+        let mut rows = Vec::new();
+        for record in rdr.records() {
+            let record = record?;
+            let field = |i: usize| record.get(i).unwrap_or("");
+            let Ok(value) = field(value_idx).parse::<f64>() else {
+                continue;
+            };
+            if !value.is_finite() {
+                continue;
+            }
+            rows.push((
+                pos_idx.map(|i| field(i).parse()).transpose()?,
+                field(crop_idx).to_string(),
+                field(t_idx).parse()?,
+                value,
+            ));
+        }
+        Ok(Self {
+            column: column.to_string(),
+            rows,
+        })
+    }
+
+    /// Mean value per frame for one crop (tables with several rows per frame, such as
+    /// spots or cells, are averaged).
+    pub fn series(&self, pos: u32, crop_id: &str) -> BTreeMap<u64, f64> {
+        let mut sums: BTreeMap<u64, (f64, u32)> = BTreeMap::new();
+        for (row_pos, crop, t, value) in &self.rows {
+            if row_pos.is_some_and(|p| p != pos) || !crop_index::same_crop(crop, crop_id) {
+                continue;
+            }
+            let entry = sums.entry(*t).or_default();
+            entry.0 += value;
+            entry.1 += 1;
+        }
+        sums.into_iter()
+            .map(|(t, (sum, n))| (t, sum / n as f64))
+            .collect()
+    }
+}
+
+/// Draw the plot of `series` over frames `t_range` into the (w, h) region, with the
+/// cursor at frame `t`.
+pub fn draw(
+    rgb: &mut [u8],
+    stride: u64,
+    (w, h): (u64, u64),
+    column: &str,
+    series: &BTreeMap<u64, f64>,
+    (t0, t1): (u64, u64),
+    t: u64,
+) {
+    overlay::fill_rect(
+        rgb,
+        stride,
+        (w, h),
+        (0, 0),
+        (w as i64, h as i64),
+        BACKGROUND,
+    );
+    let scale = overlay::text_scale(h);
+    let line = overlay::line_height(scale) as i64;
+    let margin = 2 * scale as i64;
+    overlay::draw_text(rgb, stride, (w, h), (margin, margin), column, WHITE, scale);
+
+    // Plot area below the title and above the value readout.
+    let (x0, x1) = (margin, w as i64 - 1 - margin);
+    let (y0, y1) = (
+        margin + line + margin,
+        h as i64 - 1 - margin - line - margin,
+    );
+    if x1 <= x0 || y1 <= y0 {
+        return;
+    }
+    overlay::draw_line(rgb, stride, (w, h), (x0, y1), (x1, y1), AXIS);
+    overlay::draw_line(rgb, stride, (w, h), (x0, y0), (x0, y1), AXIS);
+
+    let visible: Vec<(u64, f64)> = series.range(t0..=t1).map(|(&t, &v)| (t, v)).collect();
+    let lo = visible.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let hi = visible
+        .iter()
+        .map(|p| p.1)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let px = |ti: u64| {
+        x0 + ((ti - t0) as f64 / (t1 - t0).max(1) as f64 * (x1 - x0) as f64).round() as i64
+    };
+    let py = |v: f64| {
+        let norm = if hi > lo { (v - lo) / (hi - lo) } else { 0.5 };
+        y1 - (norm * (y1 - y0) as f64).round() as i64
+    };
+    for pair in visible.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let color = if b.0 <= t { TRACE } else { FUTURE };
+        overlay::draw_line(
+            rgb,
+            stride,
+            (w, h),
+            (px(a.0), py(a.1)),
+            (px(b.0), py(b.1)),
+            color,
+        );
+    }
+    let cursor = px(t.clamp(t0, t1));
+    overlay::draw_line(rgb, stride, (w, h), (cursor, y0), (cursor, y1), AXIS);
+    if let Some(&v) = series.get(&t) {
+        overlay::draw_marker(
+            rgb,
+            stride,
+            (w, h),
+            (cursor, py(v)),
+            2 * scale as i64,
+            TRACE,
+        );
+        let readout = output::format_float(v);
+        overlay::draw_text(
+            rgb,
+            stride,
+            (w, h),
+            (margin, y1 + margin),
+            &readout,
+            TRACE,
+            scale,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_average_rows_of_one_crop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spot.csv");
+        std::fs::write(
+            &path,
+            "t,crop,spot,intensity\n0,7,0,10\n0,007,1,20\n1,7,0,\n1,8,0,5\n2,7,0,4\n",
+        )
+        .unwrap();
+        let table = MetricTable::load(&format!("{}:intensity", path.display())).unwrap();
+        assert_eq!(table.column, "intensity");
+        let series = table.series(0, "007");
+        assert_eq!(
+            series.into_iter().collect::<Vec<_>>(),
+            vec![(0, 15.0), (2, 4.0)]
+        );
+        assert!(MetricTable::load(&format!("{}:area", path.display())).is_err());
+        assert!(MetricTable::load("no-column.csv").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Progress reporting. Commands call their `progress` callback with a `ProgressEvent`
+//! naming the stage (the same names as `stats::stage`) and the position within it; the
+//! CLI prints each event as one JSON line on stderr:
+//! `{"stage","current","total","progress","message"}` where `progress` is
+//! `current / total` of that stage. Long stages may add `rate` (items/s), `eta_s` and
+//! `position` (`{"pos","current","total"}`: progress within the position being written).
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::console;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressEvent<'a> {
+    pub stage: &'a str,
+    pub current: u64,
+    pub total: u64,
+    pub message: &'a str,
+    /// Items per second over the recent past.
+    pub rate: Option<f64>,
+    /// Estimated seconds until the stage is done.
+    pub eta_s: Option<f64>,
+    pub position: Option<PositionProgress>,
+}
+
+/// Progress within one position of a stage that works position by position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionProgress {
+    pub pos: usize,
+    pub current: u64,
+    pub total: u64,
+}
+
+impl<'a> ProgressEvent<'a> {
+    pub fn new(stage: &'a str, current: u64, total: u64, message: &'a str) -> Self {
+        Self {
+            stage,
+            current,
+            total,
+            message,
+            rate: None,
+            eta_s: None,
+            position: None,
+        }
+    }
+
+    /// Attach a throughput and time-to-completion estimate.
+    pub fn with_rate(self, rate: Option<f64>, eta: Option<Duration>) -> Self {
+        Self {
+            rate,
+            eta_s: eta.map(|d| d.as_secs_f64()),
+            ..self
+        }
+    }
+
+    /// Attach progress within position `pos`.
+    pub fn with_position(self, pos: usize, current: u64, total: u64) -> Self {
+        Self {
+            position: Some(PositionProgress {
+                pos,
+                current,
+                total,
+            }),
+            ..self
+        }
+    }
+
+    /// Final event of a command.
+    pub fn done(message: &'a str) -> Self {
+        Self::new("done", 1, 1, message)
+    }
+
+    /// Position within the stage, 0-1 (0 while the total is unknown).
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            (self.current as f64 / self.total as f64).min(1.0)
+        }
+    }
+
+    pub fn to_json(self) -> serde_json::Value {
+        let mut line = serde_json::json!({
+            "stage": self.stage,
+            "current": self.current,
+            "total": self.total,
+            "progress": self.fraction(),
+            "message": self.message,
+        });
+        if let Some(rate) = self.rate {
+            line["rate"] = rate.into();
+        }
+        if let Some(eta_s) = self.eta_s {
+            line["eta_s"] = eta_s.into();
+        }
+        if let Some(p) = self.position {
+            line["position"] = serde_json::json!({
+                "pos": p.pos,
+                "current": p.current,
+                "total": p.total,
+            });
+        }
+        line
+    }
+}
+
+/// Print `event` as a JSON line on stderr (suppressed by `--quiet`).
+pub fn emit(event: ProgressEvent) {
+    if console::quiet() {
+        return;
+    }
+    let _ = writeln!(io::stderr(), "{}", event.to_json());
+    let _ = io::stderr().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_line_keeps_the_progress_fraction() {
+        let line = ProgressEvent::new("infer", 3, 4, "Predicting 3/4").to_json();
+        assert_eq!(line["stage"], "infer");
+        assert_eq!(line["current"], 3);
+        assert_eq!(line["progress"], 0.75);
+        assert_eq!(line["message"], "Predicting 3/4");
+        assert_eq!(ProgressEvent::new("scan", 0, 0, "").fraction(), 0.0);
+        assert_eq!(ProgressEvent::done("Done").fraction(), 1.0);
+        assert!(line.get("eta_s").is_none());
+    }
+
+    #[test]
+    fn rate_eta_and_position_are_structured() {
+        let line = ProgressEvent::new("write", 5, 20, "Writing TIFFs 5/20")
+            .with_rate(Some(2.5), Some(Duration::from_secs(6)))
+            .with_position(3, 1, 4)
+            .to_json();
+        assert_eq!(line["rate"], 2.5);
+        assert_eq!(line["eta_s"], 6.0);
+        assert_eq!(
+            line["position"],
+            serde_json::json!({"pos": 3, "current": 1, "total": 4})
+        );
+    }
+}
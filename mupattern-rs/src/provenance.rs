@@ -0,0 +1,84 @@
+//! Provenance of analysis tables (`kill`, `spot`, `tissue`): the mupattern version, the
+//! model and a hash of its files, the command's parameters and the pixel calibration, so
+//! a result can be traced to the exact model and settings that produced it.
+//!
+//! `output::TableWriter` stores it with the table: a `# provenance: {json}` comment line
+//! after the schema line in CSVs, a `mupattern_provenance` row per table and position in
+//! SQLite, and the `mupattern.provenance` schema metadata key in Arrow streams.
+
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::calibration::PixelCalibration;
+
+pub struct Provenance(Map<String, Value>);
+
+impl Provenance {
+    pub fn new(command: &str) -> Self {
+        let mut fields = Map::new();
+        fields.insert("command".to_string(), command.into());
+        fields.insert("version".to_string(), env!("CARGO_PKG_VERSION").into());
+        Self(fields)
+    }
+
+    /// Record the model at `path` (a file, or a directory of model files) with an xxh3-64
+    /// hash over its content.
+    pub fn model(mut self, path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        self.0
+            .insert("model".to_string(), path.display().to_string().into());
+        self.0
+            .insert("model_xxh3".to_string(), hash_model(path)?.into());
+        Ok(self)
+    }
+
+    pub fn param(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.0.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn calibration(mut self, calibration: &PixelCalibration) -> Self {
+        let value = serde_json::to_value(calibration).unwrap_or_default();
+        self.0.insert("calibration".to_string(), value);
+        self
+    }
+
+    /// Single-line JSON.
+    pub fn to_json(&self) -> String {
+        Value::Object(self.0.clone()).to_string()
+    }
+}
+
+/// Hex xxh3-64 over the bytes of `path`; for a directory, over the name and bytes of each
+/// file in it, in name order.
+fn hash_model(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut hasher = Xxh3::new();
+    if path.is_dir() {
+        let mut files: Vec<_> = fs::read_dir(path)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file())
+            .collect();
+        files.sort();
+        for file in files {
+            hasher.update(file.file_name().unwrap_or_default().as_encoded_bytes());
+            hash_file(&mut hasher, &file)?;
+        }
+    } else {
+        hash_file(&mut hasher, path)?;
+    }
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
+fn hash_file(hasher: &mut Xxh3, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = fs::File::open(path)?;
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
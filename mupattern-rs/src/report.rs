@@ -0,0 +1,539 @@
+//! Report: one-file overview of a position's analysis outputs.
+//! Looks for Pos{N}_expression.csv, Pos{N}_prediction*.csv (kill), Pos{N}_tissue.csv and
+//! Pos{N}_spot.csv in the results directory (the names mupattern-desktop uses), and writes a
+//! self-contained HTML page with summary tables and inline SVG curves, or the same data as
+//! JSON when the output ends in .json.
+
+use clap::Args;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::manifest;
+use crate::progress::ProgressEvent;
+use crate::stats;
+
+#[derive(Args, Clone)]
+pub struct ReportArgs {
+    /// Results directory containing the Pos{N}_*.csv outputs
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Output path: .html (default) or .json
+    #[arg(long)]
+    pub output: String,
+}
+
+/// A CSV output loaded as text records, header names lowercased.
+struct Table {
+    headers: Vec<String>,
+    records: Vec<csv::StringRecord>,
+}
+
+impl Table {
+    fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let headers = reader.headers()?.iter().map(|h| h.to_lowercase()).collect();
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { headers, records })
+    }
+
+    fn col(&self, name: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        self.headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| format!("Missing {name} column").into())
+    }
+
+    /// Values of `value` grouped by the t column.
+    fn by_t(
+        &self,
+        value: impl Fn(&csv::StringRecord) -> Option<f64>,
+    ) -> Result<BTreeMap<u64, Vec<f64>>, Box<dyn std::error::Error>> {
+        let t_idx = self.col("t")?;
+        let mut out: BTreeMap<u64, Vec<f64>> = BTreeMap::new();
+        for rec in &self.records {
+            let (Some(t), Some(v)) = (rec.get(t_idx).and_then(|s| s.parse().ok()), value(rec))
+            else {
+                continue;
+            };
+            out.entry(t).or_default().push(v);
+        }
+        Ok(out)
+    }
+
+    /// Number of records per (t, crop).
+    fn counts_by_t_crop(&self) -> Result<BTreeMap<(u64, String), u64>, Box<dyn std::error::Error>> {
+        let (t_idx, crop_idx) = (self.col("t")?, self.col("crop")?);
+        let mut out = BTreeMap::new();
+        for rec in &self.records {
+            let Some(t) = rec.get(t_idx).and_then(|s| s.parse().ok()) else {
+                continue;
+            };
+            let crop = rec.get(crop_idx).unwrap_or("").to_string();
+            *out.entry((t, crop)).or_insert(0) += 1;
+        }
+        Ok(out)
+    }
+
+    fn distinct(&self, name: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let idx = self.col(name)?;
+        Ok(self
+            .records
+            .iter()
+            .filter_map(|r| r.get(idx))
+            .collect::<HashSet<_>>()
+            .len())
+    }
+}
+
+fn field_f64(rec: &csv::StringRecord, idx: usize) -> Option<f64> {
+    let s = rec.get(idx)?;
+    match s {
+        "true" | "True" => Some(1.0),
+        "false" | "False" => Some(0.0),
+        _ => s.parse().ok(),
+    }
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.0
+    }
+}
+
+#[derive(Serialize)]
+struct Curve {
+    label: String,
+    t: Vec<u64>,
+    y: Vec<f64>,
+}
+
+impl Curve {
+    fn from_groups(
+        label: &str,
+        groups: BTreeMap<u64, Vec<f64>>,
+        reduce: impl Fn(&mut [f64]) -> f64,
+    ) -> Self {
+        let mut t = Vec::new();
+        let mut y = Vec::new();
+        for (key, mut values) in groups {
+            t.push(key);
+            y.push(reduce(&mut values));
+        }
+        Self {
+            label: label.to_string(),
+            t,
+            y,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Section {
+    name: &'static str,
+    file: String,
+    rows: usize,
+    crops: usize,
+    frames: usize,
+    metrics: Vec<(String, f64)>,
+    curve: Curve,
+}
+
+/// Key metrics and the headline curve for one output.
+type Summary = Result<(Vec<(String, f64)>, Curve), Box<dyn std::error::Error>>;
+type SectionFn = fn(&Table) -> Summary;
+
+fn expression_section(table: &Table) -> Summary {
+    let (i, a, b) = (
+        table.col("intensity")?,
+        table.col("area")?,
+        table.col("background")?,
+    );
+    let groups = table.by_t(|r| Some(field_f64(r, i)? - field_f64(r, a)? * field_f64(r, b)?))?;
+    let curve = Curve::from_groups("median intensity above background", groups, median);
+    let last = curve.y.last().copied().unwrap_or(f64::NAN);
+    Ok((
+        vec![("median above background, last frame".to_string(), last)],
+        curve,
+    ))
+}
+
+fn kill_section(table: &Table) -> Summary {
+    let label = table.col("label")?;
+    let groups = table.by_t(|r| field_f64(r, label))?;
+    let curve = Curve::from_groups("fraction of crops with cell present", groups, |v| {
+        v.iter().sum::<f64>() / v.len() as f64
+    });
+    let first = curve.y.first().copied().unwrap_or(f64::NAN);
+    let last = curve.y.last().copied().unwrap_or(f64::NAN);
+    Ok((
+        vec![
+            ("fraction present, first frame".to_string(), first),
+            ("fraction present, last frame".to_string(), last),
+        ],
+        curve,
+    ))
+}
+
+fn tissue_section(table: &Table) -> Summary {
+    let area = table.col("cell_area")?;
+    let mut areas: Vec<f64> = table
+        .records
+        .iter()
+        .filter_map(|r| field_f64(r, area))
+        .collect();
+    let mut groups: BTreeMap<u64, Vec<f64>> = BTreeMap::new();
+    for ((t, _), n) in table.counts_by_t_crop()? {
+        groups.entry(t).or_default().push(n as f64);
+    }
+    let curve = Curve::from_groups("median cells per crop", groups, median);
+    Ok((
+        vec![
+            ("cell detections".to_string(), table.records.len() as f64),
+            ("median cell area (px)".to_string(), median(&mut areas)),
+        ],
+        curve,
+    ))
+}
+
+fn spot_section(table: &Table) -> Summary {
+    let mut groups: BTreeMap<u64, Vec<f64>> = BTreeMap::new();
+    for ((t, _), n) in table.counts_by_t_crop()? {
+        groups.entry(t).or_default().push(n as f64);
+    }
+    let curve = Curve::from_groups("mean spots per crop (crops with spots)", groups, |v| {
+        v.iter().sum::<f64>() / v.len() as f64
+    });
+    Ok((
+        vec![("spots".to_string(), table.records.len() as f64)],
+        curve,
+    ))
+}
+
+/// First file in `dir` named Pos{pos}_{prefix}...csv, preferring an exact Pos{pos}_{prefix}.csv.
+fn find_output(
+    dir: &Path,
+    pos: u32,
+    prefixes: &[&str],
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok()?.file_name().to_str().map(String::from))
+        .collect();
+    names.sort();
+    for prefix in prefixes {
+        let stem = format!("pos{}_{}", pos, prefix);
+        let exact = names
+            .iter()
+            .find(|n| n.to_lowercase() == format!("{stem}.csv"));
+        let loose = names.iter().find(|n| {
+            let n = n.to_lowercase();
+            n.starts_with(&stem) && n.ends_with(".csv")
+        });
+        if let Some(name) = exact.or(loose) {
+            return Ok(Some(dir.join(name)));
+        }
+    }
+    Ok(None)
+}
+
+pub fn run(
+    args: ReportArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = Path::new(&args.input);
+    if !dir.is_dir() {
+        return Err(format!("Results directory not found: {}", dir.display()).into());
+    }
+
+    stats::stage("aggregate");
+    let kinds: [(&'static str, &[&str], SectionFn); 4] = [
+        ("expression", &["expression"], expression_section),
+        ("kill", &["predictions", "prediction"], kill_section),
+        ("tissue", &["tissue"], tissue_section),
+        ("spot", &["spot"], spot_section),
+    ];
+    let mut sections = Vec::new();
+    for (i, (name, prefixes, summarize)) in kinds.into_iter().enumerate() {
+        progress(ProgressEvent::new(
+            "aggregate",
+            i as u64,
+            kinds.len() as u64,
+            &format!("Reading {} output", name),
+        ));
+        let Some(path) = find_output(dir, args.pos, prefixes)? else {
+            continue;
+        };
+        let table = Table::load(&path)?;
+        let (metrics, curve) =
+            summarize(&table).map_err(|e| format!("{}: {}", path.display(), e))?;
+        sections.push(Section {
+            name,
+            file: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            rows: table.records.len(),
+            crops: table.distinct("crop")?,
+            frames: table.distinct("t")?,
+            metrics,
+            curve,
+        });
+    }
+    if sections.is_empty() {
+        return Err(format!(
+            "No Pos{}_*.csv outputs found in {}",
+            args.pos,
+            dir.display()
+        )
+        .into());
+    }
+
+    let text = if args.output.to_lowercase().ends_with(".json") {
+        serde_json::to_string_pretty(&serde_json::json!({
+            "pos": args.pos,
+            "input": args.input,
+            "sections": sections,
+        }))?
+    } else {
+        render_html(args.pos, &args.input, &sections)
+    };
+    if let Some(parent) = Path::new(&args.output).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&args.output, text)?;
+    stats::add_bytes_written(fs::metadata(&args.output)?.len());
+    manifest::report(&args.output);
+
+    progress(ProgressEvent::done(&format!(
+        "Wrote report with {} section(s) to {}",
+        sections.len(),
+        args.output
+    )));
+    Ok(())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn fmt_value(v: f64) -> String {
+    if v.is_nan() {
+        "–".to_string()
+    } else if v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{}", v as i64)
+    } else {
+        format!("{:.3}", v)
+    }
+}
+
+/// Line plot of `curve` as an inline SVG.
+fn render_svg(curve: &Curve) -> String {
+    const W: f64 = 560.0;
+    const H: f64 = 220.0;
+    const PAD: f64 = 40.0;
+    let points: Vec<(f64, f64)> = curve
+        .t
+        .iter()
+        .zip(&curve.y)
+        .filter(|(_, y)| y.is_finite())
+        .map(|(&t, &y)| (t as f64, y))
+        .collect();
+    if points.is_empty() {
+        return String::new();
+    }
+    let (t0, t1) = points
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(a, b), p| (a.min(p.0), b.max(p.0)));
+    let (y0, y1) = points
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(a, b), p| (a.min(p.1), b.max(p.1)));
+    let sx = |t: f64| PAD + if t1 > t0 { (t - t0) / (t1 - t0) } else { 0.5 } * (W - 2.0 * PAD);
+    let sy = |y: f64| H - PAD - if y1 > y0 { (y - y0) / (y1 - y0) } else { 0.5 } * (H - 2.0 * PAD);
+    let polyline: Vec<String> = points
+        .iter()
+        .map(|&(t, y)| format!("{:.1},{:.1}", sx(t), sy(y)))
+        .collect();
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{W}" height="{H}" viewBox="0 0 {W} {H}">
+<rect x="{PAD}" y="{PAD}" width="{pw}" height="{ph}" fill="none" stroke="#ccc"/>
+<polyline fill="none" stroke="#c0392b" stroke-width="2" points="{points}"/>
+<text x="{PAD}" y="{tl}" font-size="11">t = {t0}</text>
+<text x="{tr}" y="{tl}" font-size="11" text-anchor="end">t = {t1}</text>
+<text x="{yl}" y="{PAD}" font-size="11" text-anchor="end">{ymax}</text>
+<text x="{yl}" y="{yb}" font-size="11" text-anchor="end">{ymin}</text>
+<text x="{mid}" y="16" font-size="12" text-anchor="middle">{label}</text>
+</svg>"##,
+        pw = W - 2.0 * PAD,
+        ph = H - 2.0 * PAD,
+        points = polyline.join(" "),
+        tl = H - PAD + 16.0,
+        tr = W - PAD,
+        yl = PAD - 4.0,
+        yb = H - PAD,
+        ymax = fmt_value(y1),
+        ymin = fmt_value(y0),
+        mid = W / 2.0,
+        label = escape(&curve.label),
+    )
+}
+
+fn render_html(pos: u32, input: &str, sections: &[Section]) -> String {
+    let mut body = String::new();
+    body.push_str(
+        "<table><tr><th>output</th><th>file</th><th>rows</th><th>crops</th><th>frames</th></tr>\n",
+    );
+    for s in sections {
+        body.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            s.name,
+            escape(&s.file),
+            s.rows,
+            s.crops,
+            s.frames
+        ));
+    }
+    body.push_str("</table>\n");
+    for s in sections {
+        body.push_str(&format!("<h2>{}</h2>\n<table>\n", s.name));
+        for (label, value) in &s.metrics {
+            body.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td></tr>\n",
+                escape(label),
+                fmt_value(*value)
+            ));
+        }
+        body.push_str("</table>\n");
+        body.push_str(&render_svg(&s.curve));
+        body.push('\n');
+    }
+    format!(
+        r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>mupattern report: Pos{pos}</title>
+<style>body{{font-family:sans-serif;margin:2em;max-width:60em}}table{{border-collapse:collapse;margin:1em 0}}td,th{{border:1px solid #ddd;padding:4px 10px;text-align:left}}</style>
+</head><body>
+<h1>Pos{pos}</h1>
+<p>Results: {input}</p>
+{body}</body></html>
+"#,
+        input = escape(input),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(dir: &Path, name: &str, text: &str) -> Table {
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        Table::load(&path).unwrap()
+    }
+
+    #[test]
+    fn sections_summarize_each_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let expression = table(
+            dir.path(),
+            "e.csv",
+            "# schema: mupattern-expression/1\nt,crop,intensity,area,background\n\
+             0,a,100,10,2\n0,b,50,10,1\n1,a,30,10,1\n1,b,70,10,2\n1,c,40,10,0\n",
+        );
+        let (metrics, curve) = expression_section(&expression).unwrap();
+        assert_eq!((curve.t, curve.y), (vec![0, 1], vec![60.0, 40.0]));
+        assert_eq!(metrics[0].1, 40.0);
+
+        let kill = table(
+            dir.path(),
+            "k.csv",
+            "t,crop,label\n0,a,true\n0,b,true\n1,a,false\n1,b,true\n",
+        );
+        let (metrics, curve) = kill_section(&kill).unwrap();
+        assert_eq!(curve.y, [1.0, 0.5]);
+        assert_eq!((metrics[0].1, metrics[1].1), (1.0, 0.5));
+
+        let tissue = table(
+            dir.path(),
+            "t.csv",
+            "t,crop,cell,total_fluorescence,cell_area,background\n\
+             0,a,1,5,10,0\n0,a,2,5,20,0\n0,b,1,5,30,0\n1,a,1,5,40,0\n",
+        );
+        let (metrics, curve) = tissue_section(&tissue).unwrap();
+        assert_eq!(curve.y, [1.5, 1.0]);
+        assert_eq!((metrics[0].1, metrics[1].1), (4.0, 25.0));
+        assert_eq!(
+            (
+                tissue.distinct("crop").unwrap(),
+                tissue.distinct("t").unwrap()
+            ),
+            (2, 2)
+        );
+
+        let spot = table(
+            dir.path(),
+            "s.csv",
+            "t,crop,spot,y,x\n0,a,1,1,1\n0,a,2,1,1\n0,b,1,1,1\n1,a,1,1,1\n",
+        );
+        let (metrics, curve) = spot_section(&spot).unwrap();
+        assert_eq!(curve.y, [1.5, 1.0]);
+        assert_eq!(metrics[0].1, 4.0);
+
+        let err = kill_section(&spot).err().unwrap().to_string();
+        assert_eq!(err, "Missing label column");
+    }
+
+    #[test]
+    fn outputs_are_found_by_position_and_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "Pos3_expression_old.csv",
+            "Pos3_expression.csv",
+            "Pos3_prediction_run2.csv",
+            "Pos30_spot.csv",
+            "Pos3_tissue.txt",
+        ] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let find = |prefixes: &[&str]| {
+            find_output(dir.path(), 3, prefixes)
+                .unwrap()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+        };
+        assert_eq!(
+            find(&["expression"]).as_deref(),
+            Some("Pos3_expression.csv")
+        );
+        assert_eq!(
+            find(&["predictions", "prediction"]).as_deref(),
+            Some("Pos3_prediction_run2.csv")
+        );
+        assert_eq!(find(&["spot"]), None);
+        assert_eq!(find(&["tissue"]), None);
+    }
+
+    #[test]
+    fn html_text_is_escaped() {
+        assert_eq!(
+            escape(r#"<a href="x">&</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+        let html = render_html(3, "runs/<today>", &[]);
+        assert!(html.contains("Results: runs/&lt;today&gt;"));
+    }
+}
@@ -0,0 +1,152 @@
+//! Rotation of the cell arrangement on each pattern (e.g. doublets): per frame the
+//! principal axis is estimated from image moments of a channel or of the tissue masks,
+//! unwrapped over time and differentiated into an angular velocity.
+
+use clap::Args;
+use std::path::Path;
+
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::crop;
+use crate::crop_index;
+use crate::exclude::ExclusionList;
+use crate::geometry;
+use crate::output::{ColumnType, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::zarr;
+
+const SCHEMA: Schema = Schema {
+    table: "rotation",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("angle", ColumnType::Real),
+        ("cumulative_angle", ColumnType::Real),
+        ("angular_velocity", ColumnType::Real),
+        ("anisotropy", ColumnType::Real),
+    ],
+};
+
+#[derive(Args, Clone)]
+pub struct RotationArgs {
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Estimate the axis from this channel's intensity moments (index or name)
+    #[arg(long)]
+    pub channel: Option<String>,
+    /// Estimate the axis from the cell masks in this masks.zarr (from `tissue`) instead
+    #[arg(long)]
+    pub masks: Option<String>,
+    /// Output path: CSV, or SQLite when ending in .sqlite/.db
+    #[arg(long)]
+    pub output: String,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+}
+
+pub fn run(
+    args: RotationArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.channel.is_some() == args.masks.is_some() {
+        return Err("Pass exactly one of --channel or --masks".into());
+    }
+    let crops_zarr = Path::new(&args.input);
+    let pos_id = format!("{:03}", args.pos);
+    let channel = match &args.channel {
+        Some(spec) => Some(ChannelNames::load(crops_zarr)?.resolve(spec)?),
+        None => None,
+    };
+
+    let mut crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
+
+    let mut table = TableWriter::create(&args.output, &SCHEMA, args.pos)?;
+    if crop_ids.is_empty() {
+        table.finish()?;
+        return Ok(());
+    }
+
+    let store = zarr::open_store(crops_zarr)?;
+    let masks_store = match &args.masks {
+        Some(path) => Some(zarr::open_store(Path::new(path))?),
+        None => None,
+    };
+    stats::stage("analyze");
+    let total = crop_ids.len();
+
+    for (i, crop_id) in crop_ids.iter().enumerate() {
+        let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
+        let arr = zarr::open_array(&store, &array_path)?;
+        let shape = arr.shape();
+        let (n_t, w) = (shape[0], shape[4] as usize);
+        let skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+        let missing = crop::missing_frames(&arr);
+        let mask_arr = match &masks_store {
+            Some(s) => Some(zarr::open_array(s, &array_path)?),
+            None => None,
+        };
+
+        let mut previous: Option<(u64, f64)> = None;
+        for t in 0..n_t {
+            if skip.contains(&t) {
+                continue;
+            }
+            let axis = match (&mask_arr, channel) {
+                (Some(mask_arr), _) => {
+                    let labels = zarr::read_labels(mask_arr, t)?;
+                    geometry::orientation(&labels, w, |v| if v > 0 { 1.0 } else { 0.0 })
+                }
+                (None, Some(c)) => {
+                    if missing.contains(&(t, c as u64, 0)) {
+                        continue;
+                    }
+                    let data = zarr::read_frame_u16(&arr, &[t, c as u64, 0])?;
+                    geometry::orientation(&data, w, |v| v as f64)
+                }
+                (None, None) => unreachable!("checked above"),
+            };
+            let Some((angle, anisotropy)) = axis else {
+                continue;
+            };
+            let (cumulative, velocity) = match previous {
+                Some((prev_t, prev)) => {
+                    let cumulative = geometry::unwrap_axis(prev, angle);
+                    (cumulative, (cumulative - prev) / (t - prev_t) as f64)
+                }
+                None => (angle, f64::NAN),
+            };
+            previous = Some((t, cumulative));
+            table.write_row(&[
+                Value::from(t),
+                crop_id.as_str().into(),
+                angle.into(),
+                cumulative.into(),
+                velocity.into(),
+                anisotropy.into(),
+            ])?;
+            stats::add_frames(1);
+        }
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "analyze",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Processing crop {}/{}", i + 1, total),
+        ));
+    }
+
+    let n_rows = table.finish()?;
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} rows to {}",
+        n_rows, args.output
+    )));
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! Angular intensity profile: each frame of a crop is split into equal sectors around
+//! the pattern center and the summed intensity per sector is reported over time, to
+//! quantify polarization and rotation of cells on circular patterns.
+
+use clap::Args;
+use std::path::Path;
+
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::crop;
+use crate::crop_index;
+use crate::exclude::ExclusionList;
+use crate::geometry::{self, Center};
+use crate::output::{ColumnType, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::zarr;
+
+const SCHEMA: Schema = Schema {
+    table: "sector",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("sector", ColumnType::Integer),
+        ("angle", ColumnType::Real),
+        ("intensity", ColumnType::Integer),
+        ("area", ColumnType::Integer),
+        ("mean_intensity", ColumnType::Real),
+    ],
+};
+
+#[derive(Args, Clone)]
+pub struct SectorArgs {
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Channel to measure: index or name
+    #[arg(long)]
+    pub channel: String,
+    /// Number of equal angular bins, starting at 0° (+x) and running counterclockwise
+    #[arg(long)]
+    pub sectors: usize,
+    /// Pattern center: crop (geometric center) or centroid (intensity-weighted, per frame)
+    #[arg(long)]
+    pub center: String,
+    /// Only count pixels within this many pixels of the center
+    #[arg(long)]
+    pub max_radius: Option<f64>,
+    /// Output path: CSV, or SQLite when ending in .sqlite/.db
+    #[arg(long)]
+    pub output: String,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+}
+
+/// Summed intensity and pixel count per sector.
+fn sector_sums(
+    data: &[u16],
+    h: usize,
+    w: usize,
+    center: (f64, f64),
+    n: usize,
+    max_radius: Option<f64>,
+) -> Vec<(u64, u64)> {
+    let mut sums = vec![(0u64, 0u64); n];
+    for y in 0..h {
+        for x in 0..w {
+            let (r, angle) = geometry::polar(y, x, center);
+            if max_radius.is_some_and(|m| r > m) {
+                continue;
+            }
+            let s = &mut sums[geometry::sector_of(angle, n)];
+            s.0 += data[y * w + x] as u64;
+            s.1 += 1;
+        }
+    }
+    sums
+}
+
+pub fn run(
+    args: SectorArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.sectors == 0 {
+        return Err("--sectors must be at least 1".into());
+    }
+    let center = Center::parse(&args.center)?;
+    let crops_zarr = Path::new(&args.input);
+    let pos_id = format!("{:03}", args.pos);
+    let channel = ChannelNames::load(crops_zarr)?.resolve(&args.channel)? as u64;
+
+    let mut crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
+
+    let mut table = TableWriter::create(&args.output, &SCHEMA, args.pos)?;
+    if crop_ids.is_empty() {
+        table.finish()?;
+        return Ok(());
+    }
+
+    let store = zarr::open_store(crops_zarr)?;
+    stats::stage("analyze");
+    let total = crop_ids.len();
+    let width = 360.0 / args.sectors as f64;
+
+    for (i, crop_id) in crop_ids.iter().enumerate() {
+        let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
+        let arr = zarr::open_array(&store, &array_path)?;
+        let shape = arr.shape();
+        let (n_t, h, w) = (shape[0], shape[3] as usize, shape[4] as usize);
+        let skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+        let missing = crop::missing_frames(&arr);
+
+        for t in 0..n_t {
+            if skip.contains(&t) || missing.contains(&(t, channel, 0)) {
+                continue;
+            }
+            let data = zarr::read_frame_u16(&arr, &[t, channel, 0])?;
+            let c = center.locate(&data, h, w);
+            let sums = sector_sums(&data, h, w, c, args.sectors, args.max_radius);
+            for (s, &(intensity, area)) in sums.iter().enumerate() {
+                let mean = if area > 0 {
+                    intensity as f64 / area as f64
+                } else {
+                    0.0
+                };
+                table.write_row(&[
+                    Value::from(t),
+                    crop_id.as_str().into(),
+                    s.into(),
+                    ((s as f64 + 0.5) * width).into(),
+                    intensity.into(),
+                    area.into(),
+                    mean.into(),
+                ])?;
+            }
+            stats::add_frames(1);
+        }
+
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "analyze",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Processing crop {}/{}", i + 1, total),
+        ));
+    }
+
+    let n_rows = table.finish()?;
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} rows to {}",
+        n_rows, args.output
+    )));
+    Ok(())
+}
@@ -0,0 +1,215 @@
+//! Active-learning selection: the N crop-frames whose kill `probability` is closest to 0.5,
+//! rendered as the model saw them into `{output}/{key}.png` plus a `labels.csv` sheet with
+//! an empty label column. Filled in, the sheet goes straight back into `export-training`.
+//! `--random N` adds N more crop-frames drawn from the rest (reproducible with `--seed`),
+//! so the sheet also checks predictions the model is confident about.
+
+use clap::Args;
+use std::fs;
+use std::path::Path;
+
+use crate::cancel;
+use crate::detector::DetectorRange;
+use crate::export_training::sample_key;
+use crate::kill::{self, Normalize};
+use crate::output::{self, ColumnType, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::seed;
+use crate::stats;
+use crate::zarr;
+
+const SCHEMA: Schema = Schema {
+    table: "uncertain",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("probability", ColumnType::Real),
+        ("image", ColumnType::Text),
+        ("label", ColumnType::Integer),
+    ],
+};
+
+#[derive(Args, Clone)]
+pub struct SelectUncertainArgs {
+    /// Path to crops.zarr
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// kill output CSV with a probability column
+    #[arg(long)]
+    pub predictions: String,
+    /// Folder for the selected images and the labels.csv annotation sheet
+    #[arg(long)]
+    pub output: String,
+    /// Number of crop-frames to select
+    #[arg(long, default_value_t = 100)]
+    pub count: usize,
+    /// Also pick this many of the remaining crop-frames at random (see the global --seed)
+    #[arg(long, default_value_t = 0)]
+    pub random: usize,
+    /// Edge length of the square images (the model input size)
+    #[arg(long, default_value_t = kill::DEFAULT_IMAGE_SIZE)]
+    pub size: u32,
+    /// Per-frame intensity normalization, as for `kill --normalize`
+    #[arg(long, default_value = "minmax")]
+    pub normalize: String,
+    /// Resize filter, as for `kill --resize-filter`
+    #[arg(long, default_value = "triangle")]
+    pub resize_filter: String,
+}
+
+/// One predicted crop-frame: (crop, t, present probability).
+type Candidate = (String, u64, f64);
+
+fn load_predictions(path: &str, pos: u32) -> Result<Vec<Candidate>, Box<dyn std::error::Error>> {
+    if output::is_sqlite_path(path) {
+        return Err(
+            "select-uncertain reads CSV predictions; export SQLite results to CSV first".into(),
+        );
+    }
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let col = |name: &str| headers.iter().position(|h| h == name);
+    let t_idx = col("t").ok_or_else(|| format!("Predictions {} need a t column", path))?;
+    let crop_idx = col("crop").ok_or_else(|| format!("Predictions {} need a crop column", path))?;
+    let prob_idx = col("probability").ok_or_else(|| {
+        format!(
+            "Predictions {} have no probability column; re-run kill to produce one",
+            path
+        )
+    })?;
+    let pos_idx = col("pos");
+
+    let mut candidates = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        let field = |i: usize| record.get(i).unwrap_or("");
+        if pos_idx.is_some_and(|i| field(i).parse::<u32>().ok() != Some(pos)) {
+            continue;
+        }
+        // Frames kill labelled without inference (--skip-empty) have no probability.
+        if field(prob_idx).is_empty() {
+            continue;
+        }
+        candidates.push((
+            field(crop_idx).to_string(),
+            field(t_idx).parse()?,
+            field(prob_idx).parse()?,
+        ));
+    }
+    Ok(candidates)
+}
+
+/// The `n` candidates closest to 0.5, most uncertain first (ties in crop, t order).
+fn most_uncertain(mut candidates: Vec<Candidate>, n: usize) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| {
+        (a.2 - 0.5)
+            .abs()
+            .total_cmp(&(b.2 - 0.5).abs())
+            .then_with(|| a.0.cmp(&b.0))
+            .then(a.1.cmp(&b.1))
+    });
+    candidates.truncate(n);
+    candidates
+}
+
+pub fn run(
+    args: SelectUncertainArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = kill::parse_filter(&args.resize_filter)?;
+    let candidates = load_predictions(&args.predictions, args.pos)?;
+    let n_candidates = candidates.len();
+    let mut selected = most_uncertain(candidates, n_candidates);
+    let rest = selected.split_off(args.count.min(n_candidates));
+    selected.extend(seed::rng("select-uncertain").sample(rest, args.random));
+    if selected.is_empty() {
+        return Err(format!(
+            "No predictions with a probability for position {} in {}",
+            args.pos, args.predictions
+        )
+        .into());
+    }
+
+    let crops_zarr = Path::new(&args.input);
+    let pos_id = format!("{:03}", args.pos);
+    let store = zarr::open_store(crops_zarr)?;
+    let normalize = Normalize::parse(&args.normalize, DetectorRange::from_store(&store))?;
+    let out = Path::new(&args.output);
+    fs::create_dir_all(out)?;
+    let sheet = out.join("labels.csv");
+    let mut table = TableWriter::create(&sheet.to_string_lossy(), &SCHEMA, args.pos)?;
+
+    stats::stage("write");
+    let total = selected.len();
+    for (i, (crop_id, t, probability)) in selected.iter().enumerate() {
+        let arr = zarr::open_array_cached(&store, &format!("/pos/{}/crop/{}", pos_id, crop_id))
+            .map_err(|e| format!("Predicted crop {} not in {}: {}", crop_id, args.input, e))?;
+        let shape = arr.shape();
+        let (h, w) = (shape[3] as u32, shape[4] as u32);
+        let data = zarr::read_frame_u16(&arr, &[*t, 0, 0])?;
+        let img = kill::model_image(&data, w, h, normalize, (args.size, args.size), filter);
+        let image = format!("{}.png", sample_key(&pos_id, crop_id, *t));
+        let path = out.join(&image);
+        img.save(&path)?;
+        stats::add_bytes_written(fs::metadata(&path)?.len());
+        table.write_row(&[
+            Value::from(*t),
+            crop_id.as_str().into(),
+            (*probability).into(),
+            image.as_str().into(),
+            Value::from(None::<bool>),
+        ])?;
+        stats::add_frames(1);
+
+        if (i + 1) % 100 == 0 {
+            cancel::check()?;
+        }
+        progress(ProgressEvent::new(
+            "write",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Exporting frame {}/{}", i + 1, total),
+        ));
+    }
+    table.finish()?;
+
+    progress(ProgressEvent::done(&format!(
+        "Selected {} of {} predicted frames into {}",
+        total, n_candidates, args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closest_to_half_come_first() {
+        let candidates = vec![
+            ("b".to_string(), 0, 0.95),
+            ("a".to_string(), 3, 0.375),
+            ("a".to_string(), 1, 0.625),
+            ("c".to_string(), 2, 0.02),
+            ("a".to_string(), 0, 0.75),
+        ];
+        let picked: Vec<(String, u64)> = most_uncertain(candidates, 3)
+            .into_iter()
+            .map(|(crop, t, _)| (crop, t))
+            .collect();
+        assert_eq!(
+            picked,
+            vec![
+                ("a".to_string(), 1),
+                ("a".to_string(), 3),
+                ("a".to_string(), 0)
+            ]
+        );
+    }
+}
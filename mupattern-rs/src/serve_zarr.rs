@@ -0,0 +1,293 @@
+//! serve-zarr: read-only HTTP server for crops.zarr / masks.zarr so browser viewers
+//! (vizarr, neuroglancer) can open stores on a remote machine. Each `--input` is
+//! mounted under its directory name (e.g. http://host:8080/crops.zarr/). Responses
+//! carry permissive CORS headers; byte ranges are supported for sharded arrays.
+
+use clap::Args;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server, StatusCode};
+
+use crate::cancel;
+use crate::console;
+use crate::progress::ProgressEvent;
+
+#[derive(Args, Clone)]
+pub struct ServeZarrArgs {
+    /// Store(s) to serve, e.g. crops.zarr (repeat to also serve masks.zarr)
+    #[arg(long, required = true)]
+    pub input: Vec<String>,
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+    /// Address to bind; use 0.0.0.0 to accept connections from other machines
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+    /// Worker threads handling requests
+    #[arg(long, default_value_t = 4)]
+    pub workers: usize,
+}
+
+/// Parse a single `Range: bytes=...` value against a file of `len` bytes.
+/// Returns the inclusive (start, end) range, or None when unsatisfiable or malformed.
+fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') || len == 0 {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let n: u64 = suffix.parse().ok()?;
+            if n == 0 {
+                return None;
+            }
+            (len.saturating_sub(n), len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len - 1),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(len - 1)),
+    };
+    (start <= end && start < len).then_some((start, end))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Map a request URL onto a file inside one of the mounted stores.
+/// Rejects anything that would escape the store (`..`, absolute components).
+fn resolve(mounts: &[(String, PathBuf)], url: &str) -> Option<PathBuf> {
+    let path = percent_decode(url.split(['?', '#']).next().unwrap_or(""));
+    let mut parts = path.trim_start_matches('/').splitn(2, '/');
+    let mount = parts.next()?;
+    let rest = parts.next().unwrap_or("");
+    let (_, root) = mounts.iter().find(|(name, _)| name == mount)?;
+    let rel = Path::new(rest);
+    if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let full = root.join(rel);
+    full.is_file().then_some(full)
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn with_cors(response: ResponseBox) -> ResponseBox {
+    response
+        .with_header(header("Access-Control-Allow-Origin", "*"))
+        .with_header(header("Access-Control-Allow-Methods", "GET, HEAD, OPTIONS"))
+        .with_header(header("Access-Control-Allow-Headers", "Range"))
+        .with_header(header(
+            "Access-Control-Expose-Headers",
+            "Content-Length, Content-Range, Accept-Ranges",
+        ))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+fn file_response(
+    path: &Path,
+    range: Option<&str>,
+) -> Result<ResponseBox, Box<dyn std::error::Error>> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let base = |r: ResponseBox| {
+        r.with_header(header("Content-Type", content_type(path)))
+            .with_header(header("Accept-Ranges", "bytes"))
+    };
+    let Some(range) = range else {
+        return Ok(base(Response::from_file(file).boxed()));
+    };
+    let Some((start, end)) = parse_range(range, len) else {
+        return Ok(Response::empty(416)
+            .with_header(header("Content-Range", &format!("bytes */{len}")))
+            .boxed());
+    };
+    // Stream the range instead of buffering it: shard files can be hundreds of MB.
+    file.seek(SeekFrom::Start(start))?;
+    let n = end - start + 1;
+    Ok(base(
+        Response::new(
+            StatusCode(206),
+            Vec::new(),
+            file.take(n),
+            Some(n as usize),
+            None,
+        )
+        .with_header(header(
+            "Content-Range",
+            &format!("bytes {start}-{end}/{len}"),
+        ))
+        .boxed(),
+    ))
+}
+
+fn handle(mounts: &[(String, PathBuf)], request: Request) {
+    let response = match request.method() {
+        Method::Options => Response::empty(204).boxed(),
+        Method::Get | Method::Head => match resolve(mounts, request.url()) {
+            Some(path) => {
+                let range = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Range"))
+                    .map(|h| h.value.as_str().to_string());
+                file_response(&path, range.as_deref()).unwrap_or_else(|e| {
+                    console::warn(&format!("serve-zarr: {}: {}", path.display(), e));
+                    Response::empty(500).boxed()
+                })
+            }
+            None => Response::empty(404).boxed(),
+        },
+        _ => Response::empty(405).boxed(),
+    };
+    // tiny_http drops the body for HEAD requests itself.
+    let _ = request.respond(with_cors(response));
+}
+
+pub fn run(
+    args: ServeZarrArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut mounts: Vec<(String, PathBuf)> = Vec::new();
+    for input in &args.input {
+        let root = fs::canonicalize(input).map_err(|e| format!("{}: {}", input, e))?;
+        if !root.join("zarr.json").exists() {
+            return Err(format!("{} is not a Zarr v3 store (no zarr.json)", input).into());
+        }
+        let name = root
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| format!("Cannot mount {}", input))?
+            .to_string();
+        if mounts.iter().any(|(n, _)| *n == name) {
+            return Err(format!("Two inputs share the mount name {name}").into());
+        }
+        mounts.push((name, root));
+    }
+
+    let addr = format!("{}:{}", args.host, args.port);
+    let server = Arc::new(Server::http(&addr).map_err(|e| format!("Cannot bind {}: {}", addr, e))?);
+    let mounts = Arc::new(mounts);
+    for (name, root) in mounts.iter() {
+        console::info(&format!(
+            "serve-zarr: http://{}/{}/ -> {}",
+            addr,
+            name,
+            root.display()
+        ));
+    }
+    progress(ProgressEvent::new(
+        "serve",
+        0,
+        0,
+        &format!(
+            "Serving {} store(s) on http://{}/ (Ctrl+C to stop)",
+            mounts.len(),
+            addr
+        ),
+    ));
+
+    let workers: Vec<_> = (0..args.workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let mounts = Arc::clone(&mounts);
+            std::thread::spawn(move || {
+                while cancel::check().is_ok() {
+                    match server.recv_timeout(Duration::from_millis(500)) {
+                        Ok(Some(request)) => handle(&mounts, request),
+                        Ok(None) => {}
+                        Err(e) => {
+                            console::warn(&format!("serve-zarr: {}", e));
+                            break;
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    cancel::check()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_follow_rfc_7233() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range("bytes=90-", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-16", 100), Some((84, 99)));
+        assert_eq!(parse_range("bytes=50-500", 100), Some((50, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=0-1,4-5", 100), None);
+    }
+
+    #[test]
+    fn urls_cannot_escape_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("crops.zarr");
+        fs::create_dir_all(store.join("pos")).unwrap();
+        fs::write(store.join("pos/zarr.json"), "{}").unwrap();
+        fs::write(dir.path().join("secret.txt"), "x").unwrap();
+        let mounts = vec![("crops.zarr".to_string(), store.clone())];
+
+        assert_eq!(
+            resolve(&mounts, "/crops.zarr/pos/zarr.json?x=1"),
+            Some(store.join("pos/zarr.json"))
+        );
+        assert_eq!(resolve(&mounts, "/crops.zarr/../secret.txt"), None);
+        assert_eq!(resolve(&mounts, "/crops.zarr/%2e%2e/secret.txt"), None);
+        assert_eq!(resolve(&mounts, "/masks.zarr/zarr.json"), None);
+    }
+
+    #[test]
+    fn ranges_stream_only_the_requested_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("c.0");
+        fs::write(&path, (0u8..100).collect::<Vec<_>>()).unwrap();
+
+        let mut raw = Vec::new();
+        file_response(&path, Some("bytes=10-19"))
+            .unwrap()
+            .raw_print(&mut raw, (1, 1).into(), &[], false, None)
+            .unwrap();
+        let text = String::from_utf8_lossy(&raw);
+        assert!(text.starts_with("HTTP/1.1 206"));
+        assert!(text.contains("Content-Range: bytes 10-19/100"));
+        assert!(text.contains("Content-Length: 10"));
+        assert!(raw.ends_with(&(10u8..20).collect::<Vec<_>>()));
+
+        let unsatisfiable = file_response(&path, Some("bytes=200-")).unwrap();
+        assert_eq!(unsatisfiable.status_code().0, 416);
+    }
+}
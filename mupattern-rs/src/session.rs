@@ -0,0 +1,246 @@
+//! Shared ONNX Runtime session layer: model file selection by precision, CUDA/CPU
+//! execution providers, and dtype-aware input/output conversion so fp16 and int8
+//! exports load next to the fp32 ones.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use half::f16;
+use ndarray::{ArrayD, ArrayViewD};
+#[cfg(feature = "tensorrt")]
+use ort::ep::TensorRT;
+#[cfg(any(windows, target_os = "linux"))]
+use ort::ep::{ExecutionProvider, CUDA};
+use ort::session::Session;
+use ort::tensor::TensorElementType;
+use ort::value::{DynValue, Tensor};
+
+use crate::console;
+
+static TRT_ENGINE_CACHE: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Set the TensorRT engine cache directory once at startup (`--trt-engine-cache`).
+/// Also exported through the TensorRT EP's environment variables so sessions built
+/// inside the segmentation crates reuse the same cache.
+pub fn set_trt_engine_cache(dir: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = &dir {
+        std::fs::create_dir_all(dir)?;
+        std::env::set_var("ORT_TENSORRT_ENGINE_CACHE_ENABLE", "1");
+        std::env::set_var("ORT_TENSORRT_CACHE_PATH", dir);
+    }
+    let _ = TRT_ENGINE_CACHE.set(dir);
+    Ok(())
+}
+
+fn trt_engine_cache() -> Option<&'static Path> {
+    TRT_ENGINE_CACHE.get()?.as_deref()
+}
+
+/// Model precision selected with `--precision`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    /// Use whichever export is present, preferring fp32.
+    Auto,
+    Fp32,
+    Fp16,
+    Int8,
+}
+
+impl Precision {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "fp32" | "f32" => Ok(Self::Fp32),
+            "fp16" | "f16" => Ok(Self::Fp16),
+            "int8" | "q8" => Ok(Self::Int8),
+            _ => Err(format!("Unknown precision {s:?}. Use auto, fp32, fp16 or int8.").into()),
+        }
+    }
+
+    /// Candidate file names in `model_dir`, most preferred first. Names follow
+    /// `optimum-cli` / `onnxruntime.quantization` export conventions.
+    fn file_names(self) -> &'static [&'static str] {
+        match self {
+            Self::Auto => &[
+                "model.onnx",
+                "model_fp16.onnx",
+                "model_quantized.onnx",
+                "model_int8.onnx",
+            ],
+            Self::Fp32 => &["model.onnx"],
+            Self::Fp16 => &["model_fp16.onnx"],
+            Self::Int8 => &["model_quantized.onnx", "model_int8.onnx"],
+        }
+    }
+}
+
+/// Resolve the model file for `precision` inside `model_dir`.
+pub fn resolve_model(
+    model_dir: &Path,
+    precision: Precision,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let names = precision.file_names();
+    names
+        .iter()
+        .map(|name| model_dir.join(name))
+        .find(|path| path.exists())
+        .ok_or_else(|| {
+            format!(
+                "No {:?} model in {} (looked for {})",
+                precision,
+                model_dir.display(),
+                names.join(", ")
+            )
+            .into()
+        })
+}
+
+/// Build an ONNX session. Tries TensorRT (when built with the `tensorrt` feature and an
+/// engine cache is set), then CUDA if `use_cuda`, falling back to CPU.
+/// `tag` prefixes the log lines (e.g. "kill").
+pub fn build_session(
+    model_path: &Path,
+    use_cuda: bool,
+    tag: &str,
+) -> Result<Session, Box<dyn std::error::Error>> {
+    #[cfg(feature = "tensorrt")]
+    if let (true, Some(cache)) = (use_cuda, trt_engine_cache()) {
+        match build_tensorrt_session(model_path, cache) {
+            Ok(s) => {
+                console::info(&format!(
+                    "{tag}: using TensorRT (engine cache {}).",
+                    cache.display()
+                ));
+                let _ = std::io::stderr().flush();
+                return Ok(s);
+            }
+            Err(e) => {
+                console::warn(&format!(
+                    "{tag}: TensorRT failed ({}), trying CUDA.",
+                    e.to_string().lines().next().unwrap_or("")
+                ));
+            }
+        }
+    }
+    #[cfg(not(feature = "tensorrt"))]
+    if trt_engine_cache().is_some() {
+        console::warn(&format!(
+            "{tag}: --trt-engine-cache ignored (built without the tensorrt feature)."
+        ));
+    }
+
+    #[cfg(any(windows, target_os = "linux"))]
+    if use_cuda {
+        let mut builder = Session::builder()?;
+        let cuda = CUDA::default();
+        if cuda.is_available().unwrap_or(false) {
+            if cuda.register(&mut builder).is_ok() {
+                match builder.commit_from_file(model_path) {
+                    Ok(s) => {
+                        console::info(&format!("{tag}: using CUDA for GPU acceleration."));
+                        let _ = std::io::stderr().flush();
+                        return Ok(s);
+                    }
+                    Err(e) => {
+                        let msg = e.to_string();
+                        if msg.to_lowercase().contains("cuda")
+                            || msg.contains("no CUDA-capable device")
+                        {
+                            console::warn(&format!(
+                                "{tag}: CUDA failed ({}), falling back to CPU.",
+                                msg.lines().next().unwrap_or(&msg)
+                            ));
+                            // Fall through to CPU path
+                        } else {
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    console::info(&format!("{tag}: using CPU."));
+    let _ = std::io::stderr().flush();
+    Ok(Session::builder()?.commit_from_file(model_path)?)
+}
+
+/// TensorRT EP with engine and timing caches in `cache`; CUDA handles unsupported nodes.
+/// The first run per model/input shape builds the engine, later runs load it from disk.
+#[cfg(feature = "tensorrt")]
+fn build_tensorrt_session(
+    model_path: &Path,
+    cache: &Path,
+) -> Result<Session, Box<dyn std::error::Error>> {
+    let trt = TensorRT::default()
+        .with_engine_cache(true)
+        .with_engine_cache_path(cache.display())
+        .with_timing_cache(true)
+        .with_timing_cache_path(cache.display());
+    if !trt.is_available().unwrap_or(false) {
+        return Err("TensorRT execution provider not available".into());
+    }
+    let mut builder = Session::builder()?;
+    trt.register(&mut builder)?;
+    let _ = CUDA::default().register(&mut builder);
+    Ok(builder.commit_from_file(model_path)?)
+}
+
+/// Element type of the first model input.
+pub fn input_type(session: &Session) -> Result<TensorElementType, Box<dyn std::error::Error>> {
+    session
+        .inputs()
+        .first()
+        .and_then(|input| input.dtype().tensor_type())
+        .ok_or_else(|| "Model has no tensor input".into())
+}
+
+/// Fixed batch size of the first model input, or None when the batch axis is dynamic.
+/// Statically exported (often quantized) models only accept this batch size.
+pub fn fixed_batch(session: &Session) -> Option<usize> {
+    let shape = session.inputs().first()?.dtype().tensor_shape()?;
+    shape.first().and_then(|&n| (n > 0).then_some(n as usize))
+}
+
+/// Spatial size (H, W) of a first input shaped [N, C, H, W], or None when either axis
+/// is dynamic.
+pub fn input_hw(session: &Session) -> Option<(usize, usize)> {
+    let shape = session.inputs().first()?.dtype().tensor_shape()?;
+    match shape[..] {
+        [_, _, h, w] if h > 0 && w > 0 => Some((h as usize, w as usize)),
+        _ => None,
+    }
+}
+
+/// Build an input tensor from f32 data, converting to the model's input dtype.
+/// Dynamically quantized int8 models still take f32 inputs.
+pub fn float_input(
+    data: ArrayD<f32>,
+    ty: TensorElementType,
+) -> Result<DynValue, Box<dyn std::error::Error>> {
+    match ty {
+        TensorElementType::Float32 => Ok(Tensor::from_array(data)?.into_dyn()),
+        TensorElementType::Float16 => Ok(Tensor::from_array(data.mapv(f16::from_f32))?.into_dyn()),
+        other => {
+            Err(format!("Unsupported model input type {other:?} (expected f32 or f16)").into())
+        }
+    }
+}
+
+/// Extract a float output as f32, whatever the model's output precision.
+pub fn extract_f32(output: &DynValue) -> Result<ArrayD<f32>, Box<dyn std::error::Error>> {
+    match output.dtype().tensor_type() {
+        Some(TensorElementType::Float32) => {
+            let view: ArrayViewD<f32> = output.try_extract_array()?;
+            Ok(view.to_owned())
+        }
+        Some(TensorElementType::Float16) => {
+            let view: ArrayViewD<f16> = output.try_extract_array()?;
+            Ok(view.mapv(f16::to_f32))
+        }
+        other => {
+            Err(format!("Unsupported model output type {other:?} (expected f32 or f16)").into())
+        }
+    }
+}
@@ -1,20 +1,41 @@
-/// Parse slice expressions like "all", "1,3", "0:10:2".
-/// Semantics mirror muapplication/common/slices.py (slice.indices).
+use std::collections::HashSet;
+
+/// Parse slice expressions like "all", "1,3", "0:10:2", "::-1", "5-20" or "every:5" into
+/// sorted, de-duplicated indices. Slices mirror Python's slice.indices (as in
+/// muapplication/common/slices.py); "a-b" is the inclusive range a..=b and "every:N" is
+/// "::N" without having to know the length.
 pub fn parse_slice_string(s: &str, length: usize) -> Result<Vec<usize>, String> {
+    let mut out = parse_slice_order(s, length)?;
+    out.sort_unstable();
+    out.dedup();
+    Ok(out)
+}
+
+/// Like [`parse_slice_string`], but indices come out in the order the expression lists
+/// them, repeats included: "::-1" plays backwards, "20-5" counts down, "0:5,4:0:-1" goes
+/// there and back.
+pub fn parse_slice_order(s: &str, length: usize) -> Result<Vec<usize>, String> {
     let s = s.trim();
     if s.eq_ignore_ascii_case("all") {
         return Ok((0..length).collect());
     }
 
     let len = length as isize;
-    let mut indices = std::collections::HashSet::new();
+    let mut indices = Vec::new();
 
     for segment in s.split(',') {
         let segment = segment.trim();
         if segment.is_empty() {
             continue;
         }
-        if segment.contains(':') {
+        let invalid = || format!("Invalid slice segment: {:?}", segment);
+        if let Some(step) = segment.strip_prefix("every:") {
+            let step: usize = step.trim().parse().map_err(|_| invalid())?;
+            if step == 0 {
+                return Err(format!("Slice step cannot be zero: {:?}", segment));
+            }
+            indices.extend((0..length).step_by(step));
+        } else if segment.contains(':') {
             let parts: Vec<Option<isize>> = segment
                 .split(':')
                 .map(|p| {
@@ -22,73 +43,189 @@ pub fn parse_slice_string(s: &str, length: usize) -> Result<Vec<usize>, String>
                     if t.is_empty() {
                         Ok(None)
                     } else {
-                        t.parse()
-                            .map_err(|_| format!("Invalid slice segment: {:?}", segment))
-                            .map(Some)
+                        t.parse().map_err(|_| invalid()).map(Some)
                     }
                 })
                 .collect::<Result<_, _>>()?;
             if parts.len() > 3 {
-                return Err(format!("Invalid slice segment: {:?}", segment));
+                return Err(invalid());
             }
-            let start = parts.get(0).copied().flatten().unwrap_or(0);
-            let stop = parts.get(1).copied().flatten().unwrap_or(len);
+            let start = parts.first().copied().flatten();
+            let stop = parts.get(1).copied().flatten();
             let step = parts.get(2).copied().flatten().unwrap_or(1);
 
             if step == 0 {
                 return Err(format!("Slice step cannot be zero: {:?}", segment));
             }
 
-            let (start, stop, step) = (start, stop, step);
-            let (i, j, k) = slice_indices(start, stop, step, len);
-            let mut idx = i;
-            while (k > 0 && idx < j) || (k < 0 && idx > j) {
-                if idx >= 0 && idx < len {
-                    indices.insert(idx as usize);
-                }
-                idx += k;
+            let (mut idx, stop) = slice_indices(start, stop, step, len);
+            while (step > 0 && idx < stop) || (step < 0 && idx > stop) {
+                indices.push(idx as usize);
+                idx += step;
+            }
+        } else if let Some((a, b)) = segment
+            .split_once('-')
+            .filter(|(a, _)| !a.trim().is_empty())
+        {
+            let a: usize = a.trim().parse().map_err(|_| invalid())?;
+            let b: usize = b.trim().parse().map_err(|_| invalid())?;
+            if a.max(b) >= length {
+                return Err(format!(
+                    "Range {} out of range (0-{})",
+                    segment,
+                    length as isize - 1
+                ));
+            }
+            if a <= b {
+                indices.extend(a..=b);
+            } else {
+                indices.extend((b..=a).rev());
             }
         } else {
-            let idx: isize = segment
-                .parse()
-                .map_err(|_| format!("Invalid slice segment: {:?}", segment))?;
+            let idx: isize = segment.parse().map_err(|_| invalid())?;
             if idx < -len || idx >= len {
                 return Err(format!("Index {} out of range", idx));
             }
             let idx = if idx < 0 { idx + len } else { idx };
-            indices.insert(idx as usize);
+            indices.push(idx as usize);
         }
     }
 
-    let mut out: Vec<usize> = indices.into_iter().collect();
-    out.sort_unstable();
-    Ok(out)
+    Ok(indices)
 }
 
-/// Mirror Python slice.indices(length) -> (start, stop, step).
-fn slice_indices(start: isize, stop: isize, step: isize, length: isize) -> (isize, isize, isize) {
-    let (mut start, mut stop) = (start, stop);
-    if start < 0 {
-        start = (start + length).max(0);
-    } else if start > length {
-        start = length;
-    }
-    if stop < 0 {
-        stop = (stop + length).max(0);
-    } else if stop > length {
-        stop = length;
-    }
-    if step < 0 {
-        if stop < start {
-            (start, stop.max(-1), step)
-        } else {
-            (start, -1, step)
+/// Like [`parse_slice_string`], but single indices and "a-b" ranges past the end are
+/// dropped (ranges cut at the end) instead of rejected, for expressions applied to inputs
+/// of different lengths such as `--exclude` rules matching several crops.
+pub fn parse_slice_string_within(s: &str, length: usize) -> Result<Vec<usize>, String> {
+    let last = length as isize - 1;
+    let int = |v: &str| v.trim().parse::<isize>().ok();
+    let mut kept = Vec::new();
+    for segment in s.split(',').map(str::trim) {
+        if let Ok(idx) = segment.parse::<isize>() {
+            if idx < -(length as isize) || idx > last {
+                continue;
+            }
+        } else if let Some((a, b)) = segment
+            .split_once('-')
+            .and_then(|(a, b)| Some((int(a)?, int(b)?)))
+        {
+            if a.min(b) > last {
+                continue;
+            }
+            kept.push(format!("{}-{}", a.min(b), a.max(b).min(last)));
+            continue;
         }
+        kept.push(segment.to_string());
+    }
+    parse_slice_string(&kept.join(","), length)
+}
+
+/// Frames of `0..n_t` that the selection `s` leaves out, so per-frame commands can skip
+/// them together with their `--exclude` frames.
+pub fn frames_outside(s: &str, n_t: u64) -> Result<HashSet<u64>, String> {
+    let selected = parse_slice_string(s, n_t as usize)?;
+    Ok((0..n_t)
+        .filter(|&t| selected.binary_search(&(t as usize)).is_err())
+        .collect())
+}
+
+/// Python's slice(start, stop, step).indices(length) -> (start, stop); an omitted start or
+/// stop means "from the first" / "to the last" element in the direction of `step`.
+fn slice_indices(
+    start: Option<isize>,
+    stop: Option<isize>,
+    step: isize,
+    length: isize,
+) -> (isize, isize) {
+    let (lower, upper) = if step < 0 {
+        (-1, length - 1)
     } else {
-        if start > stop {
-            (start, stop, step)
+        (0, length)
+    };
+    let clamp = |i: isize| {
+        if i < 0 {
+            (i + length).max(lower)
         } else {
-            (start, stop, step)
+            i.min(upper)
+        }
+    };
+    let start = start.map_or(if step < 0 { upper } else { lower }, clamp);
+    let stop = stop.map_or(if step < 0 { lower } else { upper }, clamp);
+    (start, stop)
+}
+
+/// `--shard i/n`: the i-th of n interleaved shares of a list (0-based), so array jobs can
+/// split positions across nodes. The same list and n always give the same split.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid shard {:?}: expected i/n with 0 <= i < n", s);
+        let (i, n) = s.trim().split_once('/').ok_or_else(invalid)?;
+        let index: usize = i.trim().parse().map_err(|_| invalid())?;
+        let count: usize = n.trim().parse().map_err(|_| invalid())?;
+        if index >= count {
+            return Err(invalid());
         }
+        Ok(Shard { index, count })
+    }
+
+    /// Every `count`-th item of `items`, starting at `index`.
+    pub fn select<T: Clone>(&self, items: &[T]) -> Vec<T> {
+        items
+            .iter()
+            .skip(self.index)
+            .step_by(self.count)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slices_match_python() {
+        // Expected values are list(range(10))[expr] in Python.
+        let cases: [(&str, &[usize]); 10] = [
+            ("::-1", &[9, 8, 7, 6, 5, 4, 3, 2, 1, 0]),
+            ("8:2:-2", &[8, 6, 4]),
+            ("-3:", &[7, 8, 9]),
+            (":-7:-1", &[9, 8, 7, 6, 5, 4]),
+            ("::3", &[0, 3, 6, 9]),
+            ("5::-2", &[5, 3, 1]),
+            ("-1:-4:-1", &[9, 8, 7]),
+            ("20:0:-3", &[9, 6, 3]),
+            ("3:100", &[3, 4, 5, 6, 7, 8, 9]),
+            ("5:2", &[]),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(parse_slice_order(expr, 10).unwrap(), expected, "{expr}");
+        }
+        assert!(parse_slice_order("::0", 10).is_err());
+    }
+
+    #[test]
+    fn ranges_and_order() {
+        assert_eq!(parse_slice_order("2-4, -1", 10).unwrap(), [2, 3, 4, 9]);
+        assert_eq!(parse_slice_order("4-2", 10).unwrap(), [4, 3, 2]);
+        assert!(parse_slice_order("5-10", 10).is_err());
+        assert_eq!(
+            parse_slice_order("0:3,2:0:-1", 10).unwrap(),
+            [0, 1, 2, 2, 1]
+        );
+        assert_eq!(parse_slice_string("0:3,2:0:-1", 10).unwrap(), [0, 1, 2]);
+        assert_eq!(parse_slice_string("::-1", 3).unwrap(), [0, 1, 2]);
+        assert_eq!(parse_slice_string("all", 3).unwrap(), [0, 1, 2]);
+        assert_eq!(parse_slice_string("every:4", 10).unwrap(), [0, 4, 8]);
+        assert!(parse_slice_string("every:0", 10).is_err());
+        let outside = frames_outside("every:2, 3", 6).unwrap();
+        assert_eq!(outside, HashSet::from([1, 5]));
     }
 }
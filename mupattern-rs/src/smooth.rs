@@ -0,0 +1,294 @@
+//! Per-crop time-series smoothing of analysis CSVs: selected metric columns are replaced
+//! by smoothed (or detrended) values while the header, schema line and row order stay as
+//! they were, so downstream tools read the result like the original table.
+
+use clap::Args;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::manifest;
+use crate::output;
+use crate::progress::ProgressEvent;
+
+/// Columns that identify a series rather than hold a metric.
+const KEY_COLUMNS: &[&str] = &["pos", "crop", "cell", "spot"];
+
+#[derive(Args, Clone)]
+pub struct SmoothArgs {
+    /// Analysis CSV (e.g. from `expression`, `tissue`, `kill`) with a t column
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub output: String,
+    /// savgol (local polynomial), median (running median) or lowess (tricube-weighted
+    /// local linear fit)
+    #[arg(long)]
+    pub method: String,
+    /// Points per window (odd)
+    #[arg(long)]
+    pub window: usize,
+    /// Polynomial order for savgol
+    #[arg(long, default_value_t = 2)]
+    pub polyorder: usize,
+    /// Comma-separated columns to smooth (default: every numeric column except t and the
+    /// series keys pos, crop, cell, spot)
+    #[arg(long)]
+    pub columns: Option<String>,
+    /// Write the residual (value minus smoothed trend) instead of the smoothed value
+    #[arg(long)]
+    pub detrend: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Method {
+    Savgol { order: usize },
+    Median,
+    Lowess,
+}
+
+impl Method {
+    fn parse(s: &str, polyorder: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "savgol" => Ok(Self::Savgol { order: polyorder }),
+            "median" => Ok(Self::Median),
+            "lowess" => Ok(Self::Lowess),
+            _ => Err(format!("Unknown method '{}' (expected savgol, median or lowess)", s).into()),
+        }
+    }
+}
+
+/// Value at `x0` of the weighted least-squares polynomial of `order` through `points`.
+fn local_fit(points: &[(f64, f64, f64)], x0: f64, order: usize) -> f64 {
+    // Normal equations in powers of (x - x0); the constant term is the fitted value.
+    let n = order + 1;
+    let mut a = vec![vec![0f64; n + 1]; n];
+    for &(x, y, w) in points {
+        let d = x - x0;
+        for (r, row) in a.iter_mut().enumerate() {
+            for (c, cell) in row[..n].iter_mut().enumerate() {
+                *cell += w * d.powi((r + c) as i32);
+            }
+            row[n] += w * y * d.powi(r as i32);
+        }
+    }
+    // Gaussian elimination with partial pivoting.
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+            .unwrap_or(col);
+        a.swap(col, pivot);
+        if a[col][col].abs() < 1e-12 {
+            // Too few distinct points for this order: fall back to the weighted mean.
+            let total: f64 = points.iter().map(|p| p.2).sum();
+            return points.iter().map(|p| p.1 * p.2).sum::<f64>() / total;
+        }
+        let (done, below) = a.split_at_mut(col + 1);
+        let pivot_row = &done[col];
+        for row in below {
+            let f = row[col] / pivot_row[col];
+            for (x, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= f * p;
+            }
+        }
+    }
+    let mut coef = vec![0f64; n];
+    for r in (0..n).rev() {
+        let tail: f64 = (r + 1..n).map(|c| a[r][c] * coef[c]).sum();
+        coef[r] = (a[r][n] - tail) / a[r][r];
+    }
+    coef[0]
+}
+
+/// Smoothed values of the series `(t, value)`, sorted by t. Windows hold `window` points
+/// centered where possible and shifted inwards at the ends.
+fn smooth_series(series: &[(f64, f64)], method: Method, window: usize) -> Vec<f64> {
+    let n = series.len();
+    let window = window.min(n);
+    (0..n)
+        .map(|i| {
+            let start = i.saturating_sub(window / 2).min(n - window);
+            let span = &series[start..start + window];
+            let x0 = series[i].0;
+            match method {
+                Method::Median => {
+                    let mut v: Vec<f64> = span.iter().map(|p| p.1).collect();
+                    v.sort_by(f64::total_cmp);
+                    let m = v.len() / 2;
+                    if v.len() % 2 == 1 {
+                        v[m]
+                    } else {
+                        (v[m - 1] + v[m]) / 2.0
+                    }
+                }
+                Method::Savgol { order } => {
+                    let points: Vec<(f64, f64, f64)> =
+                        span.iter().map(|&(x, y)| (x, y, 1.0)).collect();
+                    local_fit(&points, x0, order)
+                }
+                Method::Lowess => {
+                    let reach = span.iter().map(|p| (p.0 - x0).abs()).fold(0f64, f64::max) * 1.0001;
+                    let points: Vec<(f64, f64, f64)> = span
+                        .iter()
+                        .map(|&(x, y)| {
+                            let u = if reach > 0.0 {
+                                (x - x0).abs() / reach
+                            } else {
+                                0.0
+                            };
+                            (x, y, (1.0 - u.powi(3)).powi(3))
+                        })
+                        .collect();
+                    local_fit(&points, x0, 1)
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn run(
+    args: SmoothArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let method = Method::parse(&args.method, args.polyorder)?;
+    if args.window == 0 || args.window % 2 == 0 {
+        return Err("--window must be odd".into());
+    }
+    if let Method::Savgol { order } = method {
+        if order >= args.window {
+            return Err("--polyorder must be smaller than --window".into());
+        }
+    }
+    if output::is_sqlite_path(&args.input) {
+        return Err("smooth reads CSV tables; export SQLite results to CSV first".into());
+    }
+
+    let text = fs::read_to_string(&args.input)?;
+    let comments: Vec<&str> = text.lines().take_while(|l| l.starts_with('#')).collect();
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(text.as_bytes());
+    let headers = rdr.headers()?.clone();
+    let records: Vec<csv::StringRecord> = rdr.records().collect::<Result<_, _>>()?;
+    let col = |name: &str| headers.iter().position(|h| h == name);
+    let t_idx = col("t").ok_or("Input needs a t column")?;
+    let keys: Vec<usize> = KEY_COLUMNS.iter().filter_map(|k| col(k)).collect();
+
+    let metrics: Vec<usize> = match &args.columns {
+        Some(list) => list
+            .split(',')
+            .map(|c| {
+                col(c.trim()).ok_or_else(|| format!("No column '{}' in {}", c.trim(), args.input))
+            })
+            .collect::<Result<_, _>>()?,
+        None => (0..headers.len())
+            .filter(|i| *i != t_idx && !keys.contains(i))
+            .filter(|&i| {
+                records
+                    .iter()
+                    .all(|r| r[i].is_empty() || r[i].parse::<f64>().is_ok())
+            })
+            .collect(),
+    };
+    if metrics.is_empty() {
+        return Err("No numeric columns to smooth".into());
+    }
+
+    // Row indices of each series, in time order.
+    let mut series: BTreeMap<Vec<String>, Vec<usize>> = BTreeMap::new();
+    for (i, r) in records.iter().enumerate() {
+        series
+            .entry(keys.iter().map(|&k| r[k].to_string()).collect())
+            .or_default()
+            .push(i);
+    }
+    let t_of = |r: &csv::StringRecord| r[t_idx].parse::<f64>();
+    for rows in series.values_mut() {
+        let mut keyed: Vec<(f64, usize)> = rows
+            .iter()
+            .map(|&i| Ok((t_of(&records[i])?, i)))
+            .collect::<Result<_, std::num::ParseFloatError>>()?;
+        keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        *rows = keyed.into_iter().map(|(_, i)| i).collect();
+    }
+
+    let mut new_values: Vec<Vec<Option<String>>> = vec![vec![None; headers.len()]; records.len()];
+    let total = series.len();
+    for (n, rows) in series.values().enumerate() {
+        for &m in &metrics {
+            // Empty or non-finite cells are left out of the fit and kept as they are.
+            let points: Vec<(usize, f64, f64)> = rows
+                .iter()
+                .filter_map(|&i| {
+                    let v = records[i][m]
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|v| v.is_finite())?;
+                    Some((i, t_of(&records[i]).ok()?, v))
+                })
+                .collect();
+            if points.is_empty() {
+                continue;
+            }
+            let xy: Vec<(f64, f64)> = points.iter().map(|p| (p.1, p.2)).collect();
+            let smoothed = smooth_series(&xy, method, args.window);
+            for (&(i, _, v), s) in points.iter().zip(smoothed) {
+                let out = if args.detrend { v - s } else { s };
+                new_values[i][m] = Some(output::format_float(out));
+            }
+        }
+        progress(ProgressEvent::new(
+            "smooth",
+            (n + 1) as u64,
+            total as u64,
+            &format!("Smoothing series {}/{}", n + 1, total),
+        ));
+    }
+
+    fs::create_dir_all(Path::new(&args.output).parent().unwrap_or(Path::new(".")))?;
+    let mut file = std::io::BufWriter::new(fs::File::create(&args.output)?);
+    for line in &comments {
+        writeln!(file, "{}", line)?;
+    }
+    let mut writer = csv::Writer::from_writer(file);
+    writer.write_record(&headers)?;
+    for (r, values) in records.iter().zip(&new_values) {
+        let row: Vec<&str> = r
+            .iter()
+            .zip(values)
+            .map(|(old, new)| new.as_deref().unwrap_or(old))
+            .collect();
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    let table = comments
+        .iter()
+        .find_map(|l| l.strip_prefix("# schema: mupattern-"))
+        .and_then(|s| s.split('/').next())
+        .unwrap_or("table");
+    manifest::table(&args.output, table, records.len() as u64);
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} rows to {}",
+        records.len(),
+        args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn methods_follow_trends_and_reject_outliers() {
+        let line: Vec<(f64, f64)> = (0..7).map(|t| (t as f64, 2.0 * t as f64 + 1.0)).collect();
+        for method in [Method::Savgol { order: 2 }, Method::Lowess] {
+            for (s, p) in smooth_series(&line, method, 5).iter().zip(&line) {
+                assert!((s - p.1).abs() < 1e-9, "{:?}", method);
+            }
+        }
+        let spike = [(0.0, 1.0), (1.0, 1.0), (2.0, 50.0), (3.0, 1.0), (4.0, 1.0)];
+        assert_eq!(smooth_series(&spike, Method::Median, 3), vec![1.0; 5]);
+    }
+}
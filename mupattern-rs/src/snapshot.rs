@@ -0,0 +1,203 @@
+//! Snapshot: one frame of one crop as a PNG, rendered like a `movie` frame (same colormaps,
+//! contrast modes, channel panels, scale bar, annotations and flow arrows), for figure
+//! panels without encoding a movie and extracting a frame from it.
+
+use clap::Args;
+use std::fs;
+use std::path::Path;
+
+use crate::calibration::PixelCalibration;
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::crop_index;
+use crate::detector::DetectorRange;
+use crate::manifest;
+use crate::movie::{self, Layout, PanelStyle};
+use crate::progress::ProgressEvent;
+use crate::slices;
+use crate::zarr;
+
+#[derive(Args, Clone)]
+pub struct SnapshotArgs {
+    #[arg(long)]
+    pub input: String,
+    #[arg(long)]
+    pub pos: u32,
+    /// Crop ID as in the bbox CSV (with or without zero padding)
+    #[arg(long)]
+    pub crop: String,
+    /// Frame to render
+    #[arg(long)]
+    pub t: u64,
+    /// Channel(s) to render by index or name, e.g. "1", "0,1" or "Phase,GFP" (one panel
+    /// per channel)
+    #[arg(long, default_value = "0")]
+    pub channel: String,
+    /// Panel arrangement when several channels are rendered: hstack, vstack or grid
+    #[arg(long, default_value = "hstack")]
+    pub layout: String,
+    /// Output PNG
+    #[arg(long)]
+    pub output: String,
+    #[arg(long, default_value = "grayscale")]
+    pub colormap: String,
+    /// Intensity range: "per-movie" (min–max over the --time frames of the crop, so the
+    /// snapshot matches a movie rendered with the default contrast), "frame" (min–max of
+    /// this frame) or "detector" (the camera offset to full scale recorded by convert/crop)
+    #[arg(long, default_value = "per-movie")]
+    pub contrast: String,
+    /// Frames spanning the per-movie intensity range
+    #[arg(long, default_value = "all")]
+    pub time: String,
+    /// Draw a scale bar of this length in µm (needs pixel calibration in crops.zarr)
+    #[arg(long)]
+    pub scale_bar_um: Option<f64>,
+    /// CSV of annotations as for `movie --annotations` (columns: t, text, x, y, color)
+    #[arg(long)]
+    pub annotations: Option<String>,
+    /// Vector CSV from `flow --vectors`; shows the motion since the previous frame as arrows
+    #[arg(long)]
+    pub flow: Option<String>,
+    /// Arrow length per pixel of displacement
+    #[arg(long, default_value_t = 4.0)]
+    pub flow_scale: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Contrast {
+    PerMovie,
+    Frame,
+    Detector,
+}
+
+impl Contrast {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "per-movie" => Ok(Self::PerMovie),
+            "frame" => Ok(Self::Frame),
+            "detector" => Ok(Self::Detector),
+            _ => Err(
+                format!("Unknown contrast mode {s:?}. Use per-movie, frame or detector.").into(),
+            ),
+        }
+    }
+}
+
+pub fn run(
+    args: SnapshotArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let zarr_path = Path::new(&args.input);
+    let contrast = Contrast::parse(&args.contrast)?;
+    let layout = Layout::parse(&args.layout)?;
+    let store = zarr::open_store(zarr_path)?;
+    let names = ChannelNames::from_store(&store);
+    let channels = movie::parse_channels(&args.channel, &names)?;
+
+    let pos_id = format!("{:03}", args.pos);
+    let available = crop_index::list(zarr_path, &pos_id)?;
+    let crop_id = crop_index::find(&available, &args.crop)
+        .ok_or_else(|| format!("Crop {} not found in Pos{}", args.crop, args.pos))?;
+    let arr = zarr::open_array(&store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
+    let shape = arr.shape();
+    let (n_t, n_channels, h, w) = (shape[0], shape[1], shape[3], shape[4]);
+    if args.t >= n_t {
+        return Err(format!("Frame {} out of range (0-{})", args.t, n_t - 1).into());
+    }
+    if let Some(&c) = channels.iter().find(|&&c| c >= n_channels) {
+        return Err(format!("Channel {} out of range (0-{})", c, n_channels - 1).into());
+    }
+
+    let um_per_px = PixelCalibration::from_store(&store).um_per_px;
+    let scale_bar_px = match (args.scale_bar_um, um_per_px) {
+        (Some(um), Some(um_per_px)) => Some(((um / um_per_px).round() as u64).clamp(1, w)),
+        (Some(_), None) => {
+            return Err(
+                "--scale-bar-um needs a pixel calibration in crops.zarr (crop --pixel-size-um)"
+                    .into(),
+            )
+        }
+        _ => None,
+    };
+
+    let panels = movie::read_panels(&arr, args.t as usize, &channels)?;
+    let mut ranges = vec![(u16::MAX, u16::MIN); channels.len()];
+    movie::widen_ranges(&mut ranges, &panels);
+    match contrast {
+        Contrast::Frame => {}
+        Contrast::Detector => {
+            let bounds = DetectorRange::from_store(&store).require_bounds("--contrast detector")?;
+            ranges = vec![bounds; channels.len()];
+        }
+        Contrast::PerMovie => {
+            let time_indices = slices::parse_slice_string(&args.time, n_t as usize)?;
+            for (i, &t) in time_indices.iter().enumerate() {
+                cancel::check()?;
+                progress(ProgressEvent::new(
+                    "contrast",
+                    (i + 1) as u64,
+                    time_indices.len() as u64,
+                    &format!(
+                        "Intensity range: read {}/{} frames",
+                        i + 1,
+                        time_indices.len()
+                    ),
+                ));
+                movie::widen_ranges(&mut ranges, &movie::read_panels(&arr, t, &channels)?);
+            }
+        }
+    }
+
+    let annotations = match &args.annotations {
+        Some(path) => movie::load_annotations(path)?,
+        None => Vec::new(),
+    };
+    let flow_vectors = match &args.flow {
+        Some(path) => movie::load_flow(path)?,
+        None => Vec::new(),
+    };
+    let flow = movie::crop_flow(&flow_vectors, args.pos, crop_id);
+
+    let (cols, rows) = layout.grid(channels.len());
+    let (out_w, out_h) = (cols as u64 * w, rows as u64 * h);
+    let style = PanelStyle {
+        cols,
+        luts: movie::colormap_luts(&ranges, &args.colormap),
+        labels: &movie::panel_labels(&channels, &names),
+        scale_bar_px,
+        annotations: &annotations,
+        flow: &flow,
+        flow_scale: args.flow_scale,
+    };
+    let mut rgb = vec![0u8; (out_w * out_h * 3) as usize];
+    movie::draw_panels(&mut rgb, out_w, (w, h), &panels, args.t, &style);
+
+    let out_path = Path::new(&args.output);
+    if let Some(parent) = out_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    image::RgbImage::from_raw(out_w as u32, out_h as u32, rgb)
+        .ok_or("Snapshot buffer size mismatch")?
+        .save(out_path)?;
+    manifest::image(&args.output, out_w, out_h);
+    progress(ProgressEvent::done(&format!(
+        "Wrote Pos{} crop {} t={} to {}",
+        args.pos, crop_id, args.t, args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_cover_every_frame_per_panel() {
+        let mut ranges = vec![(u16::MAX, u16::MIN); 2];
+        movie::widen_ranges(&mut ranges, &[vec![5, 9], vec![100, 200]]);
+        movie::widen_ranges(&mut ranges, &[vec![2, 7], vec![150, 400]]);
+        assert_eq!(ranges, [(2, 9), (100, 400)]);
+        assert_eq!(Contrast::parse("frame").unwrap(), Contrast::Frame);
+        assert!(Contrast::parse("shared").is_err());
+    }
+}
@@ -1,71 +1,379 @@
 //! Spot detect: fluorescent spot detection in micropattern crops using spotiflow-rs.
-//! Output CSV: t,crop,spot,y,x (mirrors mupattern-py spot).
+//! Output CSV: t,crop,spot,y,x,y_um,x_um (mirrors mupattern-py spot; µm columns are
+//! empty when the store has no pixel calibration).
+//! With `--full-frame`, runs on uncropped Pos{N} TIFF folders in overlapping tiles;
+//! rows then use crop "full" and full-frame coordinates. Spots seen by two tiles within
+//! `--merge-radius` of each other are reported once.
+//! With `--masks` (masks.zarr from tissue), each spot gets the label of the cell it lies in
+//! (extra `cell` column, 0 = outside cells) and `--cell-counts` writes t,crop,cell,spots
+//! for every labelled cell, including cells without spots.
 
 use clap::Args;
 use spotiflow_rs::{PredictParams, SpotiflowSession};
-use std::fs;
-use std::io::Write;
+use std::collections::BTreeMap;
 use std::path::Path;
 
+use crate::calibration::PixelCalibration;
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::crop::{self, FrameData};
+use crate::crop_index;
+use crate::exclude::ExclusionList;
+use crate::output::{ColumnType, OutputFormat, Schema, TableWriter, Value};
+use crate::pixelmath;
+use crate::progress::ProgressEvent;
+use crate::provenance::Provenance;
 use crate::slices;
+use crate::stats;
 use crate::zarr;
 
+const SCHEMA: Schema = Schema {
+    table: "spot",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("spot", ColumnType::Integer),
+        ("y", ColumnType::Real),
+        ("x", ColumnType::Real),
+        ("y_um", ColumnType::Real),
+        ("x_um", ColumnType::Real),
+    ],
+};
+
+/// `SCHEMA` plus the containing cell label (`--masks`).
+const CELL_SCHEMA: Schema = Schema {
+    table: "spot_cells",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("spot", ColumnType::Integer),
+        ("y", ColumnType::Real),
+        ("x", ColumnType::Real),
+        ("y_um", ColumnType::Real),
+        ("x_um", ColumnType::Real),
+        ("cell", ColumnType::Integer),
+    ],
+};
+
+const COUNTS_SCHEMA: Schema = Schema {
+    table: "spot_counts",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("cell", ColumnType::Integer),
+        ("spots", ColumnType::Integer),
+    ],
+};
+
 #[derive(Args, Clone)]
 pub struct SpotArgs {
     #[arg(long, help = "Path to zarr store (e.g. crops.zarr)")]
     pub input: String,
     #[arg(long, help = "Position number")]
     pub pos: u32,
-    #[arg(long, help = "Channel number")]
-    pub channel: u32,
-    #[arg(long, help = "Output CSV file path")]
+    #[arg(long, help = "Channel index or name")]
+    pub channel: String,
+    #[arg(
+        long,
+        help = "Output path: CSV, SQLite when ending in .sqlite/.db, or Arrow IPC stream when ending in .arrows"
+    )]
     pub output: String,
+    #[arg(
+        long,
+        default_value = "auto",
+        help = "Table backend: auto (from the output extension) | csv | sqlite | arrow-stream"
+    )]
+    pub output_format: String,
     #[arg(
         long,
         default_value = "all",
         help = "Crops to process: \"all\" or comma-separated indices/slices, e.g. \"0:10:2, 15\""
     )]
     pub crop: String,
-    #[arg(long, help = "Path to spotiflow ONNX model dir (must contain model.onnx)")]
+    #[arg(
+        long,
+        help = "Path to spotiflow ONNX model dir (must contain model.onnx)"
+    )]
     pub model: String,
     #[arg(long, help = "Force CPU (skip CUDA)")]
     pub cpu: bool,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+    /// Frames to analyze: "all" or comma-separated indices/slices, e.g. "0:100", "every:5";
+    /// the others are skipped like --exclude frames
+    #[arg(long)]
+    pub time: String,
+    /// Input is a TIFF folder with Pos{N} subfolders (convert output) instead of crops.zarr
+    #[arg(long)]
+    pub full_frame: bool,
+    /// Tile edge in pixels for --full-frame inference (tiles overlap by TILE_OVERLAP)
+    #[arg(long, default_value_t = 1024)]
+    pub tile_size: usize,
+    /// Spots from neighbouring --full-frame tiles closer than this (px) are one spot; 0 disables merging
+    #[arg(long, default_value_t = 2.0)]
+    pub merge_radius: f32,
+    /// Distance used for --merge-radius: "euclidean" or "chebyshev" (max of |dy|, |dx|)
+    #[arg(long, default_value = "euclidean")]
+    pub nms_metric: String,
+    /// masks.zarr from tissue: assign each spot to the cell label under it
+    #[arg(long)]
+    pub masks: Option<String>,
+    /// Per-cell per-frame spot counts (t,crop,cell,spots): CSV, or SQLite when ending in .sqlite/.db. Needs --masks
+    #[arg(long)]
+    pub cell_counts: Option<String>,
 }
 
-pub fn run(
-    args: SpotArgs,
-    progress: impl Fn(f64, &str),
-) -> Result<(), Box<dyn std::error::Error>> {
-    let crops_zarr = Path::new(&args.input);
-    let pos_id = format!("{:03}", args.pos);
-    let crop_root = crops_zarr.join("pos").join(&pos_id).join("crop");
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NmsMetric {
+    Euclidean,
+    Chebyshev,
+}
+
+impl NmsMetric {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "euclidean" => Ok(Self::Euclidean),
+            "chebyshev" => Ok(Self::Chebyshev),
+            _ => Err(format!("Unknown NMS metric {s:?}. Use euclidean or chebyshev.").into()),
+        }
+    }
+
+    fn distance(self, a: (f32, f32), b: (f32, f32)) -> f32 {
+        let (dy, dx) = ((a.0 - b.0).abs(), (a.1 - b.1).abs());
+        match self {
+            Self::Euclidean => dy.hypot(dx),
+            Self::Chebyshev => dy.max(dx),
+        }
+    }
+}
+
+/// How seam duplicates are merged in --full-frame runs.
+#[derive(Clone, Copy)]
+struct SeamMerge {
+    radius: f32,
+    metric: NmsMetric,
+}
+
+/// A spot from one tile, in frame coordinates.
+struct TileSpot {
+    y: f32,
+    x: f32,
+    tile: usize,
+    /// Distance to the nearest inner tile edge; detections far from edges are trusted more.
+    edge_dist: f32,
+}
+
+/// Non-maximum suppression across tile seams: spots from different tiles within the merge
+/// radius are reported once, keeping the one furthest from its tile edge. Spots from the
+/// same tile are never merged. Output keeps the input order.
+fn suppress_seam_duplicates(spots: &[TileSpot], merge: SeamMerge) -> Vec<(f32, f32)> {
+    let mut order: Vec<usize> = (0..spots.len()).collect();
+    order.sort_by(|&a, &b| spots[b].edge_dist.total_cmp(&spots[a].edge_dist));
+    let mut kept: Vec<usize> = Vec::new();
+    for i in order {
+        let s = &spots[i];
+        let duplicate = kept.iter().any(|&k| {
+            let o = &spots[k];
+            o.tile != s.tile && merge.metric.distance((s.y, s.x), (o.y, o.x)) <= merge.radius
+        });
+        if !duplicate {
+            kept.push(i);
+        }
+    }
+    kept.sort_unstable();
+    kept.into_iter().map(|i| (spots[i].y, spots[i].x)).collect()
+}
+
+/// Overlap between neighbouring --full-frame tiles; each spot is kept by the tile whose
+/// core (the tile minus half the overlap on inner edges) contains it, up to seam merging.
+const TILE_OVERLAP: usize = 32;
+
+/// Crop ID used in rows from --full-frame runs.
+const FULL_FRAME_CROP: &str = "full";
+
+/// (t, crop, spot, y, x, cell label when --masks is given)
+type SpotRow = (u64, String, usize, f32, f32, Option<u32>);
+
+/// Label of the mask pixel containing (y, x), 0 outside the frame.
+fn cell_at(mask: &[u32], h: u64, w: u64, y: f32, x: f32) -> u32 {
+    let (row, col) = (y.floor(), x.floor());
+    if row < 0.0 || col < 0.0 || row as u64 >= h || col as u64 >= w {
+        return 0;
+    }
+    mask[(row as u64 * w + col as u64) as usize]
+}
+
+/// Spots per labelled cell of `mask`; cells without spots count 0, background is left out.
+fn count_spots_per_cell(mask: &[u32], labels: &[u32]) -> BTreeMap<u32, u64> {
+    let mut counts: BTreeMap<u32, u64> = mask.iter().filter(|&&l| l > 0).map(|&l| (l, 0)).collect();
+    for &label in labels.iter().filter(|&&l| l > 0) {
+        *counts.entry(label).or_default() += 1;
+    }
+    counts
+}
 
-    if !crop_root.exists() {
-        return Err("No crops found for position. Run crop task first.".into());
+/// Tile origins along one axis of length `n`: tiles of `tile` with `TILE_OVERLAP` overlap.
+fn tile_starts(n: usize, tile: usize) -> Vec<usize> {
+    if n <= tile {
+        return vec![0];
     }
+    let step = tile - TILE_OVERLAP;
+    let mut starts: Vec<usize> = (0..n - tile).step_by(step).collect();
+    starts.push(n - tile);
+    starts
+}
 
-    let mut all_crop_ids: Vec<String> = fs::read_dir(&crop_root)?
-        .filter_map(|e| {
-            let e = e.ok()?;
-            if e.file_type().ok()?.is_dir() {
-                e.file_name().to_str().map(String::from)
-            } else {
-                None
+/// Detect spots on a full frame tile by tile. Returns (y, x) in frame coordinates.
+/// Each tile contributes the spots in its core widened by the merge radius; the seam
+/// duplicates this produces are then suppressed.
+fn predict_tiled(
+    session: &mut SpotiflowSession,
+    img: &[f32],
+    h: usize,
+    w: usize,
+    tile: usize,
+    merge: SeamMerge,
+) -> Result<Vec<(f32, f32)>, Box<dyn std::error::Error>> {
+    let half = (TILE_OVERLAP / 2) as f32 - merge.radius;
+    let ys = tile_starts(h, tile);
+    let xs = tile_starts(w, tile);
+    let mut out = Vec::new();
+    let mut tile_idx = 0;
+    for &y0 in &ys {
+        let th = tile.min(h);
+        for &x0 in &xs {
+            let tw = tile.min(w);
+            let mut buf = Vec::with_capacity(th * tw);
+            for r in y0..y0 + th {
+                buf.extend_from_slice(&img[r * w + x0..r * w + x0 + tw]);
             }
-        })
-        .collect();
-    all_crop_ids.sort();
+            let params = PredictParams {
+                tile: None,
+                ..Default::default()
+            };
+            let (spots, _heatmaps, _flows) = session.predict(&buf, th, tw, params)?;
 
-    if all_crop_ids.is_empty() {
-        return Err("No crops found for position.".into());
+            let core_y = (
+                if y0 > 0 { half } else { 0.0 },
+                if y0 + th < h {
+                    th as f32 - half
+                } else {
+                    th as f32
+                },
+            );
+            let core_x = (
+                if x0 > 0 { half } else { 0.0 },
+                if x0 + tw < w {
+                    tw as f32 - half
+                } else {
+                    tw as f32
+                },
+            );
+            // (is an inner edge, position) for each side; image borders are not seams.
+            let edges_y = [(y0 > 0, 0.0), (y0 + th < h, th as f32)];
+            let edges_x = [(x0 > 0, 0.0), (x0 + tw < w, tw as f32)];
+            for (y, x) in spots {
+                if y >= core_y.0 && y < core_y.1 && x >= core_x.0 && x < core_x.1 {
+                    let edge_dist = edges_y
+                        .iter()
+                        .filter(|e| e.0)
+                        .map(|e| (y - e.1).abs())
+                        .chain(edges_x.iter().filter(|e| e.0).map(|e| (x - e.1).abs()))
+                        .fold(f32::INFINITY, f32::min);
+                    out.push(TileSpot {
+                        y: y + y0 as f32,
+                        x: x + x0 as f32,
+                        tile: tile_idx,
+                        edge_dist,
+                    });
+                }
+            }
+            tile_idx += 1;
+        }
     }
+    Ok(suppress_seam_duplicates(&out, merge))
+}
 
-    let crop_indices = slices::parse_slice_string(&args.crop, all_crop_ids.len())?;
-    let crop_ids: Vec<&String> = crop_indices
+fn run_full_frame(
+    args: &SpotArgs,
+    channel: u32,
+    session: &mut SpotiflowSession,
+    progress: &impl Fn(ProgressEvent),
+) -> Result<Vec<SpotRow>, Box<dyn std::error::Error>> {
+    if args.tile_size <= TILE_OVERLAP {
+        return Err(format!("--tile-size must be larger than {}", TILE_OVERLAP).into());
+    }
+    if !(0.0..=(TILE_OVERLAP / 2) as f32).contains(&args.merge_radius) {
+        return Err(format!("--merge-radius must be between 0 and {}", TILE_OVERLAP / 2).into());
+    }
+    let merge = SeamMerge {
+        radius: args.merge_radius,
+        metric: NmsMetric::parse(&args.nms_metric)?,
+    };
+    let pos_dir = Path::new(&args.input).join(format!("Pos{}", args.pos));
+    if !pos_dir.exists() {
+        return Err(format!("Position directory not found: {}", pos_dir.display()).into());
+    }
+    let index = crop::discover_tiffs(&pos_dir, args.pos, None)?;
+    let mut frames: Vec<(u32, &std::path::PathBuf)> = index
         .iter()
-        .map(|&i| &all_crop_ids[i])
+        .filter(|((c, _, z), _)| *c == channel && *z == 0)
+        .map(|((_, t, _), path)| (*t, path))
         .collect();
+    frames.sort();
+    if frames.is_empty() {
+        return Err(format!("No TIFFs for channel {} in {}", channel, pos_dir.display()).into());
+    }
+
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    let n_t = frames.iter().map(|(t, _)| *t as u64 + 1).max().unwrap_or(0);
+    let mut skip = exclusions.excluded_frames(args.pos, FULL_FRAME_CROP, n_t)?;
+    skip.extend(slices::frames_outside(&args.time, n_t)?);
+
+    let total = frames.len();
+    let mut rows = Vec::new();
+    for (i, (t, path)) in frames.into_iter().enumerate() {
+        let t = t as u64;
+        if skip.contains(&t) {
+            continue;
+        }
+        let (frame, width, height) = crop::read_tiff_frame(path)?;
+        let img: Vec<f32> = match frame {
+            FrameData::U16(v) => pixelmath::to_f32(&v),
+            FrameData::U8(v) => pixelmath::to_f32(&v),
+        };
+        let spots = predict_tiled(
+            session,
+            &img,
+            height as usize,
+            width as usize,
+            args.tile_size,
+            merge,
+        )?;
+        for (spot_idx, (y, x)) in spots.into_iter().enumerate() {
+            rows.push((t, FULL_FRAME_CROP.to_string(), spot_idx, y, x, None));
+        }
+        stats::add_frames(1);
 
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "detect",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Processing frame {}/{}", i + 1, total),
+        ));
+    }
+    Ok(rows)
+}
+
+pub fn run(
+    args: SpotArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
     let model_path = Path::new(&args.model).join("model.onnx");
     if !model_path.exists() {
         return Err(format!(
@@ -75,12 +383,68 @@ pub fn run(
         .into());
     }
 
-    progress(0.0, "Loading spotiflow model...");
+    if args.cell_counts.is_some() && args.masks.is_none() {
+        return Err("--cell-counts needs --masks".into());
+    }
+    let channel = ChannelNames::load(Path::new(&args.input))?.resolve(&args.channel)?;
+    let format = OutputFormat::parse(&args.output_format)?;
+
+    if args.full_frame {
+        if args.masks.is_some() {
+            return Err("--masks works on crops.zarr input, not with --full-frame".into());
+        }
+        stats::stage("load_model");
+        progress(ProgressEvent::new(
+            "load_model",
+            0,
+            1,
+            "Loading spotiflow model...",
+        ));
+        let mut session = SpotiflowSession::new(&model_path, args.cpu)?;
+
+        stats::stage("detect");
+        let calibration =
+            PixelCalibration::read_sidecar(Path::new(&args.input))?.unwrap_or_default();
+        let rows = run_full_frame(&args, channel, &mut session, &progress)?;
+        return write_rows(&args, &rows, &[], calibration, format, &progress);
+    }
+
+    let crops_zarr = Path::new(&args.input);
+    let pos_id = format!("{:03}", args.pos);
+
+    let all_crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+
+    if all_crop_ids.is_empty() {
+        return Err("No crops found for position. Run crop task first, or pass --full-frame for TIFF folders.".into());
+    }
+
+    let crop_indices = slices::parse_slice_string(&args.crop, all_crop_ids.len())?;
+    let crop_ids: Vec<&String> = crop_indices
+        .iter()
+        .map(|&i| &all_crop_ids[i])
+        .filter(|c| !exclusions.crop_excluded(args.pos, c))
+        .collect();
+
+    stats::stage("load_model");
+    progress(ProgressEvent::new(
+        "load_model",
+        0,
+        1,
+        "Loading spotiflow model...",
+    ));
     let mut session = SpotiflowSession::new(&model_path, args.cpu)?;
 
+    stats::stage("detect");
     let store = zarr::open_store(crops_zarr)?;
+    let calibration = PixelCalibration::from_store(&store);
+    let mask_store = match &args.masks {
+        Some(path) => Some(zarr::open_store(Path::new(path))?),
+        None => None,
+    };
     let total = crop_ids.len();
-    let mut rows: Vec<(u64, String, usize, f32, f32)> = Vec::new();
+    let mut rows: Vec<SpotRow> = Vec::new();
+    let mut counts: Vec<CountRow> = Vec::new();
 
     for (i, crop_id) in crop_ids.iter().enumerate() {
         let array_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
@@ -89,11 +453,29 @@ pub fn run(
         let n_t = shape[0];
         let h = shape[3];
         let w = shape[4];
+        let mut skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+        skip.extend(slices::frames_outside(&args.time, n_t)?);
+        skip.extend(crop::missing_times(&arr, channel as u64));
+        let mask_arr = match &mask_store {
+            Some(ms) => {
+                let mask_arr = zarr::open_array(ms, &array_path)
+                    .map_err(|e| format!("No masks for crop {} in --masks: {}", crop_id, e))?;
+                if mask_arr.shape() != [n_t, h, w] {
+                    return Err(
+                        format!("Masks for crop {} do not match the crop shape", crop_id).into(),
+                    );
+                }
+                Some(mask_arr)
+            }
+            None => None,
+        };
 
         for t in 0..n_t {
-            let chunk_indices = vec![t, args.channel as u64, 0, 0, 0];
-            let data = zarr::read_chunk_u16(&arr, &chunk_indices)?;
-            let img_f32: Vec<f32> = data.iter().map(|&v| v as f32).collect();
+            if skip.contains(&t) {
+                continue;
+            }
+            let data = zarr::read_frame_u16(&arr, &[t, channel as u64, 0])?;
+            let img_f32 = pixelmath::to_f32(&data);
 
             let params = PredictParams {
                 tile: None,
@@ -102,25 +484,161 @@ pub fn run(
             let (spots, _heatmaps, _flows) =
                 session.predict(&img_f32, h as usize, w as usize, params)?;
 
-            for (spot_idx, (y, x)) in spots.into_iter().enumerate() {
-                rows.push((t, crop_id.to_string(), spot_idx, y, x));
+            let Some(mask_arr) = &mask_arr else {
+                for (spot_idx, (y, x)) in spots.into_iter().enumerate() {
+                    rows.push((t, crop_id.to_string(), spot_idx, y, x, None));
+                }
+                stats::add_frames(1);
+                continue;
+            };
+            let mask = zarr::read_labels(mask_arr, t)?;
+            let labels: Vec<u32> = spots
+                .iter()
+                .map(|&(y, x)| cell_at(&mask, h, w, y, x))
+                .collect();
+            for (spot_idx, ((y, x), &cell)) in spots.into_iter().zip(&labels).enumerate() {
+                rows.push((t, crop_id.to_string(), spot_idx, y, x, Some(cell)));
             }
+            for (cell, n) in count_spots_per_cell(&mask, &labels) {
+                counts.push((t, crop_id.to_string(), cell, n));
+            }
+            stats::add_frames(1);
         }
 
-        progress(
-            (i + 1) as f64 / total as f64,
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "detect",
+            (i + 1) as u64,
+            total as u64,
             &format!("Processing crop {}/{}", i + 1, total),
-        );
+        ));
     }
 
-    let out_path = Path::new(&args.output);
-    fs::create_dir_all(out_path.parent().unwrap_or(Path::new(".")))?;
-    let mut fh = fs::File::create(out_path)?;
-    fh.write_all(b"t,crop,spot,y,x\n")?;
-    for (t, crop, spot, y, x) in &rows {
-        writeln!(fh, "{},{},{},{:.2},{:.2}", t, crop, spot, y, x)?;
+    write_rows(&args, &rows, &counts, calibration, format, &progress)
+}
+
+/// (t, crop, cell, spot count)
+type CountRow = (u64, String, u32, u64);
+
+fn write_rows(
+    args: &SpotArgs,
+    rows: &[SpotRow],
+    counts: &[CountRow],
+    calibration: PixelCalibration,
+    format: OutputFormat,
+    progress: &impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let provenance = Provenance::new("spot")
+        .model(&Path::new(&args.model).join("model.onnx"))?
+        .param("channel", args.channel.as_str())
+        .param("full_frame", args.full_frame)
+        .param("time", args.time.as_str())
+        .param("tile_size", args.tile_size)
+        .param("merge_radius", args.merge_radius)
+        .param("nms_metric", args.nms_metric.as_str())
+        .param("masks", args.masks.clone())
+        .calibration(&calibration);
+    let schema = if args.masks.is_some() {
+        &CELL_SCHEMA
+    } else {
+        &SCHEMA
+    };
+    let mut table =
+        TableWriter::create_with_provenance(&args.output, schema, args.pos, format, &provenance)?;
+    for (t, crop, spot, y, x, cell) in rows {
+        let mut row: Vec<Value> = vec![
+            (*t).into(),
+            crop.as_str().into(),
+            (*spot).into(),
+            (*y).into(),
+            (*x).into(),
+            calibration.length_um(*y as f64).into(),
+            calibration.length_um(*x as f64).into(),
+        ];
+        if let Some(cell) = cell {
+            row.push((*cell).into());
+        }
+        table.write_row(&row)?;
+    }
+    let n_rows = table.finish()?;
+    progress(ProgressEvent::done(&format!(
+        "Wrote {} rows to {}",
+        n_rows, args.output
+    )));
+
+    if let Some(path) = &args.cell_counts {
+        let mut table = TableWriter::create_with_provenance(
+            path,
+            &COUNTS_SCHEMA,
+            args.pos,
+            format,
+            &provenance,
+        )?;
+        for (t, crop, cell, n) in counts {
+            table.write_row(&[
+                (*t).into(),
+                crop.as_str().into(),
+                (*cell).into(),
+                (*n).into(),
+            ])?;
+        }
+        let n_rows = table.finish()?;
+        progress(ProgressEvent::done(&format!(
+            "Wrote {} cell counts to {}",
+            n_rows, path
+        )));
     }
-    progress(1.0, &format!("Wrote {} rows to {}", rows.len(), args.output));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spot(y: f32, x: f32, tile: usize, edge_dist: f32) -> TileSpot {
+        TileSpot {
+            y,
+            x,
+            tile,
+            edge_dist,
+        }
+    }
+
+    #[test]
+    fn seam_duplicates_keep_the_detection_furthest_from_its_edge() {
+        let spots = [
+            spot(10.0, 1000.0, 0, 9.0),
+            spot(10.5, 1000.5, 1, 15.0),
+            spot(10.0, 1003.0, 0, 8.0),
+            spot(50.0, 1000.0, 1, 20.0),
+        ];
+        let euclidean = SeamMerge {
+            radius: 1.2,
+            metric: NmsMetric::Euclidean,
+        };
+        assert_eq!(
+            suppress_seam_duplicates(&spots, euclidean),
+            vec![(10.5, 1000.5), (10.0, 1003.0), (50.0, 1000.0)]
+        );
+        let off = SeamMerge {
+            radius: 0.0,
+            metric: NmsMetric::Chebyshev,
+        };
+        assert_eq!(suppress_seam_duplicates(&spots, off).len(), 4);
+    }
+
+    #[test]
+    fn spots_are_counted_per_labelled_cell() {
+        let mask = [0, 1, 1, 0, 2, 2, 0, 0, 0];
+        let spots = [(0.5, 1.5), (1.2, 2.9), (2.5, 0.5), (5.0, 0.0), (0.1, 1.0)];
+        let labels: Vec<u32> = spots
+            .iter()
+            .map(|&(y, x)| cell_at(&mask, 3, 3, y, x))
+            .collect();
+        assert_eq!(labels, vec![1, 2, 0, 0, 1]);
+        let counts: Vec<(u32, u64)> = count_spots_per_cell(&mask, &labels).into_iter().collect();
+        assert_eq!(counts, vec![(1, 2), (2, 1)]);
+        assert_eq!(count_spots_per_cell(&mask, &[]).values().sum::<u64>(), 0);
+    }
+}
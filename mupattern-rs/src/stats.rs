@@ -0,0 +1,120 @@
+//! Run statistics: frame and byte counters plus per-stage timings, reported as one
+//! JSON summary line on the progress channel (stderr) when a command finishes.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static FRAMES: AtomicU64 = AtomicU64::new(0);
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+static STAGES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+static CURRENT: Mutex<Option<(&'static str, Instant)>> = Mutex::new(None);
+
+const MB: f64 = 1024.0 * 1024.0;
+
+pub fn add_frames(n: u64) {
+    FRAMES.fetch_add(n, Ordering::Relaxed);
+}
+
+pub fn add_bytes_read(n: u64) {
+    BYTES_READ.fetch_add(n, Ordering::Relaxed);
+}
+
+pub fn add_bytes_written(n: u64) {
+    BYTES_WRITTEN.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Start timing stage `name`, closing the previous one. Repeated names accumulate.
+pub fn stage(name: &'static str) {
+    let mut current = CURRENT.lock().unwrap();
+    close_stage(&mut current);
+    *current = Some((name, Instant::now()));
+}
+
+fn close_stage(current: &mut Option<(&'static str, Instant)>) {
+    if let Some((name, started)) = current.take() {
+        let mut stages = STAGES.lock().unwrap();
+        match stages.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, d)) => *d += started.elapsed(),
+            None => stages.push((name, started.elapsed())),
+        }
+    }
+}
+
+/// Weight of the newest sample in the smoothed throughput.
+const RATE_SMOOTHING: f64 = 0.1;
+
+/// Smoothed items-per-second rate, for ETAs in progress messages.
+pub struct Throughput {
+    last: Instant,
+    rate: Option<f64>,
+}
+
+impl Throughput {
+    pub fn new() -> Self {
+        Self {
+            last: Instant::now(),
+            rate: None,
+        }
+    }
+
+    /// Record `n` more finished items.
+    pub fn tick(&mut self, n: u64) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        if dt <= 0.0 {
+            return;
+        }
+        let sample = n as f64 / dt;
+        self.rate = Some(match self.rate {
+            Some(rate) => rate + RATE_SMOOTHING * (sample - rate),
+            None => sample,
+        });
+    }
+
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Estimated time for `remaining` items at the current rate.
+    pub fn eta(&self, remaining: u64) -> Option<Duration> {
+        let rate = self.rate.filter(|r| *r > 0.0)?;
+        Some(Duration::from_secs_f64(remaining as f64 / rate))
+    }
+}
+
+/// Compact duration for progress messages: "45s", "3m05s", "1h02m".
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+/// `{"summary": {...}}` for the finished run.
+pub fn summary(command: &str, elapsed: Duration) -> serde_json::Value {
+    close_stage(&mut CURRENT.lock().unwrap());
+    let frames = FRAMES.load(Ordering::Relaxed);
+    let secs = elapsed.as_secs_f64();
+    let stages: Vec<serde_json::Value> = STAGES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, d)| serde_json::json!({"stage": name, "duration_s": d.as_secs_f64()}))
+        .collect();
+    serde_json::json!({
+        "summary": {
+            "command": command,
+            "duration_s": secs,
+            "frames": frames,
+            "frames_per_s": if secs > 0.0 { frames as f64 / secs } else { 0.0 },
+            "mb_read": BYTES_READ.load(Ordering::Relaxed) as f64 / MB,
+            "mb_written": BYTES_WRITTEN.load(Ordering::Relaxed) as f64 / MB,
+            "stages": stages,
+        }
+    })
+}
@@ -0,0 +1,336 @@
+//! Survival: Kaplan-Meier curves from kill predictions. Each crop's kill time follows the
+//! desktop Kill tab: the last frame of the longest span from the first frame that is at
+//! least 80% "present" ends the cell's life, and crops present for a single frame (or
+//! never) count as unoccupied and are left out. A crop still present at its last frame is
+//! censored there; otherwise the kill is the next observed frame. Curves are written per
+//! position and pooled over all inputs.
+
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::exclude::ExclusionList;
+use crate::export_training;
+use crate::output::{ColumnType, Schema, TableWriter, Value};
+use crate::progress::ProgressEvent;
+use crate::stats;
+use crate::treatment;
+
+/// Fraction of a span's frames that must be "present" for the cell to count as alive.
+const CLEAN_THRESHOLD: f64 = 0.8;
+
+const SCHEMA: Schema = Schema {
+    table: "survival",
+    version: 1,
+    columns: &[
+        ("scope", ColumnType::Text),
+        ("t", ColumnType::Integer),
+        ("at_risk", ColumnType::Integer),
+        ("events", ColumnType::Integer),
+        ("censored", ColumnType::Integer),
+        ("survival", ColumnType::Real),
+        ("std_err", ColumnType::Real),
+    ],
+};
+
+const CROPS_SCHEMA: Schema = Schema {
+    table: "kill_time",
+    version: 1,
+    columns: &[
+        ("pos", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("t", ColumnType::Integer),
+        ("killed", ColumnType::Integer),
+    ],
+};
+
+#[derive(Args, Clone)]
+pub struct SurvivalArgs {
+    /// kill output CSV(s) (t,crop,label), repeat for more positions; the position comes from
+    /// a pos column or a Pos{N} file name
+    #[arg(long, required = true)]
+    pub input: Vec<String>,
+    /// Survival steps (scope,t,at_risk,events,censored,survival,std_err), scope being the
+    /// position or "all": CSV, or SQLite when ending in .sqlite/.db (stored under pos 0)
+    #[arg(long)]
+    pub output: String,
+    /// Per-crop kill times (pos,crop,t,killed; killed=0 means censored at t)
+    #[arg(long)]
+    pub crops: Option<String>,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+    /// Frame of drug addition: times are reported as t minus this frame
+    #[arg(long, conflicts_with = "treatments")]
+    pub treatment_frame: Option<u64>,
+    /// CSV with pos,treatment_frame rows (staggered pipetting); each position's times are
+    /// taken relative to its own treatment frame, so the pooled curve stays aligned
+    #[arg(long)]
+    pub treatments: Option<String>,
+}
+
+/// Kill time of one crop from its per-frame presence labels: `Some((t, true))` for a kill
+/// observed at `t`, `Some((t, false))` when still present at its last frame `t`, `None`
+/// when the pattern was never occupied.
+fn kill_time(frames: &BTreeMap<u64, bool>) -> Option<(u64, bool)> {
+    let (&t_min, _) = frames.first_key_value()?;
+    let (&t_max, _) = frames.last_key_value()?;
+    let present: Vec<u64> = frames.iter().filter(|(_, &p)| p).map(|(&t, _)| t).collect();
+    let first_present = *present.first()?;
+    let end = present
+        .iter()
+        .rev()
+        .copied()
+        .find(|&end| {
+            let span = frames.range(t_min..=end);
+            let n = span.clone().count();
+            let n_present = span.filter(|(_, &p)| p).count();
+            n_present as f64 / n as f64 >= CLEAN_THRESHOLD
+        })
+        .unwrap_or(first_present);
+    if end == t_min {
+        return None;
+    }
+    if end == t_max {
+        return Some((end, false));
+    }
+    let (&next, _) = frames.range(end + 1..).next()?;
+    Some((next, true))
+}
+
+/// One row of a Kaplan-Meier table.
+#[derive(Debug, PartialEq)]
+struct Step {
+    t: i64,
+    at_risk: usize,
+    events: usize,
+    censored: usize,
+    survival: f64,
+    /// Greenwood standard error of `survival`.
+    std_err: f64,
+}
+
+/// Kaplan-Meier estimate from (time, killed) pairs; the first step is at `start` with
+/// everyone at risk.
+fn kaplan_meier(times: &[(i64, bool)], start: i64) -> Vec<Step> {
+    let mut by_t: BTreeMap<i64, (usize, usize)> = BTreeMap::new();
+    for &(t, killed) in times {
+        let entry = by_t.entry(t).or_default();
+        if killed {
+            entry.0 += 1;
+        } else {
+            entry.1 += 1;
+        }
+    }
+    let mut steps = vec![Step {
+        t: start,
+        at_risk: times.len(),
+        events: 0,
+        censored: 0,
+        survival: 1.0,
+        std_err: 0.0,
+    }];
+    let (mut at_risk, mut survival, mut greenwood) = (times.len(), 1.0, 0.0);
+    for (t, (events, censored)) in by_t {
+        if events > 0 {
+            survival *= 1.0 - events as f64 / at_risk as f64;
+            if events < at_risk {
+                greenwood += events as f64 / (at_risk * (at_risk - events)) as f64;
+            }
+        }
+        let step = Step {
+            t,
+            at_risk,
+            events,
+            censored,
+            survival,
+            std_err: survival * greenwood.sqrt(),
+        };
+        if t == start {
+            steps[0] = step;
+        } else {
+            steps.push(step);
+        }
+        at_risk -= events + censored;
+    }
+    steps
+}
+
+/// Kill times of one scope (a position, or "all"), relative to treatment.
+struct Cohort {
+    scope: String,
+    times: Vec<(i64, bool)>,
+    /// Earliest observed frame.
+    start: i64,
+}
+
+/// Position of a kill CSV without a pos column, from a Pos{N} in its file name.
+fn pos_from_name(path: &str) -> Option<u32> {
+    let name = Path::new(path).file_name()?.to_str()?;
+    let digits: String = name
+        .split("Pos")
+        .nth(1)?
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Positions (from the pos column, or the file name) present in one kill CSV.
+fn positions(path: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let Some(pos_idx) = headers.iter().position(|h| h == "pos") else {
+        return pos_from_name(path).map(|p| vec![p]).ok_or_else(|| {
+            format!("{} has no pos column and no Pos{{N}} in its name", path).into()
+        });
+    };
+    let mut out = Vec::new();
+    for record in rdr.records() {
+        let pos: u32 = record?.get(pos_idx).unwrap_or("").parse()?;
+        if !out.contains(&pos) {
+            out.push(pos);
+        }
+    }
+    out.sort_unstable();
+    Ok(out)
+}
+
+pub fn run(
+    args: SurvivalArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    let mut inputs: Vec<(u32, &str)> = Vec::new();
+    for path in &args.input {
+        for pos in positions(path)? {
+            if inputs.iter().any(|(p, _)| *p == pos) {
+                return Err(format!("Position {} appears in more than one input", pos).into());
+            }
+            inputs.push((pos, path));
+        }
+    }
+    inputs.sort_unstable();
+
+    let mut crops = args
+        .crops
+        .as_deref()
+        .map(|path| TableWriter::create(path, &CROPS_SCHEMA, 0))
+        .transpose()?;
+
+    stats::stage("survival");
+    let total = inputs.len();
+    let mut cohorts: Vec<Cohort> = Vec::new();
+    for (i, &(pos, path)) in inputs.iter().enumerate() {
+        let shift =
+            treatment::treatment_frame(args.treatment_frame, args.treatments.as_deref(), pos)?
+                .unwrap_or(0);
+        let mut times = Vec::new();
+        let mut start = None;
+        for (crop_id, mut frames) in export_training::load_labels(path, pos)? {
+            if exclusions.crop_excluded(pos, &crop_id) {
+                continue;
+            }
+            let n_t = frames.keys().next_back().map_or(0, |t| t + 1);
+            let skip = exclusions.excluded_frames(pos, &crop_id, n_t)?;
+            frames.retain(|t, _| !skip.contains(t));
+            let Some(&t_min) = frames.keys().next() else {
+                continue;
+            };
+            start = Some(start.unwrap_or(t_min).min(t_min));
+            let Some((t, killed)) = kill_time(&frames) else {
+                continue;
+            };
+            if let Some(crops) = crops.as_mut() {
+                crops.write_row(&[
+                    Value::from(pos),
+                    crop_id.as_str().into(),
+                    treatment::relative(t, shift).into(),
+                    killed.into(),
+                ])?;
+            }
+            times.push((treatment::relative(t, shift), killed));
+        }
+        cohorts.push(Cohort {
+            scope: pos.to_string(),
+            times,
+            start: treatment::relative(start.unwrap_or(0), shift),
+        });
+        progress(ProgressEvent::new(
+            "survival",
+            (i + 1) as u64,
+            total as u64,
+            &format!("Processing position {}/{}", i + 1, total),
+        ));
+    }
+    let n_patterns: usize = cohorts.iter().map(|c| c.times.len()).sum();
+    if cohorts.len() > 1 {
+        let pooled = Cohort {
+            scope: "all".to_string(),
+            times: cohorts.iter().flat_map(|c| c.times.clone()).collect(),
+            start: cohorts.iter().map(|c| c.start).min().unwrap_or(0),
+        };
+        cohorts.push(pooled);
+    }
+
+    let mut table = TableWriter::create(&args.output, &SCHEMA, 0)?;
+    for cohort in &cohorts {
+        for step in kaplan_meier(&cohort.times, cohort.start) {
+            table.write_row(&[
+                Value::from(cohort.scope.as_str()),
+                step.t.into(),
+                step.at_risk.into(),
+                step.events.into(),
+                step.censored.into(),
+                step.survival.into(),
+                step.std_err.into(),
+            ])?;
+        }
+    }
+    table.finish()?;
+    if let Some(crops) = crops {
+        crops.finish()?;
+    }
+    progress(ProgressEvent::done(&format!(
+        "Wrote survival of {} occupied patterns to {}",
+        n_patterns, args.output
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kill_times_feed_kaplan_meier() {
+        let frames = |labels: &[bool]| -> BTreeMap<u64, bool> {
+            labels
+                .iter()
+                .enumerate()
+                .map(|(t, &p)| (t as u64, p))
+                .collect()
+        };
+        // Killed after frame 5 despite the flicker at 4; the false positive at 8 is ignored.
+        let killed = frames(&[
+            true, true, true, true, false, true, false, false, true, false,
+        ]);
+        assert_eq!(kill_time(&killed), Some((6, true)));
+        assert_eq!(kill_time(&frames(&[true, true, true])), Some((2, false)));
+        assert_eq!(kill_time(&frames(&[true, false, false])), None);
+        assert_eq!(kill_time(&frames(&[false, false])), None);
+
+        let steps = kaplan_meier(&[(2, true), (2, false), (4, true), (6, false)], 0);
+        let survival: Vec<(i64, usize, f64)> =
+            steps.iter().map(|s| (s.t, s.at_risk, s.survival)).collect();
+        assert_eq!(
+            survival,
+            vec![(0, 4, 1.0), (2, 4, 0.75), (4, 2, 0.375), (6, 1, 0.375)]
+        );
+        // Greenwood: 0.375 * sqrt(1/(4*3) + 1/(2*1)).
+        assert!((steps[2].std_err - 0.375 * (1.0f64 / 12.0 + 0.5).sqrt()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,116 @@
+//! Decoded TIFF frames kept for the rest of the run (`--tiff-cache-mb`), least recently
+//! used first and keyed by path. Passes that visit the same frame again (crop sizing the
+//! frame before extracting, `expression --whole-frame` reading totals after the scan, ...)
+//! then decode it once. Off by default.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::crop::FrameData;
+
+/// Budget of the cache in bytes; 0 disables it.
+static BUDGET_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Decoded pixels with width and height, as returned by `crop::read_tiff_frame`.
+type Frame = (FrameData, u32, u32);
+
+fn frame_bytes(frame: &Frame) -> u64 {
+    match &frame.0 {
+        FrameData::U16(v) => v.len() as u64 * 2,
+        FrameData::U8(v) => v.len() as u64,
+    }
+}
+
+struct FrameCache {
+    entries: VecDeque<(PathBuf, Frame)>,
+    bytes: u64,
+}
+
+impl FrameCache {
+    const fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    fn get(&mut self, path: &Path) -> Option<Frame> {
+        let pos = self.entries.iter().position(|(p, _)| p == path)?;
+        let entry = self.entries.remove(pos)?;
+        let frame = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(frame)
+    }
+
+    fn insert(&mut self, path: &Path, frame: Frame, budget: u64) {
+        let size = frame_bytes(&frame);
+        if size > budget {
+            return;
+        }
+        if let Some(pos) = self.entries.iter().position(|(p, _)| p == path) {
+            if let Some((_, old)) = self.entries.remove(pos) {
+                self.bytes -= frame_bytes(&old);
+            }
+        }
+        self.entries.push_back((path.to_path_buf(), frame));
+        self.bytes += size;
+        while self.bytes > budget {
+            let Some((_, old)) = self.entries.pop_front() else {
+                break;
+            };
+            self.bytes -= frame_bytes(&old);
+        }
+    }
+}
+
+static CACHE: Mutex<FrameCache> = Mutex::new(FrameCache::new());
+
+/// Set the cache budget (MB) once at startup.
+pub fn configure(cache_mb: u64) {
+    BUDGET_BYTES.store(cache_mb * 1024 * 1024, Ordering::Relaxed);
+}
+
+/// A copy of the cached decode of `path`, if any.
+pub(crate) fn get(path: &Path) -> Option<Frame> {
+    if BUDGET_BYTES.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    CACHE.lock().unwrap().get(path)
+}
+
+/// Keep a copy of the decode of `path` (a no-op while the cache is disabled).
+pub(crate) fn insert(path: &Path, data: &FrameData, width: u32, height: u32) {
+    let budget = BUDGET_BYTES.load(Ordering::Relaxed);
+    if budget == 0 {
+        return;
+    }
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(path, (data.clone(), width, height), budget);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_frames_are_evicted() {
+        let frame = |n: usize| (FrameData::U16(vec![0; n]), n as u32, 1);
+        let mut cache = FrameCache::new();
+        cache.insert(Path::new("a.tif"), frame(4), 24);
+        cache.insert(Path::new("b.tif"), frame(4), 24);
+        assert!(cache.get(Path::new("a.tif")).is_some());
+        cache.insert(Path::new("c.tif"), frame(4), 24);
+        assert_eq!(cache.bytes, 24);
+        assert!(cache.get(Path::new("b.tif")).is_some());
+        cache.insert(Path::new("d.tif"), frame(4), 24);
+        assert!(cache.get(Path::new("a.tif")).is_none());
+        assert!(cache.get(Path::new("c.tif")).is_some());
+        cache.insert(Path::new("big.tif"), frame(16), 24);
+        assert!(cache.get(Path::new("big.tif")).is_none());
+        assert_eq!(cache.bytes, 24);
+    }
+}
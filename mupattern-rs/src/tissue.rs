@@ -6,17 +6,93 @@
 //!     2. ONNX inference via cellpose-rs or cellsam-rs.
 //!     3. Post-process → integer mask.
 //!   Write masks to masks.zarr.
-//!   Then analyze: per-cell total_fluorescence, cell_area, background → CSV.
+//!   Then analyze: per-cell total_fluorescence, cell_area, background → CSV
+//!   plus cell_area_um2 (when crops.zarr carries a pixel calibration) and the
+//!   background-subtracted mean_intensity and the cell's saturated pixel count (at the
+//!   level recorded by `crop`), the raw mean and max fluorescence and
+//!   corrected_total = total_fluorescence - background * cell_area;
+//!   `--legacy-columns` drops all of these.
+//!   `--summary` adds per-crop per-frame totals over cells in the `expression` layout.
+//!   `--stage segment` or `--stage analyze` runs one half; analyze alone reads existing
+//!   masks, e.g. written by `import-masks`.
+//!   `--frame-batch N` (Cellpose) groups frames of equally sized crops and segments N of
+//!   them per call as one mosaic (`src/mosaic.rs`).
 
 use cellpose_rs::{CellposeSession, SegmentParams as CellposeParams};
 use cellsam_rs::{CellsamSession, SegmentParams as CellsamParams};
 use clap::Args;
-use std::fs;
-use std::io::Write;
+use std::collections::BTreeMap;
 use std::path::Path;
 
+use crate::calibration::PixelCalibration;
+use crate::cancel;
+use crate::channels::ChannelNames;
+use crate::crop;
+use crate::crop_index;
+use crate::exclude::ExclusionList;
+use crate::lock::WriteLock;
+use crate::manifest;
+use crate::mosaic::Mosaic;
+use crate::outline;
+use crate::output::{ColumnType, OutputFormat, Schema, TableWriter, Value};
+use crate::pixelmath;
+use crate::progress::ProgressEvent;
+use crate::provenance::Provenance;
+use crate::session::{self, Precision};
+use crate::stats;
 use crate::zarr;
 
+const SCHEMA: Schema = Schema {
+    table: "tissue",
+    version: 4,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("cell", ColumnType::Integer),
+        ("total_fluorescence", ColumnType::Real),
+        ("cell_area", ColumnType::Integer),
+        ("background", ColumnType::Integer),
+        ("cell_area_um2", ColumnType::Real),
+        ("mean_intensity", ColumnType::Real),
+        ("saturated_px", ColumnType::Integer),
+        ("mean_fluorescence", ColumnType::Real),
+        ("max_fluorescence", ColumnType::Integer),
+        ("corrected_total", ColumnType::Real),
+    ],
+};
+
+/// Per-crop per-frame totals over all cells (`--summary`). The leading columns match the
+/// `expression` table, so its plots and scripts read both.
+const SUMMARY_SCHEMA: Schema = Schema {
+    table: "tissue_summary",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("intensity", ColumnType::Integer),
+        ("area", ColumnType::Integer),
+        ("background", ColumnType::Integer),
+        ("saturated_px", ColumnType::Integer),
+        ("cells", ColumnType::Integer),
+        ("mean_cell_intensity", ColumnType::Real),
+        ("area_fraction", ColumnType::Real),
+    ],
+};
+
+/// Pixel-unit columns only, as written before calibrated columns were added (`--legacy-columns`).
+const LEGACY_SCHEMA: Schema = Schema {
+    table: "tissue_legacy",
+    version: 1,
+    columns: &[
+        ("t", ColumnType::Integer),
+        ("crop", ColumnType::Text),
+        ("cell", ColumnType::Integer),
+        ("total_fluorescence", ColumnType::Real),
+        ("cell_area", ColumnType::Integer),
+        ("background", ColumnType::Integer),
+    ],
+};
+
 // ---------------------------------------------------------------------------
 // CLI args
 // ---------------------------------------------------------------------------
@@ -29,30 +105,78 @@ pub struct TissueArgs {
     /// Position index
     #[arg(long)]
     pub pos: u32,
-    /// Channel index for phase contrast
+    /// Phase contrast channel: index or name
     #[arg(long)]
-    pub channel_phase: u32,
-    /// Channel index for fluorescence
+    pub channel_phase: String,
+    /// Fluorescence channel: index or name
     #[arg(long)]
-    pub channel_fluorescence: u32,
+    pub channel_fluorescence: String,
     /// Segment method: cellpose | cellsam
     #[arg(long, default_value = "cellpose")]
     pub method: String,
-    /// Path to model directory. Cellpose: model.onnx. CellSAM: image_encoder.onnx, cellfinder.onnx, mask_decoder.onnx, image_pe.npy
+    /// Path to model directory. Cellpose: model.onnx. CellSAM: image_encoder.onnx, cellfinder.onnx, mask_decoder.onnx, image_pe.npy. Not needed with --stage analyze
     #[arg(long)]
-    pub model: String,
-    /// Output CSV path (t,crop,cell,total_fluorescence,cell_area,background)
+    pub model: Option<String>,
+    /// Output path (t,crop,cell,total_fluorescence,cell_area,background,cell_area_um2,mean_intensity,saturated_px,mean_fluorescence,max_fluorescence,corrected_total): CSV, SQLite when ending in .sqlite/.db, or Arrow IPC stream when ending in .arrows
     #[arg(long)]
     pub output: String,
+    /// Table backend: auto (from the output extension) | csv | sqlite | arrow-stream (record
+    /// batches appended as results come in, readable while the run continues)
+    #[arg(long, default_value = "auto")]
+    pub output_format: String,
     /// Output masks zarr path (default: same dir as output / masks.zarr)
     #[arg(long)]
     pub masks: Option<String>,
     /// Batch size for ONNX inference (number of 256×256 tiles per forward pass)
     #[arg(long, default_value_t = 1)]
     pub batch_size: usize,
+    /// Cellpose: segment up to this many frames of equally sized crops per call, laid out
+    /// side by side with blank gaps, so crops share forward passes (1 = one call per frame).
+    /// Faster on GPU, but Cellpose tiles and normalises the mosaic as one image, so labels
+    /// can differ slightly from per-frame segmentation
+    #[arg(long, default_value_t = 1)]
+    pub frame_batch: usize,
     /// Force CPU (skip CUDA)
     #[arg(long)]
     pub cpu: bool,
+    /// Cellpose model precision: auto | fp32 (model.onnx) | fp16 (model_fp16.onnx) | int8 (model_quantized.onnx)
+    #[arg(long, default_value = "auto")]
+    pub precision: String,
+    /// Background per frame: auto (crops.zarr background array, else outside-masks) | store | outside-masks | crop-median
+    #[arg(long, default_value = "auto")]
+    pub background_mode: String,
+    /// Write only the pixel-unit columns (t,crop,cell,total_fluorescence,cell_area,background)
+    #[arg(long)]
+    pub legacy_columns: bool,
+    /// CSV of crops/frames to skip (columns: pos, crop, t; empty cell = all)
+    #[arg(long)]
+    pub exclude: Option<String>,
+    /// Write masks as uint16 like older releases (fails on frames with more than 65535 labels)
+    #[arg(long)]
+    pub u16_masks: bool,
+    /// Also write each frame's cell outlines (label boundaries) as a compact uint8 array at
+    /// /pos/{p}/outline/{crop} in the masks zarr, so `movie --masks` need not trace them
+    #[arg(long)]
+    pub outlines: bool,
+    /// Per-crop per-frame summary (t,crop,intensity,area,background,saturated_px,cells,mean_cell_intensity,area_fraction; leading columns as in `expression`): CSV, or SQLite when ending in .sqlite/.db
+    #[arg(long)]
+    pub summary: Option<String>,
+    /// all (segment then analyze) | segment (write masks only) | analyze (use existing masks, e.g. from import-masks)
+    #[arg(long, default_value = "all")]
+    pub stage: String,
+}
+
+impl TissueArgs {
+    /// Masks zarr path: `--masks` if given, else `masks.zarr` next to the CSV output.
+    pub fn masks_path(&self) -> std::path::PathBuf {
+        match &self.masks {
+            Some(p) => std::path::PathBuf::from(p),
+            None => {
+                let out = Path::new(&self.output);
+                out.parent().unwrap_or(Path::new(".")).join("masks.zarr")
+            }
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -76,15 +200,148 @@ fn median_u16(values: &[u16]) -> u16 {
     }
 }
 
+/// How `run_analyze` picks the per-frame background (`--background-mode`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BackgroundMode {
+    /// `store` when crops.zarr has a background array, else `outside-masks`.
+    Auto,
+    /// Per-frame value from `/pos/{p}/background` (written by `crop --background`).
+    Store,
+    /// Median of crop pixels outside every mask label.
+    OutsideMasks,
+    /// Median of the whole crop, cells included (the previous fallback).
+    CropMedian,
+}
+
+impl BackgroundMode {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "store" => Ok(Self::Store),
+            "outside-masks" => Ok(Self::OutsideMasks),
+            "crop-median" => Ok(Self::CropMedian),
+            _ => Err(format!(
+                "Unknown background mode {s:?}. Use auto, store, outside-masks or crop-median."
+            )
+            .into()),
+        }
+    }
+}
+
+/// Median of `values` where `masks` is 0; whole-crop median if every pixel is labelled.
+fn median_outside_masks(values: &[u16], masks: &[u32]) -> u16 {
+    let outside: Vec<u16> = values
+        .iter()
+        .zip(masks)
+        .filter(|(_, &m)| m == 0)
+        .map(|(&v, _)| v)
+        .collect();
+    if outside.is_empty() {
+        median_u16(values)
+    } else {
+        median_u16(&outside)
+    }
+}
+
+/// Per-label pixel sums, counts, saturated counts and maxima of one frame (index 0 unused).
+struct CellStats {
+    sums: Vec<f64>,
+    counts: Vec<u64>,
+    saturated: Vec<u64>,
+    maxima: Vec<u16>,
+    /// Level `saturated` was counted at; None leaves saturated_px empty.
+    saturation_level: Option<u16>,
+}
+
+impl CellStats {
+    fn measure(fluo: &[u16], masks: &[u32], max_label: u32, saturation_level: Option<u16>) -> Self {
+        let n = max_label as usize + 1;
+        let mut stats = Self {
+            sums: vec![0.0; n],
+            counts: vec![0; n],
+            saturated: vec![0; n],
+            maxima: vec![0; n],
+            saturation_level,
+        };
+        for (&v, &m) in fluo.iter().zip(masks) {
+            let lbl = m as usize;
+            if lbl > 0 {
+                stats.sums[lbl] += v as f64;
+                stats.counts[lbl] += 1;
+                stats.maxima[lbl] = stats.maxima[lbl].max(v);
+                if saturation_level.is_some_and(|l| v >= l) {
+                    stats.saturated[lbl] += 1;
+                }
+            }
+        }
+        stats
+    }
+
+    /// (mean_fluorescence, mean_intensity, corrected_total) of `lbl` against background `bg`.
+    fn metrics(&self, lbl: usize, bg: u16) -> (f64, f64, f64) {
+        let count = self.counts[lbl] as f64;
+        let mean_fluorescence = self.sums[lbl] / count;
+        (
+            mean_fluorescence,
+            mean_fluorescence - bg as f64,
+            self.sums[lbl] - bg as f64 * count,
+        )
+    }
+
+    /// The `SCHEMA` row of cell `lbl` at frame `t`; `LEGACY_SCHEMA` rows are its prefix.
+    fn cell_row(
+        &self,
+        t: usize,
+        crop_id: &str,
+        lbl: usize,
+        bg: u16,
+        calibration: &PixelCalibration,
+    ) -> Vec<Value> {
+        let (mean_fluorescence, mean_intensity, corrected_total) = self.metrics(lbl, bg);
+        vec![
+            t.into(),
+            crop_id.into(),
+            lbl.into(),
+            self.sums[lbl].into(),
+            self.counts[lbl].into(),
+            bg.into(),
+            calibration.area_um2(self.counts[lbl]).into(),
+            mean_intensity.into(),
+            self.saturation_level.map(|_| self.saturated[lbl]).into(),
+            mean_fluorescence.into(),
+            self.maxima[lbl].into(),
+            corrected_total.into(),
+        ]
+    }
+
+    /// The `SUMMARY_SCHEMA` row over all cells at frame `t` of a crop of `pixels` pixels.
+    fn summary_row(&self, t: usize, crop_id: &str, bg: u16, pixels: usize) -> Vec<Value> {
+        let cells = self.counts[1..].iter().filter(|&&n| n > 0).count();
+        let area: u64 = self.counts[1..].iter().sum();
+        let intensity: f64 = self.sums[1..].iter().sum();
+        let mean_cell_intensity = (cells > 0).then(|| intensity / cells as f64);
+        vec![
+            t.into(),
+            crop_id.into(),
+            (intensity as u64).into(),
+            area.into(),
+            bg.into(),
+            self.saturation_level
+                .map(|_| self.saturated[1..].iter().sum::<u64>())
+                .into(),
+            cells.into(),
+            mean_cell_intensity.into(),
+            (area as f64 / pixels as f64).into(),
+        ]
+    }
+}
+
 /// Build (3, H, W) CHW for CellSAM: [phase, fluo, phase], min-max normalised per channel.
 fn build_chw_cellsam(mut phase: Vec<f32>, mut fluo: Vec<f32>, h: usize, w: usize) -> Vec<f32> {
     cellsam_rs::preprocess::minmax_normalize(&mut phase);
     cellsam_rs::preprocess::minmax_normalize(&mut fluo);
-    let mut out = vec![0.0f32; 3 * h * w];
-    out[..h * w].copy_from_slice(&phase);
-    out[h * w..2 * h * w].copy_from_slice(&fluo);
-    out[2 * h * w..].copy_from_slice(&phase);
-    out
+    debug_assert_eq!(phase.len(), h * w);
+    pixelmath::pack_chw(&[&phase, &fluo, &phase])
 }
 
 /// Read a zarr crop as f32 for a given (t, channel): shape (H, W).
@@ -95,8 +352,8 @@ fn read_frame_f32(
     h: usize,
     w: usize,
 ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
-    let chunk = zarr::read_chunk_u16(crop_arr, &[t, channel, 0, 0, 0])?;
-    let out: Vec<f32> = chunk.iter().map(|&v| v as f32).collect();
+    let chunk = zarr::read_frame_u16(crop_arr, &[t, channel, 0])?;
+    let out = pixelmath::to_f32(&chunk);
     debug_assert_eq!(out.len(), h * w);
     Ok(out)
 }
@@ -105,7 +362,10 @@ fn read_frame_f32(
 // Zarr output helpers
 // ---------------------------------------------------------------------------
 
-fn ensure_mask_groups(store: &zarr::Store, pos_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub(crate) fn ensure_mask_groups(
+    store: &zarr::Store,
+    pos_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
     use std::sync::Arc;
     use zarrs::group::GroupBuilder;
     use zarrs::storage::ReadableWritableListableStorageTraits;
@@ -122,52 +382,104 @@ fn ensure_mask_groups(store: &zarr::Store, pos_id: &str) -> Result<(), Box<dyn s
     Ok(())
 }
 
+/// (T, H, W) label array for one crop: uint32, or uint16 with `--u16-masks`.
+pub(crate) fn create_mask_array(
+    store: &zarr::Store,
+    path: &str,
+    shape: &[u64],
+    u16_masks: bool,
+) -> Result<zarr::StoreArray, Box<dyn std::error::Error>> {
+    let mut attrs = serde_json::Map::new();
+    attrs.insert("axis_names".to_string(), serde_json::json!(["t", "y", "x"]));
+    let chunk_shape = vec![1, shape[1], shape[2]];
+    let shard_shape = zarr::shard_shape_t_first(shape);
+    if u16_masks {
+        zarr::create_array_u16(
+            store,
+            path,
+            shape.to_vec(),
+            chunk_shape,
+            shard_shape,
+            Some(attrs),
+        )
+    } else {
+        zarr::create_array_u32(
+            store,
+            path,
+            shape.to_vec(),
+            chunk_shape,
+            shard_shape,
+            Some(attrs),
+        )
+    }
+}
+
+/// Write the labels of frame `t`. u16 arrays reject labels that do not fit instead of
+/// wrapping them onto other cells.
+pub(crate) fn store_masks(
+    mask_arr: &zarr::StoreArray,
+    t: u64,
+    masks: Vec<u32>,
+    u16_masks: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !u16_masks {
+        return zarr::store_chunk_u32(mask_arr, &[t, 0, 0], &masks);
+    }
+    let narrowed: Vec<u16> = masks
+        .into_iter()
+        .map(u16::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Frame {t} has labels above 65535; drop --u16-masks"))?;
+    zarr::store_chunk_u16(mask_arr, &[t, 0, 0], &narrowed)
+}
+
 // ---------------------------------------------------------------------------
 // run_segment
 // ---------------------------------------------------------------------------
 
+/// Blank pixels between the frames of a `--frame-batch` mosaic, about one cell diameter,
+/// so cells of neighbouring crops are not merged.
+const MOSAIC_GAP: usize = 32;
+
 fn run_segment(
     args: &TissueArgs,
     masks_path: &Path,
-    progress: &impl Fn(f64, &str),
+    progress: &impl Fn(ProgressEvent),
 ) -> Result<(), Box<dyn std::error::Error>> {
     let crops_zarr = Path::new(&args.input);
     let pos_id = format!("{:03}", args.pos);
-    let crop_root = crops_zarr.join("pos").join(&pos_id).join("crop");
-
-    if !crop_root.exists() {
-        return Err("No crops found. Run crop task first.".into());
-    }
+    let names = ChannelNames::load(crops_zarr)?;
+    let channel_phase = names.resolve(&args.channel_phase)? as u64;
+    let channel_fluorescence = names.resolve(&args.channel_fluorescence)? as u64;
 
-    let mut crop_ids: Vec<String> = fs::read_dir(&crop_root)?
-        .filter_map(|e| {
-            let e = e.ok()?;
-            if e.file_type().ok()?.is_dir() {
-                e.file_name().to_str().map(String::from)
-            } else {
-                None
-            }
-        })
-        .collect();
-    crop_ids.sort();
+    let mut crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
 
     if crop_ids.is_empty() {
-        return Err("No crops found.".into());
+        return Err("No crops found. Run crop task first.".into());
     }
 
-    let model_dir = Path::new(&args.model);
+    let model_dir = Path::new(
+        args.model
+            .as_deref()
+            .ok_or("--model is required unless --stage analyze")?,
+    );
     let method = args.method.as_str();
+    let precision = Precision::parse(&args.precision)?;
 
+    let mut model_file = model_dir.join("model.onnx");
     if method == "cellpose" {
-        let model_file = model_dir.join("model.onnx");
-        if !model_file.exists() {
-            return Err(format!(
-                "Cellpose model not found at {}. Export with: python scripts/export_onnx.py",
-                model_file.display()
+        model_file = session::resolve_model(model_dir, precision).map_err(|e| {
+            format!(
+                "Cellpose model not found: {}. Export with: python scripts/export_onnx.py",
+                e
             )
-            .into());
-        }
+        })?;
     } else if method == "cellsam" {
+        if !matches!(precision, Precision::Auto | Precision::Fp32) {
+            return Err("--precision is only supported for cellpose models".into());
+        }
         for name in [
             "image_encoder.onnx",
             "cellfinder.onnx",
@@ -187,98 +499,155 @@ fn run_segment(
         return Err(format!("Unknown method {method:?}. Use 'cellpose' or 'cellsam'.").into());
     }
 
+    stats::stage("segment");
     let crop_store = zarr::open_store(crops_zarr)?;
+    let _lock = WriteLock::acquire(masks_path, "tissue")?;
     let mask_store = zarr::open_store(masks_path)?;
     ensure_mask_groups(&mask_store, &pos_id)?;
 
     let mut total_frames = 0u64;
     for crop_id in &crop_ids {
-        let arr = zarr::open_array(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
+        let arr =
+            zarr::open_array_cached(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
         total_frames += arr.shape()[0];
     }
     let n_crops = crop_ids.len();
+    if args.frame_batch == 0 {
+        return Err("--frame-batch must be at least 1".into());
+    }
 
     if method == "cellpose" {
-        let mut session = CellposeSession::new(&model_dir.join("model.onnx"), args.cpu)?;
+        let mut session = CellposeSession::new(&model_file, args.cpu)?;
+        // Pass 1: create the mask arrays and group the frames to segment by crop size.
         let mut done = 0u64;
+        let mut crops = Vec::with_capacity(n_crops);
+        let mut groups: BTreeMap<(usize, usize), Vec<(usize, u64)>> = BTreeMap::new();
         for (ci, crop_id) in crop_ids.iter().enumerate() {
-            let arr = zarr::open_array(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
+            let arr =
+                zarr::open_array_cached(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
             let shape = arr.shape();
-            let n_t = shape[0] as usize;
+            let n_t = shape[0];
             let h = shape[3] as usize;
             let w = shape[4] as usize;
+            let mut skip = exclusions.excluded_frames(args.pos, crop_id, n_t)?;
+            skip.extend(crop::missing_times(&arr, channel_phase));
+            skip.extend(crop::missing_times(&arr, channel_fluorescence));
 
             let mask_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
-            let mut attrs = serde_json::Map::new();
-            attrs.insert("axis_names".to_string(), serde_json::json!(["t", "y", "x"]));
-            let shape = vec![n_t as u64, h as u64, w as u64];
-            let mask_arr = zarr::create_array_u16(
-                &mask_store,
-                &mask_path,
-                shape.clone(),
-                vec![1, h as u64, w as u64],
-                zarr::shard_shape_t_first(&shape),
-                Some(attrs),
-            )?;
+            let shape = vec![n_t, h as u64, w as u64];
+            let mask_arr = create_mask_array(&mask_store, &mask_path, &shape, args.u16_masks)?;
+            manifest::array(masks_path, &mask_path, &shape);
+            let outline_arr = if args.outlines {
+                Some(outline::create_array(
+                    &mask_store,
+                    &pos_id,
+                    crop_id,
+                    &shape,
+                )?)
+            } else {
+                outline::remove(&mask_store, &pos_id, crop_id)?;
+                None
+            };
 
-            for t in 0..n_t {
-                let phase = read_frame_f32(&arr, t as u64, args.channel_phase as u64, h, w)?;
-                let fluo = read_frame_f32(&arr, t as u64, args.channel_fluorescence as u64, h, w)?;
-                let chw = cellpose_rs::preprocess::build_chw_image(phase, fluo, h, w);
+            done += skip.len() as u64;
+            let frames = groups.entry((h, w)).or_default();
+            frames.extend((0..n_t).filter(|t| !skip.contains(t)).map(|t| (ci, t)));
+            crops.push((arr, mask_arr, outline_arr));
+        }
+
+        // Pass 2: segment each size group in mosaics of up to --frame-batch frames.
+        for (&(h, w), frames) in &groups {
+            for batch in frames.chunks(args.frame_batch) {
+                let images = batch
+                    .iter()
+                    .map(|&(ci, t)| {
+                        let phase = read_frame_f32(&crops[ci].0, t, channel_phase, h, w)?;
+                        let fluo = read_frame_f32(&crops[ci].0, t, channel_fluorescence, h, w)?;
+                        Ok(cellpose_rs::preprocess::build_chw_image(phase, fluo, h, w))
+                    })
+                    .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+                let mosaic = Mosaic::new(batch.len(), h, w, MOSAIC_GAP);
                 let params = CellposeParams {
                     batch_size: args.batch_size,
                     ..Default::default()
                 };
-                let masks_u32 = session.segment(&chw, h, w, params)?;
-                let masks_u16: Vec<u16> = masks_u32.iter().map(|&v| v as u16).collect();
-                zarr::store_chunk_u16(&mask_arr, &[t as u64, 0, 0], &masks_u16)?;
-                done += 1;
-                progress(
-                    done as f64 / total_frames as f64 * 0.5,
+                let chw = mosaic.pack(&images);
+                let labels = session.segment(&chw, mosaic.height, mosaic.width, params)?;
+                for (&(ci, t), masks) in batch.iter().zip(mosaic.split(&labels)) {
+                    if let Some(outline_arr) = &crops[ci].2 {
+                        outline::store(outline_arr, t, &masks, w, h)?;
+                    }
+                    store_masks(&crops[ci].1, t, masks, args.u16_masks)?;
+                }
+                stats::add_frames(batch.len() as u64);
+                done += batch.len() as u64;
+                cancel::check()?;
+                progress(ProgressEvent::new(
+                    "segment",
+                    done,
+                    total_frames,
                     &format!(
-                        "Segment crop {}/{}, frame {}/{}",
-                        ci + 1,
-                        n_crops,
-                        t + 1,
-                        n_t
+                        "Segment frame {}/{} ({}x{} crops, {} per call)",
+                        done,
+                        total_frames,
+                        w,
+                        h,
+                        batch.len()
                     ),
-                );
+                ));
             }
         }
     } else if method == "cellsam" {
         let mut session = CellsamSession::new(model_dir, args.cpu)?;
         let mut done = 0u64;
         for (ci, crop_id) in crop_ids.iter().enumerate() {
-            let arr = zarr::open_array(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
+            let arr =
+                zarr::open_array_cached(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
             let shape = arr.shape();
             let n_t = shape[0] as usize;
             let h = shape[3] as usize;
             let w = shape[4] as usize;
+            let mut skip = exclusions.excluded_frames(args.pos, crop_id, n_t as u64)?;
+            skip.extend(crop::missing_times(&arr, channel_phase));
+            skip.extend(crop::missing_times(&arr, channel_fluorescence));
 
             let mask_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
-            let mut attrs = serde_json::Map::new();
-            attrs.insert("axis_names".to_string(), serde_json::json!(["t", "y", "x"]));
             let shape = vec![n_t as u64, h as u64, w as u64];
-            let mask_arr = zarr::create_array_u16(
-                &mask_store,
-                &mask_path,
-                shape.clone(),
-                vec![1, h as u64, w as u64],
-                zarr::shard_shape_t_first(&shape),
-                Some(attrs),
-            )?;
+            let mask_arr = create_mask_array(&mask_store, &mask_path, &shape, args.u16_masks)?;
+            manifest::array(masks_path, &mask_path, &shape);
+            let outline_arr = if args.outlines {
+                Some(outline::create_array(
+                    &mask_store,
+                    &pos_id,
+                    crop_id,
+                    &shape,
+                )?)
+            } else {
+                outline::remove(&mask_store, &pos_id, crop_id)?;
+                None
+            };
 
             for t in 0..n_t {
-                let phase = read_frame_f32(&arr, t as u64, args.channel_phase as u64, h, w)?;
-                let fluo = read_frame_f32(&arr, t as u64, args.channel_fluorescence as u64, h, w)?;
+                if skip.contains(&(t as u64)) {
+                    done += 1;
+                    continue;
+                }
+                let phase = read_frame_f32(&arr, t as u64, channel_phase, h, w)?;
+                let fluo = read_frame_f32(&arr, t as u64, channel_fluorescence, h, w)?;
                 let chw = build_chw_cellsam(phase, fluo, h, w);
                 let params = CellsamParams::default();
                 let masks_u32 = session.segment(&chw, h, w, params)?;
-                let masks_u16: Vec<u16> = masks_u32.iter().map(|&v| v as u16).collect();
-                zarr::store_chunk_u16(&mask_arr, &[t as u64, 0, 0], &masks_u16)?;
+                if let Some(outline_arr) = &outline_arr {
+                    outline::store(outline_arr, t as u64, &masks_u32, w, h)?;
+                }
+                store_masks(&mask_arr, t as u64, masks_u32, args.u16_masks)?;
+                stats::add_frames(1);
                 done += 1;
-                progress(
-                    done as f64 / total_frames as f64 * 0.5,
+                cancel::check()?;
+                progress(ProgressEvent::new(
+                    "segment",
+                    done,
+                    total_frames,
                     &format!(
                         "Segment crop {}/{}, frame {}/{}",
                         ci + 1,
@@ -286,7 +655,7 @@ fn run_segment(
                         t + 1,
                         n_t
                     ),
-                );
+                ));
             }
         }
     } else {
@@ -302,37 +671,40 @@ fn run_segment(
 fn run_analyze(
     args: &TissueArgs,
     masks_path: &Path,
-    progress: &impl Fn(f64, &str),
+    progress: &impl Fn(ProgressEvent),
 ) -> Result<(), Box<dyn std::error::Error>> {
     let crops_zarr = Path::new(&args.input);
     let pos_id = format!("{:03}", args.pos);
-    let crop_root = crops_zarr.join("pos").join(&pos_id).join("crop");
+    let channel_fluorescence =
+        ChannelNames::load(crops_zarr)?.resolve(&args.channel_fluorescence)? as u64;
 
-    let mut crop_ids: Vec<String> = fs::read_dir(&crop_root)?
-        .filter_map(|e| {
-            let e = e.ok()?;
-            if e.file_type().ok()?.is_dir() {
-                e.file_name().to_str().map(String::from)
-            } else {
-                None
-            }
-        })
-        .collect();
-    crop_ids.sort();
+    let mut crop_ids = crop_index::list(crops_zarr, &pos_id)?;
+    let exclusions = ExclusionList::load(args.exclude.as_deref())?;
+    crop_ids.retain(|c| !exclusions.crop_excluded(args.pos, c));
 
+    let background_mode = BackgroundMode::parse(&args.background_mode)?;
+    let format = OutputFormat::parse(&args.output_format)?;
+
+    stats::stage("analyze");
     let crop_store = zarr::open_store(crops_zarr)?;
     let mask_store = zarr::open_store(masks_path)?;
 
     // Load background array if present
     let bg_path = format!("/pos/{}/background", pos_id);
     let mut backgrounds: Vec<u16> = Vec::new();
-    if let Ok(bg_arr) = zarr::open_array(&crop_store, &bg_path) {
+    let bg_arr = match background_mode {
+        BackgroundMode::Auto | BackgroundMode::Store => {
+            zarr::open_array(&crop_store, &bg_path).ok()
+        }
+        BackgroundMode::OutsideMasks | BackgroundMode::CropMedian => None,
+    };
+    if let Some(bg_arr) = bg_arr {
         let sh = bg_arr.shape();
-        if sh.len() >= 2 && (args.channel_fluorescence as u64) < sh[1] {
+        if sh.len() >= 2 && channel_fluorescence < sh[1] {
             for t in 0..sh[0] {
-                let chunk_indices = [t, args.channel_fluorescence as u64, 0];
+                let frame_indices = [t, channel_fluorescence, 0];
                 backgrounds.push(
-                    zarr::read_chunk_u16(&bg_arr, &chunk_indices)
+                    zarr::read_frame_u16(&bg_arr, &frame_indices)
                         .ok()
                         .and_then(|d| d.first().copied())
                         .unwrap_or(0),
@@ -341,20 +713,54 @@ fn run_analyze(
         }
     }
 
-    let out_path = Path::new(&args.output);
-    let mut wtr = fs::File::create(out_path)?;
-    writeln!(wtr, "t,crop,cell,total_fluorescence,cell_area,background")?;
+    if background_mode == BackgroundMode::Store && backgrounds.is_empty() {
+        return Err(format!(
+            "--background-mode store: no background for channel {} at {} (run crop --background)",
+            args.channel_fluorescence, bg_path
+        )
+        .into());
+    }
+
+    let calibration = PixelCalibration::from_store(&crop_store);
+
+    // The segmentation model is known when this run also segmented (or --model is given).
+    let mut provenance = Provenance::new("tissue")
+        .param("method", args.method.as_str())
+        .param("frame_batch", args.frame_batch)
+        .param("channel_fluorescence", args.channel_fluorescence.as_str())
+        .param("background_mode", args.background_mode.as_str())
+        .param("masks", masks_path.display().to_string())
+        .calibration(&calibration);
+    if let Some(model) = &args.model {
+        provenance = provenance.model(Path::new(model))?;
+    }
+    let schema = if args.legacy_columns {
+        &LEGACY_SCHEMA
+    } else {
+        &SCHEMA
+    };
+    let create = |path: &str, schema: &Schema| {
+        TableWriter::create_with_provenance(path, schema, args.pos, format, &provenance)
+    };
+    let mut wtr = create(&args.output, schema)?;
+    let mut summary = args
+        .summary
+        .as_deref()
+        .map(|path| create(path, &SUMMARY_SCHEMA))
+        .transpose()?;
 
     let n_crops = crop_ids.len();
     let mut total_frames = 0u64;
     for crop_id in &crop_ids {
-        let arr = zarr::open_array(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
+        let arr =
+            zarr::open_array_cached(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
         total_frames += arr.shape()[0];
     }
     let mut done = 0u64;
 
     for (ci, crop_id) in crop_ids.iter().enumerate() {
-        let arr = zarr::open_array(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
+        let arr =
+            zarr::open_array_cached(&crop_store, &format!("/pos/{}/crop/{}", pos_id, crop_id))?;
         let shape = arr.shape();
         let n_t = shape[0] as usize;
         let h = shape[3] as usize;
@@ -362,17 +768,26 @@ fn run_analyze(
 
         let mask_arr_path = format!("/pos/{}/crop/{}", pos_id, crop_id);
         let mask_arr = zarr::open_array(&mask_store, &mask_arr_path)?;
+        let mut skip = exclusions.excluded_frames(args.pos, crop_id, n_t as u64)?;
+        skip.extend(crop::missing_times(&arr, channel_fluorescence));
+        let saturation_level = crop::saturation(&arr).map(|s| s.level(channel_fluorescence));
 
         for t in 0..n_t {
-            let fluo_raw =
-                zarr::read_chunk_u16(&arr, &[t as u64, args.channel_fluorescence as u64, 0, 0, 0])?;
-            let masks = zarr::read_chunk_u16(&mask_arr, &[t as u64, 0, 0])?;
+            if skip.contains(&(t as u64)) {
+                done += 1;
+                continue;
+            }
+            let fluo_raw = zarr::read_frame_u16(&arr, &[t as u64, channel_fluorescence, 0])?;
+            let masks = zarr::read_labels(&mask_arr, t as u64)?;
 
             let max_label = *masks.iter().max().unwrap_or(&0);
-            if max_label == 0 {
+            if max_label == 0 && summary.is_none() {
                 done += 1;
-                progress(
-                    0.5 + done as f64 / total_frames as f64 * 0.5,
+                cancel::check()?;
+                progress(ProgressEvent::new(
+                    "analyze",
+                    done,
+                    total_frames,
                     &format!(
                         "Analyze crop {}/{}, frame {}/{}",
                         ci + 1,
@@ -380,39 +795,34 @@ fn run_analyze(
                         t + 1,
                         n_t
                     ),
-                );
+                ));
                 continue;
             }
 
-            let mut sums = vec![0.0f64; max_label as usize + 1];
-            let mut counts = vec![0u64; max_label as usize + 1];
+            let cell_stats = CellStats::measure(&fluo_raw, &masks, max_label, saturation_level);
+            let bg_val = match backgrounds.get(t) {
+                Some(&v) => v,
+                None if background_mode == BackgroundMode::CropMedian => median_u16(&fluo_raw),
+                None => median_outside_masks(&fluo_raw, &masks),
+            };
 
-            for i in 0..h * w {
-                let lbl = masks[i] as usize;
-                if lbl > 0 {
-                    sums[lbl] += fluo_raw[i] as f64;
-                    counts[lbl] += 1;
+            for lbl in 1..=max_label as usize {
+                if cell_stats.counts[lbl] > 0 {
+                    let row = cell_stats.cell_row(t, crop_id, lbl, bg_val, &calibration);
+                    wtr.write_row(&row[..schema.columns.len()])?;
                 }
             }
 
-            let bg_val = backgrounds
-                .get(t)
-                .copied()
-                .unwrap_or_else(|| median_u16(&fluo_raw));
-
-            for lbl in 1..=max_label as usize {
-                if counts[lbl] > 0 {
-                    writeln!(
-                        wtr,
-                        "{},{},{},{},{},{}",
-                        t, crop_id, lbl, sums[lbl], counts[lbl], bg_val
-                    )?;
-                }
+            if let Some(summary) = &mut summary {
+                summary.write_row(&cell_stats.summary_row(t, crop_id, bg_val, h * w))?;
             }
 
             done += 1;
-            progress(
-                0.5 + done as f64 / total_frames as f64 * 0.5,
+            cancel::check()?;
+            progress(ProgressEvent::new(
+                "analyze",
+                done,
+                total_frames,
                 &format!(
                     "Analyze crop {}/{}, frame {}/{}",
                     ci + 1,
@@ -420,9 +830,13 @@ fn run_analyze(
                     t + 1,
                     n_t
                 ),
-            );
+            ));
         }
     }
+    wtr.finish()?;
+    if let Some(summary) = summary {
+        summary.finish()?;
+    }
     Ok(())
 }
 
@@ -430,21 +844,203 @@ fn run_analyze(
 // run (entry point)
 // ---------------------------------------------------------------------------
 
+/// Which half of the pipeline `run` executes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stage {
+    All,
+    Segment,
+    Analyze,
+}
+
+impl Stage {
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match s {
+            "all" => Ok(Self::All),
+            "segment" => Ok(Self::Segment),
+            "analyze" => Ok(Self::Analyze),
+            _ => Err(format!("Unknown stage {s:?}. Use all, segment or analyze.").into()),
+        }
+    }
+}
+
 pub fn run(
     args: TissueArgs,
-    progress: impl Fn(f64, &str),
+    progress: impl Fn(ProgressEvent),
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let masks_path = match &args.masks {
-        Some(p) => std::path::PathBuf::from(p),
-        None => {
-            let out = Path::new(&args.output);
-            out.parent().unwrap_or(Path::new(".")).join("masks.zarr")
-        }
-    };
+    let masks_path = args.masks_path();
+    let stage = Stage::parse(&args.stage)?;
 
-    run_segment(&args, &masks_path, &progress)?;
-    run_analyze(&args, &masks_path, &progress)?;
+    if stage != Stage::Analyze {
+        run_segment(&args, &masks_path, &progress)?;
+    }
+    if stage != Stage::Segment {
+        if !masks_path.exists() {
+            return Err(format!("No masks at {}", masks_path.display()).into());
+        }
+        run_analyze(&args, &masks_path, &progress)?;
+    }
 
-    progress(1.0, "Done");
+    progress(ProgressEvent::done("Done"));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_mode_parses_names() {
+        assert_eq!(BackgroundMode::parse("auto").unwrap(), BackgroundMode::Auto);
+        assert_eq!(
+            BackgroundMode::parse(" Store ").unwrap(),
+            BackgroundMode::Store
+        );
+        assert_eq!(
+            BackgroundMode::parse("outside-masks").unwrap(),
+            BackgroundMode::OutsideMasks
+        );
+        assert_eq!(
+            BackgroundMode::parse("crop-median").unwrap(),
+            BackgroundMode::CropMedian
+        );
+        assert!(BackgroundMode::parse("mean").is_err());
+    }
+
+    #[test]
+    fn median_outside_masks_skips_cells() {
+        let values = [10, 12, 500, 600, 14, 700];
+        let masks = [0, 0, 1, 1, 0, 2];
+        assert_eq!(median_outside_masks(&values, &masks), 12);
+    }
+
+    #[test]
+    fn median_outside_masks_empty_mask_is_crop_median() {
+        let values = [10, 30, 20, 40];
+        assert_eq!(median_outside_masks(&values, &[0; 4]), 25);
+        assert_eq!(median_outside_masks(&values, &[0; 4]), median_u16(&values));
+    }
+
+    #[test]
+    fn median_outside_masks_all_masked_falls_back_to_crop_median() {
+        let values = [100, 300, 200];
+        assert_eq!(median_outside_masks(&values, &[1, 2, 2]), 200);
+        assert_eq!(median_outside_masks(&[], &[]), 0);
+    }
+
+    #[test]
+    fn cell_stats_measure_per_label_columns() {
+        // Label 1 covers 10, 20, 30; label 2 covers 4095 (saturated) and 5; label 3 is unused.
+        let fluo = [10, 20, 30, 4095, 5, 7];
+        let masks = [1, 1, 1, 2, 2, 0];
+        let cells = CellStats::measure(&fluo, &masks, 3, Some(4095));
+        assert_eq!(cells.sums, [0.0, 60.0, 4100.0, 0.0]);
+        assert_eq!(cells.counts, [0, 3, 2, 0]);
+        assert_eq!(cells.maxima, [0, 30, 4095, 0]);
+        assert_eq!(cells.saturated, [0, 0, 1, 0]);
+
+        let (mean, mean_intensity, corrected_total) = cells.metrics(1, 5);
+        assert_eq!(mean, 20.0);
+        assert_eq!(mean_intensity, 15.0);
+        assert_eq!(corrected_total, 45.0);
+        let (mean, mean_intensity, corrected_total) = cells.metrics(2, 5);
+        assert_eq!(mean, 2050.0);
+        assert_eq!(mean_intensity, 2045.0);
+        assert_eq!(corrected_total, 4090.0);
+    }
+
+    #[test]
+    fn cell_rows_follow_the_schema() {
+        let cells = CellStats::measure(
+            &[10, 20, 30, 4095, 5, 7],
+            &[1, 1, 1, 2, 2, 0],
+            2,
+            Some(4095),
+        );
+        let calibration = PixelCalibration {
+            um_per_px: Some(0.5),
+            ..Default::default()
+        };
+        let row = cells.cell_row(4, "A01", 2, 5, &calibration);
+        assert_eq!(row.len(), SCHEMA.columns.len());
+        let column = |name: &str| {
+            let i = SCHEMA.columns.iter().position(|(c, _)| *c == name).unwrap();
+            row[i].clone()
+        };
+        assert_eq!(column("t"), Value::from(4u64));
+        assert_eq!(column("crop"), Value::from("A01"));
+        assert_eq!(column("cell"), Value::from(2u64));
+        assert_eq!(column("total_fluorescence"), Value::from(4100.0));
+        assert_eq!(column("cell_area"), Value::from(2u64));
+        assert_eq!(column("background"), Value::from(5u16));
+        assert_eq!(column("cell_area_um2"), Value::from(0.5));
+        assert_eq!(column("mean_intensity"), Value::from(2045.0));
+        assert_eq!(column("saturated_px"), Value::from(1u64));
+        assert_eq!(column("mean_fluorescence"), Value::from(2050.0));
+        assert_eq!(column("max_fluorescence"), Value::from(4095u16));
+        assert_eq!(column("corrected_total"), Value::from(4090.0));
+        // --legacy-columns writes the leading columns of the same row.
+        assert_eq!(
+            LEGACY_SCHEMA.columns,
+            &SCHEMA.columns[..LEGACY_SCHEMA.columns.len()]
+        );
+
+        // Uncalibrated stores and crops without a saturation level leave those cells empty.
+        let cells = CellStats::measure(&[10, 20], &[1, 1], 1, None);
+        let row = cells.cell_row(0, "A01", 1, 0, &PixelCalibration::default());
+        assert_eq!(row[6], Value::Null);
+        assert_eq!(row[8], Value::Null);
+    }
+
+    #[test]
+    fn summary_rows_total_the_cells() {
+        // Label 2 is absent from the frame, so two cells cover 5 of 8 pixels.
+        let cells = CellStats::measure(
+            &[10, 20, 30, 4095, 5, 7, 0, 1],
+            &[1, 1, 1, 3, 3, 0, 0, 0],
+            3,
+            Some(4095),
+        );
+        let row = cells.summary_row(2, "A01", 5, 8);
+        assert_eq!(row.len(), SUMMARY_SCHEMA.columns.len());
+        let expected: Vec<Value> = vec![
+            2u64.into(),
+            "A01".into(),
+            4160u64.into(),
+            5u64.into(),
+            5u16.into(),
+            1u64.into(),
+            2u64.into(),
+            2080.0.into(),
+            0.625.into(),
+        ];
+        assert_eq!(row, expected);
+        // The leading columns are those of the `expression` table.
+        let names: Vec<&str> = SUMMARY_SCHEMA.columns.iter().map(|(c, _)| *c).collect();
+        assert_eq!(
+            names[..6],
+            [
+                "t",
+                "crop",
+                "intensity",
+                "area",
+                "background",
+                "saturated_px"
+            ]
+        );
+
+        // A frame without cells still gets a row, with no mean.
+        let empty = CellStats::measure(&[3, 4], &[0, 0], 0, None).summary_row(0, "A01", 3, 2);
+        assert_eq!(
+            empty[2..6],
+            [0u64.into(), 0u64.into(), 3u16.into(), Value::Null]
+        );
+        assert_eq!(empty[6..], [0u64.into(), Value::Null, Value::from(0.0)]);
+    }
+
+    #[test]
+    fn cell_stats_without_saturation_level_count_nothing() {
+        let cells = CellStats::measure(&[u16::MAX, 1], &[1, 1], 1, None);
+        assert_eq!(cells.saturated, [0, 0]);
+        assert_eq!(cells.maxima, [0, u16::MAX]);
+    }
+}
@@ -0,0 +1,63 @@
+//! Treatment time of a position (`--treatment-frame` or a `--treatments` CSV with
+//! `pos,treatment_frame` rows for staggered pipetting). Outputs that report event times
+//! add a `t_treatment` column, `t` minus that frame, so times read relative to drug
+//! addition while `t` stays the frame index.
+
+use std::collections::HashMap;
+
+/// Treatment frame for `pos`: `frame` if given, else its row in the `csv` file.
+pub(crate) fn treatment_frame(
+    frame: Option<u64>,
+    csv: Option<&str>,
+    pos: u32,
+) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    let Some(path) = csv else {
+        return Ok(frame);
+    };
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let col = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| format!("Treatments {} need a {} column", path, name))
+    };
+    let (pos_idx, frame_idx) = (col("pos")?, col("treatment_frame")?);
+    let mut frames: HashMap<u32, u64> = HashMap::new();
+    for record in rdr.records() {
+        let record = record?;
+        let field = |i: usize| record.get(i).unwrap_or("");
+        frames.insert(field(pos_idx).parse()?, field(frame_idx).parse()?);
+    }
+    frames
+        .get(&pos)
+        .copied()
+        .map(Some)
+        .ok_or_else(|| format!("Treatments {} have no row for position {}", path, pos).into())
+}
+
+/// `t` relative to the treatment frame (negative before treatment).
+pub(crate) fn relative(t: u64, treatment_frame: u64) -> i64 {
+    t as i64 - treatment_frame as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_look_up_their_own_frame() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("treatments.csv");
+        std::fs::write(&path, "pos,treatment_frame\n0,12\n150,15\n").unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(treatment_frame(None, Some(path), 150).unwrap(), Some(15));
+        assert!(treatment_frame(None, Some(path), 3).is_err());
+        assert_eq!(treatment_frame(Some(4), None, 3).unwrap(), Some(4));
+        assert_eq!(treatment_frame(None, None, 3).unwrap(), None);
+        assert_eq!(relative(10, 12), -2);
+    }
+}
@@ -0,0 +1,123 @@
+//! Validate: check that a crops.zarr opens cleanly and, with `--checksums`, that every
+//! crop and background array still matches the checksum `crop --checksums` stored.
+
+use clap::Args;
+use std::fs;
+use std::path::Path;
+
+use crate::cancel;
+use crate::checksum::{self, Verification};
+use crate::console;
+use crate::crop_index;
+use crate::progress::ProgressEvent;
+use crate::zarr;
+
+#[derive(Args, Clone)]
+pub struct ValidateArgs {
+    /// Path to crops.zarr
+    #[arg(long)]
+    pub input: String,
+    /// Only validate this position (default: all positions)
+    #[arg(long)]
+    pub pos: Option<u32>,
+    /// Recompute array checksums and compare them with the stored ones
+    #[arg(long)]
+    pub checksums: bool,
+}
+
+fn subdirs(dir: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|e| {
+            let e = e.ok()?;
+            if e.file_type().ok()?.is_dir() {
+                e.file_name().to_str().map(String::from)
+            } else {
+                None
+            }
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Array paths to check for one position: every crop, then the background if present.
+fn position_arrays(root: &Path, pos_id: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let pos_dir = root.join("pos").join(pos_id);
+    let mut paths = Vec::new();
+    for crop_id in crop_index::list(root, pos_id)? {
+        paths.push(format!("/pos/{}/crop/{}", pos_id, crop_id));
+    }
+    if pos_dir.join("background").exists() {
+        paths.push(format!("/pos/{}/background", pos_id));
+    }
+    Ok(paths)
+}
+
+pub fn run(
+    args: ValidateArgs,
+    progress: impl Fn(ProgressEvent),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = Path::new(&args.input);
+    if !root.join("pos").exists() {
+        return Err(format!("{} has no pos group; not a crops.zarr?", root.display()).into());
+    }
+    let pos_ids = match args.pos {
+        Some(pos) => vec![format!("{:03}", pos)],
+        None => subdirs(&root.join("pos"))?,
+    };
+    let mut paths = Vec::new();
+    for pos_id in &pos_ids {
+        paths.extend(position_arrays(root, pos_id)?);
+    }
+    if paths.is_empty() {
+        return Err(format!("No arrays found in {}", root.display()).into());
+    }
+
+    let store = zarr::open_store(root)?;
+    let total = paths.len();
+    let mut problems: Vec<String> = Vec::new();
+    let mut unchecked = 0usize;
+    for (i, path) in paths.iter().enumerate() {
+        cancel::check()?;
+        progress(ProgressEvent::new(
+            "validate",
+            i as u64,
+            total as u64,
+            &format!("Validating {}/{}: {}", i + 1, total, path),
+        ));
+        if !args.checksums {
+            if let Err(e) = zarr::open_array(&store, path) {
+                problems.push(format!("{path}: {e}"));
+            }
+            continue;
+        }
+        match checksum::verify(&store, path) {
+            Ok(Verification::Match) => {}
+            Ok(Verification::Missing) => unchecked += 1,
+            Ok(Verification::Mismatch { expected, actual }) => {
+                problems.push(format!(
+                    "{path}: checksum mismatch (stored {expected}, computed {actual})"
+                ));
+            }
+            Err(e) => problems.push(format!("{path}: {e}")),
+        }
+    }
+
+    for problem in &problems {
+        console::warn(&format!("validate: {}", problem));
+    }
+    if unchecked > 0 {
+        console::warn(&format!(
+            "{} array(s) have no stored checksum (crop without --checksums)",
+            unchecked
+        ));
+    }
+    if !problems.is_empty() {
+        return Err(format!("{} of {} array(s) failed validation", problems.len(), total).into());
+    }
+    progress(ProgressEvent::done(&format!(
+        "Validated {} array(s)",
+        total
+    )));
+    Ok(())
+}
@@ -1,29 +1,156 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::ops::Deref;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 
 use zarrs::array::{
-    data_type, Array, ArrayBuilder, ArrayShardedExt, ArrayShardedReadableExt,
+    data_type, Array, ArrayBuilder, ArrayMetadata, ArrayShardedExt, ArrayShardedReadableExt,
     ArrayShardedReadableExtCache, ArraySubset, CodecOptions,
 };
 use zarrs::config::MetadataRetrieveVersion;
 use zarrs::filesystem::FilesystemStore;
-use zarrs::group::GroupBuilder;
+use zarrs::group::{Group, GroupBuilder};
 use zarrs::storage::ReadableWritableListableStorageTraits;
 
+use crate::lock;
+use crate::stats;
+
 pub type Store = Arc<FilesystemStore>;
 
 pub const SHARD_TIME_AXIS: u64 = 64;
 
+/// Open arrays kept per thread by `open_array_cached` (`--array-cache`).
+static ARRAY_CACHE_SIZE: AtomicUsize = AtomicUsize::new(256);
+/// Extra chunks along t fetched on a frame read miss (`--readahead`).
+static READAHEAD_CHUNKS: AtomicU64 = AtomicU64::new(0);
+/// Budget of the decoded chunk cache in bytes (`--chunk-cache-mb`).
+static CHUNK_CACHE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// (canonical store root, array path): the same files share cache entries whichever
+/// `Store` opened them, and a new store can never inherit another root's entries.
+type ArrayKey = (PathBuf, String);
+type ChunkKey = (ArrayKey, Vec<u64>);
+
+/// Decoded chunks shared by every thread and store (`--chunk-cache-mb`, off by default).
+/// Frame reads go through it so stages that touch the same chunk (movie overlays,
+/// kill scan then batches) decompress it once; single-pass commands gain nothing from it.
+struct ChunkCache {
+    /// Data and last-use tick per chunk.
+    entries: HashMap<ChunkKey, (u64, Arc<Vec<u16>>)>,
+    /// Keys by last-use tick, least recently used first.
+    order: BTreeMap<u64, ChunkKey>,
+    tick: u64,
+    bytes: u64,
+}
+
+impl ChunkCache {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+        }
+    }
+
+    fn get(&mut self, key: &ChunkKey) -> Option<Arc<Vec<u16>>> {
+        let (used, data) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        if let Some(key) = self.order.remove(used) {
+            self.order.insert(self.tick, key);
+        }
+        *used = self.tick;
+        Some(data.clone())
+    }
+
+    fn insert(&mut self, key: ChunkKey, data: Arc<Vec<u16>>, budget: u64) {
+        let size = entry_bytes(&key, &data);
+        if size > budget {
+            return;
+        }
+        self.remove(|k| *k == key);
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (self.tick, data));
+        self.bytes += size;
+        while self.bytes > budget {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((_, old)) = self.entries.remove(&oldest) {
+                self.bytes -= entry_bytes(&oldest, &old);
+            }
+        }
+    }
+
+    fn remove(&mut self, mut pred: impl FnMut(&ChunkKey) -> bool) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let (order, mut freed) = (&mut self.order, 0);
+        self.entries.retain(|k, (used, data)| {
+            let evict = pred(k);
+            if evict {
+                freed += entry_bytes(k, data);
+                order.remove(used);
+            }
+            !evict
+        });
+        self.bytes -= freed;
+    }
+}
+
+/// Bytes an entry holds: the decoded data plus its key, which both maps store.
+fn entry_bytes(key: &ChunkKey, data: &[u16]) -> u64 {
+    let ((root, path), indices) = key;
+    let key_bytes = std::mem::size_of::<ChunkKey>()
+        + root.as_os_str().len()
+        + path.len()
+        + indices.len() * std::mem::size_of::<u64>();
+    (data.len() * 2 + 2 * key_bytes) as u64
+}
+
+static CHUNK_CACHE: OnceLock<Mutex<ChunkCache>> = OnceLock::new();
+
+fn chunk_cache() -> &'static Mutex<ChunkCache> {
+    CHUNK_CACHE.get_or_init(|| Mutex::new(ChunkCache::new()))
+}
+
+/// Canonical root of every store from `open_store`, keyed by its address.
+static STORE_ROOTS: Mutex<Vec<(usize, PathBuf)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static ARRAY_CACHE: RefCell<VecDeque<(ArrayKey, Arc<StoreArray>)>> =
+        const { RefCell::new(VecDeque::new()) };
+}
+
+/// Set the array cache size, readahead depth and chunk cache budget (MB) once at startup.
+pub fn configure_cache(array_cache_size: usize, readahead_chunks: u64, chunk_cache_mb: u64) {
+    ARRAY_CACHE_SIZE.store(array_cache_size, Ordering::Relaxed);
+    READAHEAD_CHUNKS.store(readahead_chunks, Ordering::Relaxed);
+    CHUNK_CACHE_BYTES.store(chunk_cache_mb * 1024 * 1024, Ordering::Relaxed);
+}
+
 pub struct StoreArray {
     array: Array<dyn ReadableWritableListableStorageTraits>,
+    /// (store root, path) identity for the array and chunk caches.
+    key: ArrayKey,
     shard_cache: ArrayShardedReadableExtCache,
+    /// Chunks decoded by the last readahead, keyed by chunk indices.
+    window: Mutex<Vec<(Vec<u64>, Arc<Vec<u16>>)>>,
 }
 
 impl StoreArray {
-    fn new(array: Array<dyn ReadableWritableListableStorageTraits>) -> Self {
+    fn new(array: Array<dyn ReadableWritableListableStorageTraits>, key: ArrayKey) -> Self {
         let shard_cache = ArrayShardedReadableExtCache::new(&array);
-        Self { array, shard_cache }
+        Self {
+            array,
+            key,
+            shard_cache,
+            window: Mutex::new(Vec::new()),
+        }
     }
 
     fn chunk_subset(
@@ -44,16 +171,35 @@ impl Deref for StoreArray {
     }
 }
 
+/// Open the store at `root`; fails while another process holds its write lock (see `lock`).
 pub fn open_store(root: &Path) -> Result<Store, Box<dyn std::error::Error>> {
-    let store = FilesystemStore::new(root)?;
-    Ok(Arc::new(store))
+    lock::check_readable(root)?;
+    let store = Arc::new(FilesystemStore::new(root)?);
+    // Roots that do not exist yet cannot be canonicalized; make them absolute instead.
+    let canonical = root.canonicalize().unwrap_or_else(|_| {
+        std::env::current_dir().map_or_else(|_| root.to_path_buf(), |cwd| cwd.join(root))
+    });
+    let address = Arc::as_ptr(&store) as usize;
+    let mut roots = STORE_ROOTS.lock().unwrap();
+    // A dropped store's address can be reused; the newest store at it wins.
+    roots.retain(|(a, _)| *a != address);
+    roots.push((address, canonical));
+    Ok(store)
 }
 
 #[must_use]
 pub fn shard_shape_t_first(shape: &[u64]) -> Vec<u64> {
+    shard_shape_t_chunked(shape, 1)
+}
+
+/// Shard shape for arrays chunked `t_chunk` frames at a time: the time axis is
+/// capped at SHARD_TIME_AXIS and rounded up to a whole number of chunks.
+#[must_use]
+pub fn shard_shape_t_chunked(shape: &[u64], t_chunk: u64) -> Vec<u64> {
+    let t_chunk = t_chunk.max(1);
     let mut shard_shape = shape.to_vec();
     if let Some(t) = shard_shape.first_mut() {
-        *t = (*t).min(SHARD_TIME_AXIS);
+        *t = (*t).min(SHARD_TIME_AXIS).div_ceil(t_chunk) * t_chunk;
     }
     shard_shape
 }
@@ -62,7 +208,144 @@ pub fn shard_shape_t_first(shape: &[u64]) -> Vec<u64> {
 pub fn open_array(store: &Store, path: &str) -> Result<StoreArray, Box<dyn std::error::Error>> {
     let store_trait: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
     let array = Array::open_opt(store_trait, path, &MetadataRetrieveVersion::V3)?;
-    Ok(StoreArray::new(array))
+    Ok(StoreArray::new(array, array_key(store, path)))
+}
+
+/// Like `open_array`, but reuses handles from a per-thread LRU so commands that revisit
+/// the same crops (kill batches, tissue segment/analyze) parse metadata once per array.
+pub fn open_array_cached(
+    store: &Store,
+    path: &str,
+) -> Result<Arc<StoreArray>, Box<dyn std::error::Error>> {
+    let capacity = ARRAY_CACHE_SIZE.load(Ordering::Relaxed);
+    if capacity == 0 {
+        return Ok(Arc::new(open_array(store, path)?));
+    }
+    let key = array_key(store, path);
+    let hit = ARRAY_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let pos = cache.iter().position(|(k, _)| *k == key)?;
+        let entry = cache.remove(pos)?;
+        let array = entry.1.clone();
+        cache.push_back(entry);
+        Some(array)
+    });
+    if let Some(array) = hit {
+        return Ok(array);
+    }
+
+    let array = Arc::new(open_array(store, path)?);
+    ARRAY_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.push_back((key, array.clone()));
+        while cache.len() > capacity {
+            cache.pop_front();
+        }
+    });
+    Ok(array)
+}
+
+fn array_key(store: &Store, path: &str) -> ArrayKey {
+    let address = Arc::as_ptr(store) as usize;
+    let root = STORE_ROOTS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(a, _)| *a == address)
+        .map(|(_, root)| root.clone())
+        .unwrap_or_default();
+    (root, path.to_string())
+}
+
+/// Drop a cached handle and its cached chunks, e.g. after the array at `path` was recreated.
+fn invalidate_cached(store: &Store, path: &str) {
+    let key = array_key(store, path);
+    invalidate_chunks(&key);
+    ARRAY_CACHE.with(|cache| cache.borrow_mut().retain(|(k, _)| *k != key));
+}
+
+/// Drop cached chunks of one array after it was written.
+fn invalidate_chunks(key: &ArrayKey) {
+    if CHUNK_CACHE_BYTES.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    if let Ok(mut cache) = chunk_cache().lock() {
+        cache.remove(|(k, _)| k == key);
+    }
+}
+
+/// Read the chunk at `chunk_indices` plus up to `ahead` following chunks along t in one
+/// subset request. Returns (chunk indices, data) pairs in t order.
+fn read_chunk_window_u16(
+    array: &StoreArray,
+    chunk_indices: &[u64],
+    ahead: u64,
+) -> Result<Vec<(Vec<u64>, Arc<Vec<u16>>)>, Box<dyn std::error::Error>> {
+    let first = array.chunk_subset(chunk_indices)?;
+    let chunk_shape = first.shape().to_vec();
+    let t_chunk = chunk_shape[0];
+    let t_end = array.shape()[0];
+    let t_start = first.start()[0];
+    let t_stop = (t_start + (ahead + 1) * t_chunk).min(t_end);
+
+    let mut ranges: Vec<std::ops::Range<u64>> = first.to_ranges();
+    ranges[0] = t_start..t_stop;
+    let data = array.retrieve_array_subset::<Vec<u16>>(&ArraySubset::new_with_ranges(&ranges))?;
+    stats::add_bytes_read(data.len() as u64 * 2);
+
+    let chunk_len = chunk_shape.iter().product::<u64>() as usize;
+    let mut window = Vec::new();
+    for (k, block) in data.chunks(chunk_len).enumerate() {
+        let mut indices = chunk_indices.to_vec();
+        indices[0] += k as u64;
+        window.push((indices, Arc::new(block.to_vec())));
+    }
+    Ok(window)
+}
+
+/// Chunk for a frame read: served from the chunk cache or the readahead window when
+/// enabled, decoded chunks are added to the cache.
+fn read_chunk_for_frame(
+    array: &StoreArray,
+    chunk_indices: &[u64],
+) -> Result<Arc<Vec<u16>>, Box<dyn std::error::Error>> {
+    let budget = CHUNK_CACHE_BYTES.load(Ordering::Relaxed);
+    if budget == 0 {
+        return read_chunk_uncached(array, chunk_indices);
+    }
+    let key: ChunkKey = (array.key.clone(), chunk_indices.to_vec());
+    if let Some(data) = chunk_cache()
+        .lock()
+        .map_err(|_| "chunk cache poisoned")?
+        .get(&key)
+    {
+        return Ok(data);
+    }
+    let data = read_chunk_uncached(array, chunk_indices)?;
+    chunk_cache()
+        .lock()
+        .map_err(|_| "chunk cache poisoned")?
+        .insert(key, data.clone(), budget);
+    Ok(data)
+}
+
+fn read_chunk_uncached(
+    array: &StoreArray,
+    chunk_indices: &[u64],
+) -> Result<Arc<Vec<u16>>, Box<dyn std::error::Error>> {
+    let ahead = READAHEAD_CHUNKS.load(Ordering::Relaxed);
+    if ahead == 0 {
+        return Ok(Arc::new(read_chunk_u16(array, chunk_indices)?));
+    }
+    let mut window = array
+        .window
+        .lock()
+        .map_err(|_| "readahead window poisoned")?;
+    if let Some((_, data)) = window.iter().find(|(idx, _)| idx == chunk_indices) {
+        return Ok(data.clone());
+    }
+    *window = read_chunk_window_u16(array, chunk_indices, ahead)?;
+    Ok(window[0].1.clone())
 }
 
 pub fn read_chunk_u16(
@@ -74,17 +357,161 @@ pub fn read_chunk_u16(
         chunk_indices,
         &CodecOptions::default(),
     )?;
+    stats::add_bytes_read(data.len() as u64 * 2);
+    Ok(data)
+}
+
+/// Read one frame: fixed element indices on the leading axes (e.g. `[t, c, z]` for crops,
+/// `[t]` for masks), full extent on the remaining axes. Works for any chunk size along the
+/// leading axes, e.g. crops written with `crop --time-chunk N`. With `--readahead N` a miss
+/// also fetches the next N chunks along t.
+pub fn read_frame_u16(
+    array: &StoreArray,
+    leading: &[u64],
+) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
+    let ndim = array.dimensionality();
+    let chunk_shape = array.chunk_subset(&vec![0; ndim])?.shape().to_vec();
+    let shape = array.shape();
+    for axis in leading.len()..ndim {
+        if chunk_shape[axis] < shape[axis] {
+            return Err(format!("Frame axis {axis} spans multiple chunks").into());
+        }
+    }
+
+    let mut chunk_indices = vec![0u64; ndim];
+    let mut offset = 0u64;
+    for axis in 0..ndim {
+        let within = match leading.get(axis) {
+            Some(&idx) => {
+                chunk_indices[axis] = idx / chunk_shape[axis];
+                idx % chunk_shape[axis]
+            }
+            None => 0,
+        };
+        offset = offset * chunk_shape[axis] + within;
+    }
+    let frame_len = chunk_shape[leading.len()..].iter().product::<u64>() as usize;
+
+    let chunk = read_chunk_for_frame(array, &chunk_indices)?;
+    if chunk.len() == frame_len {
+        return Ok(Arc::unwrap_or_clone(chunk));
+    }
+    let offset = offset as usize;
+    Ok(chunk[offset..offset + frame_len].to_vec())
+}
+
+/// Write one frame addressed like `read_frame_u16`.
+pub fn store_frame_u16(
+    array: &StoreArray,
+    leading: &[u64],
+    data: &[u16],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let subset = ArraySubset::new_with_ranges(&frame_ranges(array, leading));
+    array.store_array_subset(&subset, data)?;
+    invalidate_chunks(&array.key);
+    stats::add_bytes_written(data.len() as u64 * 2);
+    Ok(())
+}
+
+/// Element ranges of the frame at `leading`: one index on the leading axes, full extent on
+/// the rest.
+fn frame_ranges(array: &StoreArray, leading: &[u64]) -> Vec<std::ops::Range<u64>> {
+    array
+        .shape()
+        .iter()
+        .enumerate()
+        .map(|(axis, &n)| match leading.get(axis) {
+            Some(&idx) => idx..idx + 1,
+            None => 0..n,
+        })
+        .collect()
+}
+
+/// Whether `array` stores uint32 elements (label masks written since masks became u32).
+pub fn is_u32(array: &StoreArray) -> bool {
+    matches!(array.metadata(), ArrayMetadata::V3(m) if m.data_type.name() == "uint32")
+}
+
+/// Read one uint32 frame addressed like `read_frame_u16`. Bypasses the chunk cache and
+/// readahead, which hold u16 chunks.
+pub fn read_frame_u32(
+    array: &StoreArray,
+    leading: &[u64],
+) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let subset = ArraySubset::new_with_ranges(&frame_ranges(array, leading));
+    let data = array.retrieve_array_subset::<Vec<u32>>(&subset)?;
+    stats::add_bytes_read(data.len() as u64 * 4);
+    Ok(data)
+}
+
+/// Read one uint8 frame addressed like `read_frame_u16` (outline arrays), bypassing the
+/// u16 chunk cache.
+pub fn read_frame_u8(
+    array: &StoreArray,
+    leading: &[u64],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let subset = ArraySubset::new_with_ranges(&frame_ranges(array, leading));
+    let data = array.retrieve_array_subset::<Vec<u8>>(&subset)?;
+    stats::add_bytes_read(data.len() as u64);
     Ok(data)
 }
 
+/// Label frame `t` of a masks.zarr array, widened to u32 for stores written with u16 masks.
+pub fn read_labels(array: &StoreArray, t: u64) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    if is_u32(array) {
+        read_frame_u32(array, &[t])
+    } else {
+        Ok(read_frame_u16(array, &[t])?
+            .into_iter()
+            .map(u32::from)
+            .collect())
+    }
+}
+
+/// Root group attributes, or an empty map if the root group does not exist yet.
+pub fn read_root_attrs(store: &Store) -> serde_json::Map<String, serde_json::Value> {
+    let store_trait: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
+    Group::open(store_trait, "/")
+        .map(|g| g.attributes().clone())
+        .unwrap_or_default()
+}
+
+/// Merge `attrs` into the root group attributes, creating the root group if needed.
+pub fn update_root_attrs(
+    store: &Store,
+    attrs: serde_json::Map<String, serde_json::Value>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut merged = read_root_attrs(store);
+    merged.extend(attrs);
+    let store_trait: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
+    let root = GroupBuilder::new()
+        .attributes(merged)
+        .build(store_trait, "/")?;
+    root.store_metadata()?;
+    Ok(())
+}
+
+/// Merge `attrs` into the attributes of an existing array.
+pub fn update_array_attrs(
+    store: &Store,
+    path: &str,
+    attrs: serde_json::Map<String, serde_json::Value>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store_trait: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
+    let mut array = Array::open_opt(store_trait, path, &MetadataRetrieveVersion::V3)?;
+    array.attributes_mut().extend(attrs);
+    array.store_metadata()?;
+    invalidate_cached(store, path);
+    Ok(())
+}
+
 /// Ensure v3 group hierarchy exists. Creates root, pos, pos/{pos_id}, pos/{pos_id}/crop.
+/// Existing root attrs are preserved.
 pub(crate) fn ensure_pos_crop_groups(
     store: &Store,
     pos_id: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let store_trait: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
-    let root = GroupBuilder::new().build(store_trait.clone(), "/")?;
-    root.store_metadata()?;
+    update_root_attrs(store, serde_json::Map::new())?;
 
     let store_trait: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
     let pos = GroupBuilder::new().build(store_trait.clone(), "/pos")?;
@@ -116,7 +543,50 @@ pub fn create_array_u16(
     }
     let array = builder.build(store_trait, path)?;
     array.store_metadata()?;
-    Ok(StoreArray::new(array))
+    invalidate_cached(store, path);
+    Ok(StoreArray::new(array, array_key(store, path)))
+}
+
+/// Like `create_array_u16` for uint32 arrays (label masks).
+pub fn create_array_u32(
+    store: &Store,
+    path: &str,
+    shape: Vec<u64>,
+    chunk_shape: Vec<u64>,
+    shard_shape: Vec<u64>,
+    attrs: Option<serde_json::Map<String, serde_json::Value>>,
+) -> Result<StoreArray, Box<dyn std::error::Error>> {
+    let store_trait: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
+    let mut builder = ArrayBuilder::new(shape, shard_shape, data_type::uint32(), 0u32);
+    builder.subchunk_shape(chunk_shape);
+    if let Some(a) = attrs {
+        builder.attributes(a);
+    }
+    let array = builder.build(store_trait, path)?;
+    array.store_metadata()?;
+    invalidate_cached(store, path);
+    Ok(StoreArray::new(array, array_key(store, path)))
+}
+
+/// Like `create_array_u16` for uint8 arrays (mask outlines).
+pub fn create_array_u8(
+    store: &Store,
+    path: &str,
+    shape: Vec<u64>,
+    chunk_shape: Vec<u64>,
+    shard_shape: Vec<u64>,
+    attrs: Option<serde_json::Map<String, serde_json::Value>>,
+) -> Result<StoreArray, Box<dyn std::error::Error>> {
+    let store_trait: Arc<dyn ReadableWritableListableStorageTraits> = store.clone();
+    let mut builder = ArrayBuilder::new(shape, shard_shape, data_type::uint8(), 0u8);
+    builder.subchunk_shape(chunk_shape);
+    if let Some(a) = attrs {
+        builder.attributes(a);
+    }
+    let array = builder.build(store_trait, path)?;
+    array.store_metadata()?;
+    invalidate_cached(store, path);
+    Ok(StoreArray::new(array, array_key(store, path)))
 }
 
 pub fn store_chunk_u16(
@@ -126,6 +596,32 @@ pub fn store_chunk_u16(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let subset = array.chunk_subset(chunk_indices)?;
     array.store_array_subset(&subset, data)?;
+    invalidate_chunks(&array.key);
+    stats::add_bytes_written(data.len() as u64 * 2);
+    Ok(())
+}
+
+pub fn store_chunk_u32(
+    array: &StoreArray,
+    chunk_indices: &[u64],
+    data: &[u32],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let subset = array.chunk_subset(chunk_indices)?;
+    array.store_array_subset(&subset, data)?;
+    invalidate_chunks(&array.key);
+    stats::add_bytes_written(data.len() as u64 * 4);
+    Ok(())
+}
+
+pub fn store_chunk_u8(
+    array: &StoreArray,
+    chunk_indices: &[u64],
+    data: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let subset = array.chunk_subset(chunk_indices)?;
+    array.store_array_subset(&subset, data)?;
+    invalidate_chunks(&array.key);
+    stats::add_bytes_written(data.len() as u64);
     Ok(())
 }
 
@@ -297,6 +793,132 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn frame_helpers_round_trip_with_multi_frame_time_chunks(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new()?;
+        let store = open_store(dir.path())?;
+        let shape = vec![10, 2, 1, 4, 5];
+        let crop = create_array_u16(
+            &store,
+            "/crop",
+            shape.clone(),
+            vec![4, 1, 1, 4, 5],
+            shard_shape_t_chunked(&shape, 4),
+            None,
+        )?;
+        assert_eq!(shard_shape_t_chunked(&shape, 4), vec![12, 2, 1, 4, 5]);
+
+        for t in 0..10u64 {
+            for c in 0..2u64 {
+                let data = sample_data(4 * 5, (t * 100 + c * 10) as u16);
+                store_frame_u16(&crop, &[t, c, 0], &data)?;
+            }
+        }
+        for t in 0..10u64 {
+            for c in 0..2u64 {
+                assert_eq!(
+                    read_frame_u16(&crop, &[t, c, 0])?,
+                    sample_data(4 * 5, (t * 100 + c * 10) as u16)
+                );
+            }
+        }
+
+        let background =
+            create_test_array(dir.path(), "background", vec![10, 2, 1], vec![1, 1, 1])?;
+        store_frame_u16(&background, &[3, 1, 0], &[77])?;
+        assert_eq!(read_frame_u16(&background, &[3, 1, 0])?, vec![77]);
+
+        let shape = vec![10, 4, 5];
+        let mask = create_array_u32(
+            &store,
+            "/mask",
+            shape.clone(),
+            vec![1, 4, 5],
+            shard_shape_t_first(&shape),
+            None,
+        )?;
+        let labels: Vec<u32> = (0..20).map(|i| 70_000 + i).collect();
+        store_chunk_u32(&mask, &[6, 0, 0], &labels)?;
+        assert!(is_u32(&mask) && !is_u32(&crop));
+        assert_eq!(read_labels(&mask, 6)?, labels);
+        let legacy = create_test_array(dir.path(), "legacy_mask", vec![10, 4, 5], vec![1, 4, 5])?;
+        store_frame_u16(&legacy, &[2], &sample_data(20, 1))?;
+        assert_eq!(read_labels(&legacy, 2)?, (1..21).collect::<Vec<u32>>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn readahead_window_and_array_cache_match_direct_reads(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new()?;
+        let store = open_store(dir.path())?;
+        let shape = vec![5, 1, 1, 2, 3];
+        let crop = create_array_u16(
+            &store,
+            "/crop",
+            shape.clone(),
+            vec![2, 1, 1, 2, 3],
+            shard_shape_t_chunked(&shape, 2),
+            None,
+        )?;
+        for t in 0..5u64 {
+            store_frame_u16(&crop, &[t, 0, 0], &sample_data(6, t as u16 * 10))?;
+        }
+
+        let window = read_chunk_window_u16(&crop, &[1, 0, 0, 0, 0], 4)?;
+        assert_eq!(window.len(), 2);
+        assert_eq!(window[0].0, vec![1, 0, 0, 0, 0]);
+        assert_eq!(*window[0].1, read_chunk_u16(&crop, &[1, 0, 0, 0, 0])?);
+        assert_eq!(window[1].0, vec![2, 0, 0, 0, 0]);
+        assert_eq!(window[1].1[..6], sample_data(6, 40)[..]);
+
+        let first = open_array_cached(&store, "/crop")?;
+        let second = open_array_cached(&store, "/crop")?;
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(read_frame_u16(&second, &[3, 0, 0])?, sample_data(6, 30));
+
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_cache_evicts_least_recently_used_within_budget() {
+        let array: ArrayKey = (PathBuf::from("/a"), "/crop".to_string());
+        let key = |t: u64| -> ChunkKey { (array.clone(), vec![t, 0, 0, 0, 0]) };
+        let chunk = |v: u16| Arc::new(vec![v; 4]);
+        // Every entry has the same size, keys included; the budget holds two.
+        let size = entry_bytes(&key(0), &chunk(0));
+        assert!(size > 8);
+        let mut cache = ChunkCache::new();
+        cache.insert(key(0), chunk(0), 2 * size);
+        cache.insert(key(1), chunk(1), 2 * size);
+        assert_eq!(cache.get(&key(0)).as_deref(), Some(&vec![0; 4]));
+        cache.insert(key(2), chunk(2), 2 * size);
+        assert!(cache.get(&key(1)).is_none());
+        assert!(cache.get(&key(0)).is_some() && cache.get(&key(2)).is_some());
+        assert_eq!(cache.bytes, 2 * size);
+        assert_eq!(cache.order.len(), 2);
+
+        cache.remove(|(array, _)| array.1 == "/crop");
+        assert_eq!(
+            (cache.entries.len(), cache.order.len(), cache.bytes),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn cache_keys_follow_the_store_root() -> Result<(), Box<dyn std::error::Error>> {
+        let (a, b) = (TempDir::new()?, TempDir::new()?);
+        let first = open_store(a.path())?;
+        let key = array_key(&first, "/crop");
+        drop(first);
+        // A store on another root never shares keys, even at a reused address.
+        assert_ne!(array_key(&open_store(b.path())?, "/crop"), key);
+        assert_eq!(array_key(&open_store(a.path())?, "/crop"), key);
+        Ok(())
+    }
+
     #[test]
     fn read_chunk_helper_remains_compatible_with_unsharded_arrays(
     ) -> Result<(), Box<dyn std::error::Error>> {
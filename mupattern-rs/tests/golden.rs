@@ -0,0 +1,328 @@
+//! Golden-output regression test (`cargo test --features golden`). Runs convert → crop →
+//! expression → movie on a tiny generated CZI file and compares array checksums, table
+//! headers and rows, and the result manifests with `tests/golden/outputs.json`.
+//!
+//! After an intentional output change, regenerate the file with
+//! `MUPATTERN_UPDATE_GOLDEN=1 cargo test --features golden` and commit it with the change.
+//! Table provenance comments are left out of the comparison (they carry the version).
+//! `MUPATTERN_GOLDEN_ND2` points at a small ND2 to smoke-test the ND2 reader as well.
+//! `movie` runs only when ffmpeg is on PATH and is checked by frame count (encoded bytes
+//! depend on the ffmpeg build).
+
+#![cfg(feature = "golden")]
+
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use xxhash_rust::xxh3::xxh3_64;
+
+const WIDTH: u32 = 32;
+const HEIGHT: u32 = 24;
+const TIMES: u32 = 3;
+const CHANNELS: u32 = 2;
+
+const BBOXES: &str = "crop,x,y,w,h\n0,2,2,10,8\n1,16,10,12,12\n";
+
+const CZI_METADATA: &str = r#"<ImageDocument><Metadata><Information><Image><Dimensions><Channels><Channel Id="Channel:0" Name="Phase"/><Channel Id="Channel:1" Name="GFP"/></Channels></Dimensions></Image></Information><Scaling><Items><Distance Id="X"><Value>6.5E-07</Value></Distance></Items></Scaling></Metadata></ImageDocument>"#;
+
+/// A smooth ramp per channel that brightens over time, plus one bright spot per frame.
+fn pixel(c: u32, t: u32, x: u32, y: u32) -> u16 {
+    let spot = if (x, y) == (5 + t, 5) { 3000 } else { 0 };
+    (100 + c * 1000 + t * 50 + x * 3 + y * 7 + spot) as u16
+}
+
+/// A ZISRAW segment: 16-byte id, allocated and used size, then `data`.
+fn czi_segment(id: &str, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; 32];
+    out[..id.len()].copy_from_slice(id.as_bytes());
+    out[16..24].copy_from_slice(&(data.len() as i64).to_le_bytes());
+    out[24..32].copy_from_slice(&(data.len() as i64).to_le_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+/// A "DV" directory entry for an uncompressed 16-bit plane at `file_position`.
+fn czi_entry(file_position: u64, dims: &[(&str, i32, i32)]) -> Vec<u8> {
+    let mut out = b"DV".to_vec();
+    out.extend_from_slice(&1i32.to_le_bytes());
+    out.extend_from_slice(&(file_position as i64).to_le_bytes());
+    out.extend_from_slice(&0i32.to_le_bytes());
+    out.extend_from_slice(&0i32.to_le_bytes());
+    out.extend_from_slice(&[0u8; 6]);
+    out.extend_from_slice(&(dims.len() as i32).to_le_bytes());
+    for &(name, start, size) in dims {
+        let mut dim = [0u8; 20];
+        dim[..name.len()].copy_from_slice(name.as_bytes());
+        dim[4..8].copy_from_slice(&start.to_le_bytes());
+        dim[8..12].copy_from_slice(&size.to_le_bytes());
+        dim[16..20].copy_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&dim);
+    }
+    out
+}
+
+/// One scene of `TIMES` × `CHANNELS` planes with channel names and a pixel size.
+fn write_czi(path: &Path) {
+    let (w, h) = (WIDTH as i32, HEIGHT as i32);
+    let mut file = czi_segment("ZISRAWFILE", &[0u8; 80]);
+    let mut entries = Vec::new();
+    for t in 0..TIMES {
+        for c in 0..CHANNELS {
+            let dims = [
+                ("X", 0, w),
+                ("Y", 0, h),
+                ("C", c as i32, 1),
+                ("T", t as i32, 1),
+            ];
+            let pixels: Vec<u8> = (0..HEIGHT)
+                .flat_map(|y| (0..WIDTH).map(move |x| pixel(c, t, x, y)))
+                .flat_map(u16::to_le_bytes)
+                .collect();
+            let dv = czi_entry(file.len() as u64, &dims);
+            let mut data = Vec::new();
+            data.extend_from_slice(&0i32.to_le_bytes());
+            data.extend_from_slice(&0i32.to_le_bytes());
+            data.extend_from_slice(&(pixels.len() as i64).to_le_bytes());
+            data.extend_from_slice(&dv);
+            data.resize(256, 0);
+            data.extend_from_slice(&pixels);
+            file.extend(czi_segment("ZISRAWSUBBLOCK", &data));
+            entries.push(dv);
+        }
+    }
+    let directory_pos = file.len() as i64;
+    let mut directory = (entries.len() as i32).to_le_bytes().to_vec();
+    directory.resize(128, 0);
+    directory.extend(entries.concat());
+    file.extend(czi_segment("ZISRAWDIRECTORY", &directory));
+    let metadata_pos = file.len() as i64;
+    let mut metadata = (CZI_METADATA.len() as i32).to_le_bytes().to_vec();
+    metadata.resize(256, 0);
+    metadata.extend_from_slice(CZI_METADATA.as_bytes());
+    file.extend(czi_segment("ZISRAWMETADATA", &metadata));
+    file[32 + 52..32 + 60].copy_from_slice(&directory_pos.to_le_bytes());
+    file[32 + 60..32 + 68].copy_from_slice(&metadata_pos.to_le_bytes());
+    fs::write(path, file).unwrap();
+}
+
+/// Run the CLI in `dir` and return its result manifest with `dir` stripped from paths.
+/// The user's mupattern.toml and MUPATTERN_* variables are kept out of the run.
+fn run(dir: &Path, args: &[&str]) -> Value {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_mupattern"));
+    for (key, _) in std::env::vars_os() {
+        if key.to_string_lossy().starts_with("MUPATTERN_") {
+            cmd.env_remove(key);
+        }
+    }
+    let output = cmd
+        .args(args)
+        .arg("--quiet")
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "mupattern {} failed:\n{}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let line = stdout.lines().last().expect("no result manifest on stdout");
+    let prefix = format!("{}/", dir.display());
+    serde_json::from_str(&line.replace(&prefix, "")).unwrap()
+}
+
+/// Schema line, header and digest of the data rows of a CSV table; the schema and header
+/// are kept in clear so layout changes show up readably in the diff.
+fn table_summary(path: &Path) -> Value {
+    let text = fs::read_to_string(path).unwrap();
+    let schema = text
+        .lines()
+        .find(|l| l.starts_with("# schema:"))
+        .unwrap_or("");
+    let mut lines = text.lines().filter(|l| !l.starts_with('#'));
+    let header = lines.next().unwrap_or("");
+    let rows: Vec<&str> = lines.collect();
+    json!({
+        "schema": schema,
+        "header": header,
+        "rows": rows.len(),
+        "xxh3": format!("{:016x}", xxh3_64(rows.join("\n").as_bytes())),
+    })
+}
+
+/// Stored `crop --checksums` digest of every crop array.
+fn crop_checksums(store: &Path) -> Value {
+    let crop_root = store.join("pos/000/crop");
+    let mut ids: Vec<String> = fs::read_dir(&crop_root)
+        .unwrap()
+        .map(|e| e.unwrap())
+        .filter(|e| e.file_type().unwrap().is_dir())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    ids.sort();
+    let mut out = serde_json::Map::new();
+    for id in ids {
+        let meta: Value = serde_json::from_str(
+            &fs::read_to_string(crop_root.join(&id).join("zarr.json")).unwrap(),
+        )
+        .unwrap();
+        out.insert(id, meta["attributes"]["checksum"]["value"].clone());
+    }
+    Value::Object(out)
+}
+
+fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+#[test]
+fn pipeline_outputs_match_golden() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write_czi(&root.join("data.czi"));
+    fs::write(root.join("bbox.csv"), BBOXES).unwrap();
+
+    if let Ok(nd2) = std::env::var("MUPATTERN_GOLDEN_ND2") {
+        let manifest = run(
+            root,
+            &[
+                "convert",
+                "--input",
+                &nd2,
+                "--pos",
+                "0",
+                "--time",
+                "0",
+                "--channel",
+                "all",
+                "--z",
+                "all",
+                "--output",
+                "nd2",
+                "--yes",
+            ],
+        );
+        assert_eq!(manifest["artifacts"][0]["type"], "tiff_folder");
+    }
+
+    let convert = run(
+        root,
+        &[
+            "convert",
+            "--input",
+            "data.czi",
+            "--pos",
+            "all",
+            "--time",
+            "all",
+            "--channel",
+            "all",
+            "--z",
+            "all",
+            "--output",
+            "converted",
+            "--yes",
+        ],
+    );
+    let crop = run(
+        root,
+        &[
+            "crop",
+            "--input",
+            "converted",
+            "--pos",
+            "0",
+            "--bbox",
+            "bbox.csv",
+            "--output",
+            "crops.zarr",
+            "--background",
+            "--checksums",
+        ],
+    );
+    let expression = run(
+        root,
+        &[
+            "expression",
+            "--input",
+            "crops.zarr",
+            "--pos",
+            "0",
+            "--channel",
+            "GFP",
+            "--time",
+            "all",
+            "--output",
+            "expression.csv",
+            "--subtract-background",
+        ],
+    );
+    let actual = json!({
+        "convert": {"manifest": convert},
+        "crop": {"manifest": crop, "checksums": crop_checksums(&root.join("crops.zarr"))},
+        "expression": {"manifest": expression, "table": table_summary(&root.join("expression.csv"))},
+    });
+
+    if ffmpeg_available() {
+        let movie = run(
+            root,
+            &[
+                "movie",
+                "--input",
+                "crops.zarr",
+                "--pos",
+                "0",
+                "--crop",
+                "all",
+                "--channel",
+                "0,1",
+                "--time",
+                "all",
+                "--output",
+                "movie_{crop}.mp4",
+            ],
+        );
+        let artifacts = movie["artifacts"].as_array().unwrap();
+        assert_eq!(artifacts.len(), 2);
+        for artifact in artifacts {
+            assert_eq!(artifact["frames"], TIMES);
+            assert!(
+                fs::metadata(root.join(artifact["path"].as_str().unwrap()))
+                    .unwrap()
+                    .len()
+                    > 0
+            );
+        }
+    }
+
+    let golden_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/outputs.json");
+    if std::env::var_os("MUPATTERN_UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        fs::write(
+            &golden_path,
+            serde_json::to_string_pretty(&actual).unwrap() + "\n",
+        )
+        .unwrap();
+        return;
+    }
+    let golden: Value = match fs::read_to_string(&golden_path) {
+        Ok(text) => serde_json::from_str(&text).unwrap(),
+        Err(_) => panic!(
+            "{} is missing; create it with MUPATTERN_UPDATE_GOLDEN=1 cargo test --features golden",
+            golden_path.display()
+        ),
+    };
+    // Value equality ignores key order, which serde_json may or may not preserve.
+    assert!(
+        actual == golden,
+        "outputs differ from {} (rerun with MUPATTERN_UPDATE_GOLDEN=1 if the change is intended):\n{}",
+        golden_path.display(),
+        serde_json::to_string_pretty(&actual).unwrap()
+    );
+}